@@ -0,0 +1,51 @@
+use cgmath::{Vector2, Vector3};
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+use multiplayer_game_demo_rust::{message::Message, Player};
+
+/// Position sent by every client at its send rate
+fn position() -> Message {
+    Message::Position(0x1234_5678_9ABC_DEF0, Vector2::new(123.4, -567.8))
+}
+
+/// Snapshot of a single player sent to every client on every tick
+fn snapshot() -> Message {
+    Message::Replicate(
+        123_456,
+        Player {
+            id: 42,
+            pos: Vector2::new(123.4, -567.8),
+            velocity: Vector2::new(0.0, 0.0),
+            color: Vector3::new(0.2, 0.4, 0.8),
+        },
+    )
+}
+
+fn bench_message(c: &mut Criterion, name: &str, msg: &Message) {
+    let mut group = c.benchmark_group(name);
+    let serialized = msg.serialize();
+
+    group.bench_function("serialize", |b| b.iter(|| msg.serialize()));
+    group.bench_function("serialize_into", |b| {
+        let mut buffer = Vec::new();
+        b.iter(|| {
+            buffer.clear();
+            msg.serialize_into(&mut buffer);
+        })
+    });
+    group.bench_function("deserialize", |b| {
+        b.iter_batched(
+            || serialized.as_slice(),
+            |bytes| Message::deserialize(bytes).unwrap(),
+            BatchSize::SmallInput,
+        )
+    });
+    group.finish();
+}
+
+fn serialization(c: &mut Criterion) {
+    bench_message(c, "POS", &position());
+    bench_message(c, "REPL", &snapshot());
+}
+
+criterion_group!(benches, serialization);
+criterion_main!(benches);
@@ -0,0 +1,477 @@
+//! Generates the protocol schema from the `Message` enum and the `DatagramHeader` in
+//! `src/message.rs`, so that alternative client implementations can follow the protocol as it
+//! grows. Emitted into `OUT_DIR` as JSON (`protocol.json`) and as Markdown (`protocol.md`), both
+//! embedded by `message.rs`.
+
+use std::{collections::HashMap, path::Path};
+
+use quote::ToTokens;
+use serde_json::json;
+
+const MESSAGE_SOURCE: &str = "src/message.rs";
+/// Prefix of the doc comment line of each variant giving its encoding
+const WIRE_FORMAT_PREFIX: &str = "Wire format:";
+/// Prefix of the constants of header flags
+const FLAG_PREFIX: &str = "FLAG_";
+/// Module holding the tag byte constants of message types
+const TAG_MODULE: &str = "tag";
+
+struct MessageSchema {
+    name: String,
+    /// Name of the message type in the wire format, logs and traffic statistics
+    message_type: String,
+    tag: u64,
+    description: String,
+    fields: Vec<String>,
+    format: String,
+    channel: String,
+}
+
+/// Binary header in front of every message
+struct HeaderSchema {
+    magic: String,
+    version: u64,
+    description: String,
+    /// Name and size in bytes of the magic and the fields of `DatagramHeader`
+    fields: Vec<(String, usize)>,
+    /// Name, bit and description of each flag
+    flags: Vec<(String, u64, String)>,
+    /// Name, ID and description of each channel
+    channels: Vec<(String, usize, String)>,
+}
+
+fn main() {
+    println!("cargo:rerun-if-changed={MESSAGE_SOURCE}");
+    let source = std::fs::read_to_string(MESSAGE_SOURCE)
+        .unwrap_or_else(|e| panic!("Failed to read {MESSAGE_SOURCE}: {e}"));
+    let file = syn::parse_file(&source)
+        .unwrap_or_else(|e| panic!("Failed to parse {MESSAGE_SOURCE}: {e}"));
+
+    let constants = string_constants(&file);
+    let message_types: HashMap<String, String> = match_arms(&file, "name")
+        .into_iter()
+        .map(|(variant, constant)| {
+            let message_type = constants
+                .get(&constant)
+                .unwrap_or_else(|| panic!("Constant {constant} not found"));
+            (variant, message_type.clone())
+        })
+        .collect();
+    let tag_constants = tag_constants(&file);
+    let tags: HashMap<String, u64> = match_arms(&file, "tag")
+        .into_iter()
+        .map(|(variant, constant)| {
+            let tag = tag_constants
+                .get(&constant)
+                .unwrap_or_else(|| panic!("Tag {constant} not found"));
+            (variant, *tag)
+        })
+        .collect();
+    let channels = match_arms(&file, "channel");
+    let header = header_schema(&file);
+    let message_enum = file
+        .items
+        .iter()
+        .find_map(|item| match item {
+            syn::Item::Enum(item) if item.ident == "Message" => Some(item),
+            _ => None,
+        })
+        .expect("Message enum not found");
+
+    let messages: Vec<MessageSchema> = message_enum
+        .variants
+        .iter()
+        .map(|variant| {
+            let name = variant.ident.to_string();
+            let docs = doc_lines(&variant.attrs);
+            let format = docs
+                .iter()
+                .find_map(|line| line.strip_prefix(WIRE_FORMAT_PREFIX))
+                .map(|format| format.trim().trim_matches('`').to_string())
+                .unwrap_or_else(|| {
+                    panic!("Message::{name} has no \"{WIRE_FORMAT_PREFIX}\" line in its docs")
+                });
+            let message_type = message_types
+                .get(&name)
+                .unwrap_or_else(|| panic!("Message::{name} is missing from Message::name()"))
+                .clone();
+            let tag = *tags
+                .get(&name)
+                .unwrap_or_else(|| panic!("Message::{name} is missing from Message::tag()"));
+            let channel = channels
+                .get(&name)
+                .unwrap_or_else(|| panic!("Message::{name} is missing from Message::channel()"))
+                .clone();
+            let starts_with_message_type = format
+                .strip_prefix(message_type.as_str())
+                .is_some_and(|rest| !rest.starts_with(|c: char| c.is_ascii_alphanumeric()));
+            if !starts_with_message_type {
+                panic!("Wire format of Message::{name} doesn't start with its type {message_type}");
+            }
+            MessageSchema {
+                description: description(&docs),
+                fields: variant
+                    .fields
+                    .iter()
+                    .map(|field| type_name(&field.ty))
+                    .collect(),
+                name,
+                message_type,
+                tag,
+                format,
+                channel,
+            }
+        })
+        .collect();
+    let mut seen_tags = HashMap::new();
+    for message in &messages {
+        if let Some(other) = seen_tags.insert(message.tag, &message.name) {
+            panic!(
+                "Message::{} and Message::{other} share the tag {:#04X}",
+                message.name, message.tag
+            );
+        }
+    }
+
+    let out_dir = std::env::var("OUT_DIR").expect("OUT_DIR is set by Cargo");
+    let out_dir = Path::new(&out_dir);
+    write(
+        &out_dir.join("protocol.json"),
+        &schema_json(
+            &description(&doc_lines(&message_enum.attrs)),
+            &header,
+            &messages,
+        ),
+    );
+    write(
+        &out_dir.join("protocol.md"),
+        &(header_table(&header) + "\n" + &schema_table(&messages)),
+    );
+}
+
+/// Values of `const NAME: &str = "VALUE";` items, which are the names of message types
+fn string_constants(file: &syn::File) -> HashMap<String, String> {
+    file.items
+        .iter()
+        .filter_map(|item| match item {
+            syn::Item::Const(item) => match &*item.expr {
+                syn::Expr::Lit(syn::ExprLit {
+                    lit: syn::Lit::Str(value),
+                    ..
+                }) => Some((item.ident.to_string(), value.value())),
+                _ => None,
+            },
+            _ => None,
+        })
+        .collect()
+}
+
+/// Values of the `const NAME: u8 = VALUE;` items in the tag module
+fn tag_constants(file: &syn::File) -> HashMap<String, u64> {
+    let items = file
+        .items
+        .iter()
+        .find_map(|item| match item {
+            syn::Item::Mod(item) if item.ident == TAG_MODULE => item.content.as_ref(),
+            _ => None,
+        })
+        .map(|(_, items)| items)
+        .unwrap_or_else(|| panic!("Module {TAG_MODULE} not found"));
+    items
+        .iter()
+        .filter_map(|item| match item {
+            syn::Item::Const(item) => match &*item.expr {
+                syn::Expr::Lit(syn::ExprLit {
+                    lit: syn::Lit::Int(value),
+                    ..
+                }) => Some((
+                    item.ident.to_string(),
+                    value.base10_parse().expect("Tag is a number"),
+                )),
+                _ => None,
+            },
+            _ => None,
+        })
+        .collect()
+}
+
+fn header_schema(file: &syn::File) -> HeaderSchema {
+    let mut magic = None;
+    let mut version = None;
+    let mut flags = Vec::new();
+    for item in &file.items {
+        let syn::Item::Const(item) = item else {
+            continue;
+        };
+        let syn::Expr::Lit(syn::ExprLit { lit, .. }) = &*item.expr else {
+            continue;
+        };
+        let name = item.ident.to_string();
+        match (name.as_str(), lit) {
+            ("PROTOCOL_MAGIC", syn::Lit::ByteStr(value)) => {
+                magic = Some(String::from_utf8(value.value()).expect("Magic is ASCII"));
+            }
+            ("PROTOCOL_VERSION", syn::Lit::Int(value)) => {
+                version = Some(value.base10_parse().expect("Version is a number"));
+            }
+            (_, syn::Lit::Int(value)) if name.starts_with(FLAG_PREFIX) => flags.push((
+                name[FLAG_PREFIX.len()..].to_lowercase(),
+                value.base10_parse().expect("Flag is a number"),
+                description(&doc_lines(&item.attrs)),
+            )),
+            _ => (),
+        }
+    }
+    let header_struct = file
+        .items
+        .iter()
+        .find_map(|item| match item {
+            syn::Item::Struct(item) if item.ident == "DatagramHeader" => Some(item),
+            _ => None,
+        })
+        .expect("DatagramHeader struct not found");
+    let magic = magic.expect("PROTOCOL_MAGIC not found");
+    let fields = std::iter::once(("magic".to_string(), magic.len()))
+        .chain(header_struct.fields.iter().map(|field| {
+            let name = field
+                .ident
+                .as_ref()
+                .expect("DatagramHeader has named fields")
+                .to_string();
+            let size = match type_name(&field.ty).as_str() {
+                "u8" | "Channel" => 1,
+                "u16" | "Sequence" => 2,
+                ty => panic!("Size of DatagramHeader::{name} of type {ty} is unknown"),
+            };
+            (name, size)
+        }))
+        .collect();
+
+    HeaderSchema {
+        magic,
+        version: version.expect("PROTOCOL_VERSION not found"),
+        description: description(&doc_lines(&header_struct.attrs)),
+        fields,
+        flags,
+        channels: channel_schemas(file),
+    }
+}
+
+/// Variants of the `Channel` enum with their IDs and descriptions
+fn channel_schemas(file: &syn::File) -> Vec<(String, usize, String)> {
+    file.items
+        .iter()
+        .find_map(|item| match item {
+            syn::Item::Enum(item) if item.ident == "Channel" => Some(item),
+            _ => None,
+        })
+        .expect("Channel enum not found")
+        .variants
+        .iter()
+        .enumerate()
+        .map(|(id, variant)| {
+            (
+                variant.ident.to_string(),
+                id,
+                description(&doc_lines(&variant.attrs)),
+            )
+        })
+        .collect()
+}
+
+/// Last path segment returned for each variant by the match arms of the given method of
+/// `Message`, e.g. the constant of the tag returned by `Message::tag()`
+fn match_arms(file: &syn::File, method: &str) -> HashMap<String, String> {
+    let method_fn = file
+        .items
+        .iter()
+        .filter_map(|item| match item {
+            syn::Item::Impl(item) if item.trait_.is_none() => Some(item),
+            _ => None,
+        })
+        .filter(|item| item.self_ty.to_token_stream().to_string() == "Message")
+        .flat_map(|item| &item.items)
+        .find_map(|item| match item {
+            syn::ImplItem::Fn(item) if item.sig.ident == method => Some(item),
+            _ => None,
+        })
+        .unwrap_or_else(|| panic!("Message::{method}() not found"));
+    let Some(syn::Stmt::Expr(syn::Expr::Match(method_match), _)) = method_fn.block.stmts.last()
+    else {
+        panic!("Message::{method}() is expected to be a single match");
+    };
+
+    let mut values = HashMap::new();
+    for arm in &method_match.arms {
+        let syn::Expr::Path(value) = &*arm.body else {
+            panic!("Message::{method}() is expected to return constants");
+        };
+        let value = value
+            .path
+            .segments
+            .last()
+            .expect("Path has a segment")
+            .ident
+            .to_string();
+        for variant in pattern_variants(&arm.pat) {
+            values.insert(variant, value.clone());
+        }
+    }
+    values
+}
+
+fn pattern_variants(pat: &syn::Pat) -> Vec<String> {
+    let path = match pat {
+        syn::Pat::Or(pat) => return pat.cases.iter().flat_map(pattern_variants).collect(),
+        syn::Pat::Path(pat) => &pat.path,
+        syn::Pat::TupleStruct(pat) => &pat.path,
+        syn::Pat::Struct(pat) => &pat.path,
+        _ => panic!("Unexpected pattern in a match on Message"),
+    };
+    path.segments
+        .last()
+        .map(|segment| segment.ident.to_string())
+        .into_iter()
+        .collect()
+}
+
+fn doc_lines(attrs: &[syn::Attribute]) -> Vec<String> {
+    attrs
+        .iter()
+        .filter_map(|attr| match &attr.meta {
+            syn::Meta::NameValue(meta) if meta.path.is_ident("doc") => match &meta.value {
+                syn::Expr::Lit(syn::ExprLit {
+                    lit: syn::Lit::Str(line),
+                    ..
+                }) => Some(line.value().trim().to_string()),
+                _ => None,
+            },
+            _ => None,
+        })
+        .collect()
+}
+
+/// First paragraph of a doc comment without notes for developers, in a single line
+fn description(docs: &[String]) -> String {
+    docs.iter()
+        .take_while(|line| !line.is_empty())
+        .filter(|line| !line.starts_with("TODO"))
+        .map(String::as_str)
+        .collect::<Vec<&str>>()
+        .join(" ")
+        .split_whitespace()
+        .collect::<Vec<&str>>()
+        .join(" ")
+}
+
+/// Rust type as written in the source, e.g. `Option<SessionToken>`
+fn type_name(ty: &syn::Type) -> String {
+    ty.to_token_stream()
+        .to_string()
+        .replace(" <", "<")
+        .replace("< ", "<")
+        .replace(" >", ">")
+        .replace(" ,", ",")
+}
+
+fn schema_json(description: &str, header: &HeaderSchema, messages: &[MessageSchema]) -> String {
+    let messages: Vec<serde_json::Value> = messages
+        .iter()
+        .map(|message| {
+            json!({
+                "name": message.name,
+                "type": message.message_type,
+                "tag": message.tag,
+                "format": message.format,
+                "fields": message.fields,
+                "channel": message.channel,
+                "description": message.description,
+            })
+        })
+        .collect();
+    let flags: Vec<serde_json::Value> = header
+        .flags
+        .iter()
+        .map(|(name, value, description)| {
+            json!({
+                "name": name,
+                "value": value,
+                "description": description,
+            })
+        })
+        .collect();
+    let fields: Vec<serde_json::Value> = header
+        .fields
+        .iter()
+        .map(|(name, size)| json!({ "name": name, "size": size }))
+        .collect();
+    let channels: Vec<serde_json::Value> = header
+        .channels
+        .iter()
+        .map(|(name, id, description)| {
+            json!({
+                "name": name,
+                "id": id,
+                "description": description,
+            })
+        })
+        .collect();
+    let schema = json!({
+        "transport": "UDP",
+        "byte_order": "big-endian",
+        "description": description,
+        "header": {
+            "magic": header.magic,
+            "version": header.version,
+            "fields": fields,
+            "flags": flags,
+            "channels": channels,
+            "description": header.description,
+        },
+        "messages": messages,
+    });
+    serde_json::to_string_pretty(&schema).expect("Schema is valid JSON") + "\n"
+}
+
+fn header_table(header: &HeaderSchema) -> String {
+    let mut table = format!(
+        "{} The magic bytes are `{}` and the protocol version is {}.\n\n",
+        header.description, header.magic, header.version
+    );
+    table += "| Flag | Value | Description |\n";
+    table += "| --- | --- | --- |\n";
+    for (name, value, description) in &header.flags {
+        table += &format!("| `{name}` | `{value:#04X}` | {description} |\n");
+    }
+    table += "\n| Channel | ID | Description |\n";
+    table += "| --- | --- | --- |\n";
+    for (name, id, description) in &header.channels {
+        table += &format!("| `{name}` | `{id}` | {description} |\n");
+    }
+    table
+}
+
+fn schema_table(messages: &[MessageSchema]) -> String {
+    let mut table = String::from("| Tag | Message | Wire format | Channel | Description |\n");
+    table += "| --- | --- | --- | --- | --- |\n";
+    for message in messages {
+        let variant = if message.fields.is_empty() {
+            message.name.clone()
+        } else {
+            format!("{}({})", message.name, message.fields.join(", "))
+        };
+        table += &format!(
+            "| `{:#04X}` | `{variant}` | `{}` | `{}` | {} |\n",
+            message.tag,
+            message.format.replace('|', "\\|"),
+            message.channel,
+            message.description.replace('|', "\\|")
+        );
+    }
+    table
+}
+
+fn write(path: &Path, contents: &str) {
+    std::fs::write(path, contents)
+        .unwrap_or_else(|e| panic!("Failed to write {}: {e}", path.display()));
+}
@@ -0,0 +1,17 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use multiplayer_game_demo_rust::message::Message;
+
+fuzz_target!(|data: &[u8]| {
+    // Message bytes after the datagram header
+    if let Ok(deserialized) = Message::deserialize(data) {
+        // Anything accepted has to be accepted again when relayed. Positions and colors are
+        // quantized, so only the second serialization has to be identical.
+        let serialized = deserialized.serialize();
+        let reserialized = Message::deserialize(&serialized)
+            .expect("Serialized message is rejected")
+            .serialize();
+        assert_eq!(serialized, reserialized);
+    }
+});
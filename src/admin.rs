@@ -0,0 +1,190 @@
+use std::{net::IpAddr, str::FromStr, time::Duration};
+
+use cgmath::Vector2;
+
+use crate::{globals, PlayerID};
+
+pub const HELP: &str = "Available commands:
+  ban <player id|address> [duration] [reason]   Kick and ban address, e.g. \"ban 3 30m spamming\"
+  unban <address>                              Lift ban of address
+  mute <player id|address> [duration] [reason]  Drop chat messages from address
+  unmute <address>                             Lift mute of address
+  kick <player id> [reason]                    Drop player from the server, they may join again
+  motd [text]                                  Set message of the day until reload, clear without text
+  stats                                        Show traffic per message type, tick durations and usage
+  reload                                       Re-read config file given with --config
+  tp <player id> <x> <y>                       Move player to a position
+  respawn <player id>                          Move player back to its spawn point
+  pause                                        Freeze the game until resumed
+  resume                                       Continue the paused game
+  timescale <factor>                           Slow down or speed up the simulation, e.g. \"timescale 0.25\"
+  storm                                        Start a storm right away
+  help                                         Show this help
+Duration is a number followed by s, m, h or d. Sanction is permanent without duration.";
+
+/// Player can be referred either by their player number while connected, or by address.
+pub enum AdminTarget {
+    Player(PlayerID),
+    Address(IpAddr),
+}
+
+/// Command entered on the admin console of a headless server.
+pub enum AdminCommand {
+    Ban {
+        target: AdminTarget,
+        duration: Option<Duration>,
+        reason: String,
+    },
+    Unban(IpAddr),
+    Mute {
+        target: AdminTarget,
+        duration: Option<Duration>,
+        reason: String,
+    },
+    Unmute(IpAddr),
+    Kick {
+        player_id: PlayerID,
+        reason: String,
+    },
+    /// Message of the day sent to players joining from now on, None clears it
+    Motd(Option<String>),
+    Stats,
+    Reload,
+    Teleport(PlayerID, Vector2<f32>),
+    Respawn(PlayerID),
+    Pause,
+    Resume,
+    TimeScale(f32),
+    Storm,
+    Help,
+}
+
+impl FromStr for AdminCommand {
+    type Err = String;
+
+    fn from_str(line: &str) -> Result<Self, Self::Err> {
+        let mut args = line.split_whitespace();
+        match args.next() {
+            Some("ban") => {
+                let (target, duration, reason) = parse_sanction_args(args)?;
+                Ok(AdminCommand::Ban {
+                    target,
+                    duration,
+                    reason,
+                })
+            }
+            Some("unban") => Ok(AdminCommand::Unban(parse_address(args.next())?)),
+            Some("mute") => {
+                let (target, duration, reason) = parse_sanction_args(args)?;
+                Ok(AdminCommand::Mute {
+                    target,
+                    duration,
+                    reason,
+                })
+            }
+            Some("unmute") => Ok(AdminCommand::Unmute(parse_address(args.next())?)),
+            Some("kick") => Ok(AdminCommand::Kick {
+                player_id: parse_player_id(args.next())?,
+                reason: args.collect::<Vec<_>>().join(" "),
+            }),
+            Some("motd") => {
+                let text = args.collect::<Vec<_>>().join(" ");
+                Ok(AdminCommand::Motd(
+                    Some(text).filter(|text| !text.is_empty()),
+                ))
+            }
+            Some("stats") => Ok(AdminCommand::Stats),
+            Some("reload") => Ok(AdminCommand::Reload),
+            Some("tp") => {
+                let player_id = parse_player_id(args.next())?;
+                let x = parse_coordinate(args.next())?;
+                let y = parse_coordinate(args.next())?;
+                Ok(AdminCommand::Teleport(player_id, Vector2::new(x, y)))
+            }
+            Some("respawn") => Ok(AdminCommand::Respawn(parse_player_id(args.next())?)),
+            Some("pause") => Ok(AdminCommand::Pause),
+            Some("resume") => Ok(AdminCommand::Resume),
+            Some("timescale") => Ok(AdminCommand::TimeScale(parse_time_scale(
+                args.next().ok_or("Missing factor")?,
+            )?)),
+            Some("storm") => Ok(AdminCommand::Storm),
+            Some("help") => Ok(AdminCommand::Help),
+            Some(command) => Err(format!(
+                "Unknown command \"{command}\". Type \"help\" for available commands."
+            )),
+            None => Err("Empty command".to_string()),
+        }
+    }
+}
+
+fn parse_sanction_args<'a>(
+    mut args: impl Iterator<Item = &'a str>,
+) -> Result<(AdminTarget, Option<Duration>, String), String> {
+    let target = match args.next() {
+        Some(arg) => match arg.parse::<PlayerID>() {
+            Ok(player_id) => AdminTarget::Player(player_id),
+            Err(_) => AdminTarget::Address(parse_address(Some(arg))?),
+        },
+        None => return Err("Missing player ID or address".to_string()),
+    };
+
+    // Duration is optional, anything else is already part of the reason
+    let mut args = args.peekable();
+    let duration = args.peek().and_then(|arg| parse_duration(arg));
+    if duration.is_some() {
+        args.next();
+    }
+    let reason = args.collect::<Vec<_>>().join(" ");
+
+    Ok((target, duration, reason))
+}
+
+/// Factor of the debug time scale, also accepted by the `/timescale` command of the client
+pub fn parse_time_scale(arg: &str) -> Result<f32, String> {
+    let scale: f32 = arg
+        .parse()
+        .map_err(|_| format!("Invalid time scale \"{arg}\""))?;
+    if !(globals::MIN_TIME_SCALE..=globals::MAX_TIME_SCALE).contains(&scale) {
+        return Err(format!(
+            "Time scale has to be between {} and {}",
+            globals::MIN_TIME_SCALE,
+            globals::MAX_TIME_SCALE
+        ));
+    }
+
+    Ok(scale)
+}
+
+fn parse_player_id(arg: Option<&str>) -> Result<PlayerID, String> {
+    let arg = arg.ok_or("Missing player ID")?;
+    arg.parse()
+        .map_err(|_| format!("Invalid player ID \"{arg}\""))
+}
+
+fn parse_coordinate(arg: Option<&str>) -> Result<f32, String> {
+    let arg = arg.ok_or("Missing coordinate")?;
+    arg.parse()
+        .ok()
+        .filter(|coordinate: &f32| coordinate.is_finite())
+        .ok_or_else(|| format!("Invalid coordinate \"{arg}\""))
+}
+
+fn parse_address(arg: Option<&str>) -> Result<IpAddr, String> {
+    let arg = arg.ok_or("Missing address")?;
+    arg.parse()
+        .map_err(|_| format!("Invalid address \"{arg}\""))
+}
+
+fn parse_duration(arg: &str) -> Option<Duration> {
+    let unit_start = arg.find(|c: char| !c.is_ascii_digit())?;
+    let amount: u64 = arg[..unit_start].parse().ok()?;
+    let unit_secs = match &arg[unit_start..] {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => return None,
+    };
+
+    Some(Duration::from_secs(amount * unit_secs))
+}
@@ -0,0 +1,39 @@
+use std::time::Duration;
+
+use serde::Serialize;
+
+/// How often the server looks for players who stopped moving
+pub const AFK_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+/// Moves shorter than this are quantization error or corrections, not input of the player
+pub const MIN_INPUT_DISTANCE: f32 = 1.0;
+
+/// Whether a player is playing, away from keyboard, or moved to spectators for being away too
+/// long. Spectators stay connected and keep watching, but are taken out of the world and don't
+/// take up a slot of the server. Ordered by how long the player has been idle.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PlayerActivity {
+    #[default]
+    Active,
+    /// Shown dimmed to the others
+    Afk,
+    Spectating,
+}
+
+/// Activity of a player who hasn't moved for `idle`. Players are away after `afk_timeout`, and
+/// spectators after `spectate_after` or as soon as they are away while others wait in the join
+/// queue for their slot. Nobody is ever away without `afk_timeout`.
+pub fn activity_after(
+    idle: Duration,
+    afk_timeout: Option<Duration>,
+    spectate_after: Option<Duration>,
+    queue_waiting: bool,
+) -> PlayerActivity {
+    if !afk_timeout.is_some_and(|afk_timeout| idle >= afk_timeout) {
+        PlayerActivity::Active
+    } else if queue_waiting || spectate_after.is_some_and(|spectate_after| idle >= spectate_after) {
+        PlayerActivity::Spectating
+    } else {
+        PlayerActivity::Afk
+    }
+}
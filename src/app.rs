@@ -1,110 +1,397 @@
-use std::{collections::HashMap, error::Error, time::Duration};
+use std::{
+    collections::{HashMap, HashSet},
+    error::Error,
+    net::SocketAddr,
+    path::PathBuf,
+    time::{Duration, Instant},
+};
 
 use cgmath::{InnerSpace, Vector2};
-use tokio::task::JoinHandle;
+use tokio::{sync::watch, task::JoinHandle};
 use winit::{
     application::ApplicationHandler,
-    event::{ElementState, KeyEvent, WindowEvent},
+    dpi::{PhysicalPosition, PhysicalSize},
+    event::{ElementState, KeyEvent, MouseButton, Touch, WindowEvent},
     event_loop::{ActiveEventLoop, EventLoop},
     keyboard::{Key, KeyCode, NamedKey, PhysicalKey},
     platform::pump_events::EventLoopExtPumpEvents,
-    window::Window,
+    window::{Fullscreen, Window},
 };
 
 use crate::{
-    client::ClientSessionResult,
+    admin,
+    afk::PlayerActivity,
+    attract::{self, AttractMatch},
+    client::{self, ConnectionState},
+    client_config::{ClientConfig, WindowGeometry},
+    editor::{self, MapEditor},
     fsm, globals,
-    gui::Gui,
+    gui::{
+        self, Gui, GuiState, HostedServer, InspectedEntity, NetcodeTuning, ReplayStatus,
+        StormWarning,
+    },
+    hud::GameMode,
+    input::{
+        InputBuffer, InputEvent, InputPlayback, InputRecorder, InputRecording, InputState,
+        KeyTarget, KeyboardFocus,
+    },
+    interpolation::SnapshotInterpolation,
+    invite,
+    lan::LanBrowser,
+    lockstep::LockstepSession,
+    map::{self, Map, MapDownload, MapInfo},
     message::{self, Message},
-    server, ClientSession, Player, PlayerID, Renderer,
+    renderer::{Camera, Environment, WindowPlacement},
+    replay::{Replay, ReplayPlayback},
+    server::{self, ServerHandle},
+    storm::{self, StormState},
+    summary::SessionSummary,
+    task,
+    touch::VirtualJoystick,
+    ClientSession, Player, PlayerID, RenderPass, Renderer, Scene, SessionToken, WorldParams,
 };
 
-pub fn run_app(rt: &tokio::runtime::Runtime) -> Result<(), Box<dyn Error>> {
-    let mut app = App::new(&rt)?;
-    let mut event_loop = EventLoop::new()?;
-    app.run(&mut event_loop);
+/// Startup parameters of the graphical client.
+pub struct LaunchOptions {
+    /// Skip the menu and join the server at this host name or IP address with port right away
+    pub connect: Option<String>,
+    /// Requested player name, server picks a default one if empty
+    pub player_name: String,
+    /// Positions sent to the server per second
+    pub send_rate: u32,
+    /// Play in peer-to-peer lockstep mode instead of joining a server
+    pub lockstep: Option<LockstepOptions>,
+    /// Play a bot match behind the menu after sitting idle in it
+    pub attract: bool,
+    /// Write the keys pressed while playing to this file on exit
+    pub record_input: Option<PathBuf>,
+    /// Play keys recorded to this file in place of the keyboard, and quit once done
+    pub play_input: Option<PathBuf>,
+    /// Watch the match replay in this file on startup
+    pub play_replay: Option<PathBuf>,
+    /// Appended to the window title to tell apart instances running on the same machine
+    pub window_title_suffix: Option<String>,
+    /// Distance the camera leads the player by when moving at full speed
+    pub camera_look_ahead: f32,
+}
 
-    Ok(())
+impl Default for LaunchOptions {
+    fn default() -> Self {
+        Self {
+            connect: None,
+            player_name: String::new(),
+            send_rate: client::DEFAULT_SEND_RATE,
+            lockstep: None,
+            attract: false,
+            record_input: None,
+            play_input: None,
+            play_replay: None,
+            window_title_suffix: None,
+            camera_look_ahead: DEFAULT_CAMERA_LOOK_AHEAD,
+        }
+    }
 }
 
-#[derive(Eq, Hash, PartialEq)]
-enum InputEvent {
-    MoveUp,
-    MoveDown,
-    MoveLeft,
-    MoveRight,
+/// Peers of a lockstep session. Every peer has to be started with the same list of addresses.
+pub struct LockstepOptions {
+    pub peers: Vec<SocketAddr>,
+    /// Position of this peer's own address in `peers`
+    pub local_index: usize,
 }
 
-// Using array instead of HashSet results in a single jump table which is more friendlier to cache,
-// avoids heap allocation and hash function calls for HashSet, has better branch prediction and has
-// fewer CPU instructions.
-//
-// (Even though gains are negligable, because bottleneck is usually not the input handling)
-type InputState = [bool; 4];
+pub fn run_app(rt: &tokio::runtime::Runtime, options: LaunchOptions) -> Result<(), Box<dyn Error>> {
+    let mut event_loop = EventLoop::new()?;
+    run_app_on_event_loop(rt, options, &mut event_loop)
+}
 
-impl std::ops::Index<InputEvent> for InputState {
-    type Output = bool;
+/// Android entry point where the event loop has to be bound to the activity handed over by the
+/// system.
+#[cfg(target_os = "android")]
+pub fn run_android_app(
+    rt: &tokio::runtime::Runtime,
+    android_app: winit::platform::android::activity::AndroidApp,
+) -> Result<(), Box<dyn Error>> {
+    use winit::platform::android::EventLoopBuilderExtAndroid;
 
-    fn index(&self, e: InputEvent) -> &Self::Output {
-        match e {
-            InputEvent::MoveUp => &self[0],
-            InputEvent::MoveDown => &self[1],
-            InputEvent::MoveLeft => &self[2],
-            InputEvent::MoveRight => &self[3],
-        }
-    }
+    let mut event_loop = EventLoop::builder().with_android_app(android_app).build()?;
+    run_app_on_event_loop(rt, LaunchOptions::default(), &mut event_loop)
 }
 
-impl std::ops::IndexMut<InputEvent> for InputState {
-    fn index_mut(&mut self, e: InputEvent) -> &mut Self::Output {
-        match e {
-            InputEvent::MoveUp => &mut self[0],
-            InputEvent::MoveDown => &mut self[1],
-            InputEvent::MoveLeft => &mut self[2],
-            InputEvent::MoveRight => &mut self[3],
-        }
-    }
+fn run_app_on_event_loop(
+    rt: &tokio::runtime::Runtime,
+    options: LaunchOptions,
+    event_loop: &mut EventLoop<()>,
+) -> Result<(), Box<dyn Error>> {
+    let mut app = App::new(rt, options)?;
+    app.run(event_loop);
+
+    Ok(())
 }
 
-type ConnectionTaskHandle = JoinHandle<ClientSessionResult>;
+/// Distance in pixels the camera is ahead of a player moving at full speed by default
+pub const DEFAULT_CAMERA_LOOK_AHEAD: f32 = 60.0;
+/// Camera can't lead the player by more than this, so the player never leaves the window
+pub const MAX_CAMERA_LOOK_AHEAD: f32 = 200.0;
+
+/// Game is considered resumed when the server stops reminding of the pause for this long, in
+/// case the resume announcement got lost
+const PAUSE_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Longest wait for window events in menus, where nothing moves without input. Connection
+/// attempts and the countdown of the attract mode still advance at this rate.
+const IDLE_EVENT_WAIT: Duration = Duration::from_millis(50);
+
+/// Joining the server of a lost session is retried for this long, enough for a server to restart
+const RECONNECT_GRACE: Duration = Duration::from_secs(20);
+/// Failed reconnection attempts are spaced out, since a refusal arrives right away
+const RECONNECT_RETRY_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Server panel of the host is updated this often, the numbers are unreadable otherwise
+const HOSTED_SERVER_REFRESH: Duration = Duration::from_secs(1);
+
+/// Local ports claimed by running instances of the client, one each, to number the instances
+/// started on the same machine
+const INSTANCE_SLOT_PORTS: std::ops::Range<u16> = 47810..47826;
+/// Distance between windows of instances on the same machine, so that they don't stack exactly on
+/// top of each other
+const INSTANCE_WINDOW_OFFSET: i32 = 48;
+
+/// Joined session, and the server handle if the player is hosting
+type ConnectionResult = Result<(ClientSession, Option<ServerHandle>), Box<dyn Error + Send + Sync>>;
+type ConnectionTaskHandle = JoinHandle<ConnectionResult>;
 type RemotePlayers = HashMap<PlayerID, Player>; // Access by ID because of position updates
 
+/// Window-system dependent resources. They only exist between `resumed` and `suspended` events,
+/// so everything that touches them has to gracefully handle their absence instead of unwrapping.
+struct Graphics {
+    window: Window,
+    renderer: Renderer,
+    gui: Gui,
+}
+
 struct App<'a> {
     rt: &'a tokio::runtime::Runtime,
-    window: Option<Window>,
-    renderer: Option<Renderer>,
-    gui: Option<Gui>,
+    graphics: Option<Graphics>,
+    /// GUI contents kept while graphics are torn down during suspension
+    gui_state: Option<GuiState>,
     client_session: Option<ClientSession>,
+    /// Server address and session token of the last session whose connection was lost, to take
+    /// the player over when joining the same server again
+    lost_session: Option<(String, SessionToken)>,
+    /// Server hosted by this player
+    server_handle: Option<ServerHandle>,
     connection_task: Option<ConnectionTaskHandle>,
+    /// Place in the join queue of a full server while the connection task waits for a slot
+    queue_position_rx: Option<watch::Receiver<Option<u32>>>,
+    /// Servers hosted on the LAN, listed in the menu. None if the beacon port can't be listened on.
+    lan_browser: Option<LanBrowser>,
     // Pushing pressed keys from event loop into this collection and processing in update() makes
     // movement continous. Naively checking for key press during event consumption leads to choppy
     // movement.
+    input_buffer: InputBuffer,
+    /// Decides whether key events go to the GUI or the game
+    keyboard_focus: KeyboardFocus,
+    /// Keys applied on the current logic update, taken from `input_buffer` or a playback
     input_state: InputState,
+    /// Logic updates spent playing, the clock of recorded and replayed input
+    input_tick: u64,
+    input_recorder: Option<(PathBuf, InputRecorder)>,
+    input_playback: Option<InputPlayback>,
+    joystick: VirtualJoystick,
     local_player: Player,
-    camera_pos: Vector2<f32>,
+    player_name: String,
+    /// Tuned live in the netcode panel, starting with the send rate of the launch options
+    netcode_tuning: NetcodeTuning,
+    /// Distance of the drawn local player from its position, left over from server corrections
+    /// being blended in
+    correction_offset: Vector2<f32>,
+    camera: Camera,
+    camera_look_ahead: f32,
+    /// Debug multiplier of how fast logic updates are run, slow motion below 1
+    time_scale: f32,
     remote_players: RemotePlayers,
+    /// Arrival of the newest snapshot of each remote player, shown in the entity inspector
+    player_updated_at: HashMap<PlayerID, Instant>,
+    /// Names announced by the server in `Profile` messages, including the local player
+    player_names: HashMap<PlayerID, String>,
+    /// Players whose chat messages are hidden on this client only
+    muted_players: HashSet<PlayerID>,
+    /// Jitter buffer positioning remote players
+    interpolation: SnapshotInterpolation,
+    /// Peer-to-peer session replacing the client session in lockstep mode
+    lockstep: Option<LockstepSession>,
+    /// Statistics of the joined server session
+    session_summary: Option<SessionSummary>,
+    /// Minutes since midnight in the world of the joined server
+    time_of_day: Option<u16>,
+    /// Storm running on the joined server, with the time its state arrived
+    storm: Option<(StormState, Instant)>,
+    /// Health of the local player, only lost while a storm is running
+    health: u8,
+    /// Player with the highest score in the match, marked with a crown
+    leader: Option<PlayerID>,
+    /// Remote players away from keyboard, drawn dimmed
+    afk_players: HashSet<PlayerID>,
+    /// Whether the server considers the local player away or moved it to spectators
+    activity: PlayerActivity,
+    /// Latest reminder of the server that the game is paused, None while running
+    paused_at: Option<Instant>,
+    /// Map of the joined server, empty until loaded
+    map: Map,
+    map_info: Option<MapInfo>,
+    map_download: Option<MapDownload>,
+    editor: MapEditor,
+    /// Last known mouse position in scene coordinates, which ignore scaling of the window
+    cursor_pos: Vector2<f32>,
+    /// Settings saved on exit, including where the window was
+    client_config: ClientConfig,
+    window_title_suffix: Option<String>,
+    /// Number of this instance among the ones running on the same machine, with the socket
+    /// claiming it for as long as the application runs
+    instance_slot: Option<(u16, std::net::UdpSocket)>,
+    /// Bot match shown behind the menu in attract mode
+    attract: Option<AttractMatch>,
+    /// Match replay being watched
+    replay: Option<ReplayPlayback>,
+    attract_enabled: bool,
+    /// Attract mode starts when no input arrives for a while
+    last_input_at: Instant,
+    /// Last update of the server panel, None while not hosting
+    hosted_server_refreshed_at: Option<Instant>,
+    /// Menus are only redrawn now and then while nothing happens
+    redrawn_at: Instant,
     state_machine: fsm::StateMachine,
 }
 
 impl<'a> App<'a> {
-    fn new(rt: &'a tokio::runtime::Runtime) -> Result<App, Box<dyn Error>> {
+    fn new(
+        rt: &'a tokio::runtime::Runtime,
+        options: LaunchOptions,
+    ) -> Result<App<'a>, Box<dyn Error>> {
         let mut state_machine = fsm::StateMachine::new();
         state_machine.push(fsm::State::Menu);
+
+        // Auto-connect behaves the same as pressing "Join server" in the menu, so failure falls
+        // back to the menu with the address already filled in.
+        let mut gui_state = GuiState::default();
+        let netcode_tuning = NetcodeTuning {
+            send_rate: options.send_rate,
+            ..Default::default()
+        };
+        gui_state.set_netcode_tuning(netcode_tuning);
+        if let Some(server_address) = options.connect {
+            gui_state.set_server_address(&server_address);
+            state_machine.push(fsm::State::Connecting {
+                server_address,
+                session_mode: fsm::SessionMode::ConnectAsClientOnly,
+            });
+        }
+
+        let mut local_player = Player::default();
+        let lockstep = match options.lockstep {
+            Some(LockstepOptions { peers, local_index }) => {
+                let lockstep = rt
+                    .block_on(LockstepSession::new(peers, local_index))
+                    .map_err(|e| e.to_string())?;
+                local_player = lockstep.local_player();
+                state_machine.push(fsm::State::Playing);
+                Some(lockstep)
+            }
+            None => None,
+        };
+
+        let input_playback = match &options.play_input {
+            Some(path) => Some(InputPlayback::new(InputRecording::load(path)?)),
+            None => None,
+        };
+        let replay = match &options.play_replay {
+            Some(path) => {
+                state_machine.push(fsm::State::Replay);
+                Some(ReplayPlayback::new(Replay::load(path)?))
+            }
+            None => None,
+        };
+        // Joining by address still works without the LAN list
+        let lan_browser = {
+            let _runtime_guard = rt.enter();
+            match LanBrowser::start() {
+                Ok(lan_browser) => Some(lan_browser),
+                Err(e) => {
+                    eprintln!("Not listening for LAN servers: {e}");
+                    None
+                }
+            }
+        };
+
         Ok(Self {
             rt,
-            window: None,
-            renderer: None,
-            gui: None,
+            graphics: None,
+            gui_state: Some(gui_state),
             client_session: None,
+            lost_session: None,
+            server_handle: None,
             connection_task: None,
+            queue_position_rx: None,
+            lan_browser,
+            input_buffer: InputBuffer::default(),
+            keyboard_focus: KeyboardFocus::default(),
             input_state: InputState::default(),
-            local_player: Player::default(),
-            camera_pos: Vector2::new(0.0, 0.0),
+            input_tick: 0,
+            input_recorder: options
+                .record_input
+                .map(|path| (path, InputRecorder::default())),
+            input_playback,
+            joystick: VirtualJoystick::default(),
+            local_player,
+            player_name: options.player_name,
+            netcode_tuning,
+            correction_offset: Vector2::new(0.0, 0.0),
+            camera: Camera::centered_on(Vector2::new(0.0, 0.0)),
+            camera_look_ahead: options.camera_look_ahead,
+            time_scale: 1.0,
             remote_players: HashMap::new(),
+            player_updated_at: HashMap::new(),
+            player_names: HashMap::new(),
+            muted_players: HashSet::new(),
+            interpolation: SnapshotInterpolation::default(),
+            lockstep,
+            session_summary: None,
+            time_of_day: None,
+            storm: None,
+            health: storm::MAX_HEALTH,
+            leader: None,
+            afk_players: HashSet::new(),
+            activity: PlayerActivity::Active,
+            paused_at: None,
+            map: Map::default(),
+            map_info: None,
+            map_download: None,
+            editor: MapEditor::default(),
+            cursor_pos: Vector2::new(0.0, 0.0),
+            client_config: ClientConfig::load(),
+            window_title_suffix: options.window_title_suffix,
+            instance_slot: claim_instance_slot(),
+            attract: None,
+            replay,
+            attract_enabled: options.attract,
+            last_input_at: Instant::now(),
+            hosted_server_refreshed_at: None,
+            redrawn_at: Instant::now(),
             state_machine,
         })
     }
 
+    /// Nothing is simulated or received in menus, so frames are only needed after input
+    fn is_idle(&self) -> bool {
+        matches!(
+            self.state_machine.peek(),
+            Some(fsm::State::Menu)
+                | Some(fsm::State::Connecting { .. })
+                | Some(fsm::State::Reconnecting { .. })
+                | Some(fsm::State::Disconnected)
+        )
+    }
+
     fn run(&mut self, event_loop: &mut EventLoop<()>) {
         // Frame-rate independent loop with fixed update, variable framerate.
         //
@@ -120,67 +407,271 @@ impl<'a> App<'a> {
             let current_time = std::time::Instant::now();
             let elapsed_time = (current_time - previous_time).as_secs_f32();
             previous_time = current_time;
-            lag += elapsed_time;
+            lag += elapsed_time * self.time_scale;
 
-            let _ = event_loop.pump_app_events(Some(Duration::ZERO), self);
-            if matches!(self.state_machine.peek().unwrap(), fsm::State::Quit) {
+            // Sleep until input arrives in menus instead of spinning a whole core
+            let event_wait = if self.is_idle() {
+                IDLE_EVENT_WAIT
+            } else {
+                Duration::ZERO
+            };
+            let _ = event_loop.pump_app_events(Some(event_wait), self);
+            // Empty state stack has nowhere to go, so treat it the same as quitting
+            if matches!(self.state_machine.peek(), None | Some(fsm::State::Quit)) {
                 break;
             }
-            if self.client_session.is_some() {
-                self.process_server_response();
-            }
+            self.process_server_response();
+            self.update_lan_servers();
+            self.interpolation
+                .apply(&mut self.remote_players, std::time::Instant::now());
 
             while lag >= globals::FIXED_UPDATE_TIMESTEP_SEC {
                 self.update();
                 lag -= globals::FIXED_UPDATE_TIMESTEP_SEC;
             }
 
-            self.window.as_ref().unwrap().request_redraw();
+            // Menus are redrawn after input and once per wait, a pending redraw would cut every
+            // wait short
+            if let Some(graphics) = &self.graphics {
+                if !self.is_idle() || self.redrawn_at.elapsed() >= IDLE_EVENT_WAIT {
+                    graphics.window.request_redraw();
+                }
+            }
         }
 
-        if self.client_session.is_some() {
-            self.client_session
-                .as_ref()
-                .unwrap()
-                .leave_server(self.local_player.id);
+        if let Some(client_session) = self.client_session.take() {
+            self.rt.block_on(client_session.leave_and_wait());
+        }
+        self.remember_window_geometry();
+        // Instances started next to the first one are cascaded, so only the first one is remembered
+        if self
+            .instance_slot
+            .as_ref()
+            .is_some_and(|(slot, _)| *slot == 0)
+        {
+            if let Err(e) = self.client_config.save() {
+                eprintln!("Failed to save client config: {e}");
+            }
+        }
+        if let Some((path, recorder)) = self.input_recorder.take() {
+            match recorder.finish(self.input_tick).save(&path) {
+                Ok(()) => println!("Input recorded to {}", path.display()),
+                Err(e) => eprintln!("Failed to save input recording to {}: {e}", path.display()),
+            }
+        }
+        if let Some(session_summary) = &self.session_summary {
+            println!("Session summary: {session_summary}");
+        }
+        if let Some(lockstep) = &self.lockstep {
+            lockstep.leave();
+        }
+        if let Some(server_handle) = self.server_handle.take() {
+            self.rt.block_on(async {
+                server_handle.shutdown().await;
+                server_handle.await_terminated().await;
+            });
         }
     }
 
     fn process_server_response(&mut self) {
-        while let Ok(msg) = self
+        while let Some(Ok(msg)) = self
             .client_session
             .as_mut()
-            .unwrap()
-            .receive_server_response()
+            .map(|client_session| client_session.receive_server_response())
         {
-            message::trace(format!("Received: {}", msg));
-            match Message::deserialize(&msg) {
-                Ok(Message::Replicate(new_player)) => {
-                    if let Some(player) = self.remote_players.get_mut(&new_player.id) {
-                        // Update existing player based on server's simulation
-                        player.pos = new_player.pos;
-                    } else {
-                        // On-demand remote player creation because replication does not
-                        // fit into the handshake ACK message.
-                        self.remote_players.insert(new_player.id, new_player);
-                        self.gui
-                            .as_mut()
-                            .unwrap()
-                            .log(format!("Player {} has joined the server", new_player.id));
+            let msg = Message::deserialize(&msg);
+            message::trace(format!("Received: {msg:?}"));
+            match msg {
+                Ok(Message::Replicate(tick, new_player)) => {
+                    if !self.client_session.as_mut().is_some_and(|client_session| {
+                        client_session.accept_snapshot(new_player.id, tick)
+                    }) {
+                        // Outdated snapshot arrived out of order
+                        continue;
                     }
+                    // Server moved the local player, e.g. an admin teleporting it
+                    if new_player.id == self.local_player.id {
+                        if self.netcode_tuning.correction_blend < 1.0 {
+                            self.correction_offset += self.local_player.pos - new_player.pos;
+                        }
+                        self.local_player.pos = new_player.pos;
+                        self.move_camera();
+                        // Confirm, so that a position sent before doesn't undo the correction
+                        if let Some(client_session) = &self.client_session {
+                            client_session.send_pos(&self.local_player);
+                        }
+                        continue;
+                    }
+                    // On-demand remote player creation because replication does not fit into
+                    // the handshake ACK message.
+                    self.remote_players.insert(new_player.id, new_player);
+                    self.player_updated_at.insert(new_player.id, Instant::now());
+                    if let Some(session_summary) = &mut self.session_summary {
+                        session_summary.record_player_count(self.remote_players.len() + 1);
+                    }
+                    // Position is set based on server's simulation, slightly in the past to
+                    // smooth out irregular arrival
+                    self.interpolation.push(
+                        new_player.id,
+                        tick,
+                        new_player.pos,
+                        std::time::Instant::now(),
+                    );
                 }
                 Ok(Message::Leave(id)) => {
                     self.remote_players.remove(&id);
-                    self.gui
-                        .as_mut()
-                        .unwrap()
-                        .log(format!("Player {} has left the server", id));
+                    self.player_updated_at.remove(&id);
+                    self.player_names.remove(&id);
+                    self.afk_players.remove(&id);
+                    self.muted_players.remove(&id);
+                    self.interpolation.remove(id);
+                    if let Some(client_session) = self.client_session.as_mut() {
+                        client_session.forget_player(id);
+                    }
+                }
+                Ok(Message::Chat(id, text)) => {
+                    if self.muted_players.contains(&id) {
+                        continue;
+                    }
+                    if id == self.local_player.id {
+                        self.log(format!("You: {text}"));
+                    } else {
+                        self.log(format!(
+                            "{}: {text}",
+                            gui::player_name(&self.player_names, id)
+                        ));
+                    }
+                }
+                Ok(Message::Profile(id, color, name)) => {
+                    if id == self.local_player.id {
+                        self.local_player.color = color;
+                        if let Some(graphics) = &mut self.graphics {
+                            graphics.gui.set_profile(color, name.clone());
+                        }
+                    } else if let Some(player) = self.remote_players.get_mut(&id) {
+                        player.color = color;
+                    }
+                    self.player_names.insert(id, name);
+                }
+                Ok(Message::Notice(text)) => {
+                    self.log(format!("Server: {text}"));
+                }
+                Ok(Message::Event(event)) => {
+                    if let Some(graphics) = &mut self.graphics {
+                        graphics.gui.push_event(event);
+                    }
+                }
+                Ok(Message::MatchEnd(countdown, standings)) => {
+                    let next_match_at =
+                        std::time::Instant::now() + Duration::from_secs(countdown.into());
+                    // Results are resent every second, keep following the server's countdown.
+                    // Dialogs on top are not interrupted, the next resend gets through once
+                    // they are closed.
+                    match self.state_machine.peek_mut() {
+                        Some(fsm::State::Playing) => {
+                            self.input_state = InputState::default(); // Avoid keys being stuck
+                            self.joystick.release();
+                            if let Some(graphics) = &mut self.graphics {
+                                graphics.gui.set_map_vote(None);
+                            }
+                            self.state_machine.change(fsm::State::Results {
+                                standings,
+                                next_match_at,
+                            });
+                        }
+                        Some(fsm::State::Results {
+                            standings: shown_standings,
+                            next_match_at: shown_next_match_at,
+                        }) => {
+                            *shown_standings = standings;
+                            *shown_next_match_at = next_match_at;
+                        }
+                        _ => (),
+                    }
+                }
+                Ok(Message::MapVote(candidates)) => {
+                    if let Some(graphics) = &mut self.graphics {
+                        graphics.gui.set_map_vote(Some(candidates));
+                    }
+                }
+                Ok(Message::Stats(stats)) => {
+                    if let Some(session_summary) = &mut self.session_summary {
+                        session_summary.record_rtt(stats.rtt_ms);
+                    }
+                    if let Some(graphics) = &mut self.graphics {
+                        graphics.gui.set_connection_stats(Some(stats));
+                    }
+                }
+                Ok(Message::TimeOfDay(minutes)) => self.time_of_day = Some(minutes),
+                Ok(Message::Storm(state)) => {
+                    self.storm = state.map(|state| (state, Instant::now()));
+                    // Health is restored when the storm clears up
+                    if self.storm.is_none() {
+                        self.health = storm::MAX_HEALTH;
+                    }
+                }
+                Ok(Message::Health(health)) => self.health = health,
+                Ok(Message::Leader(leader)) => {
+                    self.leader = leader.map(|(player_id, _)| player_id);
+                    if let Some(graphics) = &mut self.graphics {
+                        graphics.gui.set_leader(leader);
+                    }
+                }
+                Ok(Message::Activity(id, activity)) => self.set_activity(id, activity),
+                Ok(Message::Paused(paused)) => self.set_paused(paused),
+                Ok(Message::Map(info)) => self.change_map(info),
+                Ok(Message::MapChunk(checksum, offset, data)) => {
+                    let Some(map_download) = &mut self.map_download else {
+                        continue;
+                    };
+                    if map_download.info().checksum != checksum {
+                        continue;
+                    }
+                    map_download.add_chunk(offset, data);
+                    let progress = map_download.progress();
+                    if let Some(fsm::State::Loading {
+                        progress: shown_progress,
+                    }) = self.state_machine.peek_mut()
+                    {
+                        *shown_progress = progress;
+                    }
+                    self.finish_map_download();
+                }
+                Ok(Message::Shutdown) => {
+                    println!("Server is shutting down");
+                    if let Some(client_session) = &self.client_session {
+                        client_session.leave_server();
+                    }
+                    // Server may be restarting
+                    self.start_reconnecting("Server has shut down.");
+                }
+                // Server refusing an already joined player means being kicked
+                Ok(Message::Reject(reason)) => {
+                    eprintln!("Kicked from server: {reason}");
+                    self.disconnect();
+                    self.set_error_status(format!("Kicked from server: {reason}"));
                 }
                 _ => (),
             }
         }
     }
 
+    /// Keep the LAN servers of the menu up to date as their beacons arrive and stop
+    fn update_lan_servers(&mut self) {
+        let Some(lan_browser) = &mut self.lan_browser else {
+            return;
+        };
+        if !lan_browser.poll(Instant::now()) {
+            return;
+        }
+        if let Some(graphics) = &mut self.graphics {
+            graphics.gui.set_lan_servers(lan_browser.servers());
+            graphics.window.request_redraw();
+        } else if let Some(gui_state) = &mut self.gui_state {
+            gui_state.set_lan_servers(lan_browser.servers());
+        }
+    }
+
     fn update(&mut self) {
         match self.state_machine.peek_mut() {
             Some(fsm::State::Connecting {
@@ -191,149 +682,1001 @@ impl<'a> App<'a> {
                 // infinite number of tasks
                 match self.connection_task.as_ref() {
                     Some(task) if task.is_finished() => {
+                        self.queue_position_rx = None;
                         if let Some(finished_task) = self.connection_task.take() {
-                            let gui = self.gui.as_mut().unwrap();
                             match self.rt.block_on(finished_task) {
                                 Ok(result) => match result {
-                                    Ok(client_session) => {
-                                        self.local_player =
-                                            client_session.get_session_player_data();
-                                        let window = self.window.as_mut().unwrap();
-                                        window.set_title(&format!(
-                                            "{} - Player {}",
-                                            window.title(),
-                                            self.local_player.id
-                                        ));
-                                        self.client_session = Some(client_session);
-                                        self.state_machine.change(fsm::State::Playing);
-                                        gui.log(format!(
-                                            "Welcome Player {}!",
-                                            self.local_player.id
-                                        ));
+                                    Ok((client_session, server_handle)) => {
+                                        // Hosts are reached at the address they host on
+                                        let invite_code = server_handle
+                                            .as_ref()
+                                            .and(server_address.parse::<SocketAddr>().ok())
+                                            .map(|address| {
+                                                invite::encode(invite::invite_address(
+                                                    address.ip(),
+                                                    address.port(),
+                                                ))
+                                            });
+                                        self.enter_session(
+                                            client_session,
+                                            server_handle,
+                                            invite_code,
+                                        );
                                     }
                                     Err(connection_err) => {
-                                        gui.set_error_status(connection_err.to_string());
-                                        self.state_machine.change(fsm::State::Menu);
+                                        self.connection_failed(connection_err.to_string());
                                     }
                                 },
                                 Err(join_err) => {
-                                    gui.set_error_status(format!(
+                                    self.connection_failed(format!(
                                         "Connection task has aborted: {join_err}"
                                     ));
-                                    self.state_machine.change(fsm::State::Menu);
                                 }
                             }
                         }
                     }
-                    Some(_) => (), // Task is still running, nothing to do
+                    // Task is still running, only the place in the join queue may change
+                    Some(_) => {
+                        let queue_position = self
+                            .queue_position_rx
+                            .as_ref()
+                            .and_then(|queue_position_rx| *queue_position_rx.borrow());
+                        if let (Some(position), Some(graphics)) =
+                            (queue_position, &mut self.graphics)
+                        {
+                            graphics.gui.set_queue_position(position);
+                        }
+                    }
                     None => {
                         // Fire task if not exists
                         let server_address = server_address.clone();
-                        let session_mode = session_mode.clone();
-                        self.connection_task = Some(self.rt.spawn(async move {
+                        let session_mode = *session_mode;
+                        let player_name = self.player_name.clone();
+                        let send_rate = self.netcode_tuning.send_rate;
+                        let previous_token = self
+                            .lost_session
+                            .take()
+                            .filter(|(address, _)| *address == server_address)
+                            .map(|(_, token)| token);
+                        let map = self.editor.take_playtest_map();
+                        let advertise_lan = self
+                            .graphics
+                            .as_ref()
+                            .is_some_and(|graphics| graphics.gui.advertise_lan());
+                        let (queue_position_tx, queue_position_rx) = watch::channel(None);
+                        self.queue_position_rx = Some(queue_position_rx);
+                        // Peer-hosted servers are named after the hosting player
+                        let server_name = if player_name.is_empty() {
+                            globals::DEFAULT_SERVER_NAME.to_string()
+                        } else {
+                            format!("{player_name}'s server")
+                        };
+                        let _runtime_guard = self.rt.enter();
+                        self.connection_task = Some(task::spawn_named("connection", async move {
+                            let mut server_handle = None;
                             if matches!(session_mode, fsm::SessionMode::CreateServer) {
-                                let parts: Vec<&str> = server_address.split(':').collect();
-                                let port: u16 = parts[1].parse().unwrap();
-                                server::start_server(port).await?;
+                                let port: u16 = server_address
+                                    .rsplit_once(':')
+                                    .and_then(|(_, port)| port.parse().ok())
+                                    .ok_or("Invalid port number in server address.")?;
+                                server_handle = Some(
+                                    server::start_server(server::ServerConfig {
+                                        port,
+                                        name: server_name,
+                                        map,
+                                        advertise_lan,
+                                        ..Default::default()
+                                    })
+                                    .await?,
+                                );
                             }
 
-                            ClientSession::new(server_address).await
+                            match ClientSession::new(
+                                server_address,
+                                player_name,
+                                send_rate,
+                                previous_token,
+                                Some(queue_position_tx),
+                            )
+                            .await
+                            {
+                                Ok(client_session) => Ok((client_session, server_handle)),
+                                Err(e) => {
+                                    // Free the port for the next attempt
+                                    if let Some(server_handle) = server_handle {
+                                        server_handle.shutdown().await;
+                                        server_handle.await_terminated().await;
+                                    }
+                                    Err(e)
+                                }
+                            }
                         }));
                     }
                 }
             }
+            Some(fsm::State::Reconnecting { reason, give_up_at }) => {
+                let give_up_at = *give_up_at;
+                let reason = reason.clone();
+                match self.connection_task.as_ref() {
+                    Some(task) if task.is_finished() => {
+                        if let Some(finished_task) = self.connection_task.take() {
+                            match self.rt.block_on(finished_task) {
+                                Ok(Ok((client_session, _))) => {
+                                    self.lost_session = None;
+                                    self.log("Reconnected to server".to_string());
+                                    self.enter_session(client_session, None, None);
+                                }
+                                // Tried again on the next update until giving up
+                                Ok(Err(e)) => message::trace(format!("Failed to reconnect: {e}")),
+                                Err(join_err) => message::trace(format!(
+                                    "Reconnection task has aborted: {join_err}"
+                                )),
+                            }
+                        }
+                    }
+                    _ if Instant::now() >= give_up_at => {
+                        if let Some(task) = self.connection_task.take() {
+                            task.abort();
+                        }
+                        self.disconnect();
+                        self.set_error_status(format!(
+                            "{reason} Failed to reconnect within {} seconds.",
+                            RECONNECT_GRACE.as_secs()
+                        ));
+                    }
+                    Some(_) => (),
+                    None => {
+                        let Some((server_address, token)) = self.lost_session.clone() else {
+                            self.disconnect();
+                            return;
+                        };
+                        let player_name = self.player_name.clone();
+                        let send_rate = self.netcode_tuning.send_rate;
+                        let _runtime_guard = self.rt.enter();
+                        self.connection_task =
+                            Some(task::spawn_named("reconnection", async move {
+                                match ClientSession::new(
+                                    server_address,
+                                    player_name,
+                                    send_rate,
+                                    Some(token),
+                                    None,
+                                )
+                                .await
+                                {
+                                    Ok(client_session) => Ok((client_session, None)),
+                                    Err(e) => {
+                                        tokio::time::sleep(RECONNECT_RETRY_INTERVAL).await;
+                                        Err(e)
+                                    }
+                                }
+                            }));
+                    }
+                }
+            }
+            Some(fsm::State::Loading { .. }) => {
+                let Some(client_session) = self.client_session.as_ref() else {
+                    self.disconnect();
+                    return;
+                };
+                if let Some(map_download) = &mut self.map_download {
+                    let checksum = map_download.info().checksum;
+                    for offset in map_download.due_requests() {
+                        client_session.request_map_chunk(checksum, offset);
+                    }
+                }
+                self.update_connection_state();
+            }
+            Some(fsm::State::Editor) => {
+                self.input_state = self.input_buffer.take();
+                let direction = self.input_direction();
+                editor::pan_camera(&mut self.camera, direction);
+            }
             Some(fsm::State::Playing) => {
-                let base_speed = 10.0;
-                let mut direction = cgmath::vec2(0.0, 0.0);
-
-                // Apply input
-                if self.input_state[InputEvent::MoveUp] {
-                    direction.y -= 1.0;
+                // Keys pressed while joining or paused are applied once the player can move
+                if self.paused_at.is_none() {
+                    self.input_state = self.input_buffer.take();
                 }
-                if self.input_state[InputEvent::MoveDown] {
-                    direction.y += 1.0;
+                if let Some(playback) = &mut self.input_playback {
+                    if playback.is_finished() {
+                        // Printed for scripts checking the outcome of a scenario
+                        println!(
+                            "Input playback finished after {} ticks with player at ({:.1}, {:.1})",
+                            self.input_tick, self.local_player.pos.x, self.local_player.pos.y
+                        );
+                        self.state_machine.change(fsm::State::Quit);
+                        return;
+                    }
+                    self.input_state = playback.advance(self.input_tick);
                 }
-                if self.input_state[InputEvent::MoveLeft] {
-                    direction.x -= 1.0;
+                if let Some((_, recorder)) = &mut self.input_recorder {
+                    recorder.record(self.input_tick, &self.input_state);
                 }
-                if self.input_state[InputEvent::MoveRight] {
-                    direction.x += 1.0;
+                self.input_tick += 1;
+                self.correction_offset *= 1.0 - self.netcode_tuning.correction_blend;
+
+                let direction = self.input_direction();
+
+                // Every peer simulates all players in lockstep mode
+                if let Some(lockstep) = &mut self.lockstep {
+                    let result = lockstep.advance(direction);
+                    for &player in lockstep.players() {
+                        if player.id == self.local_player.id {
+                            self.local_player = player;
+                        } else {
+                            self.remote_players.insert(player.id, player);
+                            self.player_updated_at.insert(player.id, Instant::now());
+                        }
+                    }
+                    match result {
+                        Ok(notices) => {
+                            for notice in notices {
+                                self.log(notice);
+                            }
+                            self.move_camera();
+                        }
+                        Err(reason) => {
+                            self.set_error_status(reason);
+                            self.disconnect();
+                        }
+                    }
+                    return;
                 }
 
-                // Normalize for consistent movement speed between diagonal and straight directions
-                if direction != cgmath::vec2(0.0, 0.0) {
-                    direction = direction.normalize();
+                // Nothing moves until the server resumes the game, so there's nothing to predict
+                if self
+                    .paused_at
+                    .is_some_and(|paused_at| paused_at.elapsed() >= PAUSE_TIMEOUT)
+                {
+                    self.set_paused(false);
+                }
+                if self.paused_at.is_some() {
+                    self.local_player.velocity = cgmath::vec2(0.0, 0.0);
+                    self.update_connection_state();
+                    return;
                 }
 
-                // Move player
-                self.local_player.velocity = direction * base_speed;
+                // Move player. Local player is authoritative over its own position, apart from
+                // server corrections, which are blended in through `correction_offset`.
+                let previous_pos = self.local_player.pos;
+                let world_params = self.world_params();
+                self.local_player.velocity = direction * world_params.player_speed;
                 self.local_player.pos += self.local_player.velocity;
-                globals::clamp_player_to_bounds(&mut self.local_player);
+                self.map
+                    .push_out(&mut self.local_player, world_params.player_size);
+                world_params.clamp_player_to_bounds(&mut self.local_player);
+                if let Some(session_summary) = &mut self.session_summary {
+                    session_summary
+                        .record_movement((self.local_player.pos - previous_pos).magnitude());
+                }
 
                 // Move camera
                 self.move_camera();
 
+                // Playing without a session can only be an inconsistent state, so bail out the
+                // same way as on a lost connection instead of crashing.
+                let Some(client_session) = self.client_session.as_ref() else {
+                    self.disconnect();
+                    return;
+                };
+
                 // Message server
                 if self.local_player.velocity != cgmath::vec2(0.0, 0.0) {
-                    self.client_session
-                        .as_ref()
-                        .unwrap()
-                        .send_pos(&self.local_player);
+                    client_session.send_pos(&self.local_player);
                 }
 
                 // Server healthcheck
-                if !self.client_session.as_ref().unwrap().is_server_alive() {
-                    eprintln!("Connection to server was lost");
-                    self.client_session = None;
-                    self.window
-                        .as_mut()
-                        .unwrap()
-                        .set_title(globals::WINDOW_TITLE);
-                    self.input_state = InputState::default(); // Avoid keys being stuck
-                    self.remote_players.clear();
-                    self.state_machine.change(fsm::State::Disconnected);
+                self.update_connection_state();
+            }
+            Some(fsm::State::Results { next_match_at, .. }) => {
+                if std::time::Instant::now() >= *next_match_at {
+                    self.state_machine.change(fsm::State::Playing);
+                }
+                self.update_connection_state();
+            }
+            Some(fsm::State::Menu)
+                if self.attract_enabled
+                    && self.last_input_at.elapsed() >= attract::ATTRACT_DELAY =>
+            {
+                self.attract = Some(AttractMatch::new(rand::random()));
+                self.state_machine.push(fsm::State::Attract);
+            }
+            Some(fsm::State::Attract) => {
+                if let Some(attract) = &mut self.attract {
+                    attract.update();
+                    if let Some(player) = attract.followed_player() {
+                        self.camera = Camera::centered_on(player.pos);
+                    }
+                }
+            }
+            Some(fsm::State::Replay) => {
+                let Some(replay) = &mut self.replay else {
+                    self.state_machine.pop();
+                    return;
+                };
+                if replay.is_finished() {
+                    println!(
+                        "Replay of {} finished after {} ticks",
+                        replay.map_name(),
+                        replay.elapsed_ticks()
+                    );
+                    self.replay = None;
+                    self.state_machine.pop();
+                    return;
+                }
+                replay.update();
+                if let Some(player) = replay.followed_player() {
+                    self.camera = Camera::centered_on(player.pos);
                 }
             }
             _ => (),
         }
     }
 
+    /// Warn about an unstable connection while it lasts and leave the server once it's lost.
+    fn update_connection_state(&mut self) {
+        let Some(client_session) = self.client_session.as_mut() else {
+            self.disconnect();
+            return;
+        };
+        match client_session.update_connection_state() {
+            Some(ConnectionState::Lost) => {
+                eprintln!("Connection to server was lost");
+                self.start_reconnecting("Connection to server was lost.");
+            }
+            Some(state) => {
+                if let Some(graphics) = &mut self.graphics {
+                    graphics
+                        .gui
+                        .set_connection_unstable(state == ConnectionState::Degraded);
+                }
+            }
+            None => (),
+        }
+    }
+
+    /// Normalized movement direction from pressed keys and the virtual joystick
+    fn input_direction(&self) -> Vector2<f32> {
+        let mut direction = cgmath::vec2(0.0, 0.0);
+
+        // Apply input
+        if self.input_state[InputEvent::MoveUp] {
+            direction.y -= 1.0;
+        }
+        if self.input_state[InputEvent::MoveDown] {
+            direction.y += 1.0;
+        }
+        if self.input_state[InputEvent::MoveLeft] {
+            direction.x -= 1.0;
+        }
+        if self.input_state[InputEvent::MoveRight] {
+            direction.x += 1.0;
+        }
+        direction += self.joystick.direction();
+
+        // Normalize for consistent movement speed between diagonal and straight directions
+        if direction != cgmath::vec2(0.0, 0.0) {
+            direction = direction.normalize();
+        }
+        direction
+    }
+
+    /// Take over the joined session and wait for the map the server announces right after the
+    /// ACK. A reconnected session carries on with its summary.
+    fn enter_session(
+        &mut self,
+        client_session: ClientSession,
+        server_handle: Option<ServerHandle>,
+        invite_code: Option<String>,
+    ) {
+        if let Some(invite_code) = &invite_code {
+            self.log(format!("Friends can join with invite code {invite_code}"));
+        }
+        if let Some(graphics) = &mut self.graphics {
+            graphics.gui.set_invite_code(invite_code);
+        }
+        self.server_handle = server_handle;
+        self.local_player = client_session.get_session_player_data();
+        self.client_session = Some(client_session);
+        self.session_summary.get_or_insert_with(SessionSummary::new);
+        self.update_window_title();
+        self.state_machine
+            .change(fsm::State::Loading { progress: 0.0 });
+        self.log(format!("Welcome Player {}!", self.local_player.id));
+    }
+
+    /// Leave the session and join its server again in the background for `RECONNECT_GRACE`,
+    /// instead of showing the disconnected dialog right away. Hosts have nothing to reconnect to,
+    /// their server went down with the session.
+    fn start_reconnecting(&mut self, reason: &str) {
+        if let Some(client_session) = &self.client_session {
+            self.lost_session = Some((
+                client_session.get_server_address().to_string(),
+                client_session.get_session_token(),
+            ));
+        }
+        if self.server_handle.is_some() || self.lost_session.is_none() {
+            self.disconnect();
+            self.set_error_status(reason.to_string());
+            return;
+        }
+        let session_summary = self.session_summary.take();
+        self.disconnect();
+        self.session_summary = session_summary;
+        self.log(format!("{reason} Reconnecting..."));
+        self.state_machine.change(fsm::State::Reconnecting {
+            reason: reason.to_string(),
+            give_up_at: Instant::now() + RECONNECT_GRACE,
+        });
+    }
+
+    /// Player size and speed of the joined server, the defaults when playing without one
+    fn world_params(&self) -> WorldParams {
+        self.client_session
+            .as_ref()
+            .map_or_else(WorldParams::default, ClientSession::get_world_params)
+    }
+
+    fn disconnect(&mut self) {
+        self.client_session = None;
+        if let Some(session_summary) = self.session_summary.take() {
+            let session_summary = session_summary.to_string();
+            self.log(format!("Session summary: {session_summary}"));
+            if let Some(graphics) = &mut self.graphics {
+                graphics.gui.set_session_summary(session_summary);
+            }
+        }
+        if let Some(lockstep) = self.lockstep.take() {
+            lockstep.leave();
+        }
+        // Hosted server would be left running without its host otherwise
+        if let Some(server_handle) = self.server_handle.take() {
+            self.rt.spawn(async move { server_handle.shutdown().await });
+        }
+        if let Some(graphics) = &mut self.graphics {
+            graphics.gui.set_invite_code(None);
+        }
+        self.update_window_title();
+        self.input_state = InputState::default(); // Avoid keys being stuck
+        self.joystick.release();
+        self.remote_players.clear();
+        self.player_updated_at.clear();
+        // Player IDs are only meaningful on the server they were handed out by
+        self.player_names.clear();
+        self.muted_players.clear();
+        self.interpolation.clear();
+        self.correction_offset = Vector2::new(0.0, 0.0);
+        self.time_of_day = None;
+        self.storm = None;
+        self.health = storm::MAX_HEALTH;
+        self.leader = None;
+        self.afk_players.clear();
+        self.activity = PlayerActivity::Active;
+        self.set_paused(false);
+        self.map = Map::default();
+        self.map_info = None;
+        self.map_download = None;
+        if let Some(graphics) = &mut self.graphics {
+            graphics.gui.set_connection_stats(None);
+            graphics.gui.set_leader(None);
+            graphics.gui.set_connection_unstable(false);
+            graphics.gui.set_map_vote(None);
+            graphics.gui.clear_events();
+        }
+        self.state_machine.change(fsm::State::Disconnected);
+    }
+
+    /// Switch to the map announced by the server, downloading it first if it's not available
+    /// locally.
+    fn change_map(&mut self, info: MapInfo) {
+        if self.map_info.as_ref() == Some(&info)
+            || self
+                .map_download
+                .as_ref()
+                .is_some_and(|map_download| *map_download.info() == info)
+        {
+            return;
+        }
+        let map = if info.size == 0 {
+            Some(Map::default())
+        } else {
+            map::load_downloaded(&info)
+        };
+        match map {
+            Some(map) => self.apply_map(info, map),
+            None => {
+                self.log(format!("Downloading map {}", info.name));
+                self.map_download = Some(MapDownload::new(info));
+                if matches!(
+                    self.state_machine.peek(),
+                    Some(fsm::State::Playing)
+                        | Some(fsm::State::Results { .. })
+                        | Some(fsm::State::Loading { .. })
+                ) {
+                    self.state_machine
+                        .change(fsm::State::Loading { progress: 0.0 });
+                }
+            }
+        }
+    }
+
+    /// Load the downloaded map once every chunk of it has arrived. A broken map can't be played,
+    /// so the server is left in that case.
+    fn finish_map_download(&mut self) {
+        let Some(data) = self
+            .map_download
+            .as_ref()
+            .and_then(|map_download| map_download.data())
+        else {
+            return;
+        };
+        let Some(map_download) = self.map_download.take() else {
+            return;
+        };
+        let info = map_download.info().clone();
+        let map = if map::checksum(&data) == info.checksum {
+            std::str::from_utf8(&data)
+                .map_err(|e| e.to_string())
+                .and_then(Map::parse)
+        } else {
+            Err("checksum mismatch".to_string())
+        };
+        match map {
+            Ok(map) => {
+                if let Err(e) = map::save_downloaded(&info, &data) {
+                    eprintln!("Failed to save map {}: {e}", info.name);
+                }
+                self.apply_map(info, map);
+            }
+            Err(e) => {
+                if let Some(client_session) = &self.client_session {
+                    client_session.leave_server();
+                }
+                self.disconnect();
+                self.set_error_status(format!("Failed to load map {}: {e}", info.name));
+            }
+        }
+    }
+
+    fn apply_map(&mut self, info: MapInfo, map: Map) {
+        self.log(format!("Playing on map {}", info.name));
+        if let Some(spawn_point) = map.spawn_point(self.local_player.id) {
+            self.local_player.pos = spawn_point;
+            self.world_params()
+                .clamp_player_to_bounds(&mut self.local_player);
+            self.move_camera();
+            if let Some(client_session) = &self.client_session {
+                client_session.send_pos(&self.local_player);
+            }
+        }
+        self.map = map;
+        self.map_info = Some(info);
+        self.map_download = None;
+        if matches!(self.state_machine.peek(), Some(fsm::State::Loading { .. })) {
+            self.state_machine.change(fsm::State::Playing);
+        }
+    }
+
+    fn update_window_title(&self) {
+        if let Some(graphics) = &self.graphics {
+            let mut title = if let Some(client_session) = &self.client_session {
+                format!(
+                    "{} - Connected to {} - Player {}",
+                    globals::WINDOW_TITLE,
+                    client_session.get_server_name(),
+                    self.local_player.id
+                )
+            } else if let Some(lockstep) = &self.lockstep {
+                format!(
+                    "{} - Lockstep with {} peers - Player {}",
+                    globals::WINDOW_TITLE,
+                    lockstep.peer_count(),
+                    self.local_player.id
+                )
+            } else {
+                globals::WINDOW_TITLE.to_string()
+            };
+            if let Some(suffix) = &self.window_title_suffix {
+                title += &format!(" [{suffix}]");
+            }
+            graphics.window.set_title(&title);
+        }
+    }
+
+    fn create_graphics(&mut self, event_loop: &ActiveEventLoop) {
+        let gui_state = self.gui_state.take().unwrap_or_default();
+        let mut placement = self
+            .client_config
+            .window
+            .as_ref()
+            .map(|geometry| window_placement(event_loop, geometry))
+            .unwrap_or_default();
+        // First instance is placed where it was left or by the system, the others are cascaded
+        // from there or from the corner
+        if let Some(slot) = self
+            .instance_slot
+            .as_ref()
+            .map(|(slot, _)| i32::from(*slot))
+            .filter(|slot| *slot > 0)
+        {
+            let origin = placement.position.unwrap_or_default();
+            placement.position = Some(PhysicalPosition::new(
+                origin.x + slot * INSTANCE_WINDOW_OFFSET,
+                origin.y + slot * INSTANCE_WINDOW_OFFSET,
+            ));
+        }
+        match Renderer::create_graphics(event_loop, gui_state, placement) {
+            Ok((window, renderer, gui)) => {
+                // Surface may be sized by the system, like the whole display on Android
+                let size = window.inner_size().to_logical::<f32>(window.scale_factor());
+                self.joystick.resize(cgmath::vec2(size.width, size.height));
+                self.graphics = Some(Graphics {
+                    window,
+                    renderer,
+                    gui,
+                });
+                self.update_window_title();
+            }
+            Err(e) => {
+                eprintln!("Failed to initialize graphics: {e}");
+                self.state_machine.change(fsm::State::Quit);
+            }
+        }
+    }
+
+    /// Release GPU resources while keeping everything else (including the client session) alive.
+    fn destroy_graphics(&mut self) {
+        self.remember_window_geometry();
+        if let Some(Graphics {
+            window,
+            renderer,
+            gui,
+        }) = self.graphics.take()
+        {
+            self.gui_state = Some(gui.into_state());
+            // Surface has to go before the window it was created for
+            drop(renderer);
+            drop(window);
+        }
+    }
+
+    /// Keep where the window is in the client config, to be saved on exit. Size and position of a
+    /// fullscreen window are the ones it had before going fullscreen.
+    fn remember_window_geometry(&mut self) {
+        let Some(graphics) = &self.graphics else {
+            return;
+        };
+        let window = &graphics.window;
+        let monitor = window.current_monitor();
+        let fullscreen = window.fullscreen().is_some();
+        let (x, y, width, height) = if fullscreen {
+            self.client_config.window.as_ref().map_or(
+                (
+                    0,
+                    0,
+                    u32::from(globals::WINDOW_SIZE.0),
+                    u32::from(globals::WINDOW_SIZE.1),
+                ),
+                |previous| (previous.x, previous.y, previous.width, previous.height),
+            )
+        } else {
+            let position = window.outer_position().unwrap_or_default();
+            let origin = monitor
+                .as_ref()
+                .map(|monitor| monitor.position())
+                .unwrap_or_default();
+            let size = window.inner_size();
+            (
+                position.x - origin.x,
+                position.y - origin.y,
+                size.width,
+                size.height,
+            )
+        };
+        self.client_config.window = Some(WindowGeometry {
+            monitor: monitor.and_then(|monitor| monitor.name()),
+            x,
+            y,
+            width,
+            height,
+            fullscreen,
+        });
+    }
+
+    /// Pause banner is shown and the local player can't move while the server has paused the game
+    fn set_paused(&mut self, paused: bool) {
+        self.paused_at = paused.then(Instant::now);
+        if let Some(graphics) = &mut self.graphics {
+            graphics.gui.set_paused(paused);
+        }
+    }
+
+    /// Dim players away from keyboard, and take spectators out of the world until they are back.
+    /// The local player is told how to get back into the game.
+    fn set_activity(&mut self, id: PlayerID, activity: PlayerActivity) {
+        if id == self.local_player.id {
+            if activity == self.activity {
+                return;
+            }
+            self.activity = activity;
+            self.log(
+                match activity {
+                    PlayerActivity::Active => "Welcome back!",
+                    PlayerActivity::Afk => "You are away from keyboard, move to get back.",
+                    PlayerActivity::Spectating => {
+                        "You were moved to spectators for being away, move to play again."
+                    }
+                }
+                .to_string(),
+            );
+            return;
+        }
+        match activity {
+            PlayerActivity::Active => {
+                self.afk_players.remove(&id);
+            }
+            PlayerActivity::Afk => {
+                self.afk_players.insert(id);
+            }
+            // Comes back with the next snapshot once active again
+            PlayerActivity::Spectating => {
+                self.afk_players.remove(&id);
+                self.remote_players.remove(&id);
+                self.player_updated_at.remove(&id);
+                self.interpolation.remove(id);
+            }
+        }
+    }
+
+    /// Redirect message to gameplay log window. Dropped if the GUI does not exist (yet).
+    /// Go back to the menu the connection was started from, which is also below the connection
+    /// started on launch, with the reason shown in its status
+    fn connection_failed(&mut self, msg: String) {
+        self.set_error_status(msg);
+        self.state_machine.pop();
+        if !matches!(self.state_machine.peek(), Some(fsm::State::Menu)) {
+            self.state_machine.change(fsm::State::Menu);
+        }
+    }
+
+    /// Messages arriving while suspended go into the kept GUI state, so that they are still shown
+    /// after resuming
+    fn log(&mut self, msg: String) {
+        if let Some(graphics) = &mut self.graphics {
+            graphics.gui.log(msg);
+        } else if let Some(gui_state) = &mut self.gui_state {
+            gui_state.log(msg);
+        }
+    }
+
+    fn set_error_status(&mut self, msg: String) {
+        if let Some(graphics) = &mut self.graphics {
+            graphics.gui.set_error_status(msg);
+        } else if let Some(gui_state) = &mut self.gui_state {
+            gui_state.set_error_status(msg);
+        }
+    }
+
+    /// Follow the local player with the camera leading it in the direction of movement
     fn move_camera(&mut self) {
-        let half_width = globals::WINDOW_SIZE.0 as f32 / 2.0;
-        let half_height = globals::WINDOW_SIZE.1 as f32 / 2.0;
+        self.camera.follow(
+            &self.local_player,
+            self.camera_look_ahead,
+            self.world_params().player_speed,
+        );
+    }
+}
+
+/// Lowest instance number not taken by another instance running on the same machine. None if
+/// every slot is taken.
+fn claim_instance_slot() -> Option<(u16, std::net::UdpSocket)> {
+    INSTANCE_SLOT_PORTS.enumerate().find_map(|(slot, port)| {
+        std::net::UdpSocket::bind((globals::LOCALHOST, port))
+            .ok()
+            .map(|socket| (slot as u16, socket))
+    })
+}
+
+/// Put the window back where it was left. If the monitor it was on is no longer attached, the
+/// system places the window on the primary monitor instead, and the same happens when its corner
+/// would no longer be on the monitor, e.g. because the resolution got smaller.
+fn window_placement(event_loop: &ActiveEventLoop, geometry: &WindowGeometry) -> WindowPlacement {
+    let size = PhysicalSize::new(geometry.width, geometry.height);
+    let Some(monitor) = event_loop
+        .available_monitors()
+        .find(|monitor| monitor.name().is_some() && monitor.name() == geometry.monitor)
+    else {
+        return WindowPlacement {
+            position: None,
+            size: Some(size),
+            fullscreen: geometry
+                .fullscreen
+                .then(|| Fullscreen::Borderless(event_loop.primary_monitor())),
+        };
+    };
+
+    let origin = monitor.position();
+    let monitor_size = monitor.size();
+    let on_monitor = (0..monitor_size.width as i32).contains(&geometry.x)
+        && (0..monitor_size.height as i32).contains(&geometry.y);
+    WindowPlacement {
+        position: on_monitor
+            .then(|| PhysicalPosition::new(origin.x + geometry.x, origin.y + geometry.y)),
+        size: Some(PhysicalSize::new(
+            size.width.min(monitor_size.width),
+            size.height.min(monitor_size.height),
+        )),
+        fullscreen: geometry
+            .fullscreen
+            .then(|| Fullscreen::Borderless(Some(monitor))),
+    }
+}
+
+/// None if the server is not running anymore
+fn hosted_server(
+    rt: &tokio::runtime::Runtime,
+    server_handle: &ServerHandle,
+) -> Option<HostedServer> {
+    let status = rt.block_on(server_handle.status())?;
+    let metrics = rt.block_on(server_handle.metrics())?;
+    Some(HostedServer {
+        player_count: status.players.len(),
+        max_players: status.max_players,
+        ticks: metrics.ticks,
+    })
+}
+
+/// Local player first, then remote players by ID
+fn inspected_entities(
+    local_player: &Player,
+    remote_players: &RemotePlayers,
+    player_updated_at: &HashMap<PlayerID, Instant>,
+) -> Vec<InspectedEntity> {
+    let mut remote_players: Vec<&Player> = remote_players.values().collect();
+    remote_players.sort_unstable_by_key(|player| player.id);
+    let remote_entities = remote_players.into_iter().map(|player| InspectedEntity {
+        player: *player,
+        is_local: false,
+        last_update: player_updated_at
+            .get(&player.id)
+            .map(|updated_at| updated_at.elapsed()),
+    });
+    std::iter::once(InspectedEntity {
+        player: *local_player,
+        is_local: true,
+        last_update: None,
+    })
+    .chain(remote_entities)
+    .collect()
+}
 
-        // Calculate the camera's allowed range
-        let min_camera_x = globals::WORLD_BOUNDS.min_x + half_width;
-        let max_camera_x = globals::WORLD_BOUNDS.max_x - half_width;
-        let min_camera_y = globals::WORLD_BOUNDS.min_y + half_height;
-        let max_camera_y = globals::WORLD_BOUNDS.max_y - half_height;
+/// Movement of the arrow and WASD keys
+fn movement_key(key: KeyCode) -> Option<InputEvent> {
+    match key {
+        KeyCode::ArrowUp | KeyCode::KeyW => Some(InputEvent::MoveUp),
+        KeyCode::ArrowDown | KeyCode::KeyS => Some(InputEvent::MoveDown),
+        KeyCode::ArrowLeft | KeyCode::KeyA => Some(InputEvent::MoveLeft),
+        KeyCode::ArrowRight | KeyCode::KeyD => Some(InputEvent::MoveRight),
+        _ => None,
+    }
+}
 
-        // Update camera position, clamping to the allowed range
-        self.camera_pos.x = self.local_player.pos.x.clamp(min_camera_x, max_camera_x);
-        self.camera_pos.y = self.local_player.pos.y.clamp(min_camera_y, max_camera_y);
+/// Keys the game reacts to in the state, the rest is left to the GUI. Tab only follows the next
+/// player of a replay, it moves between widgets otherwise.
+fn is_game_key(key: KeyCode, state: Option<&fsm::State>) -> bool {
+    match key {
+        KeyCode::Escape | KeyCode::F3 | KeyCode::F4 | KeyCode::F5 | KeyCode::F11 => true,
+        KeyCode::Tab => matches!(state, Some(fsm::State::Replay)),
+        key => movement_key(key).is_some(),
     }
 }
 
+/// Presses are buffered while joining, until the player can move
+fn apply_movement_key(
+    input_buffer: &mut InputBuffer,
+    state: Option<&fsm::State>,
+    event: InputEvent,
+    key_state: ElementState,
+) {
+    match key_state {
+        ElementState::Pressed => match state {
+            Some(fsm::State::Connecting { .. })
+            | Some(fsm::State::Loading { .. })
+            | Some(fsm::State::Playing)
+            | Some(fsm::State::Editor) => input_buffer.press(event),
+            _ => input_buffer.hold(event),
+        },
+        ElementState::Released => input_buffer.release(event),
+    }
+}
+
+/// Players drawn dimmed: those away from keyboard, and the local player while not active
+fn dimmed_players(
+    afk_players: &HashSet<PlayerID>,
+    activity: PlayerActivity,
+    local_player_id: PlayerID,
+) -> HashSet<PlayerID> {
+    let mut dimmed = afk_players.clone();
+    if activity != PlayerActivity::Active {
+        dimmed.insert(local_player_id);
+    }
+    dimmed
+}
+
 impl ApplicationHandler for App<'_> {
     // It is recommended for winit applications to create window and initialize their graphics context
     // after the first WindowEvent::Resumed even is received. There are systems that won't allow
     // applications to create a renderer until that.
     fn resumed(&mut self, event_loop: &ActiveEventLoop) {
-        let (window, renderer, gui) = Renderer::create_graphics(&event_loop);
+        // Some platforms send more than one resume event
+        if self.graphics.is_some() {
+            return;
+        }
 
-        self.window = Some(window);
-        self.renderer = Some(renderer);
-        self.gui = Some(gui);
+        self.create_graphics(event_loop);
+    }
+
+    // Mobile platforms destroy the native surface on suspension and GPU resources can't be used
+    // until the next resume. Network session continues running in the background meanwhile.
+    fn suspended(&mut self, _event_loop: &ActiveEventLoop) {
+        self.destroy_graphics();
+        self.input_buffer.release_all(); // Avoid keys being stuck
+        self.keyboard_focus.release_all();
+        self.input_state = InputState::default();
+        self.joystick.release();
     }
 
     fn window_event(
         &mut self,
-        _event_loop: &ActiveEventLoop,
+        event_loop: &ActiveEventLoop,
         _window_id: winit::window::WindowId,
         event: winit::event::WindowEvent,
     ) {
-        let window = self.window.as_ref().unwrap();
-        let gui = self.gui.as_mut().unwrap();
+        // Events can arrive before the first resume or after teardown
+        let Some(Graphics {
+            window,
+            renderer,
+            gui,
+        }) = &mut self.graphics
+        else {
+            return;
+        };
+
+        // Any input stops the attract mode and restarts waiting for it
+        if matches!(
+            event,
+            WindowEvent::KeyboardInput { .. }
+                | WindowEvent::MouseInput { .. }
+                | WindowEvent::MouseWheel { .. }
+                | WindowEvent::CursorMoved { .. }
+                | WindowEvent::Touch(_)
+        ) {
+            self.last_input_at = Instant::now();
+            window.request_redraw();
+            if matches!(self.state_machine.peek(), Some(fsm::State::Attract)) {
+                self.state_machine.pop();
+                self.attract = None;
+            }
+        }
+
+        // Key events belong either to a focused text field or the game, never both
+        let key_target = match &event {
+            WindowEvent::KeyboardInput {
+                event:
+                    KeyEvent {
+                        physical_key: PhysicalKey::Code(physical_key),
+                        state,
+                        ..
+                    },
+                ..
+            } => Some(self.keyboard_focus.route(
+                *physical_key,
+                *state == ElementState::Pressed,
+                is_game_key(*physical_key, self.state_machine.peek()),
+                gui.wants_keyboard_input(),
+            )),
+            _ => None,
+        };
 
         match event {
             WindowEvent::CloseRequested => self.state_machine.change(fsm::State::Quit),
@@ -348,46 +1691,299 @@ impl ApplicationHandler for App<'_> {
                     },
                 is_synthetic: false,
                 ..
-            } => {
+            } if key_target == Some(KeyTarget::Game) => {
                 if matches!(logical_key, Key::Named(NamedKey::Escape)) &&
                 // Negation is an additional guard to avoid accidentally pushing duplicate states when someone holds down Esc key for too long
                 !matches!(self.state_machine.peek(), Some(fsm::State::QuitDialog))
                 {
                     self.state_machine.push(fsm::State::QuitDialog);
                 }
+                if physical_key == KeyCode::F3 && state == ElementState::Pressed {
+                    gui.toggle_inspector();
+                }
+                if cfg!(debug_assertions)
+                    && physical_key == KeyCode::F4
+                    && state == ElementState::Pressed
+                {
+                    gui.toggle_netcode_panel();
+                }
+                if physical_key == KeyCode::Tab && state == ElementState::Pressed {
+                    if let (Some(fsm::State::Replay), Some(replay)) =
+                        (self.state_machine.peek(), &mut self.replay)
+                    {
+                        replay.follow_next();
+                    }
+                }
+                if physical_key == KeyCode::F5 && state == ElementState::Pressed {
+                    gui.toggle_network_log();
+                }
+                if physical_key == KeyCode::F11 && state == ElementState::Pressed {
+                    let fullscreen = match window.fullscreen() {
+                        Some(_) => None,
+                        None => Some(Fullscreen::Borderless(None)),
+                    };
+                    window.set_fullscreen(fullscreen);
+                }
 
+                if let Some(input_event) = movement_key(physical_key) {
+                    apply_movement_key(
+                        &mut self.input_buffer,
+                        self.state_machine.peek(),
+                        input_event,
+                        state,
+                    );
+                }
+            }
+            // Keys held down while the window gains focus are reported as synthetic presses, so
+            // movement picks up where it was without pressing them again
+            WindowEvent::KeyboardInput {
+                event:
+                    KeyEvent {
+                        physical_key: PhysicalKey::Code(physical_key),
+                        state,
+                        ..
+                    },
+                is_synthetic: true,
+                ..
+            } if key_target == Some(KeyTarget::Game) => {
+                if let Some(input_event) = movement_key(physical_key) {
+                    apply_movement_key(
+                        &mut self.input_buffer,
+                        self.state_machine.peek(),
+                        input_event,
+                        state,
+                    );
+                }
+            }
+            WindowEvent::Touch(Touch {
+                phase,
+                location,
+                id,
+                ..
+            }) => {
                 if matches!(self.state_machine.peek(), Some(fsm::State::Playing)) {
-                    let input_event = match physical_key {
-                        KeyCode::ArrowUp | KeyCode::KeyW => InputEvent::MoveUp,
-                        KeyCode::ArrowDown | KeyCode::KeyS => InputEvent::MoveDown,
-                        KeyCode::ArrowLeft | KeyCode::KeyA => InputEvent::MoveLeft,
-                        KeyCode::ArrowRight | KeyCode::KeyD => InputEvent::MoveRight,
-                        _ => return,
-                    };
-                    self.input_state[input_event] = state == ElementState::Pressed;
+                    // Joystick is laid out in the same logical coordinates as the GUI
+                    let location = location.to_logical::<f32>(window.scale_factor());
+                    self.joystick
+                        .handle_touch(phase, id, cgmath::vec2(location.x, location.y));
                 }
             }
+            WindowEvent::CursorMoved { position, .. } => {
+                self.cursor_pos = renderer.window_to_scene(position);
+            }
+            WindowEvent::Resized(size) => {
+                renderer.resize(size);
+                let size = size.to_logical::<f32>(window.scale_factor());
+                self.joystick.resize(cgmath::vec2(size.width, size.height));
+            }
+            WindowEvent::MouseInput {
+                state: ElementState::Pressed,
+                button: MouseButton::Left,
+                ..
+            } if matches!(self.state_machine.peek(), Some(fsm::State::Editor))
+                && !gui.wants_pointer_input() =>
+            {
+                self.editor
+                    .toggle(self.camera.screen_to_world(self.cursor_pos));
+            }
             WindowEvent::Focused(false) => {
                 // Avoid stuck keys when window loses focus
+                self.input_buffer.release_all();
+                self.keyboard_focus.release_all();
                 self.input_state = InputState::default();
+                self.joystick.release();
             }
             WindowEvent::RedrawRequested => {
-                let renderer = self.renderer.as_ref().unwrap();
-
-                gui.prepare_frame(&window, &mut self.state_machine);
-                renderer.draw(
-                    &self.camera_pos,
-                    &self.local_player,
+                self.redrawn_at = Instant::now();
+                // Bots of the attract mode and lockstep peers have no server seed to share
+                let avatar_seed = match (&self.replay, &self.client_session) {
+                    (Some(replay), _) => replay.seed(),
+                    (None, Some(client_session)) => client_session.get_seed(),
+                    (None, None) => 0,
+                };
+                gui.set_replay_status(self.replay.as_ref().map(|replay| ReplayStatus {
+                    map_name: replay.map_name().to_string(),
+                    elapsed: Duration::from_secs_f32(
+                        replay.elapsed_ticks() as f32 * globals::FIXED_UPDATE_TIMESTEP_SEC,
+                    ),
+                    followed: replay.followed_player().map(|player| {
+                        (
+                            *player,
+                            replay
+                                .player_name(player.id)
+                                .unwrap_or_default()
+                                .to_string(),
+                        )
+                    }),
+                }));
+                gui.set_avatar_seed(avatar_seed);
+                renderer.set_avatar_seed(avatar_seed);
+                renderer.set_player_size(
+                    self.client_session
+                        .as_ref()
+                        .map_or_else(WorldParams::default, ClientSession::get_world_params)
+                        .player_size,
+                );
+                gui.set_local_player(self.local_player);
+                gui.set_player_names(&self.player_names);
+                gui.set_afk_players(&self.afk_players);
+                gui.set_activity(self.activity);
+                gui.set_game_mode(if self.lockstep.is_some() {
+                    GameMode::Lockstep
+                } else {
+                    GameMode::Online
+                });
+                if gui.is_inspector_open() {
+                    gui.set_inspected_entities(inspected_entities(
+                        &self.local_player,
+                        &self.remote_players,
+                        &self.player_updated_at,
+                    ));
+                }
+                match &self.server_handle {
+                    Some(server_handle) => {
+                        if !self.hosted_server_refreshed_at.is_some_and(|refreshed_at| {
+                            refreshed_at.elapsed() < HOSTED_SERVER_REFRESH
+                        }) {
+                            gui.set_hosted_server(hosted_server(self.rt, server_handle));
+                            self.hosted_server_refreshed_at = Some(Instant::now());
+                        }
+                    }
+                    None => {
+                        if self.hosted_server_refreshed_at.take().is_some() {
+                            gui.set_hosted_server(None);
+                        }
+                    }
+                }
+                if gui.is_netcode_panel_open() {
+                    gui.set_interpolation_delay(self.interpolation.delay());
+                }
+                // Zone closes in smoothly between the updates of the server
+                let safe_zone = self
+                    .storm
+                    .map(|(state, received_at)| state.zone_after(received_at.elapsed()));
+                gui.set_storm_warning(safe_zone.map(|safe_zone| StormWarning {
+                    health: self.health,
+                    outside: !safe_zone.contains(self.local_player.pos),
+                }));
+                gui.prepare_frame(
+                    window,
+                    &mut self.state_machine,
+                    &self.joystick,
                     &self.remote_players,
-                    self.state_machine.peek(),
+                    &mut self.muted_players,
+                    &mut self.editor,
                 );
-                gui.draw(&window);
-                renderer.swap_buffers();
+                for text in gui.take_outgoing_chat() {
+                    // Debug command handled locally instead of being sent as chat
+                    if let Some(arg) = text.strip_prefix("/timescale") {
+                        match admin::parse_time_scale(arg.trim()) {
+                            Ok(scale) => {
+                                gui.set_time_scale(scale);
+                                gui.log(format!("Time scale set to {scale}x"));
+                            }
+                            Err(e) => gui.log(e),
+                        }
+                        continue;
+                    }
+                    if let Some(client_session) = &self.client_session {
+                        client_session.send_chat(text);
+                    }
+                }
+                if let Some((color, name)) = gui.take_outgoing_profile() {
+                    if let Some(client_session) = &self.client_session {
+                        client_session.update_profile(color, name);
+                    }
+                }
+                self.time_scale = gui.time_scale();
+                let netcode_tuning = gui.netcode_tuning();
+                if netcode_tuning != self.netcode_tuning {
+                    self.interpolation
+                        .set_settings(netcode_tuning.interpolation);
+                    if let Some(client_session) = &self.client_session {
+                        client_session.set_send_rate(netcode_tuning.send_rate);
+                    }
+                    self.netcode_tuning = netcode_tuning;
+                }
+                // Host gets the Shutdown notice like everyone else and disconnects from it
+                if gui.take_stop_server_request() {
+                    if let Some(server_handle) = self.server_handle.take() {
+                        gui.log("Stopping server...".to_string());
+                        self.rt.spawn(async move { server_handle.shutdown().await });
+                    }
+                }
+                let drawn_local_player = Player {
+                    pos: self.local_player.pos + self.correction_offset,
+                    ..self.local_player
+                };
+                // Bots of the attract mode are all remote players, none of them is controlled
+                let (local_player, remote_players, map) =
+                    match (self.state_machine.peek(), &self.attract, &self.replay) {
+                        (Some(fsm::State::Attract), Some(attract), _) => (
+                            attract.followed_player().unwrap_or(&self.local_player),
+                            attract.players(),
+                            attract.map(),
+                        ),
+                        // So are the players of a replay, the camera follows one of them
+                        (Some(fsm::State::Replay), _, Some(replay)) => (
+                            replay.followed_player().unwrap_or(&self.local_player),
+                            replay.players(),
+                            replay.map(),
+                        ),
+                        // Map being edited is drawn in place of the played one
+                        (Some(fsm::State::Editor), _, _) => {
+                            (&self.local_player, &self.remote_players, &self.editor.map)
+                        }
+                        _ => (&drawn_local_player, &self.remote_players, &self.map),
+                    };
+                let mut passes = vec![RenderPass::Grid];
+                match self.state_machine.peek() {
+                    // Keep drawing players even when Quit dialog or match results are shown
+                    Some(fsm::State::Playing)
+                    | Some(fsm::State::Attract)
+                    | Some(fsm::State::Replay)
+                    | Some(fsm::State::Results { .. })
+                    | Some(fsm::State::QuitDialog) => passes.push(RenderPass::World),
+                    Some(fsm::State::Editor) => passes.push(RenderPass::Editor),
+                    _ => (),
+                }
+                passes.push(RenderPass::Gui(gui, window));
+                renderer.draw(
+                    &Scene {
+                        camera: self.camera,
+                        local_player,
+                        remote_players,
+                        environment: &Environment {
+                            time_of_day: self.time_of_day,
+                            safe_zone,
+                            leader: self.leader,
+                            afk_players: dimmed_players(
+                                &self.afk_players,
+                                self.activity,
+                                self.local_player.id,
+                            ),
+                        },
+                        map,
+                    },
+                    passes,
+                );
+                if let Err(e) = renderer.swap_buffers() {
+                    // Re-create every GPU resource from scratch on context loss
+                    if e.error_kind() == glutin::error::ErrorKind::ContextLost {
+                        eprintln!("Graphics context lost, re-creating");
+                        self.destroy_graphics();
+                        self.create_graphics(event_loop);
+                        return;
+                    }
+                    eprintln!("Failed to swap buffers: {e}");
+                }
             }
             _ => (),
         }
 
         // Forward rest of events to GUI
-        gui.handle_events(&window, &event);
+        if key_target != Some(KeyTarget::Game) {
+            gui.handle_events(window, &event);
+        }
     }
 }
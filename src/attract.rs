@@ -0,0 +1,72 @@
+use std::{collections::HashMap, time::Duration};
+
+use cgmath::Vector3;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use crate::{
+    bot::Bot,
+    map::{self, Map, ProceduralParams},
+    Player, PlayerID, WorldParams,
+};
+
+/// Time spent idle in the menu before the bot match starts
+pub const ATTRACT_DELAY: Duration = Duration::from_secs(30);
+
+const BOT_COUNT: usize = 8;
+
+/// Local match of bots on a generated map, played behind the menu while nobody touches the game
+pub struct AttractMatch {
+    map: Map,
+    players: HashMap<PlayerID, Player>,
+    bots: Vec<(PlayerID, Bot)>,
+}
+
+impl AttractMatch {
+    pub fn new(seed: u64) -> Self {
+        let map = map::generate(&ProceduralParams {
+            seed,
+            density: map::DEFAULT_PROCEDURAL_DENSITY,
+        });
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut players = HashMap::new();
+        let mut bots = Vec::new();
+        for (index, spawn_point) in map.spawn_points.iter().take(BOT_COUNT).enumerate() {
+            let player_id = index as PlayerID + 1;
+            let mut player = Player::new(player_id, Vector3::new(rng.gen(), rng.gen(), rng.gen()));
+            player.pos = *spawn_point;
+            players.insert(player_id, player);
+            // Circles of different sizes in both directions
+            let turn_rate = rng.gen_range(0.005..0.03) * if rng.gen() { 1.0 } else { -1.0 };
+            bots.push((
+                player_id,
+                Bot::new(rng.gen_range(0.0..std::f32::consts::TAU), turn_rate),
+            ));
+        }
+
+        Self { map, players, bots }
+    }
+
+    /// Move every bot for a single logic update
+    pub fn update(&mut self) {
+        for (player_id, bot) in &mut self.bots {
+            if let Some(player) = self.players.get_mut(player_id) {
+                bot.step(player, &self.map, &WorldParams::default());
+            }
+        }
+    }
+
+    pub fn map(&self) -> &Map {
+        &self.map
+    }
+
+    pub fn players(&self) -> &HashMap<PlayerID, Player> {
+        &self.players
+    }
+
+    /// Bot followed by the camera
+    pub fn followed_player(&self) -> Option<&Player> {
+        self.bots
+            .first()
+            .and_then(|(player_id, _)| self.players.get(player_id))
+    }
+}
@@ -0,0 +1,50 @@
+use crate::PlayerID;
+
+/// Cells per side of the avatar pattern
+pub const AVATAR_CELLS: usize = 5;
+/// Avatars are drawn in the player color darkened by this factor
+pub const AVATAR_SHADE: f32 = 0.5;
+/// Size of the pattern relative to the quad of the player, leaving a margin of the player color
+pub const AVATAR_PATTERN_SCALE: f32 = 0.75;
+
+/// Pattern drawn inside the quad of a player, so that players can be told apart by more than
+/// their color. Derived from the seed of the server and the player ID, so every player sees the
+/// same avatar for the same player, and reconnecting with the same ID keeps the avatar.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Avatar {
+    /// Bit per cell, row by row from the top-left corner
+    cells: u32,
+}
+
+impl Avatar {
+    pub fn new(seed: u64, player_id: PlayerID) -> Self {
+        let hash = mix(seed ^ mix(player_id));
+        // Left half and middle column are random, the right half mirrors the left
+        let half_width = AVATAR_CELLS.div_ceil(2);
+        let mut cells = 0;
+        for row in 0..AVATAR_CELLS {
+            for column in 0..half_width {
+                if hash >> (row * half_width + column) & 1 == 1 {
+                    cells |= 1 << (row * AVATAR_CELLS + column);
+                    cells |= 1 << (row * AVATAR_CELLS + AVATAR_CELLS - 1 - column);
+                }
+            }
+        }
+        Self { cells }
+    }
+
+    pub fn is_filled(&self, row: usize, column: usize) -> bool {
+        row < AVATAR_CELLS
+            && column < AVATAR_CELLS
+            && self.cells >> (row * AVATAR_CELLS + column) & 1 == 1
+    }
+}
+
+/// SplitMix64 finalizer. Unlike `std::hash`, it's guaranteed to give the same result on every
+/// platform and Rust version, which keeps avatars the same for everyone.
+fn mix(value: u64) -> u64 {
+    let mut z = value.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
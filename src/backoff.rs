@@ -0,0 +1,62 @@
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    time::{Duration, Instant},
+};
+
+/// How long an address is ignored after its first offence, doubled by every further offence
+const INITIAL_BACKOFF: Duration = Duration::from_millis(250);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+/// Offences of an address are forgotten when it behaves for this long after its backoff
+const FORGIVE_AFTER: Duration = Duration::from_secs(60);
+/// Most addresses tracked at once, so that floods from spoofed addresses can't exhaust memory
+const MAX_TRACKED_ADDRESSES: usize = 4096;
+
+struct Offender {
+    offences: u32,
+    blocked_until: Instant,
+}
+
+/// Addresses which recently sent garbage, got their join request refused or acted without
+/// joining. Their datagrams are dropped right after receiving for a backoff that grows
+/// exponentially with repeated offences, so that floods don't cost tasks and locks.
+#[derive(Default)]
+pub struct AddressBackoff {
+    offenders: HashMap<SocketAddr, Offender>,
+}
+
+impl AddressBackoff {
+    pub fn is_blocked(&self, address: &SocketAddr, now: Instant) -> bool {
+        self.offenders
+            .get(address)
+            .is_some_and(|offender| now < offender.blocked_until)
+    }
+
+    /// Block the address for twice as long as the last time, unless its previous offences were
+    /// forgiven already.
+    pub fn penalize(&mut self, address: SocketAddr, now: Instant) {
+        if self.offenders.len() >= MAX_TRACKED_ADDRESSES && !self.offenders.contains_key(&address) {
+            self.offenders
+                .retain(|_, offender| now < offender.blocked_until + FORGIVE_AFTER);
+            if self.offenders.len() >= MAX_TRACKED_ADDRESSES {
+                return;
+            }
+        }
+        let offender = self.offenders.entry(address).or_insert(Offender {
+            offences: 0,
+            blocked_until: now,
+        });
+        // Reported for datagrams received before the block, which doesn't make it worse
+        if now < offender.blocked_until {
+            return;
+        }
+        if now >= offender.blocked_until + FORGIVE_AFTER {
+            offender.offences = 0;
+        }
+        offender.offences = offender.offences.saturating_add(1);
+        let backoff = INITIAL_BACKOFF
+            .checked_mul(1 << (offender.offences - 1).min(16))
+            .map_or(MAX_BACKOFF, |backoff| backoff.min(MAX_BACKOFF));
+        offender.blocked_until = now + backoff;
+    }
+}
@@ -0,0 +1,136 @@
+use std::{
+    net::IpAddr,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::logger;
+
+/// Punishment of a single address, either permanent or until it expires.
+#[derive(Clone, Deserialize, Serialize)]
+pub struct Sanction {
+    pub address: IpAddr,
+    pub reason: String,
+    /// Unix timestamp in seconds, permanent if missing
+    pub expires: Option<u64>,
+}
+
+impl Sanction {
+    fn is_active(&self, now: u64) -> bool {
+        !matches!(self.expires, Some(expires) if expires <= now)
+    }
+}
+
+/// Banned and muted addresses, persisted as JSON so they survive server restarts. Bans are
+/// enforced on handshake, mutes on chat relay. Expired entries are purged on each modification.
+///
+/// Without a file path the lists only live in memory.
+#[derive(Default, Deserialize, Serialize)]
+pub struct Banlist {
+    bans: Vec<Sanction>,
+    mutes: Vec<Sanction>,
+    #[serde(skip)]
+    path: Option<PathBuf>,
+}
+
+impl Banlist {
+    /// Missing file is not an error, it gets created on the first ban or mute.
+    pub fn load(path: &Path) -> std::io::Result<Self> {
+        let mut banlist = match std::fs::read_to_string(path) {
+            Ok(contents) => serde_json::from_str(&contents)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Banlist::default(),
+            Err(e) => return Err(e),
+        };
+        banlist.path = Some(path.to_path_buf());
+
+        Ok(banlist)
+    }
+
+    pub fn ban(&mut self, address: IpAddr, reason: String, duration: Option<Duration>) {
+        add_sanction(&mut self.bans, address, reason, duration);
+        self.save();
+    }
+
+    /// Returns false if the address was not banned.
+    pub fn unban(&mut self, address: IpAddr) -> bool {
+        let found = remove_sanction(&mut self.bans, address);
+        self.save();
+        found
+    }
+
+    pub fn mute(&mut self, address: IpAddr, reason: String, duration: Option<Duration>) {
+        add_sanction(&mut self.mutes, address, reason, duration);
+        self.save();
+    }
+
+    /// Returns false if the address was not muted.
+    pub fn unmute(&mut self, address: IpAddr) -> bool {
+        let found = remove_sanction(&mut self.mutes, address);
+        self.save();
+        found
+    }
+
+    pub fn find_ban(&self, address: IpAddr) -> Option<&Sanction> {
+        find_sanction(&self.bans, address)
+    }
+
+    pub fn is_muted(&self, address: IpAddr) -> bool {
+        find_sanction(&self.mutes, address).is_some()
+    }
+
+    /// Write lists to the file, dropping expired entries.
+    pub fn save(&mut self) {
+        let now = unix_time_now();
+        self.bans.retain(|sanction| sanction.is_active(now));
+        self.mutes.retain(|sanction| sanction.is_active(now));
+
+        let Some(path) = &self.path else {
+            return;
+        };
+        let result = serde_json::to_string_pretty(self)
+            .map_err(std::io::Error::from)
+            .and_then(|contents| std::fs::write(path, contents));
+        if let Err(e) = result {
+            logger::error(
+                "banlist",
+                format!("Failed to save banlist to {}: {e}", path.display()),
+            );
+        }
+    }
+}
+
+pub fn unix_time_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs())
+}
+
+fn add_sanction(
+    sanctions: &mut Vec<Sanction>,
+    address: IpAddr,
+    reason: String,
+    duration: Option<Duration>,
+) {
+    // New sanction overrides the previous one for the same address
+    sanctions.retain(|sanction| sanction.address != address);
+    sanctions.push(Sanction {
+        address,
+        reason,
+        expires: duration.map(|duration| unix_time_now() + duration.as_secs()),
+    });
+}
+
+fn remove_sanction(sanctions: &mut Vec<Sanction>, address: IpAddr) -> bool {
+    let len = sanctions.len();
+    sanctions.retain(|sanction| sanction.address != address);
+    sanctions.len() != len
+}
+
+fn find_sanction(sanctions: &[Sanction], address: IpAddr) -> Option<&Sanction> {
+    let now = unix_time_now();
+    sanctions
+        .iter()
+        .find(|sanction| sanction.address == address && sanction.is_active(now))
+}
@@ -0,0 +1,35 @@
+use cgmath::Vector2;
+
+use crate::{map::Map, Player, WorldParams};
+
+/// Angle in radians a bot turns in a single logic update unless told otherwise
+pub const DEFAULT_TURN_RATE: f32 = 0.02;
+
+/// Scripted player walking around in circles, standing in for a human player in the headless
+/// client and in the attract mode of the menu
+pub struct Bot {
+    heading: f32,
+    turn_rate: f32,
+}
+
+impl Default for Bot {
+    fn default() -> Self {
+        Self::new(0.0, DEFAULT_TURN_RATE)
+    }
+}
+
+impl Bot {
+    pub fn new(heading: f32, turn_rate: f32) -> Self {
+        Self { heading, turn_rate }
+    }
+
+    /// Move the player for a single logic update: keep turning while moving forward
+    pub fn step(&mut self, player: &mut Player, map: &Map, world_params: &WorldParams) {
+        self.heading += self.turn_rate;
+        player.velocity =
+            Vector2::new(self.heading.cos(), self.heading.sin()) * world_params.player_speed;
+        player.pos += player.velocity;
+        map.push_out(player, world_params.player_size);
+        world_params.clamp_player_to_bounds(player);
+    }
+}
@@ -0,0 +1,67 @@
+use std::{sync::Mutex, time::Duration};
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+/// Faults injected into the server, for testing how clients cope with a misbehaving server.
+/// Chances are between 0 and 1. The same seed injects the same sequence of faults.
+#[derive(Clone, Debug)]
+pub struct ChaosConfig {
+    pub seed: u64,
+    /// Chance of waiting before taking the lock of the players
+    pub lock_delay_chance: f64,
+    /// Longest wait before taking the lock of the players
+    pub max_lock_delay: Duration,
+    /// Chance of a broadcast message not being sent to one of its recipients
+    pub broadcast_drop_chance: f64,
+    /// Chance of a received datagram being handled twice
+    pub duplicate_chance: f64,
+}
+
+impl Default for ChaosConfig {
+    fn default() -> Self {
+        Self {
+            seed: 0,
+            lock_delay_chance: 0.1,
+            max_lock_delay: Duration::from_millis(20),
+            broadcast_drop_chance: 0.1,
+            duplicate_chance: 0.1,
+        }
+    }
+}
+
+/// Decides where faults are injected, see `ChaosConfig`
+pub struct Chaos {
+    config: ChaosConfig,
+    rng: Mutex<StdRng>,
+}
+
+impl Chaos {
+    pub fn new(config: ChaosConfig) -> Self {
+        Self {
+            rng: Mutex::new(StdRng::seed_from_u64(config.seed)),
+            config,
+        }
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.config.seed
+    }
+
+    pub fn lock_delay(&self) -> Option<Duration> {
+        let mut rng = self.rng.lock().unwrap();
+        rng.gen_bool(self.config.lock_delay_chance)
+            .then(|| self.config.max_lock_delay.mul_f64(rng.gen()))
+    }
+
+    pub fn drops_broadcast(&self) -> bool {
+        self.chance(self.config.broadcast_drop_chance)
+    }
+
+    pub fn duplicates_datagram(&self) -> bool {
+        self.chance(self.config.duplicate_chance)
+    }
+
+    fn chance(&self, chance: f64) -> bool {
+        self.rng.lock().unwrap().gen_bool(chance)
+    }
+}
@@ -1,89 +1,364 @@
-use std::{error::Error, sync::Arc};
+use std::{
+    collections::{HashMap, VecDeque},
+    error::Error,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+};
+
+use cgmath::{InnerSpace, Vector2, Vector3};
 
 use tokio::{
     net::UdpSocket,
-    sync::mpsc::{self, error::TryRecvError},
+    sync::{
+        mpsc::{self, error::TryRecvError},
+        watch,
+    },
     task::JoinHandle,
 };
 
 use crate::{
     globals,
-    message::{self, Message},
-    Player, PlayerID,
+    message::{self, DatagramHeader, Message},
+    net::{self, Channels, RecvBatch},
+    queue,
+    snapshot::{SnapshotDecoder, WorldState},
+    task, Player, PlayerID, SessionToken, Tick, WorldParams,
 };
 
 // Non-blocking channels are used for lock-free message passing from sync main thread to async
 // context and between multiple async tasks.
 // TODO: Research how to handle backpressure
-type ChannelSender = mpsc::UnboundedSender<String>;
-type ChannelReceiver = mpsc::UnboundedReceiver<String>;
+type ChannelSender = mpsc::UnboundedSender<Vec<u8>>;
+type ChannelReceiver = mpsc::UnboundedReceiver<Vec<u8>>;
+type OutgoingSender = mpsc::UnboundedSender<Message>;
+type OutgoingReceiver = mpsc::UnboundedReceiver<Message>;
+
+/// Positions sent to the server per second by default, half the logic update rate
+pub const DEFAULT_SEND_RATE: u32 = 30;
+/// Lower rates would make players move further between two updates than the server scores
+pub const MIN_SEND_RATE: u32 = 20;
+/// Sending more often than the logic updates would only resend the same position
+pub const MAX_SEND_RATE: u32 = globals::MAX_LOGIC_UPDATE_PER_SEC as u32;
+
+/// Position changes smaller than this are not worth a datagram, like sliding along the world
+/// boundary which keeps the position in place
+const MIN_POSITION_CHANGE: f32 = 0.5;
+
+/// Most map chunk requests waiting to be sent. The download repeats requests which aren't
+/// answered, so the oldest ones can go when the queue backs up.
+const MAX_QUEUED_MAP_REQUESTS: usize = 16;
+
+/// Connection is considered unstable when nothing arrived from the server for this long. The
+/// server sends a keepalive ping at least every `globals::KEEPALIVE_INTERVAL`, so this is a few
+/// missed keepalives and well before the connection is given up.
+const DEGRADED_AFTER: std::time::Duration = std::time::Duration::from_secs(1);
+
+/// Longest wait after the session is dropped for the server to acknowledge reliable messages
+/// still in flight, like DISCONNECT. Long enough for a few resends, the server times the player
+/// out after `globals::CONNECTION_TIMEOUT_SEC` if none of them gets through.
+const LEAVE_LINGER: std::time::Duration = std::time::Duration::from_millis(500);
+
+/// Lifecycle of the connection to the server. Binding and handshaking happen while the session
+/// is being created, the rest is followed by watching traffic from the server.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ConnectionState {
+    /// Opening the local socket
+    Binding,
+    /// Waiting for the server to accept the handshake
+    Handshaking,
+    Connected,
+    /// Server has been silent for a while, but may still come back
+    Degraded,
+    /// Server has been silent for too long, the session is over
+    Lost,
+}
+
+impl std::fmt::Display for ConnectionState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let text = match self {
+            ConnectionState::Binding => "binding socket",
+            ConnectionState::Handshaking => "handshaking",
+            ConnectionState::Connected => "connected",
+            ConnectionState::Degraded => "connection unstable",
+            ConnectionState::Lost => "connection lost",
+        };
+        write!(f, "{text}")
+    }
+}
 
 pub struct ClientSession {
     listen_rx: ChannelReceiver,
-    send_tx: ChannelSender,
-    listen_task: JoinHandle<()>,
-    send_task: JoinHandle<()>,
+    send_tx: OutgoingSender,
+    /// Latest position waiting to be sent, superseding the ones not sent yet
+    position_tx: watch::Sender<Option<Vector2<f32>>>,
+    /// Positions sent per second, changeable while connected
+    send_rate_tx: watch::Sender<u32>,
+    /// Finishes once the session is dropped and the server acknowledged the last reliable
+    /// messages, taking the listen and resend tasks with it
+    send_task: Option<JoinHandle<()>>,
     /// The local player associated with the client
     session_player: Player,
-    /// Last ping time used for initiating timeout when server is unavailable
-    last_ping: std::time::Instant,
+    /// Received during handshake, identifies every message sent to the server as ours
+    token: SessionToken,
+    server_address: String,
+    /// Name of the joined server received during handshake
+    server_name: String,
+    /// Seed of the joined server received during handshake, shared by every player on it
+    seed: u64,
+    /// Player size and speed on the joined server received during handshake
+    world_params: WorldParams,
+    /// When anything last arrived from the server, for telling when the connection is lost
+    last_heard: std::time::Instant,
+    connection_state: ConnectionState,
+    /// Server tick of the newest snapshot applied per remote player
+    snapshot_ticks: HashMap<PlayerID, Tick>,
+    /// Baselines of the delta compressed snapshots from the server
+    snapshots: SnapshotDecoder,
+    /// Players of the latest snapshot decoded, so that only players who changed since are
+    /// replicated
+    snapshot_world: WorldState,
+    /// `Replicate` of every player who changed in the latest snapshot, handed out before anything
+    /// received after the snapshot
+    replicated: VecDeque<Vec<u8>>,
 }
 
 pub type ClientSessionResult = Result<ClientSession, Box<dyn Error + Send + Sync>>;
 
+/// Something that happened on the connection since the last call to
+/// [`ClientSession::try_events`]
+#[derive(Debug)]
+pub enum ClientEvent {
+    /// Message received from the server
+    Message(Message),
+    ConnectionStateChanged(ConnectionState),
+}
+
 impl ClientSession {
     /// Bind socket, initiate handshake procedure to server and setup messaging channels.
-    /// Connection and handshake are retried until timeout.
-    pub async fn new(server_address: String) -> ClientSessionResult {
-        match tokio::time::timeout(globals::CONNECTION_TIMEOUT_SEC, async {
+    /// Connection and handshake are retried until timeout. Positions are sent at most
+    /// `send_rate` times per second. With the token of a lost session on the same server, the
+    /// player of that session is taken over instead of joining as a new one.
+    ///
+    /// While waiting in the join queue of a full server, the place in the queue is sent to
+    /// `queue_position_tx` and the timeout starts over with every update.
+    pub async fn new(
+        server_address: String,
+        player_name: String,
+        send_rate: u32,
+        previous_token: Option<SessionToken>,
+        queue_position_tx: Option<watch::Sender<Option<u32>>>,
+    ) -> ClientSessionResult {
+        let mut connection_state = ConnectionState::Binding;
+        let (queue_tx, mut queue_rx) = watch::channel(None);
+        let connect = async {
             // Socket bind
             let client_socket = UdpSocket::bind("0.0.0.0:0").await?;
             let client_socket = Arc::new(client_socket);
 
             // Server connect
-            let session_player = join_server(&client_socket, &server_address).await?;
+            connection_state = ConnectionState::Handshaking;
+            let (session_player, token, seed, server_name, world_params) = join_server(
+                &client_socket,
+                &server_address,
+                player_name,
+                previous_token,
+                &queue_tx,
+            )
+            .await?;
 
             // Message handlers
             let (listen_tx, listen_rx) = mpsc::unbounded_channel();
             let (send_tx, send_rx) = mpsc::unbounded_channel();
-            let listen_task = tokio::spawn(listen_handler(client_socket.clone(), listen_tx));
-            let send_task = tokio::spawn(send_handler(
-                client_socket.clone(),
-                server_address.clone(),
-                send_rx,
-            ));
+            let (position_tx, position_rx) = watch::channel(None);
+            let (send_rate_tx, send_rate_rx) = watch::channel(send_rate);
+            let channels = Arc::new(Mutex::new(Channels::new()));
+            let listen_task = task::spawn_named(
+                "client_listen_handler",
+                listen_handler(client_socket.clone(), channels.clone(), listen_tx),
+            );
+            let resend_task = task::spawn_named(
+                "client_resend_handler",
+                resend_handler(
+                    client_socket.clone(),
+                    server_address.clone(),
+                    channels.clone(),
+                ),
+            );
+            let send_task = task::spawn_named(
+                "client_send_handler",
+                send_handler(
+                    client_socket.clone(),
+                    server_address.clone(),
+                    channels.clone(),
+                    send_rx,
+                    [listen_task, resend_task],
+                ),
+            );
+            task::spawn_named(
+                "client_position_sender",
+                position_sender(
+                    client_socket.clone(),
+                    server_address.clone(),
+                    channels,
+                    token,
+                    position_rx,
+                    send_rate_rx,
+                ),
+            );
 
             println!("Connected to server");
             Ok(Self {
                 listen_rx,
                 send_tx,
-                listen_task,
-                send_task,
+                position_tx,
+                send_rate_tx,
+                send_task: Some(send_task),
                 session_player,
-                last_ping: std::time::Instant::now(),
+                token,
+                server_address,
+                server_name,
+                seed,
+                world_params,
+                last_heard: std::time::Instant::now(),
+                connection_state: ConnectionState::Connected,
+                snapshot_ticks: HashMap::new(),
+                snapshots: SnapshotDecoder::default(),
+                snapshot_world: WorldState::new(),
+                replicated: VecDeque::new(),
             })
-        })
-        .await
-        {
-            Ok(client_session) => client_session,
-            Err(_) => Err(format!(
-                "Connection timed out after {} seconds.",
+        };
+
+        let result = {
+            tokio::pin!(connect);
+            // Waiting in the join queue of a full server doesn't count towards the timeout
+            let mut deadline = tokio::time::Instant::now() + globals::CONNECTION_TIMEOUT_SEC;
+            loop {
+                tokio::select! {
+                    result = &mut connect => break Some(result),
+                    Ok(()) = queue_rx.changed() => {
+                        deadline = tokio::time::Instant::now() + globals::CONNECTION_TIMEOUT_SEC;
+                        if let Some(queue_position_tx) = &queue_position_tx {
+                            queue_position_tx.send_replace(*queue_rx.borrow_and_update());
+                        }
+                    }
+                    () = tokio::time::sleep_until(deadline) => break None,
+                }
+            }
+        };
+        match result {
+            Some(client_session) => client_session,
+            None => Err(format!(
+                "Connection timed out after {} seconds while {connection_state}.",
                 globals::CONNECTION_TIMEOUT_SEC.as_secs()
             )
             .into()),
         }
     }
 
+    /// Join a server from synchronous code by blocking on the given runtime until the handshake
+    /// is done. Networking keeps running on the runtime afterwards, so it has to be a
+    /// multi-threaded runtime that isn't blocked by the caller.
+    ///
+    /// ```no_run
+    /// use multiplayer_game_demo_rust::client::{ClientEvent, ClientSession};
+    ///
+    /// let rt = tokio::runtime::Runtime::new().unwrap();
+    /// let mut session =
+    ///     ClientSession::connect_blocking("127.0.0.1:8080".parse().unwrap(), "Bob", &rt).unwrap();
+    /// loop {
+    ///     for event in session.try_events() {
+    ///         println!("{event:?}");
+    ///     }
+    ///     std::thread::sleep(std::time::Duration::from_millis(16));
+    /// }
+    /// ```
+    pub fn connect_blocking(
+        server_address: SocketAddr,
+        player_name: &str,
+        rt: &tokio::runtime::Runtime,
+    ) -> ClientSessionResult {
+        rt.block_on(Self::new(
+            server_address.to_string(),
+            player_name.to_string(),
+            DEFAULT_SEND_RATE,
+            None,
+            None,
+        ))
+    }
+
     pub fn get_session_player_data(&self) -> Player {
         self.session_player
     }
 
-    pub fn receive_server_response(&mut self) -> Result<String, TryRecvError> {
+    pub fn get_server_name(&self) -> &str {
+        &self.server_name
+    }
+
+    pub fn get_server_address(&self) -> &str {
+        &self.server_address
+    }
+
+    /// Token to take the player over with when reconnecting after the connection was lost
+    pub fn get_session_token(&self) -> SessionToken {
+        self.token
+    }
+
+    /// Change how many positions are sent per second from now on
+    pub fn set_send_rate(&self, send_rate: u32) {
+        let _ = self.send_rate_tx.send(send_rate);
+    }
+
+    /// Seed for randomness of cosmetic effects, so that they look the same to every player on the
+    /// server and across reconnects.
+    pub fn get_seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// Player size and speed to move and clamp the local player with, the same as on the server
+    pub fn get_world_params(&self) -> WorldParams {
+        self.world_params
+    }
+
+    /// Next message received from the server. Snapshots are acknowledged and followed by a
+    /// `Replicate` of every player who changed since the previous snapshot, the same as crowded
+    /// snapshots sent that way. Distant players held back by the server don't change.
+    pub fn receive_server_response(&mut self) -> Result<Vec<u8>, TryRecvError> {
+        if let Some(replicate) = self.replicated.pop_front() {
+            return Ok(replicate);
+        }
         match self.listen_rx.try_recv() {
             Ok(response) => {
-                // Update last ping
-                if let Ok(Message::Ping) = Message::deserialize(&response) {
-                    self.last_ping = std::time::Instant::now();
+                // Every message shows that the server is alive, pings only come when there is
+                // nothing else to send
+                self.last_heard = std::time::Instant::now();
+                match Message::deserialize(&response) {
+                    // Answer pings for the server's round-trip time measurement
+                    Ok(Message::Ping(time)) => {
+                        let _ = self.send_tx.send(Message::Pong(self.token, time));
+                    }
+                    // Confirm that the datagram got through for the server's MTU discovery
+                    Ok(Message::Probe(size)) => {
+                        let _ = self.send_tx.send(Message::ProbeAck(self.token, size));
+                    }
+                    Ok(Message::Snapshot(encoded)) => match self.snapshots.decode(&encoded) {
+                        Ok((tick, world)) => {
+                            let _ = self.send_tx.send(Message::SnapshotAck(self.token, tick));
+                            self.replicated.extend(
+                                world
+                                    .iter()
+                                    .filter(|(id, state)| {
+                                        self.snapshot_world.get(id) != Some(state)
+                                    })
+                                    .map(|(id, state)| {
+                                        Message::Replicate(tick, state.to_player(*id)).serialize()
+                                    }),
+                            );
+                            self.snapshot_world = world;
+                        }
+                        // Left unacknowledged, so the server falls back to a full snapshot
+                        Err(e) => message::trace(format!("Ignored invalid snapshot: {e}")),
+                    },
+                    _ => (),
                 }
                 Ok(response)
             }
@@ -91,27 +366,120 @@ impl ClientSession {
         }
     }
 
+    /// Everything that happened since the previous call without blocking: messages received from
+    /// the server in order of arrival, followed by the connection state if it changed. Messages
+    /// which can't be parsed are skipped.
+    pub fn try_events(&mut self) -> Vec<ClientEvent> {
+        let mut events = Vec::new();
+        while let Ok(msg) = self.receive_server_response() {
+            match Message::deserialize(&msg) {
+                Ok(msg) => {
+                    message::trace(format!("Received: {msg:?}"));
+                    events.push(ClientEvent::Message(msg));
+                }
+                Err(e) => message::trace(format!("Ignored invalid message: {e}")),
+            }
+        }
+        if let Some(state) = self.update_connection_state() {
+            events.push(ClientEvent::ConnectionStateChanged(state));
+        }
+        events
+    }
+
+    /// Whether a snapshot of a remote player taken at the given server tick is newer than the
+    /// last one applied. Datagrams can arrive out of order, and applying an older snapshot would
+    /// make the player jump back.
+    pub fn accept_snapshot(&mut self, player_id: PlayerID, tick: Tick) -> bool {
+        let last_tick = self.snapshot_ticks.entry(player_id).or_default();
+        if tick <= *last_tick {
+            return false;
+        }
+        *last_tick = tick;
+        true
+    }
+
+    /// Forget about a player who left the server.
+    pub fn forget_player(&mut self, player_id: PlayerID) {
+        self.snapshot_ticks.remove(&player_id);
+    }
+
+    /// Queue the position to be sent. Positions are sent at the send rate instead of every
+    /// logic update, and only the latest one is sent if several were queued in between. Positions
+    /// barely different from the last sent one are skipped, unless the player stops there.
     pub fn send_pos(&self, player: &Player) {
-        // TODO: Avoid position self-reporting
+        // TODO: Avoid position self-reporting. Absolute positions don't need to be resent on loss,
+        // because the next one supersedes them. Input commands would, so once movement is
+        // input-based, send the last few unacknowledged inputs in every packet.
+        self.position_tx.send_replace(Some(player.pos));
+    }
+
+    pub fn send_chat(&self, text: String) {
+        let _ = self.send_tx.send(Message::Say(self.token, text));
+    }
+
+    /// Ask the server to change the display name and color of the player. The server confirms
+    /// with a `Profile` of the local player, possibly with a disambiguated name.
+    pub fn update_profile(&self, color: Vector3<f32>, name: String) {
         let _ = self
             .send_tx
-            .send(Message::Position(player.id, player.pos).serialize());
+            .send(Message::UpdateProfile(self.token, color, name));
     }
 
-    pub fn is_server_alive(&self) -> bool {
+    pub fn request_map_chunk(&self, checksum: u32, offset: u32) {
+        let _ = self
+            .send_tx
+            .send(Message::MapRequest(self.token, checksum, offset));
+    }
+
+    pub fn connection_state(&self) -> ConnectionState {
+        self.connection_state
+    }
+
+    /// Follow the connection state based on how long the server has been silent. Returns the
+    /// new state if it changed since the last call. Lost connections don't come back.
+    pub fn update_connection_state(&mut self) -> Option<ConnectionState> {
         // There's no need for separate timeout countdown timer
-        self.last_ping.elapsed() < globals::CONNECTION_TIMEOUT_SEC
+        let silence = self.last_heard.elapsed();
+        let state = match self.connection_state {
+            ConnectionState::Lost => ConnectionState::Lost,
+            _ if silence >= globals::CONNECTION_TIMEOUT_SEC => ConnectionState::Lost,
+            _ if silence >= DEGRADED_AFTER => ConnectionState::Degraded,
+            _ => ConnectionState::Connected,
+        };
+        if state == self.connection_state {
+            return None;
+        }
+        message::trace(format!(
+            "Connection state changed from {} to {state}",
+            self.connection_state
+        ));
+        self.connection_state = state;
+        Some(state)
     }
 
-    pub fn leave_server(&self, player_id: PlayerID) {
-        let _ = self.send_tx.send(Message::Leave(player_id).serialize());
+    /// Tell the server that the player leaves. DISCONNECT is resent in the background after the
+    /// session is dropped until the server acknowledges it, see `leave_and_wait()` for waiting
+    /// on that.
+    pub fn leave_server(&self) {
+        let _ = self.send_tx.send(Message::Disconnect(self.token));
+    }
+
+    /// Leave the server and wait until it acknowledged leaving, or at most `LEAVE_LINGER`. For
+    /// leaving right before the runtime shuts down, which would cut resending short.
+    pub async fn leave_and_wait(mut self) {
+        self.leave_server();
+        let send_task = self.send_task.take();
+        drop(self);
+        if let Some(send_task) = send_task {
+            let _ = send_task.await;
+        }
     }
 }
 
 impl Drop for ClientSession {
     fn drop(&mut self) {
-        self.listen_task.abort();
-        self.send_task.abort();
+        // Tasks are not aborted, the send task flushes queued messages (like DISCONNECT) once the
+        // sender channel is dropped, waits for them to be acknowledged and then stops the others.
         self.listen_rx.close();
     }
 }
@@ -120,25 +488,46 @@ impl Drop for ClientSession {
 async fn join_server(
     client_socket: &UdpSocket,
     server_address: &String,
-) -> Result<Player, Box<dyn Error + Send + Sync>> {
-    let handshake_msg = Message::Handshake.serialize();
+    player_name: String,
+    previous_token: Option<SessionToken>,
+    queue_tx: &watch::Sender<Option<u32>>,
+) -> Result<(Player, SessionToken, u64, String, WorldParams), Box<dyn Error + Send + Sync>> {
+    let handshake_msg = Message::Handshake(player_name, previous_token);
+    let handshake_datagram = DatagramHeader::default().encode(&handshake_msg.serialize());
     // Loop abort happens on timeout in ClientSession::new()
     loop {
         // Send handshake
         client_socket
-            .send_to(handshake_msg.as_bytes(), server_address)
+            .send_to(&handshake_datagram, server_address)
             .await?;
-        message::trace(format!("Sent: {handshake_msg}"));
+        message::trace(format!("Sent: {handshake_msg:?}"));
 
         // Wait for ACK
         match receive_with_retry_timeout(client_socket).await {
             Ok(response) => {
-                if let Ok(Message::Ack(new_id, new_color)) = Message::deserialize(&response) {
-                    message::trace(format!("Handshake result: {response}"));
-                    return Ok(Player::new(new_id, new_color));
+                let response = Message::deserialize(&response);
+                message::trace(format!("Handshake response: {response:?}"));
+                match response {
+                    Ok(Message::Ack(new_id, token, new_color, seed, server_name, world_params)) => {
+                        return Ok((
+                            Player::new(new_id, new_color),
+                            token,
+                            seed,
+                            server_name,
+                            world_params,
+                        ));
+                    }
+                    // No point in retrying, server will refuse again
+                    Ok(Message::Reject(reason)) => {
+                        return Err(format!("Server refused to join: {reason}").into());
+                    }
+                    // Server is full, ask again in a while to learn the new place or to join
+                    Ok(Message::Queued(position)) => {
+                        queue_tx.send_replace(Some(position));
+                        tokio::time::sleep(queue::QUEUE_POLL_INTERVAL).await;
+                    }
+                    _ => {}
                 }
-
-                message::trace(format!("Invalid handshake response: {response}"));
             }
             _ => continue, // Keep trying, I know you can do it!
         }
@@ -147,14 +536,15 @@ async fn join_server(
 
 async fn receive_with_retry_timeout(
     socket: &UdpSocket,
-) -> Result<String, Box<dyn Error + Send + Sync>> {
+) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
     let retry_timeout = std::time::Duration::from_millis(300);
-    let mut buf = [0u8; 32];
+    let mut buf = [0u8; globals::MAX_DATAGRAM_SIZE];
     // TODO: Consider non-blocking UDP I/O
     match tokio::time::timeout(retry_timeout, socket.recv_from(&mut buf)).await {
         Ok(result) => {
             let (len, _) = result?;
-            Ok(String::from_utf8_lossy(&buf[..len]).to_string())
+            let (_, msg) = DatagramHeader::decode(&buf[..len])?;
+            Ok(msg.to_vec())
         }
         Err(_) => {
             message::trace("No response (sender or receiver package lost)".to_string());
@@ -163,29 +553,194 @@ async fn receive_with_retry_timeout(
     }
 }
 
-async fn listen_handler(socket: Arc<UdpSocket>, listen_tx: ChannelSender) {
-    let mut buf = [0u8; 1024];
+async fn listen_handler(
+    socket: Arc<UdpSocket>,
+    channels: Arc<Mutex<Channels>>,
+    listen_tx: ChannelSender,
+) {
+    let mut batch = RecvBatch::new();
+    while batch.recv(&socket).await.is_ok() {
+        let mut acks = Vec::new();
+        for (datagram, server) in batch.datagrams() {
+            let Ok((header, msg)) = DatagramHeader::decode(datagram) else {
+                continue;
+            };
+            let delivery = channels.lock().unwrap().receive(header, msg);
+            if let Some(ack) = delivery.ack {
+                acks.push((ack, server));
+            }
+            for msg in delivery.messages {
+                // Pass message to main thread. Once the session is dropped, only the
+                // acknowledgements of the last messages matter.
+                let _ = listen_tx.send(msg);
+            }
+        }
+        for (ack, server) in acks {
+            let _ = socket.send_to(&ack, server).await;
+        }
+    }
+}
+
+/// Resend reliable messages the server hasn't acknowledged in time. Stops once the server has
+/// been silent for so long that the connection is lost anyway.
+async fn resend_handler(
+    socket: Arc<UdpSocket>,
+    server_address: String,
+    channels: Arc<Mutex<Channels>>,
+) {
+    let mut interval = tokio::time::interval(net::RESEND_INTERVAL / 2);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
     loop {
-        // TODO: Consider non-blocking UDP I/O
-        match socket.recv_from(&mut buf).await {
-            Ok((len, _)) => {
-                if let Ok(msg) = std::str::from_utf8(&buf[..len]) {
-                    // Pass message to main thread
-                    if listen_tx.send(msg.to_string()).is_err() {
-                        break;
-                    }
+        interval.tick().await;
+        let Some(resends) = channels
+            .lock()
+            .unwrap()
+            .resend_due(std::time::Instant::now())
+        else {
+            message::trace("Server stopped acknowledging messages, no more resends".to_string());
+            return;
+        };
+        for datagram in resends {
+            let _ = socket.send_to(&datagram, &server_address).await;
+        }
+    }
+}
+
+/// Messages waiting to be sent, in order of priority. Messages sent only once, like chat and
+/// leaving the server, are never dropped. The newest pong and snapshot acknowledgement supersede
+/// older ones, and map chunk requests are repeated anyway. Positions don't go through here, they
+/// are coalesced by `position_sender`.
+#[derive(Default)]
+struct OutgoingQueue {
+    reliable: VecDeque<Message>,
+    latest_pong: Option<Message>,
+    latest_snapshot_ack: Option<Message>,
+    map_requests: VecDeque<Message>,
+}
+
+impl OutgoingQueue {
+    fn push(&mut self, msg: Message) {
+        match msg {
+            Message::Pong(..) => self.latest_pong = Some(msg),
+            Message::SnapshotAck(..) => self.latest_snapshot_ack = Some(msg),
+            Message::MapRequest(..) => {
+                if self.map_requests.len() >= MAX_QUEUED_MAP_REQUESTS {
+                    self.map_requests.pop_front();
                 }
+                self.map_requests.push_back(msg);
             }
-            Err(_) => {
-                break;
-            }
+            _ => self.reliable.push_back(msg),
+        }
+    }
+
+    fn pop(&mut self) -> Option<Message> {
+        self.reliable
+            .pop_front()
+            .or_else(|| self.latest_pong.take())
+            .or_else(|| self.latest_snapshot_ack.take())
+            .or_else(|| self.map_requests.pop_front())
+    }
+}
+
+async fn send_handler(
+    socket: Arc<UdpSocket>,
+    server_address: String,
+    channels: Arc<Mutex<Channels>>,
+    mut rx: OutgoingReceiver,
+    background_tasks: [JoinHandle<()>; 2],
+) {
+    let mut queue = OutgoingQueue::default();
+    loop {
+        // Everything that piled up while sending is queued at once, so that superseded messages
+        // can be dropped
+        while let Ok(msg) = rx.try_recv() {
+            queue.push(msg);
         }
+        let msg = match queue.pop() {
+            Some(msg) => msg,
+            // Queued messages are flushed before finishing once the session is dropped
+            None => match rx.recv().await {
+                Some(msg) => msg,
+                None => break,
+            },
+        };
+        let datagram = channels.lock().unwrap().send(
+            msg.channel(),
+            &msg.serialize(),
+            std::time::Instant::now(),
+        );
+        let _ = socket.send_to(&datagram, &server_address).await;
+        message::trace(format!("Sent: {msg:?}"));
+    }
+
+    // Resend handler keeps resending unacknowledged messages until the listen handler receives
+    // their acknowledgements
+    let deadline = tokio::time::Instant::now() + LEAVE_LINGER;
+    while !channels.lock().unwrap().all_acknowledged() && tokio::time::Instant::now() < deadline {
+        tokio::time::sleep(net::RESEND_INTERVAL / 2).await;
+    }
+    for task in background_tasks {
+        task.abort();
     }
 }
 
-async fn send_handler(socket: Arc<UdpSocket>, server_address: String, mut rx: ChannelReceiver) {
-    while let Some(msg) = rx.recv().await {
-        let _ = socket.send_to(&msg.as_bytes(), &server_address).await;
-        message::trace(format!("Sent: {msg}"));
+/// Send the latest queued position at most `send_rate` times per second. Nothing is sent while
+/// the position doesn't change meaningfully, except for the final position once the player stops,
+/// so that everyone sees where the player really stopped.
+async fn position_sender(
+    socket: Arc<UdpSocket>,
+    server_address: String,
+    channels: Arc<Mutex<Channels>>,
+    token: SessionToken,
+    mut position_rx: watch::Receiver<Option<Vector2<f32>>>,
+    send_rate_rx: watch::Receiver<u32>,
+) {
+    let mut last_sent_pos: Option<Vector2<f32>> = None;
+    // Position skipped for being too close to the last sent one
+    let mut unsent_pos: Option<Vector2<f32>> = None;
+    loop {
+        let send_interval = std::time::Duration::from_secs_f32(1.0 / *send_rate_rx.borrow() as f32);
+        let pos = match unsent_pos {
+            // Player stopped if nothing changed for a send interval
+            Some(unsent) => {
+                match tokio::time::timeout(send_interval, position_rx.changed()).await {
+                    Ok(Ok(())) => *position_rx.borrow_and_update(),
+                    Ok(Err(_)) => break,
+                    Err(_) => Some(unsent),
+                }
+            }
+            // Session is gone once the sender is dropped
+            None => match position_rx.changed().await {
+                Ok(()) => *position_rx.borrow_and_update(),
+                Err(_) => break,
+            },
+        };
+        let Some(pos) = pos else {
+            continue;
+        };
+        // Compared to the last sent position, so that slow movement still adds up. The same
+        // position twice in a row means that the player stopped too.
+        if last_sent_pos
+            .is_some_and(|last_sent_pos| (pos - last_sent_pos).magnitude() < MIN_POSITION_CHANGE)
+            && unsent_pos != Some(pos)
+        {
+            unsent_pos = Some(pos);
+            continue;
+        }
+        unsent_pos = None;
+        if last_sent_pos == Some(pos) {
+            continue;
+        }
+        let msg = Message::Position(token, pos);
+        let datagram = channels.lock().unwrap().send(
+            msg.channel(),
+            &msg.serialize(),
+            std::time::Instant::now(),
+        );
+        let _ = socket.send_to(&datagram, &server_address).await;
+        message::trace(format!("Sent: {msg:?}"));
+        last_sent_pos = Some(pos);
+        // Positions queued meanwhile are coalesced into the latest one
+        tokio::time::sleep(send_interval).await;
     }
 }
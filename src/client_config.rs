@@ -0,0 +1,65 @@
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+/// Client settings remembered between runs, in the working directory like downloaded maps
+const CLIENT_CONFIG_PATH: &str = "client.toml";
+
+/// Settings of the graphical client, saved on exit and restored on startup.
+///
+/// ```toml
+/// [window]
+/// monitor = "DP-1"
+/// x = 120
+/// y = 80
+/// width = 800
+/// height = 600
+/// fullscreen = false
+/// ```
+#[derive(Default, Deserialize, Serialize)]
+pub struct ClientConfig {
+    pub window: Option<WindowGeometry>,
+}
+
+/// Placement of the window when the application was closed
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct WindowGeometry {
+    /// Name of the monitor the window was on, if the system names monitors
+    pub monitor: Option<String>,
+    /// Position of the outer top-left corner of the window relative to the top-left corner of its
+    /// monitor in physical pixels
+    pub x: i32,
+    pub y: i32,
+    /// Inner size in physical pixels, kept from before going fullscreen
+    pub width: u32,
+    pub height: u32,
+    pub fullscreen: bool,
+}
+
+impl ClientConfig {
+    /// Missing file is the same as an empty one. An invalid file is reported and ignored, it gets
+    /// overwritten on exit.
+    pub fn load() -> Self {
+        Self::load_from(Path::new(CLIENT_CONFIG_PATH))
+    }
+
+    fn load_from(path: &Path) -> Self {
+        let contents = match std::fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Self::default(),
+            Err(e) => {
+                eprintln!("Failed to read {}: {e}", path.display());
+                return Self::default();
+            }
+        };
+        toml::from_str(&contents).unwrap_or_else(|e| {
+            eprintln!("Ignoring invalid {}: {e}", path.display());
+            Self::default()
+        })
+    }
+
+    pub fn save(&self) -> Result<(), String> {
+        let contents = toml::to_string(self).map_err(|e| e.to_string())?;
+        std::fs::write(CLIENT_CONFIG_PATH, contents).map_err(|e| e.to_string())
+    }
+}
@@ -0,0 +1,189 @@
+use std::{
+    fmt::Write,
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+
+use crate::{client::ConnectionState, message::ConnectionStats, task, PlayerID};
+
+/// Most bytes of a scrape request read before answering, enough for the request line and the
+/// headers sent by Prometheus
+const MAX_REQUEST_SIZE: usize = 4096;
+/// Scrapers sending their request slower than this are hung up on
+const REQUEST_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// Connection metrics of a headless client, scraped by automated test rigs running many bot
+/// clients. Every metric is labeled with the player and the server, so that the series of a bot
+/// can be matched with the server side of its connection.
+pub struct ClientMetrics {
+    player_id: PlayerID,
+    player_name: String,
+    server_address: String,
+    connected_at: Instant,
+    /// Latest connection quality reported by the server, None until the first report
+    stats: Option<ConnectionStats>,
+    connection_state: ConnectionState,
+    messages_received: u64,
+    remote_players: usize,
+}
+
+impl ClientMetrics {
+    pub fn new(player_id: PlayerID, player_name: String, server_address: String) -> Self {
+        Self {
+            player_id,
+            player_name,
+            server_address,
+            connected_at: Instant::now(),
+            stats: None,
+            connection_state: ConnectionState::Connected,
+            messages_received: 0,
+            remote_players: 0,
+        }
+    }
+
+    pub fn record_stats(&mut self, stats: ConnectionStats) {
+        self.stats = Some(stats);
+    }
+
+    pub fn record_connection_state(&mut self, connection_state: ConnectionState) {
+        self.connection_state = connection_state;
+    }
+
+    pub fn record_message(&mut self) {
+        self.messages_received += 1;
+    }
+
+    pub fn record_remote_players(&mut self, remote_players: usize) {
+        self.remote_players = remote_players;
+    }
+
+    /// Metrics in the Prometheus text exposition format
+    pub fn render(&self) -> String {
+        let labels = format!(
+            "player_id=\"{}\",player_name=\"{}\",server=\"{}\"",
+            self.player_id,
+            escape_label_value(&self.player_name),
+            escape_label_value(&self.server_address)
+        );
+        let mut out = String::new();
+        let mut metric = |name: &str, kind: &str, help: &str, value: f64| {
+            let _ = writeln!(out, "# HELP {name} {help}");
+            let _ = writeln!(out, "# TYPE {name} {kind}");
+            let _ = writeln!(out, "{name}{{{labels}}} {value}");
+        };
+        metric(
+            "game_client_connected",
+            "gauge",
+            "Whether the server was heard from recently, 0 while the connection is unstable",
+            (self.connection_state == ConnectionState::Connected) as u8 as f64,
+        );
+        metric(
+            "game_client_session_seconds",
+            "gauge",
+            "Time since joining the server",
+            self.connected_at.elapsed().as_secs_f64(),
+        );
+        metric(
+            "game_client_messages_received_total",
+            "counter",
+            "Messages received from the server",
+            self.messages_received as f64,
+        );
+        metric(
+            "game_client_remote_players",
+            "gauge",
+            "Other players replicated to the client",
+            self.remote_players as f64,
+        );
+        // Left out until the server reports them, rather than claiming a perfect connection
+        if let Some(stats) = self.stats {
+            metric(
+                "game_client_rtt_milliseconds",
+                "gauge",
+                "Smoothed round-trip time measured by the server",
+                stats.rtt_ms as f64,
+            );
+            metric(
+                "game_client_packet_loss_ratio",
+                "gauge",
+                "Smoothed share of pings left unanswered, measured by the server",
+                stats.packet_loss_percent as f64 / 100.0,
+            );
+            metric(
+                "game_client_corrections_total",
+                "counter",
+                "Reported positions the server had to correct",
+                stats.corrections as f64,
+            );
+        }
+        out
+    }
+}
+
+/// Backslash, double quote and line feed are escaped in label values
+pub fn escape_label_value(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '"' => escaped.push_str("\\\""),
+            '\n' => escaped.push_str("\\n"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+pub async fn bind(port: u16) -> std::io::Result<TcpListener> {
+    TcpListener::bind(("0.0.0.0", port)).await
+}
+
+/// Answer HTTP requests of scrapers on `GET /metrics` with the current metrics. Only the request
+/// line is looked at, scrapes are short-lived requests without a body.
+pub async fn serve(listener: TcpListener, metrics: Arc<Mutex<ClientMetrics>>) {
+    loop {
+        let Ok((stream, _)) = listener.accept().await else {
+            continue;
+        };
+        task::spawn_named("client_metrics_scrape", answer(stream, metrics.clone()));
+    }
+}
+
+async fn answer(mut stream: TcpStream, metrics: Arc<Mutex<ClientMetrics>>) {
+    let Ok(Some(request)) = tokio::time::timeout(REQUEST_TIMEOUT, read_request(&mut stream)).await
+    else {
+        return;
+    };
+    let request_line = String::from_utf8_lossy(&request);
+    let response = if request_line.starts_with("GET /metrics ") {
+        let body = metrics.lock().unwrap().render();
+        format!(
+            "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+            body.len()
+        )
+    } else {
+        "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string()
+    };
+    let _ = stream.write_all(response.as_bytes()).await;
+}
+
+/// Request line and headers, None if the connection closed or the request is too large
+async fn read_request(stream: &mut TcpStream) -> Option<Vec<u8>> {
+    let mut request = Vec::new();
+    let mut buf = [0u8; 512];
+    while !request.windows(4).any(|window| window == b"\r\n\r\n") {
+        match stream.read(&mut buf).await {
+            Ok(0) | Err(_) => return None,
+            Ok(len) => request.extend_from_slice(&buf[..len]),
+        }
+        if request.len() > MAX_REQUEST_SIZE {
+            return None;
+        }
+    }
+    Some(request)
+}
@@ -0,0 +1,161 @@
+use std::{
+    collections::VecDeque,
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use axum::{
+    extract::State,
+    http::{header, HeaderMap, StatusCode},
+    response::Html,
+    routing::{get, post},
+    Json, Router,
+};
+use serde::Serialize;
+use tokio::sync::broadcast::{self, error::RecvError};
+
+use crate::{
+    observer::ObserverEvent,
+    server::{ServerHandle, ServerStatus},
+    PlayerID,
+};
+
+/// Chat messages kept for operators opening the dashboard
+const CHAT_HISTORY: usize = 50;
+
+const PAGE: &str = include_str!("dashboard.html");
+
+/// Secret the dashboard asks operators for, read from a file so it doesn't show up in the
+/// process list
+pub fn read_token(path: &Path) -> Result<String, std::io::Error> {
+    let token = std::fs::read_to_string(path)?.trim().to_string();
+    if token.is_empty() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            "token is empty",
+        ));
+    }
+    Ok(token)
+}
+
+#[derive(Clone, Serialize)]
+struct ChatLine {
+    player_id: PlayerID,
+    name: String,
+    text: String,
+}
+
+#[derive(Serialize)]
+struct DashboardState {
+    status: ServerStatus,
+    chat: Vec<ChatLine>,
+}
+
+/// Web UI for server operators, served on the status endpoint. The page itself is public, the
+/// API behind it needs the admin token in the `Authorization: Bearer` header.
+///
+/// - `GET /admin`: the dashboard page, polling the other endpoints
+/// - `GET /admin/state`: server status with players and message of the day, and recent chat
+/// - `POST /admin/command`: execute an admin console command given as the plain text body,
+///   answered with its response
+///
+/// The tick time graph is drawn from the public `/metrics`.
+#[derive(Clone)]
+pub struct Dashboard {
+    server_handle: ServerHandle,
+    token: Arc<str>,
+    chat: Arc<Mutex<VecDeque<ChatLine>>>,
+}
+
+impl Dashboard {
+    pub fn new(server_handle: ServerHandle, token: String) -> Self {
+        Self {
+            server_handle,
+            token: token.into(),
+            chat: Arc::new(Mutex::new(VecDeque::with_capacity(CHAT_HISTORY))),
+        }
+    }
+
+    pub fn router(self) -> Router {
+        Router::new()
+            .route("/admin", get(page))
+            .route("/admin/state", get(state))
+            .route("/admin/command", post(command))
+            .with_state(self)
+    }
+
+    /// Keep the latest chat messages of the game events until the server stops
+    pub async fn collect_chat(self, mut events: broadcast::Receiver<ObserverEvent>) {
+        loop {
+            match events.recv().await {
+                Ok(ObserverEvent::Chat {
+                    player_id,
+                    name,
+                    text,
+                }) => {
+                    let mut chat = self.chat.lock().unwrap();
+                    if chat.len() == CHAT_HISTORY {
+                        chat.pop_front();
+                    }
+                    chat.push_back(ChatLine {
+                        player_id,
+                        name,
+                        text,
+                    });
+                }
+                Ok(_) | Err(RecvError::Lagged(_)) => (),
+                Err(RecvError::Closed) => return,
+            }
+        }
+    }
+
+    fn authorize(&self, headers: &HeaderMap) -> Result<(), StatusCode> {
+        let token = headers
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .ok_or(StatusCode::UNAUTHORIZED)?;
+        if constant_time_eq(token.as_bytes(), self.token.as_bytes()) {
+            Ok(())
+        } else {
+            Err(StatusCode::UNAUTHORIZED)
+        }
+    }
+}
+
+async fn page() -> Html<&'static str> {
+    Html(PAGE)
+}
+
+async fn state(
+    State(dashboard): State<Dashboard>,
+    headers: HeaderMap,
+) -> Result<Json<DashboardState>, StatusCode> {
+    dashboard.authorize(&headers)?;
+    let status = dashboard
+        .server_handle
+        .status()
+        .await
+        .ok_or(StatusCode::SERVICE_UNAVAILABLE)?;
+    let chat = dashboard.chat.lock().unwrap().iter().cloned().collect();
+    Ok(Json(DashboardState { status, chat }))
+}
+
+async fn command(
+    State(dashboard): State<Dashboard>,
+    headers: HeaderMap,
+    line: String,
+) -> Result<String, StatusCode> {
+    dashboard.authorize(&headers)?;
+    dashboard
+        .server_handle
+        .execute_admin_command(&line)
+        .await
+        .ok_or(StatusCode::SERVICE_UNAVAILABLE)
+}
+
+/// Comparison taking the same time wherever the first difference is, so that the token can't be
+/// guessed byte by byte from response times
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
@@ -0,0 +1,147 @@
+use std::path::{Path, PathBuf};
+
+use cgmath::Vector2;
+
+use crate::{
+    globals,
+    map::{self, Map, Obstacle},
+    renderer::{self, Camera},
+};
+
+/// Obstacles and spawn points are snapped to the cells of the drawn grid
+const CELL_SIZE: f32 =
+    (globals::WORLD_BOUNDS.max_x - globals::WORLD_BOUNDS.min_x) / renderer::GRID_COL_COUNT as f32;
+
+/// Camera movement per update while panning around the map
+const PAN_SPEED: f32 = 2.0 * globals::DEFAULT_PLAYER_SPEED;
+
+const DEFAULT_MAP_PATH: &str = "maps/custom.map";
+
+/// What clicking on the grid places or removes
+#[derive(Clone, Copy, PartialEq)]
+pub enum EditorTool {
+    Obstacle,
+    SpawnPoint,
+}
+
+/// Map file being edited in the client. Saved maps can be hosted right away for playtesting.
+pub struct MapEditor {
+    pub map: Map,
+    pub tool: EditorTool,
+    /// File the map is loaded from and saved to
+    pub path: String,
+    /// Host the saved map on the next server created by this client
+    playtest: bool,
+}
+
+impl Default for MapEditor {
+    fn default() -> Self {
+        Self {
+            map: Map::default(),
+            tool: EditorTool::Obstacle,
+            path: DEFAULT_MAP_PATH.to_string(),
+            playtest: false,
+        }
+    }
+}
+
+impl MapEditor {
+    /// Place an obstacle or spawn point depending on the tool in the grid cell at the world
+    /// position, or remove the one already there.
+    pub fn toggle(&mut self, pos: Vector2<f32>) {
+        let bounds = &globals::WORLD_BOUNDS;
+        if pos.x < bounds.min_x
+            || pos.y < bounds.min_y
+            || pos.x >= bounds.max_x
+            || pos.y >= bounds.max_y
+        {
+            return;
+        }
+        let cell_min = Vector2::new(
+            bounds.min_x + ((pos.x - bounds.min_x) / CELL_SIZE).floor() * CELL_SIZE,
+            bounds.min_y + ((pos.y - bounds.min_y) / CELL_SIZE).floor() * CELL_SIZE,
+        );
+        let in_cell = |point: &Vector2<f32>| {
+            point.x >= cell_min.x
+                && point.y >= cell_min.y
+                && point.x < cell_min.x + CELL_SIZE
+                && point.y < cell_min.y + CELL_SIZE
+        };
+
+        match self.tool {
+            EditorTool::Obstacle => {
+                // Obstacles of loaded maps don't have to be aligned to the grid
+                let clicked = self.map.obstacles.iter().position(|obstacle| {
+                    pos.x >= obstacle.min.x
+                        && pos.y >= obstacle.min.y
+                        && pos.x < obstacle.min.x + obstacle.size.x
+                        && pos.y < obstacle.min.y + obstacle.size.y
+                });
+                match clicked {
+                    Some(index) => {
+                        self.map.obstacles.remove(index);
+                    }
+                    None if self.map.obstacles.len() < map::MAX_OBSTACLES => {
+                        self.map.obstacles.push(Obstacle {
+                            min: cell_min,
+                            size: Vector2::new(CELL_SIZE, CELL_SIZE),
+                        });
+                    }
+                    None => (),
+                }
+            }
+            EditorTool::SpawnPoint => match self.map.spawn_points.iter().position(in_cell) {
+                Some(index) => {
+                    self.map.spawn_points.remove(index);
+                }
+                None if self.map.spawn_points.len() < map::MAX_SPAWN_POINTS => {
+                    self.map
+                        .spawn_points
+                        .push(cell_min + Vector2::new(CELL_SIZE / 2.0, CELL_SIZE / 2.0));
+                }
+                None => (),
+            },
+        }
+    }
+
+    pub fn load(&mut self) -> Result<(), String> {
+        let text = std::fs::read_to_string(&self.path).map_err(|e| e.to_string())?;
+        self.map = Map::parse(&text)?;
+        Ok(())
+    }
+
+    /// Write the map to its file. The file name has to be a valid map name, because maps are
+    /// named after their files.
+    pub fn save(&self) -> Result<(), String> {
+        let path = Path::new(&self.path);
+        map::validate_map_name(&map::map_name(path))?;
+        let data = self.map.serialize();
+        if data.len() > map::MAX_MAP_SIZE {
+            return Err(format!(
+                "Map file can't be larger than {} bytes",
+                map::MAX_MAP_SIZE
+            ));
+        }
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+        }
+        std::fs::write(path, data).map_err(|e| e.to_string())
+    }
+
+    /// Save the map and host it on the next server created by this client
+    pub fn start_playtest(&mut self) -> Result<(), String> {
+        self.save()?;
+        self.playtest = true;
+        Ok(())
+    }
+
+    /// Map file to host if a playtest was started. Resets the request.
+    pub fn take_playtest_map(&mut self) -> Option<PathBuf> {
+        std::mem::take(&mut self.playtest).then(|| PathBuf::from(&self.path))
+    }
+}
+
+/// Move the camera around the map in the given direction, keeping the view inside the world
+pub fn pan_camera(camera: &mut Camera, direction: Vector2<f32>) {
+    camera.pan(direction * PAN_SPEED);
+}
@@ -0,0 +1,46 @@
+use serde::Deserialize;
+
+/// What happens to player names and chat messages containing a blocked word.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum WordFilterAction {
+    /// Replace every character of blocked words with `*`
+    #[default]
+    Mask,
+    /// Refuse the name or drop the message
+    Reject,
+}
+
+const MASK_CHAR: char = '*';
+
+/// Apply the word filter to a player name or chat message. Returns the text to use, or `None` if
+/// it has to be refused.
+///
+/// Words are matched case-insensitively anywhere in the text, also inside other words, so that
+/// gluing letters around them doesn't get past the filter.
+pub fn apply(text: &str, blocked_words: &[String], action: WordFilterAction) -> Option<String> {
+    let mut chars: Vec<char> = text.chars().collect();
+    let mut found = false;
+    for blocked_word in blocked_words {
+        let blocked_chars: Vec<char> = blocked_word.chars().collect();
+        if blocked_chars.is_empty() || blocked_chars.len() > chars.len() {
+            continue;
+        }
+        for start in 0..=chars.len() - blocked_chars.len() {
+            let matches = chars[start..]
+                .iter()
+                .zip(&blocked_chars)
+                .all(|(c, blocked)| c.to_lowercase().eq(blocked.to_lowercase()));
+            if matches {
+                found = true;
+                chars[start..start + blocked_chars.len()].fill(MASK_CHAR);
+            }
+        }
+    }
+
+    match (found, action) {
+        (false, _) => Some(text.to_string()),
+        (true, WordFilterAction::Mask) => Some(chars.into_iter().collect()),
+        (true, WordFilterAction::Reject) => None,
+    }
+}
@@ -1,18 +1,42 @@
+use crate::message::MatchResult;
+
 /// Parameter used for first connection establishment
 #[derive(Clone, Copy)]
 pub enum SessionMode {
-    CreateServer,   /// Peer-hosted, hybrid server-client session
+    CreateServer,
+    /// Peer-hosted, hybrid server-client session
     ConnectAsClientOnly,
 }
 
 pub enum State {
     Menu,
+    /// Bot match played behind the menu after sitting idle in it, until any input
+    Attract,
+    /// Watching a match replay recorded by a server
+    Replay,
     Connecting {
         server_address: String,
         session_mode: SessionMode,
     },
+    /// Downloading the map of the joined server, with the fraction of it received so far
+    Loading {
+        progress: f32,
+    },
     Playing,
+    /// Joining the server of a lost session again with its token, so that a quick server restart
+    /// doesn't end the session, with why the session was lost and when to give up
+    Reconnecting {
+        reason: String,
+        give_up_at: std::time::Instant,
+    },
+    /// Standings of the match that just ended, until the next one starts
+    Results {
+        standings: Vec<MatchResult>,
+        next_match_at: std::time::Instant,
+    },
     Disconnected,
+    /// Placing obstacles and spawn points of a map file
+    Editor,
     QuitDialog,
     Quit,
 }
@@ -27,6 +51,12 @@ pub struct StateMachine {
     state_stack: Vec<State>,
 }
 
+impl Default for StateMachine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl StateMachine {
     pub fn new() -> Self {
         Self {
@@ -1,26 +1,276 @@
-use std::{net::IpAddr, sync::Arc};
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    net::IpAddr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
+use cgmath::Vector3;
 use egui::{
-    Align2, Button, CentralPanel, Color32, Frame, Grid, Rounding, Shadow, TextEdit, Visuals, Window,
+    Align2, Button, CentralPanel, Color32, Frame, Grid, ProgressBar, Rounding, Shadow, TextEdit,
+    Visuals, Window,
 };
 use egui_glow::EguiGlow;
 use winit::{event::WindowEvent, event_loop::ActiveEventLoop};
 
-use crate::{fsm, globals};
+use crate::{
+    afk::PlayerActivity,
+    avatar::{Avatar, AVATAR_CELLS, AVATAR_PATTERN_SCALE, AVATAR_SHADE},
+    client,
+    editor::{EditorTool, MapEditor},
+    fsm, globals,
+    hud::{self, GameMode, HudWidget},
+    interpolation::{self, InterpolationSettings},
+    invite,
+    lan::LanServer,
+    logger::{self, LogDedup},
+    message::{self, ConnectionStats, GameEvent, MatchResult},
+    netlog,
+    stats::TickSummary,
+    storm,
+    touch::{self, VirtualJoystick},
+    Player, PlayerID,
+};
+
+/// Events disappear from the event feed after this long
+const EVENT_LIFETIME: Duration = Duration::from_secs(5);
+/// Oldest events are dropped from the feed when more happen at once
+const MAX_SHOWN_EVENTS: usize = 5;
+
+/// Connection warning is shown above these
+const HIGH_PING_MS: u32 = 200;
+const HIGH_PACKET_LOSS_PERCENT: u8 = 5;
+/// Times per second the connection warning icon blinks
+const WARNING_BLINK_RATE: f64 = 2.0;
+/// Leader highlighted in the player list
+const LEADER_COLOR: Color32 = Color32::from_rgb(200, 150, 0);
+/// Storm warning and the storm in the event feed
+const STORM_COLOR: Color32 = Color32::from_rgb(110, 40, 160);
+/// File the network log is exported to unless changed, relative to the working directory
+const DEFAULT_NETWORK_LOG_PATH: &str = "network.log";
+
+/// Player shown in the entity inspector
+pub struct InspectedEntity {
+    pub player: Player,
+    pub is_local: bool,
+    /// Time since the last snapshot of a remote player arrived
+    pub last_update: Option<Duration>,
+}
+
+/// Health of the local player while a storm is running on the server
+pub struct StormWarning {
+    pub health: u8,
+    /// Local player is outside the safe zone, losing health
+    pub outside: bool,
+}
+
+/// Server hosted by this client, shown to the host
+pub struct HostedServer {
+    pub player_count: usize,
+    pub max_players: Option<usize>,
+    pub ticks: TickSummary,
+}
+
+/// Match replay being watched, shown in the replay banner
+pub struct ReplayStatus {
+    pub map_name: String,
+    /// Time of the match played back so far
+    pub elapsed: Duration,
+    /// Player followed by the camera, with its name
+    pub followed: Option<(Player, String)>,
+}
+
+/// Netcode values tuned live in the debug panel
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct NetcodeTuning {
+    pub interpolation: InterpolationSettings,
+    /// Part of a server correction of the local player blended into its drawn position per logic
+    /// update. The position snaps right away at 1.
+    pub correction_blend: f32,
+    /// Positions sent to the server per second
+    pub send_rate: u32,
+}
+
+impl Default for NetcodeTuning {
+    fn default() -> Self {
+        Self {
+            interpolation: InterpolationSettings::default(),
+            correction_blend: 1.0,
+            send_rate: client::DEFAULT_SEND_RATE,
+        }
+    }
+}
 
 /// GUI layer for all dialog boxes and the gameplay log output window.
 pub struct Gui {
     egui_glow: EguiGlow,
+    state: GuiState,
+}
+
+/// Contents of the GUI that have to outlive the graphics context, so that log messages and menu
+/// inputs are not lost when the application is suspended and resumed.
+pub struct GuiState {
     log_messages: String,
+    /// Collapses repeated log messages, like errors while the connection is degraded
+    log_repeats: LogDedup<String>,
     server_hostname: String,
     server_port: String,
+    /// Code typed into the menu to join a friend's server
+    invite_code_input: String,
+    /// Hosted servers announce themselves to clients on the LAN
+    advertise_lan: bool,
+    /// Servers advertised on the LAN, updated as their beacons arrive
+    lan_servers: Vec<LanServer>,
     status_text: String,
     status_color: Color32,
+    chat_input: String,
+    /// Chat messages submitted since the last frame, waiting to be sent
+    outgoing_chat: Vec<String>,
+    /// Name and color edited in the Profile window, reset to the ones confirmed by the server
+    profile_name: String,
+    profile_color: [f32; 3],
+    /// Color and name applied in the Profile window since the last frame, waiting to be sent
+    outgoing_profile: Option<(Vector3<f32>, String)>,
+    /// Latest connection quality reported by the server
+    connection_stats: Option<ConnectionStats>,
+    /// Server has been silent for a while, but the connection is not given up yet
+    connection_unstable: bool,
+    /// Recent game events with the time they were received, oldest first
+    events: VecDeque<(Instant, GameEvent)>,
+    /// Statistics of the last session, shown on the Disconnected dialog
+    session_summary: Option<String>,
+    /// Maps of the running map vote with their votes, shown with match results
+    map_vote: Option<Vec<(String, u32)>>,
+    /// Outcome of the last load or save in the map editor
+    editor_status: String,
+    /// Debug window of replicated players, toggled with F3
+    inspector_open: bool,
+    inspected_entities: Vec<InspectedEntity>,
+    /// Speed of logic updates, changed with the debug slider or the `/timescale` command
+    time_scale: f32,
+    /// Debug window of netcode tuning, toggled with F4 in debug builds
+    netcode_panel_open: bool,
+    netcode_tuning: NetcodeTuning,
+    /// Window of recently traced messages, toggled with F5 or from the disconnected dialog
+    network_log_open: bool,
+    network_log_path: String,
+    /// Outcome of the last export of the network log
+    network_log_status: String,
+    /// Interpolation delay currently in use, in ticks
+    interpolation_delay: f64,
+    /// Server has paused the game
+    paused: bool,
+    /// Shown while a storm is running
+    storm_warning: Option<StormWarning>,
+    /// Player with the highest score in the match and the score, highlighted in the player list
+    leader: Option<(PlayerID, u32)>,
+    /// Player controlled on this client, named in a banner to tell instances apart
+    local_player: Player,
+    player_labels: PlayerLabels,
+    /// Whether the server considers the local player away or moved it to spectators
+    activity: PlayerActivity,
+    /// Seed of the server avatars of players are derived from
+    avatar_seed: u64,
+    /// Shown in the server panel while hosting
+    hosted_server: Option<HostedServer>,
+    /// Code of the hosted server for friends to join with, see `invite::encode()`
+    invite_code: Option<String>,
+    /// Stop Server button was clicked since the last frame
+    stop_server_requested: bool,
+    replay_status: Option<ReplayStatus>,
+    /// Decides the widgets of the HUD during gameplay
+    game_mode: GameMode,
+}
+
+impl Default for GuiState {
+    fn default() -> Self {
+        Self {
+            log_messages: String::new(),
+            log_repeats: LogDedup::new(logger::REPEAT_WINDOW),
+            server_hostname: String::from(globals::LOCALHOST),
+            server_port: globals::DEFAULT_PORT.to_string(),
+            invite_code_input: String::new(),
+            advertise_lan: true,
+            lan_servers: Vec::new(),
+            status_text: String::from("Ready."),
+            status_color: Color32::BLACK,
+            chat_input: String::new(),
+            outgoing_chat: Vec::new(),
+            profile_name: String::new(),
+            profile_color: [1.0, 1.0, 1.0],
+            outgoing_profile: None,
+            connection_stats: None,
+            connection_unstable: false,
+            events: VecDeque::new(),
+            session_summary: None,
+            map_vote: None,
+            editor_status: String::new(),
+            inspector_open: false,
+            inspected_entities: Vec::new(),
+            time_scale: 1.0,
+            netcode_panel_open: false,
+            netcode_tuning: NetcodeTuning::default(),
+            network_log_open: false,
+            network_log_path: DEFAULT_NETWORK_LOG_PATH.to_string(),
+            network_log_status: String::new(),
+            interpolation_delay: 0.0,
+            paused: false,
+            storm_warning: None,
+            leader: None,
+            local_player: Player::default(),
+            player_labels: PlayerLabels::default(),
+            activity: PlayerActivity::Active,
+            avatar_seed: 0,
+            hosted_server: None,
+            invite_code: None,
+            stop_server_requested: false,
+            replay_status: None,
+            game_mode: GameMode::Online,
+        }
+    }
+}
+
+impl GuiState {
+    /// Pre-fill server address fields of the connection menu from a host name or IP address
+    /// followed by the port.
+    pub fn set_server_address(&mut self, address: &str) {
+        if let Some((hostname, port)) = address.rsplit_once(':') {
+            self.server_hostname = hostname.to_string();
+            self.server_port = port.to_string();
+        }
+    }
+
+    pub fn set_netcode_tuning(&mut self, tuning: NetcodeTuning) {
+        self.netcode_tuning = tuning;
+    }
+
+    pub fn set_lan_servers(&mut self, lan_servers: Vec<LanServer>) {
+        self.lan_servers = lan_servers;
+    }
+
+    /// Add a message to the gameplay log window, collapsing repeats like the console log does
+    pub fn log(&mut self, msg: String) {
+        self.flush_log_repeats();
+        if self.log_repeats.admit(msg.clone(), Instant::now()) {
+            self.log_messages += &format!("{msg}\n");
+        }
+    }
+
+    fn flush_log_repeats(&mut self) {
+        for (msg, count) in self.log_repeats.expired(Instant::now()) {
+            self.log_messages += &format!("{}\n", logger::repeated(&msg, count));
+        }
+    }
+
+    pub fn set_error_status(&mut self, msg: String) {
+        self.status_color = Color32::RED;
+        self.status_text = msg;
+    }
 }
 
 impl Gui {
-    pub fn new(event_loop: &ActiveEventLoop, gl: Arc<glow::Context>) -> Self {
-        let egui_glow = EguiGlow::new(&event_loop, gl, None, None, true);
+    pub fn new(event_loop: &ActiveEventLoop, gl: Arc<glow::Context>, state: GuiState) -> Self {
+        let egui_glow = EguiGlow::new(event_loop, gl, None, None, true);
 
         egui_glow.egui_ctx.style_mut(|style| {
             style.visuals = Visuals::light();
@@ -28,80 +278,400 @@ impl Gui {
             style.visuals.window_rounding = Rounding::ZERO;
         });
 
-        Self {
-            egui_glow,
-            log_messages: String::new(),
-            server_hostname: String::from(globals::LOCALHOST),
-            server_port: globals::DEFAULT_PORT.to_string(),
-            status_text: String::from("Ready."),
-            status_color: Color32::BLACK,
-        }
+        Self { egui_glow, state }
+    }
+
+    /// Release GPU resources of the GUI while the graphics context is still current and keep
+    /// the rest for when the GUI is re-created.
+    pub fn into_state(mut self) -> GuiState {
+        self.egui_glow.destroy();
+        self.state
     }
 
     /// Forward native window events like input to egui.
     pub fn handle_events(&mut self, window: &winit::window::Window, event: &WindowEvent) {
-        let _ = self.egui_glow.on_window_event(&window, &event);
+        let _ = self.egui_glow.on_window_event(window, event);
+    }
+
+    /// True while a text field has focus, so key presses should not control the game.
+    pub fn wants_keyboard_input(&self) -> bool {
+        self.egui_glow.egui_ctx.wants_keyboard_input()
+    }
+
+    /// True while the mouse is over a window, so clicks should not reach the game.
+    pub fn wants_pointer_input(&self) -> bool {
+        self.egui_glow.egui_ctx.wants_pointer_input()
     }
 
-    /// Execute UI code and populate batch before draw call
+    /// Execute UI code and populate batch before draw call. Remote players can be muted in the
+    /// player list.
     pub fn prepare_frame(
         &mut self,
         window: &winit::window::Window,
         state_machine: &mut fsm::StateMachine,
+        joystick: &VirtualJoystick,
+        remote_players: &HashMap<PlayerID, Player>,
+        muted_players: &mut HashSet<PlayerID>,
+        editor: &mut MapEditor,
     ) {
-        self.egui_glow
-            .run(&window, |ctx| match state_machine.peek() {
+        self.state.flush_log_repeats();
+        let state = &mut self.state;
+        self.egui_glow.run(window, |ctx| {
+            for &widget in hud::widgets(state_machine.peek(), state.game_mode) {
+                show_hud_widget(
+                    ctx,
+                    widget,
+                    state,
+                    state_machine,
+                    joystick,
+                    remote_players,
+                    muted_players,
+                );
+            }
+            // Available in every state, problems are looked into after they happened
+            if state.network_log_open {
+                show_network_log(
+                    ctx,
+                    &mut state.network_log_open,
+                    &mut state.network_log_path,
+                    &mut state.network_log_status,
+                );
+            }
+            match state_machine.peek() {
                 // Starter connection menu
-                Some(fsm::State::Menu) | Some(fsm::State::Connecting { .. }) => show_menu(
+                Some(fsm::State::Menu)
+                | Some(fsm::State::Attract)
+                | Some(fsm::State::Connecting { .. }) => show_menu(ctx, state_machine, state),
+                Some(fsm::State::Loading { progress }) => show_loading(ctx, *progress),
+                Some(fsm::State::Reconnecting { reason, give_up_at }) => {
+                    show_reconnecting(ctx, reason, *give_up_at)
+                }
+                Some(fsm::State::Editor) => show_editor(
                     ctx,
                     state_machine,
-                    &mut self.server_hostname,
-                    &mut self.server_port,
-                    &mut self.status_text,
-                    &mut self.status_color,
+                    editor,
+                    &state.server_port,
+                    &mut state.editor_status,
                 ),
-                // Gameplay state
-                Some(fsm::State::Playing) => show_log(ctx, &self.log_messages),
-                // Disconnect dialog
-                Some(fsm::State::Disconnected) => show_disconnected_dialog(
+                // Standings between matches
+                Some(fsm::State::Results {
+                    standings,
+                    next_match_at,
+                }) => show_results(
                     ctx,
-                    state_machine,
-                    &mut self.log_messages,
-                    &mut self.status_text,
-                    &mut self.status_color,
+                    standings,
+                    *next_match_at,
+                    state.map_vote.as_deref(),
+                    &mut state.outgoing_chat,
                 ),
+                // Disconnect dialog
+                Some(fsm::State::Disconnected) => {
+                    show_disconnected_dialog(ctx, state_machine, state)
+                }
                 // Quit confirm dialog
                 Some(fsm::State::QuitDialog) => show_quit_dialog(ctx, state_machine),
                 _ => {}
-            });
+            }
+        });
     }
 
     /// Issue batched draw call
     pub fn draw(&mut self, window: &winit::window::Window) {
-        self.egui_glow.paint(&window);
+        self.egui_glow.paint(window);
     }
 
-    /// Redirect message to gameplay log window
+    /// Redirect message to gameplay log window, collapsing repeats like the console log does
     pub fn log(&mut self, msg: String) {
-        self.log_messages += &format!("{msg}\n");
+        self.state.log(msg);
+    }
+
+    /// Chat messages typed in by the player since the previous call
+    pub fn take_outgoing_chat(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.state.outgoing_chat)
+    }
+
+    /// Fill the Profile window with the color and name the server knows the local player by
+    pub fn set_profile(&mut self, color: Vector3<f32>, name: String) {
+        self.state.profile_color = color.into();
+        self.state.profile_name = name;
+    }
+
+    /// Color and name applied in the Profile window since the previous call
+    pub fn take_outgoing_profile(&mut self) -> Option<(Vector3<f32>, String)> {
+        self.state.outgoing_profile.take()
+    }
+
+    /// Show a game event in the event feed for a few seconds
+    pub fn push_event(&mut self, event: GameEvent) {
+        let events = &mut self.state.events;
+        events.push_back((Instant::now(), event));
+        while events.len() > MAX_SHOWN_EVENTS {
+            events.pop_front();
+        }
+    }
+
+    pub fn clear_events(&mut self) {
+        self.state.events.clear();
+    }
+
+    /// Statistics of the session that just ended, shown on the Disconnected dialog
+    pub fn set_session_summary(&mut self, summary: String) {
+        self.state.session_summary = Some(summary);
+    }
+
+    /// Map vote shown with match results, hidden if `None`
+    pub fn set_map_vote(&mut self, candidates: Option<Vec<(String, u32)>>) {
+        self.state.map_vote = candidates;
+    }
+
+    /// Warning shown during gameplay while the server is silent
+    pub fn set_connection_unstable(&mut self, unstable: bool) {
+        self.state.connection_unstable = unstable;
+    }
+
+    /// Connection quality shown during gameplay, hidden if `None`
+    pub fn set_connection_stats(&mut self, stats: Option<ConnectionStats>) {
+        self.state.connection_stats = stats;
+    }
+
+    pub fn toggle_inspector(&mut self) {
+        self.state.inspector_open = !self.state.inspector_open;
+    }
+
+    /// Entities are only worth collecting for the inspector while it is open
+    pub fn is_inspector_open(&self) -> bool {
+        self.state.inspector_open
+    }
+
+    /// Players listed in the entity inspector on the next frame
+    pub fn set_inspected_entities(&mut self, entities: Vec<InspectedEntity>) {
+        self.state.inspected_entities = entities;
+    }
+
+    pub fn toggle_netcode_panel(&mut self) {
+        self.state.netcode_panel_open = !self.state.netcode_panel_open;
+    }
+
+    pub fn is_netcode_panel_open(&self) -> bool {
+        self.state.netcode_panel_open
+    }
+
+    pub fn toggle_network_log(&mut self) {
+        self.state.network_log_open = !self.state.network_log_open;
+    }
+
+    pub fn netcode_tuning(&self) -> NetcodeTuning {
+        self.state.netcode_tuning
+    }
+
+    pub fn set_interpolation_delay(&mut self, delay: f64) {
+        self.state.interpolation_delay = delay;
+    }
+
+    pub fn time_scale(&self) -> f32 {
+        self.state.time_scale
+    }
+
+    pub fn set_time_scale(&mut self, scale: f32) {
+        self.state.time_scale = scale;
+    }
+
+    pub fn set_avatar_seed(&mut self, seed: u64) {
+        self.state.avatar_seed = seed;
+    }
+
+    pub fn set_local_player(&mut self, player: Player) {
+        self.state.local_player = player;
+    }
+
+    pub fn set_player_names(&mut self, player_names: &HashMap<PlayerID, String>) {
+        self.state.player_labels.names.clone_from(player_names);
+    }
+
+    pub fn set_afk_players(&mut self, afk_players: &HashSet<PlayerID>) {
+        self.state.player_labels.afk.clone_from(afk_players);
+    }
+
+    pub fn set_activity(&mut self, activity: PlayerActivity) {
+        self.state.activity = activity;
+    }
+
+    pub fn set_hosted_server(&mut self, hosted_server: Option<HostedServer>) {
+        self.state.hosted_server = hosted_server;
+    }
+
+    pub fn set_invite_code(&mut self, invite_code: Option<String>) {
+        self.state.invite_code = invite_code;
+    }
+
+    /// Whether the player wants the server to be announced on the LAN when hosting
+    pub fn advertise_lan(&self) -> bool {
+        self.state.advertise_lan
+    }
+
+    pub fn set_lan_servers(&mut self, lan_servers: Vec<LanServer>) {
+        self.state.set_lan_servers(lan_servers);
+    }
+
+    pub fn set_replay_status(&mut self, replay_status: Option<ReplayStatus>) {
+        self.state.replay_status = replay_status;
+    }
+
+    /// True once after the host has clicked Stop Server
+    pub fn take_stop_server_request(&mut self) -> bool {
+        std::mem::take(&mut self.state.stop_server_requested)
+    }
+
+    pub fn set_game_mode(&mut self, game_mode: GameMode) {
+        self.state.game_mode = game_mode;
+    }
+
+    pub fn set_paused(&mut self, paused: bool) {
+        self.state.paused = paused;
+    }
+
+    pub fn set_leader(&mut self, leader: Option<(PlayerID, u32)>) {
+        self.state.leader = leader;
+    }
+
+    /// Health and warning shown during gameplay, hidden if `None`
+    pub fn set_storm_warning(&mut self, storm_warning: Option<StormWarning>) {
+        self.state.storm_warning = storm_warning;
+    }
+
+    /// Status on connection menu while waiting in the join queue of a full server
+    pub fn set_queue_position(&mut self, position: u32) {
+        self.state.status_color = Color32::BLACK;
+        self.state.status_text =
+            format!("Server is full, waiting in line ({position}. in queue)...");
     }
 
     /// Error status on connection menu and Disconnected message dialog
     pub fn set_error_status(&mut self, msg: String) {
-        self.status_color = Color32::RED;
-        self.status_text = msg;
+        self.state.set_error_status(msg);
     }
 }
 
 /// Starter connection menu
-fn show_menu(
+/// Draw a single widget of the HUD, see `hud::widgets()` for which ones are shown when
+fn show_hud_widget(
     ctx: &egui::Context,
+    widget: HudWidget,
+    state: &mut GuiState,
     state_machine: &mut fsm::StateMachine,
-    server_hostname: &mut String,
-    server_port: &mut String,
-    status_text: &mut String,
-    status_color: &mut Color32,
+    joystick: &VirtualJoystick,
+    remote_players: &HashMap<PlayerID, Player>,
+    muted_players: &mut HashSet<PlayerID>,
 ) {
+    match widget {
+        HudWidget::Log => show_log(
+            ctx,
+            &state.log_messages,
+            &mut state.chat_input,
+            &mut state.outgoing_chat,
+        ),
+        HudWidget::PlayerList => show_player_list(
+            ctx,
+            remote_players,
+            &state.player_labels,
+            muted_players,
+            state.avatar_seed,
+            state.leader,
+            state.local_player.id,
+        ),
+        HudWidget::EventFeed => {
+            state
+                .events
+                .retain(|(received, _)| received.elapsed() < EVENT_LIFETIME);
+            show_event_feed(ctx, &state.events);
+        }
+        HudWidget::NetworkIndicator => {
+            if let Some(connection_stats) = &state.connection_stats {
+                show_connection_stats(ctx, connection_stats);
+            }
+            let warnings =
+                connection_warnings(state.connection_stats.as_ref(), state.connection_unstable);
+            if !warnings.is_empty() {
+                show_connection_warning(ctx, &warnings);
+            }
+        }
+        HudWidget::TouchControls => {
+            if joystick.is_visible() {
+                show_touch_controls(ctx, state_machine, joystick);
+            }
+        }
+        HudWidget::LocalPlayerBanner => show_local_player_banner(
+            ctx,
+            &state.local_player,
+            &state.player_labels.name(state.local_player.id),
+            state.activity,
+            state.avatar_seed,
+        ),
+        HudWidget::Profile => show_profile_window(
+            ctx,
+            &mut state.profile_name,
+            &mut state.profile_color,
+            &mut state.outgoing_profile,
+        ),
+        HudWidget::PauseBanner => {
+            if state.paused {
+                show_pause_banner(ctx);
+            }
+        }
+        HudWidget::HealthBar => {
+            if let Some(storm_warning) = &state.storm_warning {
+                show_storm_warning(ctx, storm_warning);
+            }
+        }
+        HudWidget::DebugWindows => {
+            // Slow motion is easy to forget about, so the slider stays while it lasts
+            if state.inspector_open || state.time_scale != 1.0 {
+                show_time_scale(ctx, &mut state.time_scale);
+            }
+            if state.netcode_panel_open {
+                show_netcode_panel(
+                    ctx,
+                    &mut state.netcode_panel_open,
+                    &mut state.netcode_tuning,
+                    state.interpolation_delay,
+                );
+            }
+            if state.inspector_open {
+                show_entity_inspector(ctx, &mut state.inspector_open, &state.inspected_entities);
+            }
+        }
+        HudWidget::ServerPanel => {
+            if let Some(hosted_server) = &state.hosted_server {
+                show_server_panel(
+                    ctx,
+                    hosted_server,
+                    state.invite_code.as_deref(),
+                    &mut state.stop_server_requested,
+                );
+            }
+        }
+        HudWidget::ReplayBanner => {
+            if let Some(replay_status) = &state.replay_status {
+                show_replay_banner(ctx, state_machine, replay_status, state.avatar_seed);
+            }
+        }
+    }
+}
+
+fn show_menu(ctx: &egui::Context, state_machine: &mut fsm::StateMachine, state: &mut GuiState) {
+    let GuiState {
+        server_hostname,
+        server_port,
+        invite_code_input: invite_code,
+        advertise_lan,
+        lan_servers,
+        status_text,
+        status_color,
+        ..
+    } = state;
+    // Disable connect buttons while client is trying to connect
+    let connect_buttons_enabled =
+        !matches!(state_machine.peek(), Some(fsm::State::Connecting { .. }));
     Window::new("join_server_menu")
         .title_bar(false)
         .collapsible(false)
@@ -123,9 +693,42 @@ fn show_menu(
                     ui.add(TextEdit::singleline(server_port).desired_width(150.0));
                     ui.end_row();
 
-                    // Disable "Connect" button while client is trying to connect
-                    let connect_buttons_enabled =
-                        !matches!(state_machine.peek(), Some(fsm::State::Connecting { .. }));
+                    // Invite code textbox with its own "Join" button, instead of address and port
+                    ui.label("Invite code:");
+                    ui.horizontal(|ui| {
+                        ui.add(
+                            TextEdit::singleline(invite_code)
+                                .hint_text("XXXX-XXXX-XXX")
+                                .desired_width(100.0),
+                        );
+                        let join_button =
+                            ui.add_enabled(connect_buttons_enabled, Button::new("Join"));
+                        if join_button.clicked() {
+                            match invite::decode(invite_code) {
+                                Ok(address) => {
+                                    // Filled in for reconnecting after a lost connection
+                                    *server_hostname = address.ip().to_string();
+                                    *server_port = address.port().to_string();
+                                    *status_text = String::from("Connecting...");
+                                    *status_color = Color32::BLACK;
+                                    state_machine.push(fsm::State::Connecting {
+                                        server_address: address.to_string(),
+                                        session_mode: fsm::SessionMode::ConnectAsClientOnly,
+                                    });
+                                }
+                                Err(e) => {
+                                    *status_text = format!("Error: {e}.");
+                                    *status_color = Color32::RED;
+                                }
+                            }
+                        }
+                    });
+                    ui.end_row();
+
+                    // Announcing the hosted server to the LAN
+                    ui.checkbox(advertise_lan, "Advertise on LAN")
+                        .on_hover_text("Players on the local network see the server in their menu");
+                    ui.end_row();
 
                     // "Create server" button
                     let create_button =
@@ -168,7 +771,16 @@ fn show_menu(
                     }
 
                     // Status label
-                    ui.colored_label(*status_color, status_text);
+                    ui.colored_label(*status_color, status_text.as_str());
+                    ui.end_row();
+
+                    // "Map editor" button
+                    if ui
+                        .add_enabled(connect_buttons_enabled, Button::new("Map editor"))
+                        .clicked()
+                    {
+                        state_machine.change(fsm::State::Editor);
+                    }
                     ui.end_row();
 
                     // "Quit" button
@@ -177,10 +789,47 @@ fn show_menu(
                     }
                     ui.end_row();
                 });
+
+            // Servers on the LAN, joined with a click instead of typing their address
+            ui.separator();
+            ui.label("LAN servers:");
+            if lan_servers.is_empty() {
+                ui.weak("Looking for servers...");
+            }
+            for server in lan_servers.iter() {
+                ui.horizontal(|ui| {
+                    let players = match server.players {
+                        1 => "1 player".to_string(),
+                        players => format!("{players} players"),
+                    };
+                    ui.label(format!("{} ({players})", server.name))
+                        .on_hover_text(server.address.to_string());
+                    if ui
+                        .add_enabled(connect_buttons_enabled, Button::new("Join"))
+                        .clicked()
+                    {
+                        // Filled in for reconnecting after a lost connection
+                        *server_hostname = server.address.ip().to_string();
+                        *server_port = server.address.port().to_string();
+                        *status_text = String::from("Connecting...");
+                        *status_color = Color32::BLACK;
+                        state_machine.push(fsm::State::Connecting {
+                            server_address: server.address.to_string(),
+                            session_mode: fsm::SessionMode::ConnectAsClientOnly,
+                        });
+                    }
+                });
+            }
         });
 }
 
-fn show_log(ctx: &egui::Context, log_messages: &String) {
+/// Gameplay log with chat input line below
+fn show_log(
+    ctx: &egui::Context,
+    log_messages: &String,
+    chat_input: &mut String,
+    outgoing_chat: &mut Vec<String>,
+) {
     // Set window transparent just for this widget
     let style = (*ctx.style()).clone();
     ctx.style_mut(|style| {
@@ -190,27 +839,792 @@ fn show_log(ctx: &egui::Context, log_messages: &String) {
     Window::new("log")
         .title_bar(false)
         .anchor(Align2::LEFT_TOP, egui::Vec2::ZERO)
-        .fixed_size([200.0, 80.0])
-        .show(&ctx, |ui| {
+        .fixed_size([200.0, 100.0])
+        .show(ctx, |ui| {
             egui::ScrollArea::vertical()
                 .auto_shrink(false)
+                .max_height(80.0)
                 .stick_to_bottom(true)
                 .show(ui, |ui| {
                     ui.label(log_messages);
                 });
+
+            let chat_edit = ui.add(
+                TextEdit::singleline(chat_input)
+                    .hint_text("Chat")
+                    .char_limit(globals::MAX_CHAT_MESSAGE_LEN)
+                    .desired_width(f32::INFINITY),
+            );
+            // Single-line text edit loses focus when Enter is pressed
+            if chat_edit.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter)) {
+                if message::validate_chat_message(chat_input).is_ok() {
+                    outgoing_chat.push(std::mem::take(chat_input));
+                }
+                chat_edit.request_focus();
+            }
         });
 
     // Reset style back for other dialog widgets
     ctx.set_style(style);
 }
 
+/// Name a player is known by on the server, or the default name until the server announced it
+pub fn player_name(player_names: &HashMap<PlayerID, String>, player_id: PlayerID) -> String {
+    player_names
+        .get(&player_id)
+        .cloned()
+        .unwrap_or_else(|| format!("Player {player_id}"))
+}
+
+/// Names of the players announced by the server and who of them is away from keyboard, shown in
+/// the player list and the banner
+#[derive(Default)]
+struct PlayerLabels {
+    names: HashMap<PlayerID, String>,
+    afk: HashSet<PlayerID>,
+}
+
+impl PlayerLabels {
+    fn name(&self, player_id: PlayerID) -> String {
+        player_name(&self.names, player_id)
+    }
+}
+
+/// Collapsible list of remote players where their chat can be muted locally
+fn show_player_list(
+    ctx: &egui::Context,
+    remote_players: &HashMap<PlayerID, Player>,
+    labels: &PlayerLabels,
+    muted_players: &mut HashSet<PlayerID>,
+    avatar_seed: u64,
+    leader: Option<(PlayerID, u32)>,
+    local_player_id: PlayerID,
+) {
+    let mut player_ids: Vec<PlayerID> = remote_players.keys().copied().collect();
+    player_ids.sort_unstable();
+
+    // Leave room for connection stats below
+    Window::new("Players")
+        .anchor(Align2::RIGHT_BOTTOM, egui::vec2(-10.0, -40.0))
+        .resizable(false)
+        .default_open(false)
+        .show(ctx, |ui| {
+            match leader {
+                Some((leader_id, score)) if leader_id == local_player_id => {
+                    ui.colored_label(LEADER_COLOR, format!("👑 You lead with {score}"));
+                }
+                Some((leader_id, score)) => {
+                    ui.label(format!("{} leads with {score}", labels.name(leader_id)));
+                }
+                None => (),
+            }
+            if player_ids.is_empty() {
+                ui.label("Nobody else is here.");
+                return;
+            }
+            Grid::new("player_list").num_columns(2).show(ui, |ui| {
+                for player_id in player_ids {
+                    ui.horizontal(|ui| {
+                        paint_avatar(ui, &remote_players[&player_id], avatar_seed);
+                        let mut name = labels.name(player_id);
+                        let afk = labels.afk.contains(&player_id);
+                        if afk {
+                            name += " (away)";
+                        }
+                        if leader.is_some_and(|(leader_id, _)| leader_id == player_id) {
+                            ui.colored_label(LEADER_COLOR, format!("👑 {name}"));
+                        } else if afk {
+                            ui.weak(name);
+                        } else {
+                            ui.label(name);
+                        }
+                    });
+                    let muted = muted_players.contains(&player_id);
+                    if ui.button(if muted { "Unmute" } else { "Mute" }).clicked() {
+                        if muted {
+                            muted_players.remove(&player_id);
+                        } else {
+                            muted_players.insert(player_id);
+                        }
+                    }
+                    ui.end_row();
+                }
+            });
+        });
+}
+
+/// Player count, tick time and invite code of the hosted server below the log, with a button to
+/// stop it
+fn show_server_panel(
+    ctx: &egui::Context,
+    server: &HostedServer,
+    invite_code: Option<&str>,
+    stop_requested: &mut bool,
+) {
+    Window::new("Server")
+        .anchor(Align2::LEFT_TOP, egui::vec2(10.0, 150.0))
+        .resizable(false)
+        .show(ctx, |ui| {
+            Grid::new("server_panel").num_columns(2).show(ui, |ui| {
+                ui.label("Players:");
+                ui.label(match server.max_players {
+                    Some(max_players) => format!("{} / {max_players}", server.player_count),
+                    None => server.player_count.to_string(),
+                });
+                ui.end_row();
+
+                ui.label("Tick time:");
+                let tick_time = format!(
+                    "{:.2} ms (avg {:.2}, budget {:.2})",
+                    server.ticks.last_ms, server.ticks.average_ms, server.ticks.budget_ms
+                );
+                if server.ticks.overloaded {
+                    ui.colored_label(Color32::DARK_RED, format!("{tick_time}, overloaded"));
+                } else {
+                    ui.label(tick_time);
+                }
+                ui.end_row();
+
+                if let Some(invite_code) = invite_code {
+                    ui.label("Invite code:");
+                    ui.horizontal(|ui| {
+                        ui.monospace(invite_code);
+                        if ui.small_button("Copy").clicked() {
+                            ui.ctx().copy_text(invite_code.to_string());
+                        }
+                    });
+                    ui.end_row();
+                }
+            });
+            if ui.button("Stop Server").clicked() {
+                *stop_requested = true;
+            }
+        });
+}
+
+/// Name of the local player in its color at the top of the screen, below connection warnings, so
+/// that windows of several instances can be told apart. Players away from keyboard or spectating
+/// are reminded how to get back.
+fn show_local_player_banner(
+    ctx: &egui::Context,
+    player: &Player,
+    name: &str,
+    activity: PlayerActivity,
+    avatar_seed: u64,
+) {
+    egui::Area::new(egui::Id::new("local_player_banner"))
+        .anchor(Align2::CENTER_TOP, egui::vec2(0.0, 40.0))
+        .interactable(false)
+        .show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                paint_avatar(ui, player, avatar_seed);
+                ui.strong(format!("{name} (you)"));
+            });
+            match activity {
+                PlayerActivity::Active => (),
+                PlayerActivity::Afk => {
+                    ui.label("Away from keyboard, move to get back");
+                }
+                PlayerActivity::Spectating => {
+                    ui.label("Spectating, move to play again once a slot is free");
+                }
+            }
+        });
+}
+
+/// Collapsible window in the bottom-left corner for changing the name and color of the local
+/// player mid-session. Changes are only sent when applied, the server answers with the accepted
+/// ones.
+fn show_profile_window(
+    ctx: &egui::Context,
+    name: &mut String,
+    color: &mut [f32; 3],
+    outgoing_profile: &mut Option<(Vector3<f32>, String)>,
+) {
+    Window::new("Profile")
+        .anchor(Align2::LEFT_BOTTOM, egui::vec2(10.0, -10.0))
+        .resizable(false)
+        .default_open(false)
+        .show(ctx, |ui| {
+            Grid::new("profile").num_columns(2).show(ui, |ui| {
+                ui.label("Name:");
+                ui.add(
+                    TextEdit::singleline(name)
+                        .char_limit(globals::MAX_PLAYER_NAME_LEN)
+                        .desired_width(120.0),
+                );
+                ui.end_row();
+
+                ui.label("Color:");
+                ui.color_edit_button_rgb(color);
+                ui.end_row();
+            });
+            let validation = message::validate_player_name(name.trim());
+            if let Err(e) = &validation {
+                ui.colored_label(Color32::DARK_RED, e);
+            }
+            if ui
+                .add_enabled(validation.is_ok(), Button::new("Apply"))
+                .clicked()
+            {
+                *outgoing_profile = Some(((*color).into(), name.trim().to_string()));
+            }
+        });
+}
+
+/// Map, match time and followed player at the top of the screen while watching a replay, with a
+/// button to go back to the menu
+fn show_replay_banner(
+    ctx: &egui::Context,
+    state_machine: &mut fsm::StateMachine,
+    replay_status: &ReplayStatus,
+    avatar_seed: u64,
+) {
+    egui::Area::new(egui::Id::new("replay_banner"))
+        .anchor(Align2::CENTER_TOP, egui::vec2(0.0, 10.0))
+        .show(ctx, |ui| {
+            Frame::popup(ui.style()).show(ui, |ui| {
+                ui.horizontal(|ui| {
+                    let elapsed_secs = replay_status.elapsed.as_secs();
+                    ui.strong(format!(
+                        "Replay of {} {}:{:02}",
+                        replay_status.map_name,
+                        elapsed_secs / 60,
+                        elapsed_secs % 60
+                    ));
+                    ui.separator();
+                    match &replay_status.followed {
+                        Some((player, name)) => {
+                            paint_avatar(ui, player, avatar_seed);
+                            ui.label(format!("Following {name} (Tab to switch)"));
+                        }
+                        None => {
+                            ui.label("Nobody is playing");
+                        }
+                    }
+                    ui.separator();
+                    if ui.button("Stop").clicked() {
+                        state_machine.pop();
+                    }
+                });
+            });
+        });
+}
+
+/// Banner in the middle of the screen while the server has paused the game
+fn show_pause_banner(ctx: &egui::Context) {
+    egui::Area::new(egui::Id::new("pause_banner"))
+        .anchor(Align2::CENTER_CENTER, egui::Vec2::ZERO)
+        .interactable(false)
+        .show(ctx, |ui| {
+            Frame::popup(ui.style()).show(ui, |ui| {
+                ui.vertical_centered(|ui| {
+                    ui.heading("PAUSED");
+                    ui.label("The server has paused the game.");
+                });
+            });
+        });
+}
+
+/// Debug slider of the speed of logic updates at the bottom of the screen
+fn show_time_scale(ctx: &egui::Context, time_scale: &mut f32) {
+    egui::Area::new(egui::Id::new("time_scale"))
+        .anchor(Align2::CENTER_BOTTOM, egui::vec2(0.0, -10.0))
+        .show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                ui.label("Time scale");
+                ui.add(
+                    egui::Slider::new(
+                        time_scale,
+                        globals::MIN_TIME_SCALE..=globals::MAX_TIME_SCALE,
+                    )
+                    .suffix("x"),
+                );
+                if ui.button("Reset").clicked() {
+                    *time_scale = 1.0;
+                }
+            });
+        });
+}
+
+/// Messages traced recently, kept even without `--trace`, with export to a file for bug reports
+fn show_network_log(
+    ctx: &egui::Context,
+    open: &mut bool,
+    export_path: &mut String,
+    export_status: &mut String,
+) {
+    Window::new("Network log")
+        .open(open)
+        .default_pos(egui::pos2(220.0, 10.0))
+        .default_size([500.0, 300.0])
+        .show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                ui.label("File:");
+                ui.add(TextEdit::singleline(export_path).desired_width(150.0));
+                if ui.button("Export").clicked() {
+                    *export_status =
+                        match netlog::export(std::path::Path::new(export_path.as_str())) {
+                            Ok(count) => format!("Exported {count} messages to {export_path}"),
+                            Err(e) => format!("Failed to export to {export_path}: {e}"),
+                        };
+                }
+                if ui.button("Clear").clicked() {
+                    netlog::clear();
+                }
+            });
+            if !export_status.is_empty() {
+                ui.label(export_status.as_str());
+            }
+            ui.separator();
+            let row_height = ui.text_style_height(&egui::TextStyle::Monospace);
+            egui::ScrollArea::both()
+                .auto_shrink(false)
+                .stick_to_bottom(true)
+                .show_rows(ui, row_height, netlog::len(), |ui, rows| {
+                    // Rows have to keep their height, long messages are scrolled to instead
+                    for entry in netlog::entries(rows) {
+                        ui.add(
+                            egui::Label::new(
+                                egui::RichText::new(format!(
+                                    "{} {}",
+                                    entry.time.format("%H:%M:%S%.3f"),
+                                    entry.text
+                                ))
+                                .monospace(),
+                            )
+                            .extend(),
+                        );
+                    }
+                });
+        });
+}
+
+/// Debug window for tuning netcode live, e.g. while testing under poor network conditions
+fn show_netcode_panel(
+    ctx: &egui::Context,
+    open: &mut bool,
+    tuning: &mut NetcodeTuning,
+    interpolation_delay: f64,
+) {
+    Window::new("Netcode")
+        .open(open)
+        .default_pos(egui::pos2(10.0, 300.0))
+        .resizable(false)
+        .show(ctx, |ui| {
+            Grid::new("netcode_panel").num_columns(2).show(ui, |ui| {
+                ui.label("Interpolation delay");
+                ui.horizontal(|ui| {
+                    let mut adaptive = tuning.interpolation.fixed_delay.is_none();
+                    if ui.checkbox(&mut adaptive, "Adaptive").changed() {
+                        // Tuning starts from the delay that was adapted to
+                        tuning.interpolation.fixed_delay =
+                            (!adaptive).then_some(interpolation_delay.clamp(
+                                interpolation::MIN_DELAY_TICKS,
+                                interpolation::MAX_DELAY_TICKS,
+                            ));
+                    }
+                    match &mut tuning.interpolation.fixed_delay {
+                        Some(delay) => {
+                            ui.add(
+                                egui::Slider::new(
+                                    delay,
+                                    interpolation::MIN_DELAY_TICKS..=interpolation::MAX_DELAY_TICKS,
+                                )
+                                .suffix(" ticks"),
+                            );
+                        }
+                        None => {
+                            ui.label(format!("{interpolation_delay:.1} ticks"));
+                        }
+                    }
+                });
+                ui.end_row();
+
+                ui.label("Extrapolation limit");
+                ui.add(
+                    egui::Slider::new(
+                        &mut tuning.interpolation.extrapolation_limit,
+                        0.0..=interpolation::MAX_EXTRAPOLATION_TICKS,
+                    )
+                    .suffix(" ticks"),
+                );
+                ui.end_row();
+
+                ui.label("Correction blend");
+                ui.add(egui::Slider::new(&mut tuning.correction_blend, 0.05..=1.0));
+                ui.end_row();
+
+                ui.label("Send rate");
+                ui.add(
+                    egui::Slider::new(
+                        &mut tuning.send_rate,
+                        client::MIN_SEND_RATE..=client::MAX_SEND_RATE,
+                    )
+                    .suffix("/s"),
+                );
+                ui.end_row();
+            });
+            if ui.button("Reset").clicked() {
+                *tuning = NetcodeTuning::default();
+            }
+        });
+}
+
+/// Debug window with the live state of every player, to find stuck players and ghosts of players
+/// who are long gone
+fn show_entity_inspector(ctx: &egui::Context, open: &mut bool, entities: &[InspectedEntity]) {
+    Window::new("Entity Inspector")
+        .open(open)
+        .default_pos(egui::pos2(10.0, 10.0))
+        .resizable(false)
+        .show(ctx, |ui| {
+            Grid::new("entity_inspector")
+                .num_columns(5)
+                .striped(true)
+                .show(ui, |ui| {
+                    for header in ["Player", "Position", "Velocity", "Last update", "Color"] {
+                        ui.strong(header);
+                    }
+                    ui.end_row();
+                    for entity in entities {
+                        let player = &entity.player;
+                        if entity.is_local {
+                            ui.label(format!("Player {} (you)", player.id));
+                        } else {
+                            ui.label(format!("Player {}", player.id));
+                        }
+                        ui.monospace(format!("{:7.1} {:7.1}", player.pos.x, player.pos.y));
+                        ui.monospace(format!(
+                            "{:5.2} {:5.2}",
+                            player.velocity.x, player.velocity.y
+                        ));
+                        match entity.last_update {
+                            Some(age) => ui.monospace(format!("{} ms", age.as_millis())),
+                            None => ui.label("-"),
+                        };
+                        ui.horizontal(|ui| {
+                            ui.colored_label(player_color(player), "⬛");
+                            ui.monospace(format!(
+                                "{:.2} {:.2} {:.2}",
+                                player.color.x, player.color.y, player.color.z
+                            ));
+                        });
+                        ui.end_row();
+                    }
+                });
+        });
+}
+
+/// Recent game events in the top-right corner, below the touch menu button
+fn show_event_feed(ctx: &egui::Context, events: &VecDeque<(Instant, GameEvent)>) {
+    egui::Area::new(egui::Id::new("event_feed"))
+        .anchor(Align2::RIGHT_TOP, egui::vec2(-10.0, 60.0))
+        .interactable(false)
+        .show(ctx, |ui| {
+            ui.with_layout(egui::Layout::top_down(egui::Align::Max), |ui| {
+                for (_, event) in events {
+                    let (icon, icon_color, text) = match event {
+                        GameEvent::Joined(_, name) => {
+                            ("➡", Color32::DARK_GREEN, format!("{name} joined"))
+                        }
+                        GameEvent::Left(_, name) => {
+                            ("⬅", Color32::DARK_RED, format!("{name} left"))
+                        }
+                        GameEvent::KnockedOut(_, name) => (
+                            "⚡",
+                            STORM_COLOR,
+                            format!("{name} was knocked out by the storm"),
+                        ),
+                    };
+                    ui.horizontal(|ui| {
+                        ui.colored_label(icon_color, icon);
+                        ui.label(text);
+                    });
+                }
+            });
+        });
+}
+
+/// Connection quality measured by the server in the bottom-right corner
+fn show_connection_stats(ctx: &egui::Context, stats: &ConnectionStats) {
+    egui::Area::new(egui::Id::new("connection_stats"))
+        .anchor(Align2::RIGHT_BOTTOM, egui::vec2(-10.0, -10.0))
+        .interactable(false)
+        .show(ctx, |ui| {
+            ui.label(format!(
+                "RTT: {} ms | Loss: {}% | Corrections: {}",
+                stats.rtt_ms, stats.packet_loss_percent, stats.corrections
+            ));
+        });
+}
+
+/// Problems with the connection worth warning about before it's declared dead
+fn connection_warnings(stats: Option<&ConnectionStats>, unstable: bool) -> Vec<&'static str> {
+    let mut warnings = Vec::new();
+    if unstable {
+        warnings.push("connection unstable");
+    }
+    if let Some(stats) = stats {
+        if stats.packet_loss_percent > HIGH_PACKET_LOSS_PERCENT {
+            warnings.push("high packet loss");
+        }
+        if stats.rtt_ms > HIGH_PING_MS {
+            warnings.push("high ping");
+        }
+    }
+    warnings
+}
+
+/// Blinking network icon with the connection problems at the top of the screen
+fn show_connection_warning(ctx: &egui::Context, warnings: &[&str]) {
+    let icon_visible = (ctx.input(|i| i.time) * WARNING_BLINK_RATE * 2.0) as u64 % 2 == 0;
+    let mut text = warnings.join(" / ");
+    if let Some(first) = text.get_mut(..1) {
+        first.make_ascii_uppercase();
+    }
+    egui::Area::new(egui::Id::new("connection_warning"))
+        .anchor(Align2::CENTER_TOP, egui::vec2(0.0, 10.0))
+        .interactable(false)
+        .show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                // Icon keeps its place while hidden, so that the text doesn't jump around
+                let icon_color = if icon_visible {
+                    Color32::RED
+                } else {
+                    Color32::TRANSPARENT
+                };
+                ui.colored_label(icon_color, "📶");
+                ui.colored_label(Color32::RED, text);
+            });
+        });
+}
+
+/// Health bar of the local player below the connection warning, with a blinking warning while
+/// outside the safe zone
+fn show_storm_warning(ctx: &egui::Context, storm_warning: &StormWarning) {
+    let warning_visible = (ctx.input(|i| i.time) * WARNING_BLINK_RATE * 2.0) as u64 % 2 == 0;
+    egui::Area::new(egui::Id::new("storm_warning"))
+        .anchor(Align2::CENTER_TOP, egui::vec2(0.0, 40.0))
+        .interactable(false)
+        .show(ctx, |ui| {
+            ui.vertical_centered(|ui| {
+                ui.add(
+                    ProgressBar::new(storm_warning.health as f32 / storm::MAX_HEALTH as f32)
+                        .desired_width(200.0)
+                        .fill(STORM_COLOR)
+                        .text(format!("Health {}", storm_warning.health)),
+                );
+                if storm_warning.outside && warning_visible {
+                    ui.colored_label(Color32::RED, "Get back into the safe zone!");
+                }
+            });
+        });
+}
+
+/// Virtual joystick and on-screen replacement for the Esc key. Touch input itself is processed by
+/// the application, this only draws the current state.
+fn show_touch_controls(
+    ctx: &egui::Context,
+    state_machine: &mut fsm::StateMachine,
+    joystick: &VirtualJoystick,
+) {
+    let painter = ctx.layer_painter(egui::LayerId::background());
+    let center = egui::pos2(joystick.center().x, joystick.center().y);
+    let knob_offset = joystick.knob_offset();
+    painter.circle_filled(
+        center,
+        touch::JOYSTICK_RADIUS,
+        Color32::from_black_alpha(48),
+    );
+    painter.circle_filled(
+        center + egui::vec2(knob_offset.x, knob_offset.y),
+        touch::JOYSTICK_RADIUS / 2.5,
+        Color32::from_black_alpha(96),
+    );
+
+    egui::Area::new(egui::Id::new("touch_menu_button"))
+        .anchor(Align2::RIGHT_TOP, egui::vec2(-10.0, 10.0))
+        .show(ctx, |ui| {
+            if ui
+                .add(Button::new("Menu").min_size(egui::vec2(60.0, 40.0)))
+                .clicked()
+            {
+                state_machine.push(fsm::State::QuitDialog);
+            }
+        });
+}
+
+/// Progress of downloading the map of the joined server
+fn show_loading(ctx: &egui::Context, progress: f32) {
+    Window::new("loading")
+        .title_bar(false)
+        .collapsible(false)
+        .resizable(false)
+        .anchor(Align2::CENTER_CENTER, egui::Vec2::ZERO)
+        .fixed_size([300.0, 50.0])
+        .show(ctx, |ui| {
+            ui.vertical_centered(|ui| {
+                ui.label("Loading map...");
+                ui.add(ProgressBar::new(progress).show_percentage());
+            });
+        });
+}
+
+/// Banner shown while joining the server of a lost session again, counting down until giving up
+fn show_reconnecting(ctx: &egui::Context, reason: &str, give_up_at: std::time::Instant) {
+    let remaining = give_up_at.saturating_duration_since(std::time::Instant::now());
+    Window::new("reconnecting")
+        .title_bar(false)
+        .collapsible(false)
+        .resizable(false)
+        .anchor(Align2::CENTER_TOP, egui::vec2(0.0, 10.0))
+        .fixed_size([300.0, 50.0])
+        .show(ctx, |ui| {
+            ui.vertical_centered(|ui| {
+                ui.label(reason);
+                ui.horizontal(|ui| {
+                    ui.spinner();
+                    ui.label(format!(
+                        "Reconnecting... giving up in {} s",
+                        remaining.as_secs() + 1
+                    ));
+                });
+            });
+        });
+}
+
+/// Tools of the map editor. Saved maps can be hosted right away on the port set in the menu.
+fn show_editor(
+    ctx: &egui::Context,
+    state_machine: &mut fsm::StateMachine,
+    editor: &mut MapEditor,
+    server_port: &str,
+    editor_status: &mut String,
+) {
+    Window::new("Map editor")
+        .anchor(Align2::LEFT_TOP, egui::vec2(10.0, 10.0))
+        .collapsible(false)
+        .resizable(false)
+        .show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                ui.radio_value(&mut editor.tool, EditorTool::Obstacle, "Obstacle");
+                ui.radio_value(&mut editor.tool, EditorTool::SpawnPoint, "Spawn point");
+            });
+            ui.label("Click on the grid to place or remove, move the view with arrow keys.");
+            ui.horizontal(|ui| {
+                ui.label("File:");
+                ui.add(TextEdit::singleline(&mut editor.path).desired_width(150.0));
+            });
+            ui.horizontal(|ui| {
+                if ui.button("Load").clicked() {
+                    *editor_status = match editor.load() {
+                        Ok(()) => format!("Loaded {}", editor.path),
+                        Err(e) => format!("Failed to load {}: {e}", editor.path),
+                    };
+                }
+                if ui.button("Save").clicked() {
+                    *editor_status = match editor.save() {
+                        Ok(()) => format!("Saved {}", editor.path),
+                        Err(e) => format!("Failed to save {}: {e}", editor.path),
+                    };
+                }
+                if ui.button("Host & playtest").clicked() {
+                    match editor.start_playtest() {
+                        Ok(()) => {
+                            editor_status.clear();
+                            state_machine.change(fsm::State::Connecting {
+                                server_address: format!("{}:{server_port}", globals::LOCALHOST),
+                                session_mode: fsm::SessionMode::CreateServer,
+                            });
+                        }
+                        Err(e) => *editor_status = format!("Failed to save {}: {e}", editor.path),
+                    }
+                }
+                if ui.button("Back").clicked() {
+                    editor_status.clear();
+                    state_machine.change(fsm::State::Menu);
+                }
+            });
+            if !editor_status.is_empty() {
+                ui.label(editor_status.as_str());
+            }
+        });
+}
+
+/// Standings table of the match that just ended with countdown to the next match. Votes for the
+/// next map are sent as chat commands.
+fn show_results(
+    ctx: &egui::Context,
+    standings: &[MatchResult],
+    next_match_at: Instant,
+    map_vote: Option<&[(String, u32)]>,
+    outgoing_chat: &mut Vec<String>,
+) {
+    Window::new("results")
+        .title_bar(false)
+        .collapsible(false)
+        .resizable(false)
+        .anchor(Align2::CENTER_CENTER, egui::Vec2::ZERO)
+        .fixed_size([300.0, 250.0])
+        .show(ctx, |ui| {
+            ui.vertical_centered(|ui| {
+                ui.heading("Match over");
+            });
+            ui.separator();
+            Grid::new("standings")
+                .num_columns(3)
+                .striped(true)
+                .show(ui, |ui| {
+                    ui.strong("#");
+                    ui.strong("Player");
+                    ui.strong("Distance");
+                    ui.end_row();
+                    for (rank, result) in standings.iter().enumerate() {
+                        ui.label(format!("{}.", rank + 1));
+                        ui.label(&result.name);
+                        ui.label(result.score.to_string());
+                        ui.end_row();
+                    }
+                });
+            if let Some(map_vote) = map_vote {
+                ui.separator();
+                ui.strong("Vote for the next map");
+                Grid::new("map_vote").num_columns(3).show(ui, |ui| {
+                    for (index, (name, votes)) in map_vote.iter().enumerate() {
+                        ui.label(name);
+                        ui.label(format!("{votes} votes"));
+                        if ui.button("Vote").clicked() {
+                            outgoing_chat.push(format!("/vote {}", index + 1));
+                        }
+                        ui.end_row();
+                    }
+                });
+            }
+            ui.separator();
+            let seconds_left = next_match_at
+                .saturating_duration_since(Instant::now())
+                .as_secs_f32()
+                .ceil();
+            ui.vertical_centered(|ui| {
+                ui.label(format!("Next match starts in {seconds_left} seconds"));
+            });
+        });
+}
+
 fn show_disconnected_dialog(
     ctx: &egui::Context,
     state_machine: &mut fsm::StateMachine,
-    log_messages: &mut String,
-    status_text: &mut String,
-    status_color: &mut Color32,
+    state: &mut GuiState,
 ) {
+    let GuiState {
+        log_messages,
+        status_text,
+        status_color,
+        session_summary,
+        network_log_open,
+        ..
+    } = state;
     CentralPanel::default()
         .frame(Frame::none().fill(Color32::from_black_alpha(192)))
         .show(ctx, |_| {});
@@ -223,10 +1637,24 @@ fn show_disconnected_dialog(
         .fixed_size([300.0, 100.0])
         .show(ctx, |ui| {
             ui.vertical_centered(|ui| {
-                ui.label("Connection to server was lost.");
+                // Error status carries the reason when the server kicked the player
+                if *status_color == Color32::RED {
+                    ui.label(status_text.as_str());
+                } else {
+                    ui.label("Connection to server was lost.");
+                }
+                if let Some(session_summary) = session_summary {
+                    ui.separator();
+                    ui.label(session_summary.as_str());
+                }
+                // Traffic leading up to the disconnect
+                if ui.button("Network log").clicked() {
+                    *network_log_open = true;
+                }
                 if ui.button("Ok").clicked() {
                     state_machine.change(fsm::State::Menu);
                     log_messages.clear();
+                    *session_summary = None;
                     *status_text = String::from("Ready.");
                     *status_color = Color32::BLACK;
                 }
@@ -279,10 +1707,50 @@ fn show_quit_dialog(ctx: &egui::Context, state_machine: &mut fsm::StateMachine)
         });
 }
 
+/// Quad of the player with its avatar, the same as drawn in the world
+fn paint_avatar(ui: &mut egui::Ui, player: &Player, avatar_seed: u64) {
+    let (rect, _) = ui.allocate_exact_size(egui::vec2(14.0, 14.0), egui::Sense::hover());
+    let painter = ui.painter();
+    painter.rect_filled(rect, 0.0, player_color(player));
+
+    let avatar = Avatar::new(avatar_seed, player.id);
+    let pattern = rect.shrink(rect.width() * (1.0 - AVATAR_PATTERN_SCALE) / 2.0);
+    let cell_size = pattern.width() / AVATAR_CELLS as f32;
+    let shade = rgb_color(player.color * AVATAR_SHADE);
+    for row in 0..AVATAR_CELLS {
+        for column in 0..AVATAR_CELLS {
+            if avatar.is_filled(row, column) {
+                let min = pattern.min + egui::vec2(column as f32, row as f32) * cell_size;
+                painter.rect_filled(
+                    egui::Rect::from_min_size(min, egui::vec2(cell_size, cell_size)),
+                    0.0,
+                    shade,
+                );
+            }
+        }
+    }
+}
+
+fn player_color(player: &Player) -> Color32 {
+    rgb_color(player.color)
+}
+
+fn rgb_color(color: Vector3<f32>) -> Color32 {
+    Color32::from_rgb(
+        (color.x * 255.0) as u8,
+        (color.y * 255.0) as u8,
+        (color.z * 255.0) as u8,
+    )
+}
+
 fn verify_address_format(address: &str, port: &str) -> Result<(), String> {
-    match address.parse::<IpAddr>() {
-        Ok(_) => {}
-        Err(_) => return Err("Error: Invalid IP address format.".to_string()),
+    // Host names are resolved when connecting
+    let is_host_name = !address.is_empty()
+        && address.split('.').all(|label| {
+            !label.is_empty() && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        });
+    if address.parse::<IpAddr>().is_err() && !is_host_name {
+        return Err("Error: Invalid IP address or host name format.".to_string());
     }
 
     match port.parse::<u16>() {
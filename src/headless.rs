@@ -0,0 +1,175 @@
+use std::{
+    collections::HashMap,
+    error::Error,
+    sync::{Arc, Mutex},
+};
+
+use cgmath::InnerSpace;
+use tokio::task::JoinHandle;
+
+use crate::{
+    bot::Bot,
+    client::{ClientEvent, ConnectionState},
+    client_metrics::{self, ClientMetrics},
+    globals,
+    map::Map,
+    message::Message,
+    summary::SessionSummary,
+    task, ClientSession, Player, PlayerID,
+};
+
+/// Client without window and graphics, connecting to a server and walking the local player around
+/// in a circle. Replication events are printed on standard output. Meant for machines without a
+/// display, like servers and CI runners.
+///
+/// Runs until CTRL+C is pressed or the server is lost, then prints a summary of the session.
+///
+/// With `metrics_port`, connection metrics are served for scraping by Prometheus on
+/// `http://<host>:<port>/metrics` once joined, see `ClientMetrics`.
+pub async fn run_headless_client(
+    server_address: String,
+    player_name: String,
+    send_rate: u32,
+    metrics_port: Option<u16>,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let mut client_session = ClientSession::new(
+        server_address.clone(),
+        player_name.clone(),
+        send_rate,
+        None,
+        None,
+    )
+    .await?;
+    let mut local_player = client_session.get_session_player_data();
+    println!(
+        "Joined \"{}\" as Player {} (seed {})",
+        client_session.get_server_name(),
+        local_player.id,
+        client_session.get_seed()
+    );
+    let metrics = Arc::new(Mutex::new(ClientMetrics::new(
+        local_player.id,
+        player_name,
+        server_address,
+    )));
+    let _metrics_endpoint = match metrics_port {
+        Some(metrics_port) => {
+            let listener = client_metrics::bind(metrics_port).await?;
+            println!("Serving metrics on http://0.0.0.0:{metrics_port}/metrics");
+            Some(AbortOnDrop(task::spawn_named(
+                "client_metrics_endpoint",
+                client_metrics::serve(listener, metrics.clone()),
+            )))
+        }
+        None => None,
+    };
+
+    let mut remote_players: HashMap<PlayerID, Player> = HashMap::new();
+    let mut session_summary = SessionSummary::new();
+    let mut bot = Bot::default();
+    // Map is not downloaded, so obstacles are walked through
+    let map = Map::default();
+    let mut interval = tokio::time::interval(std::time::Duration::from_secs_f32(
+        globals::FIXED_UPDATE_TIMESTEP_SEC,
+    ));
+
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {}
+            _ = tokio::signal::ctrl_c() => {
+                println!("CTRL+C interrupt received. Leaving server...");
+                leave_server(client_session, &session_summary).await;
+                return Ok(());
+            }
+        }
+
+        for event in client_session.try_events() {
+            match &event {
+                ClientEvent::Message(_) => metrics.lock().unwrap().record_message(),
+                ClientEvent::ConnectionStateChanged(state) => {
+                    metrics.lock().unwrap().record_connection_state(*state)
+                }
+            }
+            let msg = match event {
+                ClientEvent::Message(msg) => msg,
+                ClientEvent::ConnectionStateChanged(ConnectionState::Degraded) => {
+                    println!("Connection unstable");
+                    continue;
+                }
+                ClientEvent::ConnectionStateChanged(ConnectionState::Connected) => {
+                    println!("Connection recovered");
+                    continue;
+                }
+                ClientEvent::ConnectionStateChanged(ConnectionState::Lost) => {
+                    println!("Session summary: {session_summary}");
+                    return Err("Connection to server was lost".into());
+                }
+                ClientEvent::ConnectionStateChanged(_) => continue,
+            };
+            match msg {
+                Message::Replicate(tick, remote_player) => {
+                    if !client_session.accept_snapshot(remote_player.id, tick) {
+                        // Outdated snapshot arrived out of order
+                        continue;
+                    }
+                    match remote_players.insert(remote_player.id, remote_player) {
+                        None => println!(
+                            "Player {} joined at ({}, {})",
+                            remote_player.id, remote_player.pos.x, remote_player.pos.y
+                        ),
+                        Some(previous) if previous.pos != remote_player.pos => println!(
+                            "Player {} moved to ({}, {})",
+                            remote_player.id, remote_player.pos.x, remote_player.pos.y
+                        ),
+                        Some(_) => (),
+                    }
+                    session_summary.record_player_count(remote_players.len() + 1);
+                }
+                Message::Leave(id) => {
+                    remote_players.remove(&id);
+                    client_session.forget_player(id);
+                    println!("Player {id} left");
+                }
+                Message::Notice(text) => println!("Server: {text}"),
+                Message::Stats(stats) => {
+                    println!(
+                        "RTT: {} ms, loss: {}%, corrections: {}",
+                        stats.rtt_ms, stats.packet_loss_percent, stats.corrections
+                    );
+                    session_summary.record_rtt(stats.rtt_ms);
+                    metrics.lock().unwrap().record_stats(stats);
+                }
+                Message::Shutdown => {
+                    println!("Server is shutting down. Leaving server...");
+                    leave_server(client_session, &session_summary).await;
+                    return Ok(());
+                }
+                _ => (),
+            }
+        }
+        metrics
+            .lock()
+            .unwrap()
+            .record_remote_players(remote_players.len());
+
+        let previous_pos = local_player.pos;
+        bot.step(&mut local_player, &map, &client_session.get_world_params());
+        session_summary.record_movement((local_player.pos - previous_pos).magnitude());
+        client_session.send_pos(&local_player);
+    }
+}
+
+/// Stops the metrics endpoint when the session is over
+struct AbortOnDrop(JoinHandle<()>);
+
+impl Drop for AbortOnDrop {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+async fn leave_server(client_session: ClientSession, session_summary: &SessionSummary) {
+    println!("Session summary: {session_summary}");
+    // DISCONNECT has to get through before the runtime shuts down
+    client_session.leave_and_wait().await;
+}
@@ -0,0 +1,76 @@
+use std::{collections::VecDeque, time::Instant};
+
+use cgmath::Vector2;
+
+use crate::Tick;
+
+/// How far back positions are kept, enough to look back by the round-trip time of a laggy player
+pub const HISTORY_DURATION: std::time::Duration = std::time::Duration::from_secs(1);
+/// Most positions kept regardless of how often they arrive
+pub const MAX_HISTORY_SAMPLES: usize = 128;
+
+/// Position of a player at a point in time
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PositionSample {
+    pub at: Instant,
+    /// Simulation tick of the server when the position was recorded
+    pub tick: Tick,
+    pub pos: Vector2<f32>,
+}
+
+/// Recent positions of a single player, oldest first. Meant as a common base for looking into
+/// the past, like lag compensation, replays and checking whether movement is plausible.
+#[derive(Clone, Debug, Default)]
+pub struct PositionHistory {
+    samples: VecDeque<PositionSample>,
+}
+
+impl PositionHistory {
+    /// Add the newest position and forget positions older than `HISTORY_DURATION` before it.
+    /// Samples older than the newest one are ignored.
+    pub fn record(&mut self, sample: PositionSample) {
+        if self
+            .samples
+            .back()
+            .is_some_and(|latest| sample.at < latest.at)
+        {
+            return;
+        }
+        self.samples.push_back(sample);
+        while self.samples.len() > MAX_HISTORY_SAMPLES
+            || self
+                .samples
+                .front()
+                .is_some_and(|oldest| sample.at.duration_since(oldest.at) > HISTORY_DURATION)
+        {
+            self.samples.pop_front();
+        }
+    }
+
+    pub fn samples(&self) -> impl Iterator<Item = &PositionSample> {
+        self.samples.iter()
+    }
+
+    pub fn latest(&self) -> Option<&PositionSample> {
+        self.samples.back()
+    }
+
+    /// Position at the given time, interpolated between the samples around it. Times outside of
+    /// the history get the oldest or newest position. None if nothing was recorded yet.
+    pub fn position_at(&self, at: Instant) -> Option<Vector2<f32>> {
+        let after_index = self.samples.partition_point(|sample| sample.at <= at);
+        let after = self.samples.get(after_index);
+        let before = after_index
+            .checked_sub(1)
+            .and_then(|index| self.samples.get(index));
+        match (before, after) {
+            (Some(before), Some(after)) => {
+                let span = after.at.duration_since(before.at).as_secs_f32();
+                let t = at.duration_since(before.at).as_secs_f32() / span;
+                Some(before.pos + (after.pos - before.pos) * t)
+            }
+            (Some(sample), None) | (None, Some(sample)) => Some(sample.pos),
+            (None, None) => None,
+        }
+    }
+}
@@ -0,0 +1,74 @@
+use crate::fsm;
+
+/// How the session being played is run, deciding which parts of the HUD make sense
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum GameMode {
+    /// Joined or hosting a server
+    #[default]
+    Online,
+    /// Peer-to-peer lockstep session without a server
+    Lockstep,
+}
+
+/// Part of the HUD drawn over the game. Widgets without anything to show, like the health bar
+/// while there's no storm, draw nothing.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum HudWidget {
+    /// Gameplay log with the chat input
+    Log,
+    PlayerList,
+    EventFeed,
+    /// Connection quality reported by the server and the warning when it gets bad
+    NetworkIndicator,
+    /// Virtual joystick and buttons on touch screens
+    TouchControls,
+    /// Name and avatar of the local player
+    LocalPlayerBanner,
+    /// Window for changing name and color
+    Profile,
+    PauseBanner,
+    /// Health and warning while a storm is running
+    HealthBar,
+    /// Time scale slider, netcode panel and entity inspector
+    DebugWindows,
+    /// Server panel of the host
+    ServerPanel,
+    /// Played back match and the followed player
+    ReplayBanner,
+}
+
+const ONLINE_HUD: &[HudWidget] = &[
+    HudWidget::Log,
+    HudWidget::PlayerList,
+    HudWidget::EventFeed,
+    HudWidget::NetworkIndicator,
+    HudWidget::TouchControls,
+    HudWidget::LocalPlayerBanner,
+    HudWidget::Profile,
+    HudWidget::PauseBanner,
+    HudWidget::HealthBar,
+    HudWidget::DebugWindows,
+    HudWidget::ServerPanel,
+];
+
+/// Peers have no server to report the connection, announce events, or pause and storm the game
+const LOCKSTEP_HUD: &[HudWidget] = &[
+    HudWidget::Log,
+    HudWidget::PlayerList,
+    HudWidget::TouchControls,
+    HudWidget::LocalPlayerBanner,
+    HudWidget::DebugWindows,
+];
+
+const REPLAY_HUD: &[HudWidget] = &[HudWidget::ReplayBanner];
+
+/// Widgets of the HUD in the given state and mode, in drawing order. Menus and dialogs are not
+/// part of the HUD, there are no widgets in those states.
+pub fn widgets(state: Option<&fsm::State>, mode: GameMode) -> &'static [HudWidget] {
+    match (state, mode) {
+        (Some(fsm::State::Playing), GameMode::Online) => ONLINE_HUD,
+        (Some(fsm::State::Playing), GameMode::Lockstep) => LOCKSTEP_HUD,
+        (Some(fsm::State::Replay), _) => REPLAY_HUD,
+        _ => &[],
+    }
+}
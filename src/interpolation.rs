@@ -0,0 +1,154 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    time::Instant,
+};
+
+use cgmath::{Vector2, VectorSpace};
+
+use crate::{globals, Player, PlayerID, Tick};
+
+/// Delay is kept at least one tick, so there's usually a snapshot to interpolate towards
+pub const MIN_DELAY_TICKS: f64 = 1.0;
+/// Remote players lagging behind more than half a second would be worse than stutter
+pub const MAX_DELAY_TICKS: f64 = 30.0;
+/// Guessing where a remote player went for longer than this is more often wrong than right
+pub const MAX_EXTRAPOLATION_TICKS: f64 = 15.0;
+/// Number of snapshot intervals to stay behind, so a single late snapshot doesn't leave a gap
+const DELAY_SNAPSHOT_INTERVALS: f64 = 2.0;
+/// Multiple of the measured jitter added on top of the delay
+const DELAY_JITTER_FACTOR: f64 = 2.0;
+/// Weight of a new sample in moving averages
+const SMOOTHING: f64 = 0.1;
+/// Server clock estimate is reset instead of corrected when it is off by this many ticks, e.g.
+/// after the window was minimized for a while
+const CLOCK_RESYNC_TICKS: f64 = 30.0;
+
+/// Jitter buffer for remote player snapshots.
+///
+/// Remote players are drawn at a point in server time slightly behind the newest snapshot,
+/// interpolating between the two snapshots around it. The delay adapts to the snapshot interval
+/// and the measured jitter of arrival, so irregular packet arrival doesn't make remote players
+/// stutter.
+pub struct SnapshotInterpolation {
+    /// Estimated server tick at `clock_instant`, corrected on every snapshot
+    clock_tick: f64,
+    clock_instant: Option<Instant>,
+    /// Average deviation of snapshot arrival from the estimated server clock, in ticks
+    jitter: f64,
+    /// Average number of ticks between snapshots of the same player
+    snapshot_interval: f64,
+    /// Rendering is this many ticks behind the estimated server clock
+    delay: f64,
+    /// Snapshots of each remote player in tick order
+    snapshots: HashMap<PlayerID, VecDeque<(Tick, Vector2<f32>)>>,
+    settings: InterpolationSettings,
+}
+
+/// Tuning of the interpolation, kept across sessions
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct InterpolationSettings {
+    /// Delay in ticks used instead of the adaptive one
+    pub fixed_delay: Option<f64>,
+    /// Ticks remote players keep moving along their last direction when rendering gets past their
+    /// newest snapshot. They are held at the newest snapshot if zero.
+    pub extrapolation_limit: f64,
+}
+
+impl Default for SnapshotInterpolation {
+    fn default() -> Self {
+        Self {
+            clock_tick: 0.0,
+            clock_instant: None,
+            jitter: 0.0,
+            snapshot_interval: 1.0,
+            delay: DELAY_SNAPSHOT_INTERVALS,
+            snapshots: HashMap::new(),
+            settings: InterpolationSettings::default(),
+        }
+    }
+}
+
+impl SnapshotInterpolation {
+    /// Buffer a snapshot received just now. Snapshots have to be newer than the previous one of
+    /// the same player.
+    pub fn push(&mut self, player_id: PlayerID, tick: Tick, pos: Vector2<f32>, now: Instant) {
+        match self.server_tick_at(now) {
+            Some(estimated_tick) if (tick as f64 - estimated_tick).abs() < CLOCK_RESYNC_TICKS => {
+                let deviation = tick as f64 - estimated_tick;
+                self.jitter += (deviation.abs() - self.jitter) * SMOOTHING;
+                self.clock_tick = estimated_tick + deviation * SMOOTHING;
+            }
+            _ => self.clock_tick = tick as f64,
+        }
+        self.clock_instant = Some(now);
+
+        let snapshots = self.snapshots.entry(player_id).or_default();
+        if let Some(&(previous_tick, _)) = snapshots.back() {
+            let interval = tick.saturating_sub(previous_tick) as f64;
+            self.snapshot_interval += (interval - self.snapshot_interval) * SMOOTHING;
+        }
+        snapshots.push_back((tick, pos));
+
+        let target_delay = (DELAY_SNAPSHOT_INTERVALS * self.snapshot_interval
+            + DELAY_JITTER_FACTOR * self.jitter)
+            .clamp(MIN_DELAY_TICKS, MAX_DELAY_TICKS);
+        self.delay += (target_delay - self.delay) * SMOOTHING;
+    }
+
+    pub fn remove(&mut self, player_id: PlayerID) {
+        self.snapshots.remove(&player_id);
+    }
+
+    pub fn clear(&mut self) {
+        *self = Self {
+            settings: self.settings,
+            ..Self::default()
+        };
+    }
+
+    pub fn set_settings(&mut self, settings: InterpolationSettings) {
+        self.settings = settings;
+    }
+
+    /// Ticks rendering is behind the server, either fixed or adapted to the connection
+    pub fn delay(&self) -> f64 {
+        self.settings.fixed_delay.unwrap_or(self.delay)
+    }
+
+    /// Move remote players to their interpolated positions at the current render time. Players
+    /// past their newest snapshot are extrapolated for a limited time, then held there.
+    pub fn apply(&mut self, players: &mut HashMap<PlayerID, Player>, now: Instant) {
+        let Some(server_tick) = self.server_tick_at(now) else {
+            return;
+        };
+        let render_tick = server_tick - self.delay();
+
+        for (player_id, snapshots) in &mut self.snapshots {
+            // Snapshots before the one preceding render time are not needed anymore, except for
+            // the last two giving the direction to extrapolate in
+            while snapshots.len() > 2 && snapshots[1].0 as f64 <= render_tick {
+                snapshots.pop_front();
+            }
+            let Some(player) = players.get_mut(player_id) else {
+                continue;
+            };
+            player.pos = match (snapshots.front(), snapshots.get(1)) {
+                (Some(&(from_tick, from)), Some(&(to_tick, to)))
+                    if render_tick > from_tick as f64 =>
+                {
+                    let render_tick =
+                        render_tick.min(to_tick as f64 + self.settings.extrapolation_limit);
+                    let amount = (render_tick - from_tick as f64) / (to_tick - from_tick) as f64;
+                    from.lerp(to, amount as f32)
+                }
+                (Some(&(_, pos)), _) => pos,
+                _ => continue,
+            };
+        }
+    }
+
+    fn server_tick_at(&self, now: Instant) -> Option<f64> {
+        let elapsed = now.saturating_duration_since(self.clock_instant?);
+        Some(self.clock_tick + elapsed.as_secs_f64() * globals::MAX_LOGIC_UPDATE_PER_SEC as f64)
+    }
+}
@@ -0,0 +1,124 @@
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket};
+
+/// Crockford's base32 alphabet, which leaves out letters easily mistaken for digits
+const ALPHABET: &[u8; 32] = b"0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+/// Characters between dashes of a code
+const GROUP_LEN: usize = 4;
+/// Characters encoding the address and port, without the checksum
+const IPV4_CODE_LEN: usize = 10;
+const IPV6_CODE_LEN: usize = 29;
+
+/// Short code standing for the address of a hosted server, e.g. `R2M0-22GY-C4M` for
+/// 192.168.1.10:7777, to be told to friends instead of an IP address and port. The last character
+/// is a checksum, so that typos are reported instead of connecting somewhere else.
+pub fn encode(address: SocketAddr) -> String {
+    let mut bytes = match address.ip() {
+        IpAddr::V4(ip) => ip.octets().to_vec(),
+        IpAddr::V6(ip) => ip.octets().to_vec(),
+    };
+    bytes.extend_from_slice(&address.port().to_be_bytes());
+
+    let mut values = Vec::new();
+    let mut bits: u32 = 0;
+    let mut bit_count = 0;
+    for byte in bytes {
+        bits = (bits << 8) | u32::from(byte);
+        bit_count += 8;
+        while bit_count >= 5 {
+            bit_count -= 5;
+            values.push((bits >> bit_count) as u8 & 0x1F);
+        }
+    }
+    if bit_count > 0 {
+        values.push((bits << (5 - bit_count)) as u8 & 0x1F);
+    }
+    values.push(checksum(&values));
+
+    let mut code = String::new();
+    for (index, value) in values.into_iter().enumerate() {
+        if index > 0 && index % GROUP_LEN == 0 {
+            code.push('-');
+        }
+        code.push(ALPHABET[usize::from(value)] as char);
+    }
+    code
+}
+
+/// Address of the server behind a code. Case, dashes and spaces don't matter, and letters
+/// mistaken for digits are read as the digits.
+pub fn decode(code: &str) -> Result<SocketAddr, String> {
+    let mut values = Vec::new();
+    for c in code.chars().filter(|c| !matches!(c, '-' | ' ')) {
+        let c = match c.to_ascii_uppercase() {
+            'O' => '0',
+            'I' | 'L' => '1',
+            c => c,
+        };
+        let value = ALPHABET
+            .iter()
+            .position(|&known| char::from(known) == c)
+            .ok_or_else(|| format!("Invalid character '{c}' in invite code"))?;
+        values.push(value as u8);
+    }
+    let Some(expected_checksum) = values.pop() else {
+        return Err("Invite code is empty".to_string());
+    };
+    if !matches!(values.len(), IPV4_CODE_LEN | IPV6_CODE_LEN) {
+        return Err("Invite code has the wrong length".to_string());
+    }
+    if checksum(&values) != expected_checksum {
+        return Err("Invite code has a typo".to_string());
+    }
+
+    let mut bytes = Vec::new();
+    let mut bits: u32 = 0;
+    let mut bit_count = 0;
+    for value in values {
+        bits = (bits << 5) | u32::from(value);
+        bit_count += 5;
+        if bit_count >= 8 {
+            bit_count -= 8;
+            bytes.push((bits >> bit_count) as u8);
+        }
+    }
+    // Padding of the last character is always zero in codes made by `encode()`
+    if bits & ((1 << bit_count) - 1) != 0 {
+        return Err("Invite code has a typo".to_string());
+    }
+
+    let (ip, port) = bytes.split_at(bytes.len() - 2);
+    let ip = match <[u8; 4]>::try_from(ip) {
+        Ok(octets) => IpAddr::from(octets),
+        Err(_) => IpAddr::from(<[u8; 16]>::try_from(ip).map_err(|_| "Invalid invite code")?),
+    };
+    Ok(SocketAddr::new(ip, u16::from_be_bytes([port[0], port[1]])))
+}
+
+/// Address other players can reach a server hosted on this machine at. Servers bound to the
+/// loopback or unspecified address are given the address of the network interface used for going
+/// online, which is the local network address behind a router.
+pub fn invite_address(host: IpAddr, port: u16) -> SocketAddr {
+    let ip = if host.is_loopback() || host.is_unspecified() {
+        local_network_address().unwrap_or(host)
+    } else {
+        host
+    };
+    SocketAddr::new(ip, port)
+}
+
+fn local_network_address() -> Option<IpAddr> {
+    // Connecting a UDP socket only picks the interface routing to the address, nothing is sent
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).ok()?;
+    socket.connect((Ipv4Addr::new(1, 1, 1, 1), 80)).ok()?;
+    Some(socket.local_addr().ok()?.ip())
+}
+
+/// Odd weights make every single mistyped character change the checksum
+fn checksum(values: &[u8]) -> u8 {
+    let sum = values
+        .iter()
+        .enumerate()
+        .map(|(index, &value)| (2 * index + 1) * usize::from(value))
+        .sum::<usize>();
+    (sum % ALPHABET.len()) as u8
+}
@@ -0,0 +1,160 @@
+use std::{
+    collections::HashMap,
+    net::{Ipv4Addr, SocketAddr, SocketAddrV4},
+    time::{Duration, Instant},
+};
+
+use socket2::{Domain, Protocol, Socket, Type};
+use tokio::{net::UdpSocket, sync::mpsc, task::JoinHandle};
+
+use crate::{
+    globals,
+    message::{self, DatagramHeader, Message},
+    task,
+};
+
+/// Administratively scoped multicast group, which routers don't forward beyond the local network
+pub const MULTICAST_GROUP: Ipv4Addr = Ipv4Addr::new(239, 255, 80, 80);
+/// Beacons are sent to this port instead of the game port, so that every client on a machine
+/// can listen to them while one of them is hosting
+pub const BEACON_PORT: u16 = globals::DEFAULT_PORT - 1;
+pub const BEACON_INTERVAL: Duration = Duration::from_secs(2);
+/// Servers are dropped from the list after missing a few beacons in a row
+pub const SERVER_TIMEOUT: Duration = Duration::from_secs(7);
+
+/// Server hosted on the LAN, as announced by its latest beacon
+#[derive(Clone, Debug, PartialEq)]
+pub struct LanServer {
+    /// Address to join at, the address the beacon came from with the announced game port
+    pub address: SocketAddr,
+    pub name: String,
+    pub players: u32,
+}
+
+/// Servers heard from recently, updated by every beacon and forgetting servers which stopped
+/// advertising
+#[derive(Debug, Default)]
+pub struct LanServers {
+    servers: HashMap<SocketAddr, (LanServer, Instant)>,
+}
+
+impl LanServers {
+    /// Add the server or update its name and players. True if the list changed.
+    pub fn record(&mut self, server: LanServer, now: Instant) -> bool {
+        match self.servers.insert(server.address, (server.clone(), now)) {
+            Some((previous, _)) => previous != server,
+            None => true,
+        }
+    }
+
+    /// Forget servers without a beacon for `SERVER_TIMEOUT`. True if the list changed.
+    pub fn expire(&mut self, now: Instant) -> bool {
+        let count = self.servers.len();
+        self.servers
+            .retain(|_, (_, heard_at)| now.duration_since(*heard_at) < SERVER_TIMEOUT);
+        self.servers.len() != count
+    }
+
+    /// Servers ordered by name, then by address
+    pub fn list(&self) -> Vec<LanServer> {
+        let mut servers: Vec<LanServer> = self
+            .servers
+            .values()
+            .map(|(server, _)| server.clone())
+            .collect();
+        servers.sort_by(|a, b| a.name.cmp(&b.name).then(a.address.cmp(&b.address)));
+        servers
+    }
+}
+
+/// Socket beacons are sent from. Beacons don't leave the local network.
+pub async fn bind_advertiser() -> std::io::Result<UdpSocket> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).await?;
+    socket.set_multicast_ttl_v4(1)?;
+    Ok(socket)
+}
+
+/// Send a `Message::Beacon` to the LAN multicast group, returning the size of the datagram
+pub async fn send_beacon(socket: &UdpSocket, beacon: &Message) -> std::io::Result<usize> {
+    let datagram = DatagramHeader::default().encode(&beacon.serialize());
+    socket
+        .send_to(&datagram, SocketAddrV4::new(MULTICAST_GROUP, BEACON_PORT))
+        .await?;
+    Ok(datagram.len())
+}
+
+/// Joined the LAN multicast group, sharing the beacon port with other clients on the machine
+fn bind_listener() -> std::io::Result<UdpSocket> {
+    let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+    socket.set_reuse_address(true)?;
+    // BSDs and macOS only share ports between sockets that all set this
+    #[cfg(all(unix, not(any(target_os = "solaris", target_os = "illumos"))))]
+    socket.set_reuse_port(true)?;
+    socket.bind(&SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, BEACON_PORT).into())?;
+    socket.join_multicast_v4(&MULTICAST_GROUP, &Ipv4Addr::UNSPECIFIED)?;
+    socket.set_nonblocking(true)?;
+    UdpSocket::from_std(socket.into())
+}
+
+/// Servers advertised on the LAN, kept up to date in the background while the browser exists
+pub struct LanBrowser {
+    beacon_rx: mpsc::UnboundedReceiver<LanServer>,
+    servers: LanServers,
+    listener: JoinHandle<()>,
+}
+
+impl LanBrowser {
+    /// Start listening to beacons. Has to be called within a Tokio runtime.
+    pub fn start() -> std::io::Result<Self> {
+        let socket = bind_listener()?;
+        let (beacon_tx, beacon_rx) = mpsc::unbounded_channel();
+        Ok(Self {
+            beacon_rx,
+            servers: LanServers::default(),
+            listener: task::spawn_named("lan_listener", beacon_listener(socket, beacon_tx)),
+        })
+    }
+
+    /// Take the beacons received since the last call and forget servers gone silent. True if
+    /// the list changed.
+    pub fn poll(&mut self, now: Instant) -> bool {
+        let mut changed = false;
+        while let Ok(server) = self.beacon_rx.try_recv() {
+            changed |= self.servers.record(server, now);
+        }
+        self.servers.expire(now) || changed
+    }
+
+    pub fn servers(&self) -> Vec<LanServer> {
+        self.servers.list()
+    }
+}
+
+impl Drop for LanBrowser {
+    fn drop(&mut self) {
+        self.listener.abort();
+    }
+}
+
+async fn beacon_listener(socket: UdpSocket, beacon_tx: mpsc::UnboundedSender<LanServer>) {
+    let mut buf = [0u8; globals::MAX_DATAGRAM_SIZE];
+    loop {
+        let Ok((len, sender)) = socket.recv_from(&mut buf).await else {
+            continue;
+        };
+        let Ok((_, payload)) = DatagramHeader::decode(&buf[..len]) else {
+            continue;
+        };
+        if let Ok(Message::Beacon(name, port, players)) = Message::deserialize(payload) {
+            message::trace(format!("Beacon from {sender}: {name} on port {port}"));
+            let server = LanServer {
+                address: SocketAddr::new(sender.ip(), port),
+                name,
+                players,
+            };
+            if beacon_tx.send(server).is_err() {
+                break;
+            }
+        }
+    }
+}
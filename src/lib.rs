@@ -1,17 +1,79 @@
+mod admin;
+pub mod afk;
 pub mod app;
+mod attract;
+pub mod avatar;
+mod backoff;
+mod banlist;
+mod bot;
+#[cfg(feature = "chaos")]
+pub mod chaos;
 pub mod client;
+mod client_config;
+pub mod client_metrics;
 pub use client::ClientSession;
+#[cfg(feature = "admin-dashboard")]
+mod dashboard;
+pub mod editor;
+mod filter;
 pub mod fsm;
 pub use fsm::StateMachine;
 pub mod gui;
+pub mod headless;
+pub mod history;
+pub mod hud;
+pub mod input;
+mod interpolation;
+pub mod invite;
+pub mod lan;
+mod lockstep;
+pub mod logger;
+pub mod map;
 pub mod message;
+pub mod net;
+pub mod netlog;
+mod observer;
+pub mod queue;
 mod renderer;
-pub use renderer::Renderer;
+pub use renderer::{Camera, RenderPass, Renderer, Scene};
+pub mod replay;
 pub mod server;
+mod settings;
+pub mod snapshot;
+pub mod soak;
+pub mod spatial;
+pub mod storm;
+mod stats;
+mod summary;
+#[cfg(feature = "status-http")]
+mod status;
+pub mod task;
+pub mod touch;
+pub mod transcript;
+mod whitelist;
 
 use cgmath::{Vector2, Vector3};
 
+/// Entry point called by `android-activity` when the app is launched on Android. Only the
+/// graphical client is available there, there's no command line to start a headless server.
+#[cfg(target_os = "android")]
+#[no_mangle]
+fn android_main(android_app: winit::platform::android::activity::AndroidApp) {
+    let rt = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .expect("Failed to create Tokio runtime");
+    if let Err(e) = app::run_android_app(&rt, android_app) {
+        eprintln!("Application error: {e}");
+    }
+}
+
 type PlayerID = u64;
+/// Secret handed to a client when joining a server, proving that messages come from that client
+/// and not just from its address
+type SessionToken = u64;
+/// Number of the server simulation tick, counted from server start
+type Tick = u64;
 
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub struct Player {
@@ -23,10 +85,11 @@ pub struct Player {
 
 impl Player {
     pub fn new(id: PlayerID, color: Vector3<f32>) -> Self {
-        let mut player = Player::default();
-        player.id = id;
-        player.color = color;
-        player
+        Self {
+            id,
+            color,
+            ..Default::default()
+        }
     }
 }
 
@@ -48,12 +111,49 @@ pub struct WorldBounds {
     pub max_y: f32,
 }
 
+/// Feel of the world chosen by the server and sent to players when they join, so that movement
+/// and clamping agree on both sides
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct WorldParams {
+    /// Side length of the square of a player
+    pub player_size: f32,
+    /// Distance travelled by a player in a single logic update
+    pub player_speed: f32,
+}
+
+impl Default for WorldParams {
+    fn default() -> Self {
+        Self {
+            player_size: globals::DEFAULT_PLAYER_SIZE,
+            player_speed: globals::DEFAULT_PLAYER_SPEED,
+        }
+    }
+}
+
+impl WorldParams {
+    /// Keep the whole square of the player within world bounds
+    pub fn clamp_player_to_bounds(&self, player: &mut Player) {
+        let bounds = &globals::WORLD_BOUNDS;
+        let half_size = self.player_size / 2.0;
+        player.pos.x = player
+            .pos
+            .x
+            .clamp(bounds.min_x + half_size, bounds.max_x - half_size);
+        player.pos.y = player
+            .pos
+            .y
+            .clamp(bounds.min_y + half_size, bounds.max_y - half_size);
+    }
+}
+
 pub mod globals {
-    use crate::{Player, WorldBounds};
+    use crate::WorldBounds;
 
     pub const LOCALHOST: &str = "127.0.0.1";
     pub const DEFAULT_PORT: u16 = 8080;
-    pub const PING_INTERVAL_MS: std::time::Duration = std::time::Duration::from_millis(20);
+    /// Longest time the server stays silent towards a player. Snapshots keep the connection
+    /// alive while the game is going, pings only fill the gaps.
+    pub const KEEPALIVE_INTERVAL: std::time::Duration = std::time::Duration::from_millis(250);
     pub const CONNECTION_TIMEOUT_SEC: std::time::Duration = std::time::Duration::from_secs(5);
 
     pub const WINDOW_SIZE: (u16, u16) = (800, 600);
@@ -65,13 +165,35 @@ pub mod globals {
     ///
     /// 60 logic updates per second is a common value used in games.
     /// - Higher update rate (120) can lead to smoother gameplay, more precise
-    /// control, at the cost of CPU load. Keep mobile devices in mind.
+    ///   control, at the cost of CPU load. Keep mobile devices in mind.
     /// - Lower update rate (30) reduces CPU load, runs game logic less frequently,
-    /// but can make game less responsive.
+    ///   but can make game less responsive.
     pub const MAX_LOGIC_UPDATE_PER_SEC: f32 = 60.0;
     pub const FIXED_UPDATE_TIMESTEP_SEC: f32 = 1.0 / MAX_LOGIC_UPDATE_PER_SEC;
+    /// Range of the debug time scale, which multiplies the speed of logic updates to examine
+    /// fast interactions in slow motion
+    pub const MIN_TIME_SCALE: f32 = 0.1;
+    pub const MAX_TIME_SCALE: f32 = 2.0;
 
-    pub const PLAYER_QUAD_SIZE: f32 = 24.0;
+    /// Player size and speed unless the server sets them, see `WorldParams`
+    pub const DEFAULT_PLAYER_SIZE: f32 = 24.0;
+    pub const DEFAULT_PLAYER_SPEED: f32 = 10.0;
+    /// Range of player sizes a server can set. Generated maps leave room for the largest.
+    pub const MIN_PLAYER_SIZE: f32 = 8.0;
+    pub const MAX_PLAYER_SIZE: f32 = 48.0;
+    /// Range of player speeds a server can set. Velocities are quantized up to the fastest.
+    pub const MIN_PLAYER_SPEED: f32 = 2.0;
+    pub const MAX_PLAYER_SPEED: f32 = 30.0;
+    pub const MAX_PLAYER_NAME_LEN: usize = 16;
+    pub const MAX_SERVER_NAME_LEN: usize = 32;
+    pub const MAX_CHAT_MESSAGE_LEN: usize = 100;
+    pub const DEFAULT_SERVER_NAME: &str = "Multiplayer demo server";
+    /// Length of a day in the world's time of day, which is replicated in minutes since midnight
+    pub const MINUTES_PER_DAY: u16 = 24 * 60;
+    /// Longest datagram accepted from the network, which is also the size of receive buffers.
+    /// Datagrams beyond this size are truncated and fail to parse. The most that fits into an
+    /// Ethernet frame after the IPv4 and UDP headers, the usual path MTU.
+    pub const MAX_DATAGRAM_SIZE: usize = 1472;
 
     /// World bounds are relative to origin (0,0)
     pub const WORLD_BOUNDS: WorldBounds = WorldBounds {
@@ -80,15 +202,4 @@ pub mod globals {
         max_x: 1200.0,
         max_y: 1200.0,
     };
-
-    pub fn clamp_player_to_bounds(player: &mut Player) {
-        player.pos.x = player.pos.x.clamp(
-            WORLD_BOUNDS.min_x + (PLAYER_QUAD_SIZE / 2.0),
-            WORLD_BOUNDS.max_x - (PLAYER_QUAD_SIZE / 2.0),
-        );
-        player.pos.y = player.pos.y.clamp(
-            WORLD_BOUNDS.min_y + (PLAYER_QUAD_SIZE / 2.0),
-            WORLD_BOUNDS.max_y - (PLAYER_QUAD_SIZE / 2.0),
-        );
-    }
 }
@@ -0,0 +1,381 @@
+use std::{
+    collections::BTreeMap,
+    error::Error,
+    net::{Ipv4Addr, SocketAddr},
+    sync::Arc,
+    time::Instant,
+};
+
+use cgmath::{InnerSpace, Vector2, Vector3};
+use tokio::{net::UdpSocket, sync::mpsc, task::JoinHandle};
+
+use crate::{
+    globals,
+    message::{self, DatagramHeader, Message},
+    task, Player, PlayerID, Tick, WorldParams,
+};
+
+pub const MAX_PEERS: usize = 4;
+/// Local input is scheduled this many ticks ahead, giving it time to reach the other peers before
+/// it is simulated, so that most ticks don't need a rollback
+const INPUT_DELAY_TICKS: Tick = 2;
+/// Simulation stalls instead of running further ahead than this on predicted inputs
+const MAX_PREDICTION_TICKS: Tick = 8;
+/// Peers can't be further ahead than this, because they stall on waiting for local inputs too.
+/// Inputs beyond it are ignored, so they can't pile up.
+const MAX_INPUT_LEAD_TICKS: Tick = 2 * (MAX_PREDICTION_TICKS + INPUT_DELAY_TICKS);
+/// Number of most recent local inputs sent in every message, so a lost datagram doesn't have to
+/// be resent. A peer can be waiting for inputs at most two prediction windows plus the input delay
+/// behind the local tick, which this has to cover.
+const INPUT_REDUNDANCY: usize = 24;
+const _: () = assert!(INPUT_REDUNDANCY <= message::MAX_INPUTS_PER_MESSAGE);
+
+// Inputs are sent as one hex digit of direction bits per tick
+const INPUT_UP: u8 = 1;
+const INPUT_DOWN: u8 = 2;
+const INPUT_LEFT: u8 = 4;
+const INPUT_RIGHT: u8 = 8;
+/// Sine of 22.5 degrees, so analog directions snap to the closest of the 8 directions
+const INPUT_AXIS_THRESHOLD: f32 = 0.383;
+
+/// Colors are fixed per peer, because there's no server handing them out
+const PLAYER_COLORS: [Vector3<f32>; MAX_PEERS] = [
+    Vector3::new(0.9, 0.2, 0.2),
+    Vector3::new(0.2, 0.5, 0.9),
+    Vector3::new(0.2, 0.8, 0.3),
+    Vector3::new(0.9, 0.8, 0.2),
+];
+const SPAWN_DISTANCE: f32 = 100.0;
+
+type ChannelSender = mpsc::UnboundedSender<(Vec<u8>, SocketAddr)>;
+type ChannelReceiver = mpsc::UnboundedReceiver<(Vec<u8>, SocketAddr)>;
+
+/// Peer-to-peer session where every peer simulates the same deterministic game from the inputs of
+/// all peers, like fighting games do. There's no server, only inputs are exchanged.
+///
+/// Inputs of remote peers that haven't arrived yet are predicted to be the same as their last
+/// known one. When an input arrives that differs from the prediction, the game state is rolled
+/// back to the tick of that input and simulated again up to the current tick.
+pub struct LockstepSession {
+    /// Position of this peer in `peers`, also deciding player ID, color and spawn position
+    local_index: usize,
+    /// Addresses of all peers in the same order on every peer, including this one
+    peers: Vec<SocketAddr>,
+    listen_rx: ChannelReceiver,
+    send_tx: ChannelSender,
+    listen_task: JoinHandle<()>,
+    /// Next tick to be simulated
+    tick: Tick,
+    /// Players in peer order
+    players: Vec<Player>,
+    /// Known inputs of every peer by tick
+    inputs: Vec<BTreeMap<Tick, u8>>,
+    /// First tick of every peer whose input is not known yet. Inputs before it are all known.
+    next_unknown: Vec<Tick>,
+    /// Inputs the not yet confirmed ticks were simulated with, in peer order
+    used_inputs: BTreeMap<Tick, Vec<u8>>,
+    /// Game state before simulating each not yet confirmed tick
+    saved_states: BTreeMap<Tick, Vec<Player>>,
+    /// Arrival of the last message of every peer, `None` until the first one
+    last_received: Vec<Option<Instant>>,
+}
+
+pub type LockstepSessionResult = Result<LockstepSession, Box<dyn Error + Send + Sync>>;
+
+impl LockstepSession {
+    /// Bind the port of this peer's own address and setup messaging channels. Peers are not
+    /// waited for, simulation stalls until their inputs arrive.
+    pub async fn new(peers: Vec<SocketAddr>, local_index: usize) -> LockstepSessionResult {
+        if !(2..=MAX_PEERS).contains(&peers.len()) {
+            return Err(format!("Lockstep mode needs 2 to {MAX_PEERS} peers.").into());
+        }
+        let Some(local_address) = peers.get(local_index) else {
+            return Err(format!(
+                "Peer index {local_index} is out of range for {} peers.",
+                peers.len()
+            )
+            .into());
+        };
+
+        let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, local_address.port())).await?;
+        let socket = Arc::new(socket);
+
+        let (listen_tx, listen_rx) = mpsc::unbounded_channel();
+        let (send_tx, send_rx) = mpsc::unbounded_channel();
+        let listen_task = task::spawn_named(
+            "lockstep_listen_handler",
+            listen_handler(socket.clone(), listen_tx),
+        );
+        task::spawn_named("lockstep_send_handler", send_handler(socket, send_rx));
+
+        let players = (0..peers.len()).map(spawn_player).collect();
+        // Ticks before the input delay can't have input from anyone
+        let inputs = vec![(0..INPUT_DELAY_TICKS).map(|tick| (tick, 0)).collect(); peers.len()];
+
+        println!("Waiting for peers");
+        Ok(Self {
+            local_index,
+            next_unknown: vec![INPUT_DELAY_TICKS; peers.len()],
+            last_received: vec![None; peers.len()],
+            peers,
+            listen_rx,
+            send_tx,
+            listen_task,
+            tick: 0,
+            players,
+            inputs,
+            used_inputs: BTreeMap::new(),
+            saved_states: BTreeMap::new(),
+        })
+    }
+
+    pub fn local_player(&self) -> Player {
+        self.players[self.local_index]
+    }
+
+    pub fn players(&self) -> &[Player] {
+        &self.players
+    }
+
+    pub fn peer_count(&self) -> usize {
+        self.peers.len()
+    }
+
+    /// Process inputs received from peers and simulate the next tick with the given local
+    /// movement direction. Returns notices for the gameplay log, or the reason why the session is
+    /// over.
+    pub fn advance(&mut self, direction: Vector2<f32>) -> Result<Vec<String>, String> {
+        let mut notices = Vec::new();
+
+        // Apply received inputs, finding the oldest tick that was simulated with a wrong prediction
+        let mut rollback_tick = None;
+        while let Ok((msg, sender)) = self.listen_rx.try_recv() {
+            let Some(index) = self.peers.iter().position(|peer| *peer == sender) else {
+                continue;
+            };
+            if index == self.local_index {
+                continue;
+            }
+            match Message::deserialize(&msg) {
+                Ok(Message::Input(player_id, first_tick, inputs))
+                    if player_id == peer_player_id(index) =>
+                {
+                    if self.last_received[index].is_none() {
+                        notices.push(format!("Player {player_id} connected"));
+                    }
+                    self.last_received[index] = Some(Instant::now());
+                    for (tick, input) in (first_tick..).zip(inputs) {
+                        if tick < self.next_unknown[index]
+                            || tick > self.tick + MAX_INPUT_LEAD_TICKS
+                            || self.inputs[index].insert(tick, input).is_some()
+                        {
+                            continue;
+                        }
+                        if self
+                            .used_inputs
+                            .get(&tick)
+                            .is_some_and(|used| used[index] != input)
+                        {
+                            rollback_tick = Some(rollback_tick.map_or(tick, |t: Tick| t.min(tick)));
+                        }
+                    }
+                    while self.inputs[index].contains_key(&self.next_unknown[index]) {
+                        self.next_unknown[index] += 1;
+                    }
+                }
+                Ok(Message::Leave(player_id)) if player_id == peer_player_id(index) => {
+                    return Err(format!("Player {player_id} left the session."));
+                }
+                _ => (),
+            }
+        }
+
+        // Peers are only timed out once they showed up, waiting for the first one is indefinite
+        for (index, last_received) in self.last_received.iter().enumerate() {
+            if last_received.is_some_and(|last| last.elapsed() >= globals::CONNECTION_TIMEOUT_SEC) {
+                return Err(format!(
+                    "Connection to Player {} was lost.",
+                    peer_player_id(index)
+                ));
+            }
+        }
+
+        if let Some(rollback_tick) = rollback_tick {
+            if let Some(state) = self.saved_states.get(&rollback_tick) {
+                self.players = state.clone();
+                for tick in rollback_tick..self.tick {
+                    self.simulate(tick);
+                }
+            }
+        }
+
+        let oldest_unknown = self.next_unknown.iter().copied().min().unwrap_or(self.tick);
+        if self.tick >= oldest_unknown + MAX_PREDICTION_TICKS {
+            // Keep resending, the peer being waited for might be missing inputs too
+            self.send_inputs();
+            return Ok(notices);
+        }
+
+        let local_tick = self.tick + INPUT_DELAY_TICKS;
+        self.inputs[self.local_index].insert(local_tick, input_bits(direction));
+        self.next_unknown[self.local_index] = local_tick + 1;
+        self.send_inputs();
+
+        self.simulate(self.tick);
+        self.tick += 1;
+
+        // States and inputs of ticks known by everyone won't be needed for rollback anymore.
+        // Local inputs are kept for a while longer to be resent.
+        let oldest_unknown = self.next_unknown.iter().copied().min().unwrap_or(self.tick);
+        self.used_inputs = self.used_inputs.split_off(&oldest_unknown);
+        self.saved_states = self.saved_states.split_off(&oldest_unknown);
+        let oldest_kept = oldest_unknown.saturating_sub(INPUT_REDUNDANCY as Tick);
+        for inputs in &mut self.inputs {
+            *inputs = inputs.split_off(&oldest_kept);
+        }
+
+        Ok(notices)
+    }
+
+    /// Tell peers that this one is gone, so they don't wait for the timeout.
+    pub fn leave(&self) {
+        let msg = Message::Leave(peer_player_id(self.local_index)).serialize();
+        for &peer in self.remote_peers() {
+            let _ = self.send_tx.send((msg.clone(), peer));
+        }
+    }
+
+    fn send_inputs(&self) {
+        let oldest_sent =
+            self.next_unknown[self.local_index].saturating_sub(INPUT_REDUNDANCY as Tick);
+        let mut local_inputs = self.inputs[self.local_index]
+            .range(oldest_sent..)
+            .peekable();
+        let Some(&(&first_tick, _)) = local_inputs.peek() else {
+            return;
+        };
+        let inputs = local_inputs.map(|(_, &input)| input).collect();
+        let msg = Message::Input(peer_player_id(self.local_index), first_tick, inputs).serialize();
+        for &peer in self.remote_peers() {
+            let _ = self.send_tx.send((msg.clone(), peer));
+        }
+    }
+
+    fn remote_peers(&self) -> impl Iterator<Item = &SocketAddr> {
+        self.peers
+            .iter()
+            .enumerate()
+            .filter(|(index, _)| *index != self.local_index)
+            .map(|(_, peer)| peer)
+    }
+
+    /// Simulate a tick from the current state. Remote inputs not known yet are predicted to be the
+    /// same as the last known one.
+    fn simulate(&mut self, tick: Tick) {
+        self.saved_states.insert(tick, self.players.clone());
+        let inputs: Vec<u8> = self
+            .inputs
+            .iter()
+            .map(|inputs| {
+                inputs
+                    .range(..=tick)
+                    .next_back()
+                    .map_or(0, |(_, &input)| input)
+            })
+            .collect();
+        for (player, &input) in self.players.iter_mut().zip(&inputs) {
+            // Peers have no server to tell them the feel of the world
+            let world_params = WorldParams::default();
+            player.velocity = input_direction(input) * world_params.player_speed;
+            player.pos += player.velocity;
+            world_params.clamp_player_to_bounds(player);
+        }
+        self.used_inputs.insert(tick, inputs);
+    }
+}
+
+impl Drop for LockstepSession {
+    fn drop(&mut self) {
+        self.listen_task.abort();
+        // Send task finishes by itself after flushing queued messages (like LEAVE)
+        self.listen_rx.close();
+    }
+}
+
+fn peer_player_id(index: usize) -> PlayerID {
+    index as PlayerID + 1
+}
+
+/// Peers start around the center of the world, in the same place on every peer
+fn spawn_player(index: usize) -> Player {
+    let angle = index as f32 * std::f32::consts::FRAC_PI_2;
+    let mut player = Player::new(peer_player_id(index), PLAYER_COLORS[index]);
+    player.pos = Vector2::new(angle.cos(), angle.sin()) * SPAWN_DISTANCE;
+    player
+}
+
+/// Snap a movement direction to direction bits. Only the bits are exchanged and simulated, so
+/// every peer arrives at the same positions.
+fn input_bits(direction: Vector2<f32>) -> u8 {
+    let mut input = 0;
+    if direction.y < -INPUT_AXIS_THRESHOLD {
+        input |= INPUT_UP;
+    }
+    if direction.y > INPUT_AXIS_THRESHOLD {
+        input |= INPUT_DOWN;
+    }
+    if direction.x < -INPUT_AXIS_THRESHOLD {
+        input |= INPUT_LEFT;
+    }
+    if direction.x > INPUT_AXIS_THRESHOLD {
+        input |= INPUT_RIGHT;
+    }
+    input
+}
+
+fn input_direction(input: u8) -> Vector2<f32> {
+    let mut direction = Vector2::new(0.0, 0.0);
+    if input & INPUT_UP != 0 {
+        direction.y -= 1.0;
+    }
+    if input & INPUT_DOWN != 0 {
+        direction.y += 1.0;
+    }
+    if input & INPUT_LEFT != 0 {
+        direction.x -= 1.0;
+    }
+    if input & INPUT_RIGHT != 0 {
+        direction.x += 1.0;
+    }
+    if direction != Vector2::new(0.0, 0.0) {
+        direction = direction.normalize();
+    }
+    direction
+}
+
+async fn listen_handler(socket: Arc<UdpSocket>, listen_tx: ChannelSender) {
+    let mut buf = [0u8; globals::MAX_DATAGRAM_SIZE];
+    // Errors like ICMP port unreachable from a peer that hasn't started yet are not fatal
+    loop {
+        let Ok((len, sender)) = socket.recv_from(&mut buf).await else {
+            continue;
+        };
+        if let Ok((_, msg)) = DatagramHeader::decode(&buf[..len]) {
+            message::trace(format!(
+                "Received from {sender}: {:?}",
+                Message::deserialize(msg)
+            ));
+            if listen_tx.send((msg.to_vec(), sender)).is_err() {
+                break;
+            }
+        }
+    }
+}
+
+async fn send_handler(socket: Arc<UdpSocket>, mut rx: ChannelReceiver) {
+    while let Some((msg, peer)) = rx.recv().await {
+        let datagram = DatagramHeader::default().encode(&msg);
+        let _ = socket.send_to(&datagram, peer).await;
+        message::trace(format!("Sent to {peer}: {:?}", Message::deserialize(&msg)));
+    }
+}
@@ -0,0 +1,238 @@
+use std::{
+    collections::HashMap,
+    fs::{File, OpenOptions},
+    hash::Hash,
+    io::{IsTerminal, Write},
+    path::{Path, PathBuf},
+    sync::{Mutex, OnceLock},
+    time::{Duration, Instant},
+};
+
+/// Log file is rotated when it grows beyond this size
+const MAX_LOG_FILE_SIZE: u64 = 10 * 1024 * 1024;
+/// Number of rotated log files kept besides the current one, e.g. `server.log.1` to `server.log.3`
+const LOG_FILE_BACKUPS: usize = 3;
+/// Repeats of the same log line within this long after it was written are collapsed into a
+/// single line counting them
+pub const REPEAT_WINDOW: Duration = Duration::from_secs(10);
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Level {
+    Trace,
+    Info,
+    Warn,
+    Error,
+}
+
+impl Level {
+    fn label(self) -> &'static str {
+        match self {
+            Level::Trace => "TRACE",
+            Level::Info => "INFO",
+            Level::Warn => "WARN",
+            Level::Error => "ERROR",
+        }
+    }
+
+    fn ansi_color(self) -> &'static str {
+        match self {
+            Level::Trace => "\x1b[90m", // Gray
+            Level::Info => "\x1b[32m",  // Green
+            Level::Warn => "\x1b[33m",  // Yellow
+            Level::Error => "\x1b[31m", // Red
+        }
+    }
+}
+
+static LOG_FILE: OnceLock<Mutex<RollingFile>> = OnceLock::new();
+
+/// Log lines written recently, keyed by level, subsystem and message
+type LineKey = (Level, String, String);
+static REPEATS: OnceLock<Mutex<LogDedup<LineKey>>> = OnceLock::new();
+
+fn repeats() -> &'static Mutex<LogDedup<LineKey>> {
+    REPEATS.get_or_init(|| Mutex::new(LogDedup::new(REPEAT_WINDOW)))
+}
+
+/// Additionally write every log line into the given file, rotating it when it gets too large.
+pub fn set_log_file(path: &Path) -> std::io::Result<()> {
+    let file = RollingFile::open(path)?;
+    LOG_FILE
+        .set(Mutex::new(file))
+        .map_err(|_| std::io::Error::other("Log file is already set"))
+}
+
+pub fn info(subsystem: &str, msg: String) {
+    log(Level::Info, subsystem, msg);
+}
+
+pub fn warn(subsystem: &str, msg: String) {
+    log(Level::Warn, subsystem, msg);
+}
+
+pub fn error(subsystem: &str, msg: String) {
+    log(Level::Error, subsystem, msg);
+}
+
+/// Print a timestamped log line. Warnings and errors go to standard error, and are colored like
+/// the rest only if the output is a terminal and `NO_COLOR` is not set.
+///
+/// Repeats of a line within `REPEAT_WINDOW`, like the same error for every datagram while a
+/// connection is degraded, are counted instead of printed. Traces are printed as they are, each
+/// message matters there.
+pub fn log(level: Level, subsystem: &str, msg: String) {
+    if level == Level::Trace {
+        write_line(level, subsystem, &msg);
+        return;
+    }
+    let (summaries, admitted) = {
+        let mut repeats = repeats().lock().unwrap();
+        let now = Instant::now();
+        let summaries = repeats.expired(now);
+        let admitted = repeats.admit((level, subsystem.to_string(), msg.clone()), now);
+        (summaries, admitted)
+    };
+    for ((level, subsystem, msg), count) in summaries {
+        write_line(level, &subsystem, &repeated(&msg, count));
+    }
+    if admitted {
+        write_line(level, subsystem, &msg);
+    }
+}
+
+/// Print the counts of repeated lines whose window has passed, for when nothing else is logged
+/// for a while.
+pub fn flush_repeats() {
+    let summaries = repeats().lock().unwrap().expired(Instant::now());
+    for ((level, subsystem, msg), count) in summaries {
+        write_line(level, &subsystem, &repeated(&msg, count));
+    }
+}
+
+/// Line standing for the given number of repeats of a message left out of the log
+pub fn repeated(msg: &str, count: u32) -> String {
+    format!("{msg} (repeated \u{d7}{count})")
+}
+
+fn write_line(level: Level, subsystem: &str, msg: &str) {
+    let timestamp = chrono::Local::now().format("%Y-%m-%d %H:%M:%S%.3f");
+    let line = format!("{timestamp} {:<5} [{subsystem}] {msg}", level.label());
+
+    if matches!(level, Level::Warn | Level::Error) {
+        let mut stderr = std::io::stderr().lock();
+        let _ = writeln!(stderr, "{}", colorize(level, &line, stderr.is_terminal()));
+    } else {
+        let mut stdout = std::io::stdout().lock();
+        let _ = writeln!(stdout, "{}", colorize(level, &line, stdout.is_terminal()));
+    }
+
+    if let Some(file) = LOG_FILE.get() {
+        if let Ok(mut file) = file.lock() {
+            file.write_line(&line);
+        }
+    }
+}
+
+fn colorize(level: Level, line: &str, is_terminal: bool) -> String {
+    if !is_terminal || std::env::var_os("NO_COLOR").is_some() {
+        return line.to_string();
+    }
+    format!("{}{line}\x1b[0m", level.ansi_color())
+}
+
+/// Collapses repeats of log lines. The first occurrence of a line is written, further occurrences
+/// within the window are only counted, and the count is handed out once the window has passed.
+pub struct LogDedup<K> {
+    window: Duration,
+    /// When each line was last written, and how many times it was left out since
+    recent: HashMap<K, (Instant, u32)>,
+}
+
+impl<K: Hash + Eq> LogDedup<K> {
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            recent: HashMap::new(),
+        }
+    }
+
+    /// Whether the line should be written, otherwise it's counted as a repeat. Repeats of lines
+    /// written longer than the window ago are only reported by `expired()`, which is to be called
+    /// first.
+    pub fn admit(&mut self, line: K, now: Instant) -> bool {
+        match self.recent.get_mut(&line) {
+            Some((written, repeats)) if now.duration_since(*written) < self.window => {
+                *repeats += 1;
+                false
+            }
+            _ => {
+                self.recent.insert(line, (now, 0));
+                true
+            }
+        }
+    }
+
+    /// Forget lines written longer than the window ago, returning those that were repeated with
+    /// the number of repeats left out.
+    pub fn expired(&mut self, now: Instant) -> Vec<(K, u32)> {
+        let window = self.window;
+        let is_expired = |written: &Instant| now.duration_since(*written) >= window;
+        if !self.recent.values().any(|(written, _)| is_expired(written)) {
+            return Vec::new();
+        }
+        let (expired, recent) = std::mem::take(&mut self.recent)
+            .into_iter()
+            .partition::<HashMap<_, _>, _>(|(_, (written, _))| is_expired(written));
+        self.recent = recent;
+        expired
+            .into_iter()
+            .filter(|(_, (_, repeats))| *repeats > 0)
+            .map(|(line, (_, repeats))| (line, repeats))
+            .collect()
+    }
+}
+
+/// Append-only log file that is renamed to `<path>.1` when full, shifting older backups.
+struct RollingFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+}
+
+impl RollingFile {
+    fn open(path: &Path) -> std::io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let size = file.metadata()?.len();
+        Ok(Self {
+            path: path.to_path_buf(),
+            file,
+            size,
+        })
+    }
+
+    fn write_line(&mut self, line: &str) {
+        if self.size >= MAX_LOG_FILE_SIZE {
+            if let Err(e) = self.rotate() {
+                eprintln!("Failed to rotate log file {}: {e}", self.path.display());
+            }
+        }
+        if writeln!(self.file, "{line}").is_ok() {
+            self.size += line.len() as u64 + 1;
+        }
+    }
+
+    fn rotate(&mut self) -> std::io::Result<()> {
+        for i in (1..LOG_FILE_BACKUPS).rev() {
+            let _ = std::fs::rename(self.backup_path(i), self.backup_path(i + 1));
+        }
+        std::fs::rename(&self.path, self.backup_path(1))?;
+        *self = Self::open(&self.path)?;
+        Ok(())
+    }
+
+    fn backup_path(&self, index: usize) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{index}"));
+        PathBuf::from(path)
+    }
+}
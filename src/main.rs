@@ -1,8 +1,10 @@
-use std::error::Error;
+use std::{error::Error, net::SocketAddr, path::PathBuf};
 
 use clap::Parser;
 
-use multiplayer_game_demo_rust::{app, globals, message, server};
+use multiplayer_game_demo_rust::{
+    app, client, globals, headless, logger, message, server, soak, task, transcript, WorldParams,
+};
 
 #[derive(Parser)]
 #[command(
@@ -24,12 +26,379 @@ struct Cli {
     )]
     port: u16,
 
+    #[arg(
+        long,
+        requires = "connect",
+        conflicts_with = "server_only",
+        help = "Starts a client only in headless mode without graphical user interface, moving the player around in a scripted way. Requires --connect."
+    )]
+    client_only: bool,
+
+    #[arg(
+        long,
+        require_equals = true,
+        requires = "client_only",
+        help = "Serve connection metrics of the headless client (round-trip time, packet loss, corrections) for Prometheus on http://<host>:<PORT>/metrics. Used with --client-only."
+    )]
+    metrics_port: Option<u16>,
+
+    #[arg(
+        long,
+        require_equals = true,
+        requires = "server_only",
+        help = "File of player names and IP addresses allowed to join, one per line. Makes the server private. Changes are picked up without restart. Used with --server-only."
+    )]
+    whitelist: Option<PathBuf>,
+
+    #[arg(
+        long,
+        require_equals = true,
+        requires = "server_only",
+        help = "JSON file where bans and mutes issued on the admin console are stored. Created if missing. Used with --server-only."
+    )]
+    banlist: Option<PathBuf>,
+
+    #[arg(
+        long,
+        require_equals = true,
+        requires = "server_only",
+        help = "TOML file of settings (motd, max_players, snapshot_rate, banlist) which can be reloaded with the reload admin command or SIGHUP. Used with --server-only."
+    )]
+    config: Option<PathBuf>,
+
+    #[arg(
+        long,
+        requires = "server_only",
+        help = "Replicate world state less often while the server can't keep up with the tick rate. Used with --server-only."
+    )]
+    shed_load: bool,
+
+    #[arg(
+        long,
+        requires = "server_only",
+        help = "Announce the server to clients on the local network, which list it in their menu. Used with --server-only."
+    )]
+    advertise_lan: bool,
+
+    #[arg(
+        long,
+        require_equals = true,
+        requires = "server_only",
+        help = "Seed shared with players for cosmetic randomness, so that effects look the same after a restart. Random if omitted. Used with --server-only."
+    )]
+    seed: Option<u64>,
+
+    #[arg(
+        long,
+        require_equals = true,
+        requires = "server_only",
+        default_value_t = globals::DEFAULT_PLAYER_SIZE,
+        value_parser = parse_player_size,
+        help = "Side length of the square of each player in world units, sent to players when they join. Used with --server-only."
+    )]
+    player_size: f32,
+
+    #[arg(
+        long,
+        require_equals = true,
+        requires = "server_only",
+        default_value_t = globals::DEFAULT_PLAYER_SPEED,
+        value_parser = parse_player_speed,
+        help = "Distance travelled by players in a single logic update, sent to players when they join. Used with --server-only."
+    )]
+    player_speed: f32,
+
+    #[arg(
+        long,
+        require_equals = true,
+        requires = "server_only",
+        help = "Also write server log into this file. Rotated when it reaches 10 MB, keeping 3 old files. Used with --server-only."
+    )]
+    log_file: Option<PathBuf>,
+
+    #[cfg(feature = "status-http")]
+    #[arg(
+        long,
+        require_equals = true,
+        requires = "server_only",
+        help = "Serve server status on http://<host>:<PORT>/status and metrics on /metrics as JSON. Used with --server-only."
+    )]
+    status_port: Option<u16>,
+
+    #[cfg(feature = "admin-dashboard")]
+    #[arg(
+        long,
+        require_equals = true,
+        requires = "status_port",
+        help = "Serve the admin dashboard on http://<host>:<PORT>/admin of --status-port, asking for the token in this file. Used with --server-only."
+    )]
+    admin_token_file: Option<PathBuf>,
+
+    #[arg(
+        long,
+        require_equals = true,
+        requires = "server_only",
+        help = "Stream joins, leaves, positions and scores as JSON lines to local tools connecting to this TCP port. Used with --server-only."
+    )]
+    observer_port: Option<u16>,
+
+    #[arg(
+        long,
+        require_equals = true,
+        requires = "server_only",
+        help = "Record every match with its map and seed into a replay file in this directory, which can be watched with --play-replay. Created if missing. Used with --server-only."
+    )]
+    replay_dir: Option<PathBuf>,
+
+    #[arg(
+        long,
+        require_equals = true,
+        conflicts_with_all = ["server_only", "client_only", "connect", "lockstep_peers", "attract", "play_replay"],
+        help = "Run a server on --port with bots joining, walking around and leaving it for a soak test, writing memory usage, task counts and tick durations into this CSV file every 10 seconds. Runs until CTRL+C or --soak-minutes."
+    )]
+    soak: Option<PathBuf>,
+
+    #[arg(
+        long,
+        require_equals = true,
+        default_value_t = soak::DEFAULT_BOTS,
+        requires = "soak",
+        help = "Number of bots in the soak test (--soak)."
+    )]
+    soak_bots: usize,
+
+    #[arg(
+        long,
+        require_equals = true,
+        requires = "soak",
+        help = "Stop the soak test (--soak) after this many minutes."
+    )]
+    soak_minutes: Option<u64>,
+
     #[arg(long, help = "Enable tracing of UDP messages on console log.")]
     trace: bool,
+
+    #[arg(
+        long,
+        require_equals = true,
+        value_parser = ["json", "markdown"],
+        help = "Print the schema of every protocol message as JSON or as a Markdown table, then exit. Meant for implementing clients in other languages."
+    )]
+    print_protocol: Option<String>,
+
+    #[arg(
+        long,
+        require_equals = true,
+        help = "Record the datagrams of a scripted client/server session into this golden transcript file, then exit."
+    )]
+    record_transcript: Option<PathBuf>,
+
+    #[arg(
+        long,
+        require_equals = true,
+        conflicts_with = "record_transcript",
+        help = "Compare the datagrams of a scripted client/server session against this golden transcript file, then exit. Fails on any difference, catching unintended changes of the wire format."
+    )]
+    check_transcript: Option<PathBuf>,
+
+    #[arg(
+        long,
+        require_equals = true,
+        conflicts_with = "server_only",
+        value_parser = parse_server_address,
+        help = "Skip the menu and connect to the server at given address (e.g. 127.0.0.1:8080 or myserver:8080) on startup."
+    )]
+    connect: Option<String>,
+
+    #[arg(
+        long,
+        require_equals = true,
+        value_parser = parse_name,
+        help = "Player name shown to others, or server name advertised to players in headless mode (--server-only). Default name is picked if omitted."
+    )]
+    name: Option<String>,
+
+    #[arg(
+        long,
+        require_equals = true,
+        default_value_t = client::DEFAULT_SEND_RATE,
+        value_parser = parse_send_rate,
+        conflicts_with = "server_only",
+        help = "Positions sent to the server per second while moving, between 20 and 60."
+    )]
+    send_rate: u32,
+
+    #[arg(
+        long,
+        require_equals = true,
+        value_delimiter = ',',
+        requires = "lockstep_index",
+        conflicts_with_all = ["server_only", "client_only", "connect"],
+        help = "Play without a server in peer-to-peer rollback lockstep mode with 2 to 4 peers, given as comma-separated addresses including this one (e.g. 127.0.0.1:9001,127.0.0.1:9002). Every peer has to use the same list. Requires --lockstep-index."
+    )]
+    lockstep_peers: Vec<SocketAddr>,
+
+    #[arg(
+        long,
+        require_equals = true,
+        requires = "lockstep_peers",
+        help = "Position of this peer's own address in --lockstep-peers, counted from 0."
+    )]
+    lockstep_index: Option<usize>,
+
+    #[arg(
+        long,
+        conflicts_with_all = ["server_only", "client_only", "lockstep_peers"],
+        help = "Play a match of bots behind the menu after sitting idle in it for 30 seconds, until any input."
+    )]
+    attract: bool,
+
+    #[arg(
+        long,
+        require_equals = true,
+        conflicts_with_all = ["server_only", "client_only"],
+        help = "Write the movement keys pressed while playing to this file on exit, one change per line with the number of logic updates spent playing before it."
+    )]
+    record_input: Option<PathBuf>,
+
+    #[arg(
+        long,
+        require_equals = true,
+        conflicts_with_all = ["server_only", "client_only", "attract"],
+        help = "Move the player with keys recorded by --record-input instead of the keyboard, then quit once every key has been played back."
+    )]
+    play_input: Option<PathBuf>,
+
+    #[arg(
+        long,
+        require_equals = true,
+        conflicts_with_all = ["server_only", "client_only", "connect", "lockstep_peers", "play_input"],
+        help = "Watch a match replay recorded by a server with --replay-dir, then return to the menu. Tab switches the followed player."
+    )]
+    play_replay: Option<PathBuf>,
+
+    #[arg(
+        long,
+        require_equals = true,
+        conflicts_with_all = ["server_only", "client_only"],
+        help = "Text appended to the window title, to tell apart instances running on the same machine in the taskbar."
+    )]
+    window_title_suffix: Option<String>,
+
+    #[arg(
+        long,
+        require_equals = true,
+        default_value_t = app::DEFAULT_CAMERA_LOOK_AHEAD,
+        value_parser = parse_camera_look_ahead,
+        conflicts_with_all = ["server_only", "client_only"],
+        help = "Distance in pixels the camera leads the player by in the direction of movement at full speed, between 0 (centered on the player) and 200."
+    )]
+    camera_look_ahead: f32,
+}
+
+/// Only the looser server name rules can be checked during parsing, player names are validated
+/// once the mode is known.
+fn parse_name(name: &str) -> Result<String, String> {
+    message::validate_server_name(name)?;
+    Ok(name.to_string())
+}
+
+/// Host name or IP address with a port, resolved when connecting the same as in the menu
+fn parse_server_address(address: &str) -> Result<String, String> {
+    match address.rsplit_once(':') {
+        Some((host, port)) if !host.is_empty() && port.parse::<u16>().is_ok() => {
+            Ok(address.to_string())
+        }
+        _ => Err("must be a host name or IP address followed by :PORT".to_string()),
+    }
+}
+
+fn parse_send_rate(send_rate: &str) -> Result<u32, String> {
+    send_rate
+        .parse()
+        .ok()
+        .filter(|send_rate| (client::MIN_SEND_RATE..=client::MAX_SEND_RATE).contains(send_rate))
+        .ok_or_else(|| {
+            format!(
+                "must be between {} and {}",
+                client::MIN_SEND_RATE,
+                client::MAX_SEND_RATE
+            )
+        })
+}
+
+fn parse_camera_look_ahead(distance: &str) -> Result<f32, String> {
+    distance
+        .parse()
+        .ok()
+        .filter(|distance| (0.0..=app::MAX_CAMERA_LOOK_AHEAD).contains(distance))
+        .ok_or_else(|| format!("must be between 0 and {}", app::MAX_CAMERA_LOOK_AHEAD))
+}
+
+fn parse_player_size(size: &str) -> Result<f32, String> {
+    size.parse()
+        .ok()
+        .filter(|size| (globals::MIN_PLAYER_SIZE..=globals::MAX_PLAYER_SIZE).contains(size))
+        .ok_or_else(|| {
+            format!(
+                "must be between {} and {}",
+                globals::MIN_PLAYER_SIZE,
+                globals::MAX_PLAYER_SIZE
+            )
+        })
+}
+
+fn parse_player_speed(speed: &str) -> Result<f32, String> {
+    speed
+        .parse()
+        .ok()
+        .filter(|speed| (globals::MIN_PLAYER_SPEED..=globals::MAX_PLAYER_SPEED).contains(speed))
+        .ok_or_else(|| {
+            format!(
+                "must be between {} and {}",
+                globals::MIN_PLAYER_SPEED,
+                globals::MAX_PLAYER_SPEED
+            )
+        })
 }
 
 fn main() -> Result<(), Box<dyn Error>> {
     let cli = Cli::parse();
+    match cli.print_protocol.as_deref() {
+        Some("json") => {
+            print!("{}", message::PROTOCOL_SCHEMA);
+            return Ok(());
+        }
+        Some(_) => {
+            print!("{}", message::PROTOCOL_TABLE);
+            return Ok(());
+        }
+        None => (),
+    }
+    if let Some(path) = &cli.record_transcript {
+        let datagrams = transcript::record();
+        std::fs::write(path, transcript::format(&datagrams))?;
+        println!(
+            "Recorded {} datagrams into {}",
+            datagrams.len(),
+            path.display()
+        );
+        return Ok(());
+    }
+    if let Some(path) = &cli.check_transcript {
+        let golden = transcript::parse(&std::fs::read_to_string(path)?)?;
+        let differences = transcript::compare(&golden, &transcript::record());
+        if differences.is_empty() {
+            println!("Datagrams match {}", path.display());
+            return Ok(());
+        }
+        for difference in &differences {
+            eprintln!("{difference}");
+        }
+        eprintln!("Wire format differs from {}", path.display());
+        std::process::exit(1);
+    }
+    task::init_instrumentation();
 
     if cli.trace {
         println!("Message tracing enabled.");
@@ -45,27 +414,181 @@ fn main() -> Result<(), Box<dyn Error>> {
 
     // Start a headless server only if option is set.
     if cli.server_only {
-        println!("Starting server in headless mode");
+        if let Some(log_file) = &cli.log_file {
+            if let Err(e) = logger::set_log_file(log_file) {
+                eprintln!("Failed to open log file {}: {e}", log_file.display());
+                std::process::exit(1);
+            }
+        }
+        logger::info("server", "Starting server in headless mode".to_string());
         rt.block_on(async {
-            match server::start_server(cli.port).await {
-                Ok(_) => {
-                    println!("Server started successfully. Waiting for CTRL+C to shut down.");
-                    match tokio::signal::ctrl_c().await {
-                        Ok(_) => println!(
-                            "\nCTRL+C interrupt received. Shutting down server gracefully..."
-                        ),
-                        Err(e) => eprintln!("Failed to listen for CTRL+C event: {}", e),
+            let config = server::ServerConfig {
+                port: cli.port,
+                name: cli
+                    .name
+                    .unwrap_or_else(|| globals::DEFAULT_SERVER_NAME.to_string()),
+                whitelist_path: cli.whitelist,
+                banlist_path: cli.banlist,
+                admin_console: true,
+                shed_load: cli.shed_load,
+                report_usage: true,
+                advertise_lan: cli.advertise_lan,
+                settings_path: cli.config,
+                seed: cli.seed,
+                world_params: WorldParams {
+                    player_size: cli.player_size,
+                    player_speed: cli.player_speed,
+                },
+                map: None,
+                #[cfg(feature = "status-http")]
+                status_port: cli.status_port,
+                #[cfg(feature = "admin-dashboard")]
+                admin_token_file: cli.admin_token_file,
+                observer_port: cli.observer_port,
+                replay_dir: cli.replay_dir,
+                #[cfg(feature = "chaos")]
+                chaos: None,
+            };
+            match server::start_server(config).await {
+                Ok(server_handle) => {
+                    logger::info(
+                        "server",
+                        "Server started successfully. Waiting for CTRL+C to shut down.".to_string(),
+                    );
+                    #[cfg(unix)]
+                    task::spawn_named("reload_on_sighup", reload_on_sighup(server_handle.clone()));
+                    let failed = tokio::select! {
+                        signal = shutdown_signal() => {
+                            match signal {
+                                Ok(signal) => logger::info(
+                                    "server",
+                                    format!("{signal} received. Shutting down server gracefully..."),
+                                ),
+                                Err(e) => logger::error(
+                                    "server",
+                                    format!("Failed to listen for shutdown signals: {}", e),
+                                ),
+                            }
+                            server_handle.shutdown().await;
+                            false
+                        }
+                        _ = server_handle.stopped() => true,
+                    };
+                    server_handle.await_terminated().await;
+                    logger::info("server", "Server shut down".to_string());
+                    if failed {
+                        std::process::exit(1);
                     }
                 }
                 Err(e) => {
-                    eprintln!("Server failed to start: {}", e);
+                    logger::error("server", format!("Server failed to start: {}", e));
                     std::process::exit(1);
                 }
             }
         });
+        // Admin console blocks a thread on reading standard input, which would hang runtime
+        // shutdown until the next line is entered.
+        rt.shutdown_background();
+        return Ok(());
+    }
+
+    if let Some(csv_path) = cli.soak {
+        let result = rt.block_on(soak::run_soak_test(soak::SoakOptions {
+            port: cli.port,
+            bots: cli.soak_bots,
+            duration: cli
+                .soak_minutes
+                .map(|minutes| std::time::Duration::from_secs(minutes * 60)),
+            csv_path,
+        }));
+        if let Err(e) = result {
+            eprintln!("Soak test stopped: {}", e);
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
+    let player_name = cli.name.unwrap_or_default();
+    if let Err(e) = message::validate_player_name(&player_name) {
+        eprintln!("{e}");
+        std::process::exit(1);
+    }
+
+    // Start a headless client only if option is set.
+    if cli.client_only {
+        // Required by argument parser
+        let Some(server_address) = cli.connect else {
+            return Err("--client-only requires --connect".into());
+        };
+        println!("Starting client in headless mode");
+        if let Err(e) = rt.block_on(headless::run_headless_client(
+            server_address,
+            player_name,
+            cli.send_rate,
+            cli.metrics_port,
+        )) {
+            eprintln!("Client stopped: {}", e);
+            std::process::exit(1);
+        }
         return Ok(());
     }
 
     // Run graphical client otherwise.
-    app::run_app(&rt)
+    app::run_app(
+        &rt,
+        app::LaunchOptions {
+            connect: cli.connect,
+            player_name,
+            send_rate: cli.send_rate,
+            lockstep: cli.lockstep_index.map(|local_index| app::LockstepOptions {
+                peers: cli.lockstep_peers,
+                local_index,
+            }),
+            attract: cli.attract,
+            record_input: cli.record_input,
+            play_input: cli.play_input,
+            play_replay: cli.play_replay,
+            window_title_suffix: cli.window_title_suffix,
+            camera_look_ahead: cli.camera_look_ahead,
+        },
+    )
+}
+
+/// Wait for CTRL+C, or SIGTERM sent by container runtimes like Docker and Kubernetes on stop.
+async fn shutdown_signal() -> std::io::Result<&'static str> {
+    #[cfg(unix)]
+    {
+        let mut sigterm =
+            tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())?;
+        tokio::select! {
+            result = tokio::signal::ctrl_c() => result.map(|_| "CTRL+C interrupt"),
+            _ = sigterm.recv() => Ok("SIGTERM"),
+        }
+    }
+    #[cfg(not(unix))]
+    tokio::signal::ctrl_c().await.map(|_| "CTRL+C interrupt")
+}
+
+/// Reload server configuration on SIGHUP, the conventional way to signal daemons.
+#[cfg(unix)]
+async fn reload_on_sighup(server_handle: server::ServerHandle) {
+    let mut sighup = match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup()) {
+        Ok(sighup) => sighup,
+        Err(e) => {
+            logger::error("config", format!("Failed to listen for SIGHUP: {e}"));
+            return;
+        }
+    };
+    while sighup.recv().await.is_some() {
+        match server_handle.reload().await {
+            Ok(_) => logger::info(
+                "config",
+                "SIGHUP received, configuration reloaded".to_string(),
+            ),
+            Err(e) => logger::error(
+                "config",
+                format!("SIGHUP received, failed to reload configuration: {e}"),
+            ),
+        }
+    }
 }
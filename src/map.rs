@@ -0,0 +1,556 @@
+use std::{
+    error::Error,
+    path::{Path, PathBuf},
+    time::Instant,
+};
+
+use cgmath::{InnerSpace, Vector2};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use crate::{globals, Player, PlayerID};
+
+/// Most obstacles in a map
+pub const MAX_OBSTACLES: usize = 256;
+/// Most spawn points in a map
+pub const MAX_SPAWN_POINTS: usize = 64;
+/// Largest map file in bytes, so that downloading takes a few seconds at most
+pub const MAX_MAP_SIZE: usize = 16 * 1024;
+/// Bytes of map file carried by a single `MapChunk` message, so that it fits into a datagram
+pub const MAP_CHUNK_SIZE: usize = 200;
+pub const MAX_MAP_NAME_LEN: usize = 32;
+
+/// Name of the map played on servers without a map file
+const OPEN_FIELD_MAP_NAME: &str = "open-field";
+
+/// Directory of maps downloaded from servers, relative to the working directory
+const DOWNLOADED_MAP_DIR: &str = "maps/downloaded";
+
+/// Map entries starting with this are generated instead of loaded from a file
+const PROCEDURAL_MAP_PREFIX: &str = "procedural:";
+/// Share of the world covered by obstacles on generated maps unless given
+pub const DEFAULT_PROCEDURAL_DENSITY: f32 = 0.1;
+/// Generated maps are mostly open to leave room for moving around
+const MAX_PROCEDURAL_DENSITY: f32 = 0.5;
+/// Side lengths of generated obstacles in world units
+const GENERATED_OBSTACLE_SIZE: std::ops::RangeInclusive<i32> = 40..=240;
+/// Free space between generated obstacles, so that players of any size fit through
+const GENERATED_OBSTACLE_GAP: f32 = globals::MAX_PLAYER_SIZE;
+/// Free space around spawn points of generated maps
+const SPAWN_CLEARANCE: f32 = 100.0;
+/// Spawn points of generated maps are evenly spread on a circle around the center of the world
+const GENERATED_SPAWN_POINT_COUNT: usize = 8;
+const GENERATED_SPAWN_RADIUS: f32 = 800.0;
+/// Obstacles are placed at random positions until the density is reached or this many of them
+/// didn't fit
+const MAX_PLACEMENT_ATTEMPTS: usize = 10_000;
+
+/// Chunks requested at once while downloading
+const DOWNLOAD_WINDOW: usize = 8;
+/// Chunks are requested again if they don't arrive in time
+const CHUNK_RETRY_INTERVAL: std::time::Duration = std::time::Duration::from_millis(250);
+
+/// Axis-aligned rectangle in the world blocking player movement
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Obstacle {
+    /// Top-left corner
+    pub min: Vector2<f32>,
+    pub size: Vector2<f32>,
+}
+
+/// Static layout of the world, played on a server and downloaded by clients who don't have it yet.
+///
+/// Map files are text with one obstacle per line given by its top-left corner, width and height
+/// in world units, and spawn points given by their position. Empty lines and lines starting with
+/// `#` are ignored:
+///
+/// ```text
+/// # Wall in the middle
+/// obstacle -100 -20 200 40
+/// spawn 0 -200
+/// ```
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Map {
+    pub obstacles: Vec<Obstacle>,
+    /// Where players appear when the map starts, anywhere if empty
+    pub spawn_points: Vec<Vector2<f32>>,
+}
+
+impl Map {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut map = Map::default();
+        for (index, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            match fields[..] {
+                ["obstacle", x, y, width, height] => map.obstacles.push(
+                    parse_obstacle(x, y, width, height)
+                        .map_err(|e| format!("Line {}: {e}", index + 1))?,
+                ),
+                ["spawn", x, y] => map
+                    .spawn_points
+                    .push(parse_spawn_point(x, y).map_err(|e| format!("Line {}: {e}", index + 1))?),
+                _ => return Err(format!("Line {}: unknown entry", index + 1)),
+            }
+        }
+        if map.obstacles.len() > MAX_OBSTACLES {
+            return Err(format!(
+                "Map can't have more than {MAX_OBSTACLES} obstacles"
+            ));
+        }
+        if map.spawn_points.len() > MAX_SPAWN_POINTS {
+            return Err(format!(
+                "Map can't have more than {MAX_SPAWN_POINTS} spawn points"
+            ));
+        }
+
+        Ok(map)
+    }
+
+    pub fn serialize(&self) -> String {
+        let obstacles = self.obstacles.iter().map(|obstacle| {
+            format!(
+                "obstacle {} {} {} {}\n",
+                obstacle.min.x, obstacle.min.y, obstacle.size.x, obstacle.size.y
+            )
+        });
+        let spawn_points = self
+            .spawn_points
+            .iter()
+            .map(|spawn_point| format!("spawn {} {}\n", spawn_point.x, spawn_point.y));
+        obstacles.chain(spawn_points).collect()
+    }
+
+    /// Spawn point of a player, spreading players over the spawn points by their ID. None if the
+    /// map has no spawn points.
+    pub fn spawn_point(&self, player_id: PlayerID) -> Option<Vector2<f32>> {
+        if self.spawn_points.is_empty() {
+            return None;
+        }
+        Some(self.spawn_points[player_id as usize % self.spawn_points.len()])
+    }
+
+    /// Move a player of the given size out of the obstacles it overlaps with along the shortest
+    /// way out, so that obstacles can't be walked through.
+    pub fn push_out(&self, player: &mut Player, player_size: f32) {
+        let half_size = player_size / 2.0;
+        for obstacle in &self.obstacles {
+            let left = player.pos.x + half_size - obstacle.min.x;
+            let right = obstacle.min.x + obstacle.size.x - (player.pos.x - half_size);
+            let up = player.pos.y + half_size - obstacle.min.y;
+            let down = obstacle.min.y + obstacle.size.y - (player.pos.y - half_size);
+            if left <= 0.0 || right <= 0.0 || up <= 0.0 || down <= 0.0 {
+                continue;
+            }
+            let shortest = left.min(right).min(up).min(down);
+            if shortest == left {
+                player.pos.x -= left;
+            } else if shortest == right {
+                player.pos.x += right;
+            } else if shortest == up {
+                player.pos.y -= up;
+            } else {
+                player.pos.y += down;
+            }
+        }
+    }
+}
+
+fn parse_coordinate(value: &str) -> Result<f32, String> {
+    value
+        .parse::<f32>()
+        .ok()
+        .filter(|value| value.is_finite())
+        .ok_or_else(|| format!("invalid number {value}"))
+}
+
+fn parse_obstacle(x: &str, y: &str, width: &str, height: &str) -> Result<Obstacle, String> {
+    let obstacle = Obstacle {
+        min: Vector2::new(parse_coordinate(x)?, parse_coordinate(y)?),
+        size: Vector2::new(parse_coordinate(width)?, parse_coordinate(height)?),
+    };
+    if obstacle.size.x <= 0.0 || obstacle.size.y <= 0.0 {
+        return Err("obstacle size must be positive".to_string());
+    }
+    let bounds = &globals::WORLD_BOUNDS;
+    if obstacle.min.x < bounds.min_x
+        || obstacle.min.y < bounds.min_y
+        || obstacle.min.x + obstacle.size.x > bounds.max_x
+        || obstacle.min.y + obstacle.size.y > bounds.max_y
+    {
+        return Err("obstacle is outside of the world".to_string());
+    }
+
+    Ok(obstacle)
+}
+
+fn parse_spawn_point(x: &str, y: &str) -> Result<Vector2<f32>, String> {
+    let spawn_point = Vector2::new(parse_coordinate(x)?, parse_coordinate(y)?);
+    let bounds = &globals::WORLD_BOUNDS;
+    if spawn_point.x < bounds.min_x
+        || spawn_point.y < bounds.min_y
+        || spawn_point.x > bounds.max_x
+        || spawn_point.y > bounds.max_y
+    {
+        return Err("spawn point is outside of the world".to_string());
+    }
+
+    Ok(spawn_point)
+}
+
+/// Identifies a map file, so that clients can tell whether they already have it
+#[derive(Clone, Debug, PartialEq)]
+pub struct MapInfo {
+    pub name: String,
+    /// Checksum of the map file
+    pub checksum: u32,
+    /// Size of the map file in bytes
+    pub size: u32,
+}
+
+/// Map as served to clients, together with the file it was loaded from.
+pub struct ServedMap {
+    /// File the map was loaded from, None for the open field
+    pub path: Option<PathBuf>,
+    pub info: MapInfo,
+    pub data: Vec<u8>,
+    pub map: Map,
+}
+
+impl ServedMap {
+    /// Load a map file, which is named after the file name without extension.
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        if let Some(params) = ProceduralParams::from_entry(path) {
+            return Ok(Self {
+                path: Some(path.to_path_buf()),
+                ..Self::generate(&params?)
+            });
+        }
+        let name = map_name(path);
+        validate_map_name(&name)?;
+        let data = std::fs::read(path)?;
+        if data.len() > MAX_MAP_SIZE {
+            return Err(format!("Map file can't be larger than {MAX_MAP_SIZE} bytes").into());
+        }
+        let map = Map::parse(std::str::from_utf8(&data)?)?;
+
+        Ok(Self {
+            path: Some(path.to_path_buf()),
+            info: MapInfo {
+                name,
+                checksum: checksum(&data),
+                size: data.len() as u32,
+            },
+            data,
+            map,
+        })
+    }
+
+    /// Generate a map from parameters. It's served the same way as a map file, so clients
+    /// download it like any other map.
+    pub fn generate(params: &ProceduralParams) -> Self {
+        let map = generate(params);
+        let data = map.serialize().into_bytes();
+        Self {
+            path: None,
+            info: MapInfo {
+                name: params.name(),
+                checksum: checksum(&data),
+                size: data.len() as u32,
+            },
+            data,
+            map,
+        }
+    }
+
+    /// Empty world played when no map file is given
+    pub fn open_field() -> Self {
+        Self {
+            path: None,
+            info: MapInfo {
+                name: OPEN_FIELD_MAP_NAME.to_string(),
+                checksum: checksum(&[]),
+                size: 0,
+            },
+            data: Vec::new(),
+            map: Map::default(),
+        }
+    }
+
+    /// Part of the map file starting at the given offset, None if the offset is out of range
+    pub fn chunk(&self, offset: u32) -> Option<&[u8]> {
+        let offset = offset as usize;
+        if offset >= self.data.len() || offset % MAP_CHUNK_SIZE != 0 {
+            return None;
+        }
+        Some(&self.data[offset..self.data.len().min(offset + MAP_CHUNK_SIZE)])
+    }
+}
+
+/// Maps are named after their file name without extension, generated maps after their seed
+pub fn map_name(path: &Path) -> String {
+    if let Some(Ok(params)) = ProceduralParams::from_entry(path) {
+        return params.name();
+    }
+    path.file_stem()
+        .and_then(|stem| stem.to_str())
+        .unwrap_or_default()
+        .to_string()
+}
+
+/// Map names end up in file names of downloaded maps, so only a safe set of characters is
+/// allowed.
+pub fn validate_map_name(name: &str) -> Result<(), String> {
+    if name.is_empty() || name.len() > MAX_MAP_NAME_LEN {
+        return Err(format!(
+            "Map name must be between 1 and {MAX_MAP_NAME_LEN} characters."
+        ));
+    }
+    if !name
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        return Err("Map name can only contain letters, digits, - and _.".to_string());
+    }
+
+    Ok(())
+}
+
+/// Parameters of a generated map, given in place of a map file as
+/// `procedural:seed=42,density=0.1`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ProceduralParams {
+    pub seed: u64,
+    /// Fraction of the world covered by obstacles
+    pub density: f32,
+}
+
+impl ProceduralParams {
+    /// Parameters of a generated map entry, None if the entry is a map file
+    pub fn from_entry(path: &Path) -> Option<Result<Self, String>> {
+        let params = path.to_str()?.strip_prefix(PROCEDURAL_MAP_PREFIX)?;
+        Some(Self::parse(params))
+    }
+
+    fn parse(params: &str) -> Result<Self, String> {
+        let mut seed = 0;
+        let mut density = DEFAULT_PROCEDURAL_DENSITY;
+        for param in params.split(',').filter(|param| !param.is_empty()) {
+            match param.split_once('=') {
+                Some(("seed", value)) => {
+                    seed = value.parse().map_err(|_| format!("invalid seed {value}"))?;
+                }
+                Some(("density", value)) => {
+                    density = value
+                        .parse()
+                        .ok()
+                        .filter(|density| (0.0..=MAX_PROCEDURAL_DENSITY).contains(density))
+                        .ok_or_else(|| {
+                            format!("density must be between 0 and {MAX_PROCEDURAL_DENSITY}")
+                        })?;
+                }
+                _ => return Err(format!("unknown parameter {param}")),
+            }
+        }
+
+        Ok(Self { seed, density })
+    }
+
+    fn name(&self) -> String {
+        format!("procedural-{}", self.seed)
+    }
+}
+
+/// Scatter obstacles across the world at random until they cover the requested fraction of it.
+/// Obstacles don't overlap and keep clear of the spawn points, so that every player starts in
+/// the open. The same parameters always generate the same map.
+pub fn generate(params: &ProceduralParams) -> Map {
+    let mut rng = StdRng::seed_from_u64(params.seed);
+    let bounds = &globals::WORLD_BOUNDS;
+    let spawn_points: Vec<Vector2<f32>> = (0..GENERATED_SPAWN_POINT_COUNT)
+        .map(|index| {
+            let angle = std::f32::consts::TAU * index as f32 / GENERATED_SPAWN_POINT_COUNT as f32;
+            Vector2::new(
+                (angle.cos() * GENERATED_SPAWN_RADIUS).round(),
+                (angle.sin() * GENERATED_SPAWN_RADIUS).round(),
+            )
+        })
+        .collect();
+    let target_area =
+        params.density * (bounds.max_x - bounds.min_x) * (bounds.max_y - bounds.min_y);
+
+    let mut obstacles: Vec<Obstacle> = Vec::new();
+    let mut covered_area = 0.0;
+    for _ in 0..MAX_PLACEMENT_ATTEMPTS {
+        if covered_area >= target_area || obstacles.len() >= MAX_OBSTACLES {
+            break;
+        }
+        // Whole units keep map files short
+        let size = Vector2::new(
+            rng.gen_range(GENERATED_OBSTACLE_SIZE),
+            rng.gen_range(GENERATED_OBSTACLE_SIZE),
+        );
+        let min = Vector2::new(
+            rng.gen_range(bounds.min_x as i32..=bounds.max_x as i32 - size.x),
+            rng.gen_range(bounds.min_y as i32..=bounds.max_y as i32 - size.y),
+        );
+        let obstacle = Obstacle {
+            min: min.cast().unwrap(),
+            size: size.cast().unwrap(),
+        };
+        let blocks_spawn_point = spawn_points
+            .iter()
+            .any(|spawn_point| distance_to(&obstacle, *spawn_point) < SPAWN_CLEARANCE);
+        let too_close = obstacles
+            .iter()
+            .any(|other| gap_between(&obstacle, other) < GENERATED_OBSTACLE_GAP);
+        if !blocks_spawn_point && !too_close {
+            covered_area += obstacle.size.x * obstacle.size.y;
+            obstacles.push(obstacle);
+        }
+    }
+
+    Map {
+        obstacles,
+        spawn_points,
+    }
+}
+
+/// Distance of a point from the nearest point of an obstacle, zero if it's inside
+pub fn distance_to(obstacle: &Obstacle, point: Vector2<f32>) -> f32 {
+    let nearest = Vector2::new(
+        point
+            .x
+            .clamp(obstacle.min.x, obstacle.min.x + obstacle.size.x),
+        point
+            .y
+            .clamp(obstacle.min.y, obstacle.min.y + obstacle.size.y),
+    );
+    (point - nearest).magnitude()
+}
+
+/// Free space between two obstacles along the axis they are furthest apart on, negative if they
+/// overlap
+pub fn gap_between(a: &Obstacle, b: &Obstacle) -> f32 {
+    let gap_x = (b.min.x - (a.min.x + a.size.x)).max(a.min.x - (b.min.x + b.size.x));
+    let gap_y = (b.min.y - (a.min.y + a.size.y)).max(a.min.y - (b.min.y + b.size.y));
+    gap_x.max(gap_y)
+}
+
+/// 32-bit FNV-1a hash, good enough to tell different versions of a map apart
+pub fn checksum(data: &[u8]) -> u32 {
+    data.iter().fold(0x811c_9dc5, |hash, byte| {
+        (hash ^ *byte as u32).wrapping_mul(0x0100_0193)
+    })
+}
+
+/// Map previously downloaded from a server, if it is still intact
+pub fn load_downloaded(info: &MapInfo) -> Option<Map> {
+    let data = std::fs::read(downloaded_map_path(info)).ok()?;
+    if checksum(&data) != info.checksum {
+        return None;
+    }
+    Map::parse(std::str::from_utf8(&data).ok()?).ok()
+}
+
+/// Keep a downloaded map so that it doesn't have to be downloaded again.
+pub fn save_downloaded(info: &MapInfo, data: &[u8]) -> std::io::Result<()> {
+    std::fs::create_dir_all(DOWNLOADED_MAP_DIR)?;
+    std::fs::write(downloaded_map_path(info), data)
+}
+
+/// Different versions of a map are kept side by side
+fn downloaded_map_path(info: &MapInfo) -> PathBuf {
+    Path::new(DOWNLOADED_MAP_DIR).join(format!("{}-{:08x}.map", info.name, info.checksum))
+}
+
+/// Client-side progress of downloading a map file from the server in chunks.
+///
+/// There's no reliable channel, so the client asks for chunks by their offset and asks again for
+/// chunks which don't arrive in time. A few chunks are requested at once to make up for the
+/// round-trip time.
+pub struct MapDownload {
+    info: MapInfo,
+    chunks: Vec<Option<Vec<u8>>>,
+    requested_at: Vec<Option<Instant>>,
+}
+
+impl MapDownload {
+    pub fn new(info: MapInfo) -> Self {
+        let chunk_count = (info.size as usize).div_ceil(MAP_CHUNK_SIZE);
+        Self {
+            info,
+            chunks: vec![None; chunk_count],
+            requested_at: vec![None; chunk_count],
+        }
+    }
+
+    pub fn info(&self) -> &MapInfo {
+        &self.info
+    }
+
+    /// Offsets of chunks to request now. Marks them as requested.
+    pub fn due_requests(&mut self) -> Vec<u32> {
+        let now = Instant::now();
+        let in_flight = self
+            .chunks
+            .iter()
+            .zip(&self.requested_at)
+            .filter(|(chunk, requested_at)| {
+                chunk.is_none()
+                    && requested_at.is_some_and(|at| now.duration_since(at) < CHUNK_RETRY_INTERVAL)
+            })
+            .count();
+        let mut offsets = Vec::new();
+        for (index, (chunk, requested_at)) in
+            self.chunks.iter().zip(&mut self.requested_at).enumerate()
+        {
+            if in_flight + offsets.len() >= DOWNLOAD_WINDOW {
+                break;
+            }
+            let due = chunk.is_none()
+                && !requested_at.is_some_and(|at| now.duration_since(at) < CHUNK_RETRY_INTERVAL);
+            if due {
+                *requested_at = Some(now);
+                offsets.push((index * MAP_CHUNK_SIZE) as u32);
+            }
+        }
+        offsets
+    }
+
+    /// Store a received chunk. Chunks which don't fit into the map file are ignored.
+    pub fn add_chunk(&mut self, offset: u32, data: Vec<u8>) {
+        let offset = offset as usize;
+        if offset % MAP_CHUNK_SIZE != 0 {
+            return;
+        }
+        let index = offset / MAP_CHUNK_SIZE;
+        let expected_len = (self.info.size as usize)
+            .saturating_sub(offset)
+            .min(MAP_CHUNK_SIZE);
+        if let Some(chunk) = self.chunks.get_mut(index) {
+            if data.len() == expected_len {
+                *chunk = Some(data);
+            }
+        }
+    }
+
+    /// Fraction of the map file received so far
+    pub fn progress(&self) -> f32 {
+        if self.chunks.is_empty() {
+            return 1.0;
+        }
+        self.chunks.iter().filter(|chunk| chunk.is_some()).count() as f32 / self.chunks.len() as f32
+    }
+
+    /// Whole map file once every chunk has arrived
+    pub fn data(&self) -> Option<Vec<u8>> {
+        self.chunks
+            .iter()
+            .map(|chunk| chunk.as_deref())
+            .collect::<Option<Vec<&[u8]>>>()
+            .map(|chunks| chunks.concat())
+    }
+}
@@ -1,180 +1,1405 @@
 use std::sync::atomic::{AtomicBool, Ordering};
 
 use cgmath::{Vector2, Vector3};
+use serde::Serialize;
 
-use crate::{Player, PlayerID};
+use crate::{
+    afk::PlayerActivity,
+    globals, logger,
+    map::{self, MapInfo},
+    netlog,
+    storm::{self, SafeZone, StormState},
+    Player, PlayerID, SessionToken, Tick, WorldParams,
+};
 
-#[derive(PartialEq)]
+/// Datagrams exchanged between clients and the server after a binary `DatagramHeader`. Messages
+/// start with a byte identifying their type, followed by their fields in a fixed order. Integers
+/// are big-endian, `token` and `player_id` are u64, `str` is UTF-8 text after its length in bytes
+/// as u16 and `bytes` is the same for raw bytes. `f32` is an IEEE 754 float. `pos` is a position
+/// quantized to a u16 per axis across the world bounds, and `color` is a u8 per channel in RGB
+/// order. `[...]?` is a u8 of 0 or 1 followed by the group if 1, and `[...]*` is a u8 count
+/// followed by that many groups.
+///
+/// Every variant documents its wire format, which `build.rs` turns into `PROTOCOL_SCHEMA`. The
+/// format starts with the name of the message type, standing for its tag byte.
+#[derive(Clone, Debug, PartialEq)]
 pub enum Message {
-    /// Periodic ping message for server healthcheck
+    /// Keepalive for server healthcheck, sent when nothing else was sent to the player for a
+    /// while and regularly for measuring round-trip time. Carries the time it was sent at in
+    /// milliseconds since server start
+    ///
+    /// Wire format: `PING time:u64`
     // TODO: Extend for client disconnect check
-    Ping,
+    Ping(u64),
 
-    /// Initial handshake by client on join. Retried on UDP packet loss until timeout.
-    Handshake,
+    /// Client reply to `Ping` echoing its time, so the server can measure round-trip time and
+    /// packet loss
+    ///
+    /// Wire format: `PONG token time:u64`
+    Pong(SessionToken, u64),
 
-    /// Server response to received handshake
-    Ack(PlayerID, Vector3<f32>),
+    /// Initial handshake by client on join carrying the requested player name (can be empty) and
+    /// the token of a previous session on the server to take the player over from, e.g. after
+    /// reconnecting from another address. Retried on UDP packet loss until timeout.
+    ///
+    /// Wire format: `HANDSHAKE name:str [token]?`
+    Handshake(String, Option<SessionToken>),
+
+    /// Server response to received handshake with the new player's info, the session token the
+    /// client has to send along with its messages from then on, the server's seed for cosmetic
+    /// randomness, the server name, and the player size and speed on the server
+    ///
+    /// Wire format: `ACK player_id token color seed:u64 server_name:str size:f32 speed:f32`
+    Ack(
+        PlayerID,
+        SessionToken,
+        Vector3<f32>,
+        u64,
+        String,
+        WorldParams,
+    ),
+
+    /// Server response to received handshake when the player is not allowed to join, with the
+    /// reason of refusal.
+    ///
+    /// Wire format: `REJECT reason:str`
+    Reject(String),
+
+    /// Server response to received handshake when the server is full and the player waits in
+    /// the join queue, with the place in the queue starting from 1. The client keeps retrying
+    /// the handshake, which is accepted once a slot frees up for them.
+    ///
+    /// Wire format: `QUEUED position:u32`
+    Queued(u32),
+
+    /// Request of server info by tools like server browsers without joining the game
+    ///
+    /// Wire format: `QUERY`
+    Query,
+
+    /// Server response to query with server name and usage of the server since it started
+    ///
+    /// Wire format: `INFO server_name:str players:u64 peak_players:u64 joins:u64 messages:u64`
+    Info(String, ServerUsage),
+
+    /// Periodic announcement of a server hosted with LAN advertisement to the LAN multicast group,
+    /// with the server name, the UDP port to join on and the number of players. Not a response
+    /// to anything, see `lan`.
+    ///
+    /// Wire format: `BEACON server_name:str port:u16 players:u32`
+    Beacon(String, u16, u32),
 
     /// Server response notifying all players still remaining on server about player exit so they
     /// can update their state.
+    ///
+    /// Wire format: `LEAVE player_id`
     Leave(PlayerID),
 
-    /// Server's world replication of a single player position
-    /// TODO: Currently sent one-by-one, make it a bulk send instead
-    Replicate(Player),
+    /// Client leaving the server
+    ///
+    /// Wire format: `DISCONNECT token`
+    Disconnect(SessionToken),
+
+    /// Server notifying all players that it is going away. Players are expected to answer with
+    /// `Disconnect`.
+    ///
+    /// Wire format: `SHUTDOWN`
+    Shutdown,
+
+    /// Server's world replication of a single player position, stamped with the simulation tick
+    /// it was taken at. Sent for snapshots too crowded for `Snapshot`, and when the server moves
+    /// the receiving player.
+    ///
+    /// Wire format: `REPL tick:u64 player_id pos color`
+    Replicate(Tick, Player),
+
+    /// Server's world replication of every player in view of the receiving player, delta
+    /// compressed against the latest snapshot the client acknowledged, see `snapshot.rs`
+    ///
+    /// Wire format: `SNAPSHOT snapshot:bytes`
+    Snapshot(Vec<u8>),
+
+    /// Client confirming that the `Snapshot` of the given tick arrived, so that later snapshots
+    /// can leave out what it already has
+    ///
+    /// Wire format: `SNAPSHOTACK token tick:u64`
+    SnapshotAck(SessionToken, Tick),
+
+    /// Announcement of the server shown to players, like the message of the day
+    ///
+    /// Wire format: `NOTICE text:str`
+    Notice(String),
+
+    /// Chat message of a player relayed by the server to everyone including the sender
+    ///
+    /// Wire format: `CHAT player_id text:str`
+    Chat(PlayerID, String),
+
+    /// Chat message sent by a client, relayed as `Chat` with the ID of the sender
+    ///
+    /// Wire format: `SAY token text:str`
+    Say(SessionToken, String),
+
+    /// Player changing their display name and color mid-session, answered with `Profile` if the
+    /// server accepts the change. An empty name stands for the default name.
+    ///
+    /// Wire format: `UPDATEPROFILE token color name:str`
+    UpdateProfile(SessionToken, Vector3<f32>, String),
+
+    /// Display name and color of a player, sent to everyone when the player changes them and to
+    /// joining players about everyone already present
+    ///
+    /// Wire format: `PROFILE player_id color name:str`
+    Profile(PlayerID, Vector3<f32>, String),
 
     /// Player's position response after movement change.
+    ///
+    /// Wire format: `POS token pos`
     // TODO: Avoid clients self-reporting their exact own position and opt for sending input action
     // instead
-    Position(PlayerID, Vector2<f32>),
+    Position(SessionToken, Vector2<f32>),
+
+    /// Movement input of a player in lockstep mode for consecutive ticks starting from the given
+    /// one, sent directly between peers. Recent inputs are repeated in every message to cover
+    /// packet loss. Inputs have a bit for each direction: up, down, left and right from the
+    /// lowest bit.
+    ///
+    /// Wire format: `INPUT player_id first_tick:u64 [input:u8]*`
+    Input(PlayerID, Tick, Vec<u8>),
+
+    /// Connection quality of a player as measured by the server, sent periodically to that player
+    ///
+    /// Wire format: `STATS rtt_ms:u32 packet_loss_percent:u8 corrections:u64`
+    Stats(ConnectionStats),
+
+    /// Gameplay event shown in the event feed of every player. Kind is 0 for joining, 1 for
+    /// leaving and 2 for being knocked out.
+    ///
+    /// Wire format: `EVENT kind:u8 player_id name:str`
+    Event(GameEvent),
+
+    /// Server announcing the end of the match with the seconds left until the next one and the
+    /// final standings, best first. Resent every second until the next match starts.
+    ///
+    /// Wire format: `MATCHEND countdown:u32 [player_id score:u32 name:str]*`
+    MatchEnd(u32, Vec<MatchResult>),
+
+    /// Server's periodic replication of the time of day in the world in minutes since midnight,
+    /// tinting the atmosphere on every client
+    ///
+    /// Wire format: `TIME minutes:u16`
+    TimeOfDay(u16),
+
+    /// Map played on the server, resent every second so that clients notice when it changes.
+    /// Clients who don't have the map download it with `MapRequest`.
+    ///
+    /// Wire format: `MAP name:str checksum:u32 size:u32`
+    Map(MapInfo),
+
+    /// Client request of the part of a map file starting at the given offset, with the checksum
+    /// of the map to make sure that chunks of different maps are not mixed up
+    ///
+    /// Wire format: `MAPREQ token checksum:u32 offset:u32`
+    MapRequest(SessionToken, u32, u32),
+
+    /// Server response to `MapRequest` with the checksum of the map, the offset and the bytes of
+    /// the map file starting there
+    ///
+    /// Wire format: `MAPCHUNK checksum:u32 offset:u32 data:bytes`
+    MapChunk(u32, u32, Vec<u8>),
+
+    /// Server freezing (1) or resuming (0) the game. Freezing is resent every second while it
+    /// lasts, so clients can tell when a resume got lost.
+    ///
+    /// Wire format: `PAUSED paused:u8`
+    Paused(bool),
+
+    /// Maps players can vote for to be played next with their votes so far, resent every second
+    /// while match results are shown. Players vote with the `/vote <number>` chat command.
+    ///
+    /// Wire format: `MAPVOTE [name:str votes:u32]*`
+    MapVote(Vec<(String, u32)>),
+
+    /// Server probing the path MTU after a player joined, padded with zeros to make the datagram
+    /// the given number of bytes long. Answered with `ProbeAck` if it arrives.
+    ///
+    /// Wire format: `PROBE size:u16 padding`
+    Probe(u16),
+
+    /// Client confirming that a `Probe` of the given size arrived
+    ///
+    /// Wire format: `PROBEACK token size:u16`
+    ProbeAck(SessionToken, u16),
+
+    /// Safe zone of the storm running on the server, closing in on the target zone over the
+    /// given milliseconds, or no fields while there's no storm. Resent several times a second.
+    /// Radiuses are in whole world units.
+    ///
+    /// Wire format: `STORM [pos radius:u16 pos radius:u16 ms_left:u32]?`
+    Storm(Option<StormState>),
+
+    /// Health of the receiving player, sent to every player while a storm is running
+    ///
+    /// Wire format: `HEALTH health:u8`
+    Health(u8),
+
+    /// Player with the highest score in the current match and the score, derived from the scores
+    /// on the server and resent twice a second. No fields while nobody has scored yet.
+    ///
+    /// Wire format: `LEADER [player_id score:u32]?`
+    Leader(Option<(PlayerID, u32)>),
+
+    /// Player became away from keyboard (1), was moved to spectators (2) or is back in the game
+    /// (0), sent to everyone when it happens and to joining players about everyone not active
+    ///
+    /// Wire format: `ACTIVITY player_id activity:u8`
+    Activity(PlayerID, PlayerActivity),
+}
+
+/// Final score of a single player in a match
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct MatchResult {
+    pub player_id: PlayerID,
+    pub name: String,
+    /// Distance walked during the match in world units
+    pub score: u32,
+}
+
+/// Most players listed in `MatchEnd`
+pub const MAX_MATCH_RESULTS: usize = 10;
+
+/// Most maps players can choose from in a map vote, so that they can be voted for by a single
+/// digit
+pub const MAX_MAP_VOTE_CANDIDATES: usize = 9;
+
+/// Something that happened in the game, worth showing to players for a few seconds
+// TODO: Add tags and pickups once the game has such mechanics
+// TODO: Once players can shoot, confirm projectile hits to the shooter with the tick they landed
+// on, so the client can reconcile its predicted hit markers with them: confirmed markers stay and
+// play a sound, markers the server didn't confirm within the round-trip time fade out
+#[derive(Clone, Debug, PartialEq)]
+pub enum GameEvent {
+    /// Player with the given ID and name joined the server
+    Joined(PlayerID, String),
+    /// Player with the given ID and name left the server
+    Left(PlayerID, String),
+    /// Player with the given ID and name ran out of health in the storm and was put back into
+    /// the safe zone
+    KnockedOut(PlayerID, String),
+}
+
+/// Authoritative connection quality of a single player
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ConnectionStats {
+    /// Smoothed round-trip time of pings
+    pub rtt_ms: u32,
+    /// Smoothed share of pings left unanswered
+    pub packet_loss_percent: u8,
+    /// Reported positions the server had to correct since joining
+    pub corrections: u64,
+}
+
+/// Usage of a server since it started, for operators reporting how much their server is played
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize)]
+pub struct ServerUsage {
+    /// Players connected right now
+    pub players: usize,
+    /// Most players connected at the same time
+    pub peak_players: usize,
+    /// Sessions started, not counting players taken over after reconnecting
+    pub joins: u64,
+    /// Messages received from clients and processed
+    pub messages: u64,
+}
+
+/// Most inputs carried by a single `Input` message
+pub const MAX_INPUTS_PER_MESSAGE: usize = 32;
+
+/// Tag, wire format, field types and description of every message as JSON, generated from
+/// `Message` at build time for implementing clients in other languages
+pub const PROTOCOL_SCHEMA: &str = include_str!(concat!(env!("OUT_DIR"), "/protocol.json"));
+/// Same as `PROTOCOL_SCHEMA` as a Markdown table for humans
+pub const PROTOCOL_TABLE: &str = include_str!(concat!(env!("OUT_DIR"), "/protocol.md"));
+
+/// Bytes every datagram starts with, so that stray UDP traffic is dropped before parsing
+pub const PROTOCOL_MAGIC: &[u8; 2] = b"MG";
+/// Incremented on changes older peers can't parse. Datagrams of other versions are dropped.
+pub const PROTOCOL_VERSION: u8 = 5;
+/// Size of `DatagramHeader` on the wire
+pub const HEADER_LEN: usize = PROTOCOL_MAGIC.len() + 5;
+/// Longest message fitting into a datagram after the header
+pub const MAX_PAYLOAD_SIZE: usize = globals::MAX_DATAGRAM_SIZE - HEADER_LEN;
+
+/// Messages are compressed. Reserved, not sent yet.
+pub const FLAG_COMPRESSED: u8 = 0x01;
+/// Messages are encrypted. Reserved, not sent yet.
+pub const FLAG_ENCRYPTED: u8 = 0x02;
+/// Datagram is a fragment of a message too long for a single datagram. Reserved, not sent yet.
+pub const FLAG_FRAGMENT: u8 = 0x04;
+/// Datagram has no message, it acknowledges the message with the channel and sequence number of
+/// the header.
+pub const FLAG_ACK: u8 = 0x08;
+/// Datagram carries several messages, each after its length in bytes as u16, which are
+/// delivered together
+pub const FLAG_BATCH: u8 = 0x10;
+/// Flags this version can handle. The payload of datagrams with other flags can't be read as it
+/// is, so they are dropped.
+const SUPPORTED_FLAGS: u8 = FLAG_ACK | FLAG_BATCH;
+
+/// Number of a message on its channel, counting up from zero and wrapping around. Each channel
+/// of each direction between two peers has its own numbering.
+pub type Sequence = u16;
+
+/// Delivery guarantee of a message. Every message type is sent on the same channel, see
+/// `Message::channel()`.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Channel {
+    /// Sent once without a sequence number. Used for messages exchanged without joining, for
+    /// the handshake and map downloads, which are retried anyway, and by lockstep peers, whose
+    /// inputs are redundant.
+    Unreliable,
+    /// Sent once, and dropped on arrival if a newer message of the same kind arrived earlier, see
+    /// `sequence_key()`. Used for state superseded by the next update, like positions.
+    UnreliableSequenced,
+    /// Resent until acknowledged and delivered once in order of arrival
+    ReliableUnordered,
+    /// Resent until acknowledged and delivered once in the order of sending, held back until the
+    /// messages before it arrive
+    ReliableOrdered,
+}
+
+impl Channel {
+    pub const ALL: [Channel; 4] = [
+        Channel::Unreliable,
+        Channel::UnreliableSequenced,
+        Channel::ReliableUnordered,
+        Channel::ReliableOrdered,
+    ];
+
+    /// Channel ID in the datagram header
+    pub fn id(self) -> u8 {
+        self as u8
+    }
+
+    pub fn from_id(id: u8) -> Option<Self> {
+        Self::ALL.get(usize::from(id)).copied()
+    }
+
+    pub fn is_reliable(self) -> bool {
+        matches!(self, Channel::ReliableUnordered | Channel::ReliableOrdered)
+    }
+}
+
+/// Binary header in front of the messages of every datagram: the magic bytes, the protocol
+/// version, a byte of flags, the channel ID and the sequence number as a big-endian 16-bit
+/// integer. New features are announced by flags, so that peers without them reject the datagram
+/// instead of misreading it.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct DatagramHeader {
+    pub version: u8,
+    pub flags: u8,
+    pub channel: Channel,
+    /// Zero on the unreliable channel
+    pub sequence: Sequence,
+}
+
+impl Default for DatagramHeader {
+    fn default() -> Self {
+        Self {
+            version: PROTOCOL_VERSION,
+            flags: 0,
+            channel: Channel::Unreliable,
+            sequence: 0,
+        }
+    }
+}
+
+impl DatagramHeader {
+    /// Datagram consisting of this header and the serialized message
+    pub fn encode(&self, payload: &[u8]) -> Vec<u8> {
+        let mut datagram = Vec::with_capacity(HEADER_LEN + payload.len());
+        datagram.extend_from_slice(PROTOCOL_MAGIC);
+        datagram.extend_from_slice(&[self.version, self.flags, self.channel.id()]);
+        datagram.extend_from_slice(&self.sequence.to_be_bytes());
+        datagram.extend_from_slice(payload);
+        datagram
+    }
+
+    /// Split a received datagram into its header and messages. Like `Message::deserialize()`, it
+    /// must not panic on any input.
+    pub fn decode(datagram: &[u8]) -> Result<(Self, &[u8]), std::io::Error> {
+        let invalid = |reason: String| std::io::Error::new(std::io::ErrorKind::InvalidData, reason);
+        let Some(([magic @ .., version, flags, channel, sequence_high, sequence_low], payload)) =
+            datagram.split_first_chunk::<HEADER_LEN>()
+        else {
+            return Err(invalid("Datagram is too short".to_string()));
+        };
+        if magic != PROTOCOL_MAGIC {
+            return Err(invalid("Not a game datagram".to_string()));
+        }
+        if *version != PROTOCOL_VERSION {
+            return Err(invalid(format!("Unsupported protocol version {version}")));
+        }
+        if flags & !SUPPORTED_FLAGS != 0 {
+            return Err(invalid(format!("Unsupported flags {flags:#04X}")));
+        }
+        let channel = Channel::from_id(*channel)
+            .ok_or_else(|| invalid(format!("Unknown channel {channel}")))?;
+        let header = Self {
+            version: *version,
+            flags: *flags,
+            channel,
+            sequence: Sequence::from_be_bytes([*sequence_high, *sequence_low]),
+        };
+        Ok((header, payload))
+    }
 }
 
+/// Names of the message types, used for telling them apart in logs and traffic statistics
 const PING: &str = "PING";
 const HANDSHAKE: &str = "HANDSHAKE";
 const ACK: &str = "ACK";
+const REJECT: &str = "REJECT";
+const QUEUED: &str = "QUEUED";
+const QUERY: &str = "QUERY";
+const INFO: &str = "INFO";
 const LEAVE: &str = "LEAVE";
+const DISCONNECT: &str = "DISCONNECT";
+const SHUTDOWN: &str = "SHUTDOWN";
 const REPL: &str = "REPL";
 const POS: &str = "POS";
+const CHAT: &str = "CHAT";
+const SAY: &str = "SAY";
+const UPDATEPROFILE: &str = "UPDATEPROFILE";
+const PROFILE: &str = "PROFILE";
+const NOTICE: &str = "NOTICE";
+const INPUT: &str = "INPUT";
+const PONG: &str = "PONG";
+const STATS: &str = "STATS";
+const EVENT: &str = "EVENT";
+const MATCHEND: &str = "MATCHEND";
+const TIME: &str = "TIME";
+const MAP: &str = "MAP";
+const MAPREQ: &str = "MAPREQ";
+const MAPCHUNK: &str = "MAPCHUNK";
+const MAPVOTE: &str = "MAPVOTE";
+const PAUSED: &str = "PAUSED";
+const PROBE: &str = "PROBE";
+const PROBEACK: &str = "PROBEACK";
+const STORM: &str = "STORM";
+const HEALTH: &str = "HEALTH";
+const LEADER: &str = "LEADER";
+const ACTIVITY: &str = "ACTIVITY";
+const BEACON: &str = "BEACON";
+const SNAPSHOT: &str = "SNAPSHOT";
+const SNAPSHOTACK: &str = "SNAPSHOTACK";
+
+/// Bytes identifying the message type at the start of every message. Tags of message types that
+/// are removed are not reused, so that older peers reject the new message instead of misreading
+/// it.
+mod tag {
+    pub const PING: u8 = 0x01;
+    pub const HANDSHAKE: u8 = 0x02;
+    pub const ACK: u8 = 0x03;
+    pub const REJECT: u8 = 0x04;
+    pub const QUEUED: u8 = 0x05;
+    pub const QUERY: u8 = 0x06;
+    pub const INFO: u8 = 0x07;
+    pub const LEAVE: u8 = 0x08;
+    pub const DISCONNECT: u8 = 0x09;
+    pub const SHUTDOWN: u8 = 0x0A;
+    pub const REPL: u8 = 0x0B;
+    pub const POS: u8 = 0x0C;
+    pub const CHAT: u8 = 0x0D;
+    pub const SAY: u8 = 0x0E;
+    pub const UPDATEPROFILE: u8 = 0x0F;
+    pub const PROFILE: u8 = 0x10;
+    pub const NOTICE: u8 = 0x11;
+    pub const INPUT: u8 = 0x12;
+    pub const PONG: u8 = 0x13;
+    pub const STATS: u8 = 0x14;
+    pub const EVENT: u8 = 0x15;
+    pub const MATCHEND: u8 = 0x16;
+    pub const TIME: u8 = 0x17;
+    pub const MAP: u8 = 0x18;
+    pub const MAPREQ: u8 = 0x19;
+    pub const MAPCHUNK: u8 = 0x1A;
+    pub const MAPVOTE: u8 = 0x1B;
+    pub const PAUSED: u8 = 0x1C;
+    pub const PROBE: u8 = 0x1D;
+    pub const PROBEACK: u8 = 0x1E;
+    pub const STORM: u8 = 0x1F;
+    pub const HEALTH: u8 = 0x20;
+    pub const LEADER: u8 = 0x21;
+    pub const ACTIVITY: u8 = 0x22;
+    pub const BEACON: u8 = 0x23;
+    pub const SNAPSHOT: u8 = 0x24;
+    pub const SNAPSHOTACK: u8 = 0x25;
+}
+
+/// Sequenced messages with the same key supersede each other: messages of the same type, told
+/// apart by their tag. None for snapshots, which only supersede snapshots of the same player and
+/// are ordered by their tick in `ClientSession::accept_snapshot()` instead.
+pub fn sequence_key(payload: &[u8]) -> Option<u8> {
+    match payload.first() {
+        Some(&tag::REPL) => None,
+        tag => tag.copied(),
+    }
+}
+
+/// Kinds of `GameEvent` on the wire
+const EVENT_JOINED: u8 = 0;
+const EVENT_LEFT: u8 = 1;
+const EVENT_KNOCKED_OUT: u8 = 2;
+
+/// Activities of `Activity` on the wire
+const ACTIVITY_ACTIVE: u8 = 0;
+const ACTIVITY_AFK: u8 = 1;
+const ACTIVITY_SPECTATING: u8 = 2;
+
+/// Highest value of an input in `Input`, which has a bit for each direction
+const MAX_INPUT: u8 = 0xF;
 
 impl Message {
-    pub fn serialize(&self) -> String {
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        self.serialize_into(&mut out);
+        out
+    }
+
+    /// Append the message to `out`, so that a buffer can be reused for every message on hot paths
+    /// like replication
+    pub fn serialize_into(&self, out: &mut Vec<u8>) {
+        out.push(self.tag());
         match self {
-            Message::Ping | Message::Handshake => self.name().to_string(),
-            Message::Ack(player_id, color) => {
-                format!("{}:{}:{}", self.name(), player_id, serialize_color(&color))
-            }
-            Message::Leave(player_id) => {
-                format!("{}:{}", self.name(), player_id)
-            }
-            Message::Replicate(player_state) => format!(
-                "{}:{}:{},{},{}",
-                self.name(),
-                player_state.id,
-                player_state.pos.x as i32,
-                player_state.pos.y as i32,
-                serialize_color(&player_state.color),
-            ),
-            Message::Position(player_id, pos) => format!(
-                "{}:{}:{},{}",
-                self.name(),
-                player_id,
-                pos.x as i32,
-                pos.y as i32
-            ),
+            Message::Query | Message::Shutdown => {}
+            Message::Ping(time) => out.extend_from_slice(&time.to_be_bytes()),
+            Message::Pong(token, time) => {
+                out.extend_from_slice(&token.to_be_bytes());
+                out.extend_from_slice(&time.to_be_bytes());
+            }
+            Message::TimeOfDay(minutes) => out.extend_from_slice(&minutes.to_be_bytes()),
+            Message::Paused(paused) => out.push(u8::from(*paused)),
+            Message::Health(health) => out.push(*health),
+            Message::Leader(leader) => {
+                out.push(u8::from(leader.is_some()));
+                if let Some((player_id, score)) = leader {
+                    out.extend_from_slice(&player_id.to_be_bytes());
+                    out.extend_from_slice(&score.to_be_bytes());
+                }
+            }
+            Message::Activity(player_id, activity) => {
+                out.extend_from_slice(&player_id.to_be_bytes());
+                out.push(match activity {
+                    PlayerActivity::Active => ACTIVITY_ACTIVE,
+                    PlayerActivity::Afk => ACTIVITY_AFK,
+                    PlayerActivity::Spectating => ACTIVITY_SPECTATING,
+                });
+            }
+            Message::Storm(storm) => {
+                out.push(u8::from(storm.is_some()));
+                if let Some(storm) = storm {
+                    write_safe_zone(out, &storm.zone);
+                    write_safe_zone(out, &storm.target);
+                    let time_left = u32::try_from(storm.time_left.as_millis()).unwrap_or(u32::MAX);
+                    out.extend_from_slice(&time_left.to_be_bytes());
+                }
+            }
+            Message::Probe(size) => {
+                let start = out.len() - 1;
+                out.extend_from_slice(&size.to_be_bytes());
+                let padding = usize::from(*size).saturating_sub(HEADER_LEN + out.len() - start);
+                out.resize(out.len() + padding, 0);
+            }
+            Message::ProbeAck(token, size) => {
+                out.extend_from_slice(&token.to_be_bytes());
+                out.extend_from_slice(&size.to_be_bytes());
+            }
+            Message::Map(info) => {
+                write_str(out, &info.name);
+                out.extend_from_slice(&info.checksum.to_be_bytes());
+                out.extend_from_slice(&info.size.to_be_bytes());
+            }
+            Message::MapRequest(token, checksum, offset) => {
+                out.extend_from_slice(&token.to_be_bytes());
+                out.extend_from_slice(&checksum.to_be_bytes());
+                out.extend_from_slice(&offset.to_be_bytes());
+            }
+            Message::MapChunk(checksum, offset, data) => {
+                out.extend_from_slice(&checksum.to_be_bytes());
+                out.extend_from_slice(&offset.to_be_bytes());
+                write_bytes(out, data);
+            }
+            Message::Handshake(player_name, token) => {
+                write_str(out, player_name);
+                out.push(u8::from(token.is_some()));
+                if let Some(token) = token {
+                    out.extend_from_slice(&token.to_be_bytes());
+                }
+            }
+            Message::Ack(player_id, token, color, seed, server_name, world_params) => {
+                out.extend_from_slice(&player_id.to_be_bytes());
+                out.extend_from_slice(&token.to_be_bytes());
+                out.extend_from_slice(&quantize_color(color));
+                out.extend_from_slice(&seed.to_be_bytes());
+                write_str(out, server_name);
+                out.extend_from_slice(&world_params.player_size.to_be_bytes());
+                out.extend_from_slice(&world_params.player_speed.to_be_bytes());
+            }
+            Message::Reject(reason) => write_str(out, reason),
+            Message::Queued(position) => out.extend_from_slice(&position.to_be_bytes()),
+            Message::Notice(text) => write_str(out, text),
+            Message::Input(player_id, first_tick, inputs) => {
+                out.extend_from_slice(&player_id.to_be_bytes());
+                out.extend_from_slice(&first_tick.to_be_bytes());
+                write_count(out, inputs.len());
+                out.extend(inputs.iter().map(|input| input & MAX_INPUT));
+            }
+            Message::Chat(player_id, text) => {
+                out.extend_from_slice(&player_id.to_be_bytes());
+                write_str(out, text);
+            }
+            Message::Say(token, text) => {
+                out.extend_from_slice(&token.to_be_bytes());
+                write_str(out, text);
+            }
+            Message::UpdateProfile(token, color, player_name) => {
+                out.extend_from_slice(&token.to_be_bytes());
+                out.extend_from_slice(&quantize_color(color));
+                write_str(out, player_name);
+            }
+            Message::Profile(player_id, color, player_name) => {
+                out.extend_from_slice(&player_id.to_be_bytes());
+                out.extend_from_slice(&quantize_color(color));
+                write_str(out, player_name);
+            }
+            Message::Info(server_name, usage) => {
+                write_str(out, server_name);
+                out.extend_from_slice(&(usage.players as u64).to_be_bytes());
+                out.extend_from_slice(&(usage.peak_players as u64).to_be_bytes());
+                out.extend_from_slice(&usage.joins.to_be_bytes());
+                out.extend_from_slice(&usage.messages.to_be_bytes());
+            }
+            Message::Beacon(server_name, port, players) => {
+                write_str(out, server_name);
+                out.extend_from_slice(&port.to_be_bytes());
+                out.extend_from_slice(&players.to_be_bytes());
+            }
+            Message::Leave(player_id) => out.extend_from_slice(&player_id.to_be_bytes()),
+            Message::Disconnect(token) => out.extend_from_slice(&token.to_be_bytes()),
+            Message::Replicate(tick, player_state) => {
+                out.extend_from_slice(&tick.to_be_bytes());
+                out.extend_from_slice(&player_state.id.to_be_bytes());
+                write_position(out, player_state.pos);
+                out.extend_from_slice(&quantize_color(&player_state.color));
+            }
+            Message::Position(token, pos) => {
+                out.extend_from_slice(&token.to_be_bytes());
+                write_position(out, *pos);
+            }
+            Message::Snapshot(snapshot) => write_bytes(out, snapshot),
+            Message::SnapshotAck(token, tick) => {
+                out.extend_from_slice(&token.to_be_bytes());
+                out.extend_from_slice(&tick.to_be_bytes());
+            }
+            Message::Event(event) => {
+                let (kind, player_id, player_name) = match event {
+                    GameEvent::Joined(player_id, player_name) => {
+                        (EVENT_JOINED, player_id, player_name)
+                    }
+                    GameEvent::Left(player_id, player_name) => (EVENT_LEFT, player_id, player_name),
+                    GameEvent::KnockedOut(player_id, player_name) => {
+                        (EVENT_KNOCKED_OUT, player_id, player_name)
+                    }
+                };
+                out.push(kind);
+                out.extend_from_slice(&player_id.to_be_bytes());
+                write_str(out, player_name);
+            }
+            Message::MatchEnd(countdown, standings) => {
+                out.extend_from_slice(&countdown.to_be_bytes());
+                write_count(out, standings.len());
+                for result in standings {
+                    out.extend_from_slice(&result.player_id.to_be_bytes());
+                    out.extend_from_slice(&result.score.to_be_bytes());
+                    write_str(out, &result.name);
+                }
+            }
+            Message::MapVote(candidates) => {
+                write_count(out, candidates.len());
+                for (map_name, votes) in candidates {
+                    write_str(out, map_name);
+                    out.extend_from_slice(&votes.to_be_bytes());
+                }
+            }
+            Message::Stats(stats) => {
+                out.extend_from_slice(&stats.rtt_ms.to_be_bytes());
+                out.push(stats.packet_loss_percent);
+                out.extend_from_slice(&stats.corrections.to_be_bytes());
+            }
         }
     }
 
-    pub fn deserialize(msg: &str) -> Result<Message, std::io::Error> {
-        let parts: Vec<&str> = msg.split(':').collect();
-        match parts.get(0).map(|s| *s) {
-            Some(PING) => Ok(Message::Ping),
-            Some(HANDSHAKE) => Ok(Message::Handshake),
-            Some(ACK) if parts.len() == 3 => {
-                let player_id = parts[1].parse().map_err(|_| {
-                    std::io::Error::new(std::io::ErrorKind::InvalidData, "Invalid PlayerID")
-                })?;
-                let color = deserialize_color(parts[2])
-                    .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
-                Ok(Message::Ack(player_id, color))
-            }
-            Some(LEAVE) if parts.len() == 2 => {
-                let player_id = parts[1].parse().map_err(|_| {
-                    std::io::Error::new(std::io::ErrorKind::InvalidData, "Invalid PlayerID")
-                })?;
-                Ok(Message::Leave(player_id))
-            }
-            Some(REPL) if parts.len() == 3 => {
-                let player_id = parts[1].parse().map_err(|_| {
-                    std::io::Error::new(std::io::ErrorKind::InvalidData, "Invalid PlayerID")
-                })?;
-                let data_parts: Vec<&str> = parts[2].split(',').collect();
-                if data_parts.len() != 3 {
-                    return Err(std::io::Error::new(
-                        std::io::ErrorKind::InvalidData,
-                        "Invalid format",
-                    ));
-                }
-                let x = data_parts[0].parse().map_err(|_| {
-                    std::io::Error::new(std::io::ErrorKind::InvalidData, "Invalid x coordinate")
-                })?;
-                let y = data_parts[1].parse().map_err(|_| {
-                    std::io::Error::new(std::io::ErrorKind::InvalidData, "Invalid y coordinate")
-                })?;
-                let color = deserialize_color(data_parts[2])
-                    .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
-                Ok(Message::Replicate(Player {
-                    id: player_id,
-                    pos: Vector2::new(x, y),
-                    velocity: Vector2::new(0.0, 0.0),
-                    color,
-                }))
-            }
-            Some(POS) if parts.len() == 3 => {
-                let player_id = parts[1].parse().map_err(|_| {
-                    std::io::Error::new(std::io::ErrorKind::InvalidData, "Invalid PlayerID")
-                })?;
-                let pos_parts: Vec<&str> = parts[2].split(',').collect();
-                if pos_parts.len() != 2 {
-                    return Err(std::io::Error::new(
-                        std::io::ErrorKind::InvalidData,
-                        "Invalid position format",
-                    ));
-                }
-                let x = pos_parts[0].parse().map_err(|_| {
-                    std::io::Error::new(std::io::ErrorKind::InvalidData, "Invalid x coordinate")
-                })?;
-                let y = pos_parts[1].parse().map_err(|_| {
-                    std::io::Error::new(std::io::ErrorKind::InvalidData, "Invalid y coordinate")
-                })?;
-                Ok(Message::Position(player_id, Vector2::new(x, y)))
-            }
-            _ => Err(std::io::Error::new(
-                std::io::ErrorKind::InvalidData,
-                "Unknown or invalid message format",
-            )),
+    /// Parse a message received from the network. Must not panic on any input, which is checked
+    /// by the fuzz target in `fuzz/`.
+    pub fn deserialize(bytes: &[u8]) -> Result<Message, std::io::Error> {
+        if bytes.len() > MAX_PAYLOAD_SIZE {
+            return Err(invalid_data("Message is too long"));
         }
+        let mut fields = Fields { bytes };
+        let msg = match fields.u8()? {
+            tag::PING => Message::Ping(fields.u64()?),
+            tag::PONG => Message::Pong(fields.u64()?, fields.u64()?),
+            tag::MAP => {
+                let name = fields.str()?;
+                map::validate_map_name(name).map_err(invalid_data)?;
+                let checksum = fields.u32()?;
+                let size = fields.u32()?;
+                if size as usize > map::MAX_MAP_SIZE {
+                    return Err(invalid_data("Invalid map size"));
+                }
+                Message::Map(MapInfo {
+                    name: name.to_string(),
+                    checksum,
+                    size,
+                })
+            }
+            tag::MAPVOTE => {
+                let count = fields.u8()?;
+                if !(1..=MAX_MAP_VOTE_CANDIDATES).contains(&usize::from(count)) {
+                    return Err(invalid_data("Invalid map vote"));
+                }
+                let candidates = (0..count)
+                    .map(|_| {
+                        let name = fields.str()?;
+                        map::validate_map_name(name)
+                            .map_err(|_| invalid_data("Invalid map vote"))?;
+                        Ok((name.to_string(), fields.u32()?))
+                    })
+                    .collect::<Result<Vec<(String, u32)>, std::io::Error>>()?;
+                Message::MapVote(candidates)
+            }
+            tag::MAPREQ => Message::MapRequest(fields.u64()?, fields.u32()?, fields.map_offset()?),
+            tag::MAPCHUNK => {
+                let checksum = fields.u32()?;
+                let offset = fields.map_offset()?;
+                let data = fields.bytes()?;
+                if data.is_empty() || data.len() > map::MAP_CHUNK_SIZE {
+                    return Err(invalid_data("Invalid map chunk"));
+                }
+                Message::MapChunk(checksum, offset, data.to_vec())
+            }
+            tag::TIME => {
+                let minutes = fields.u16()?;
+                if minutes >= globals::MINUTES_PER_DAY {
+                    return Err(invalid_data("Invalid time of day"));
+                }
+                Message::TimeOfDay(minutes)
+            }
+            tag::EVENT => {
+                let kind = fields.u8()?;
+                let player_id = fields.player_id()?;
+                let name = fields.str()?;
+                validate_player_name(name).map_err(invalid_data)?;
+                let name = name.to_string();
+                match kind {
+                    EVENT_JOINED => Message::Event(GameEvent::Joined(player_id, name)),
+                    EVENT_LEFT => Message::Event(GameEvent::Left(player_id, name)),
+                    EVENT_KNOCKED_OUT => Message::Event(GameEvent::KnockedOut(player_id, name)),
+                    _ => return Err(invalid_data("Unknown event")),
+                }
+            }
+            tag::MATCHEND => {
+                let countdown = fields.u32()?;
+                let count = fields.u8()?;
+                if usize::from(count) > MAX_MATCH_RESULTS {
+                    return Err(invalid_data("Too many match results"));
+                }
+                let standings = (0..count)
+                    .map(|_| {
+                        let player_id = fields.player_id()?;
+                        let score = fields.u32()?;
+                        let name = fields.str()?;
+                        validate_player_name(name)
+                            .map_err(|_| invalid_data("Invalid match result"))?;
+                        Ok(MatchResult {
+                            player_id,
+                            name: name.to_string(),
+                            score,
+                        })
+                    })
+                    .collect::<Result<Vec<MatchResult>, std::io::Error>>()?;
+                Message::MatchEnd(countdown, standings)
+            }
+            tag::STATS => {
+                let rtt_ms = fields.u32()?;
+                let packet_loss_percent = fields.u8()?;
+                if packet_loss_percent > 100 {
+                    return Err(invalid_data("Invalid stats"));
+                }
+                Message::Stats(ConnectionStats {
+                    rtt_ms,
+                    packet_loss_percent,
+                    corrections: fields.u64()?,
+                })
+            }
+            tag::HANDSHAKE => {
+                let player_name = fields.str()?;
+                validate_player_name(player_name).map_err(invalid_data)?;
+                let token = if fields.flag()? {
+                    Some(fields.u64()?)
+                } else {
+                    None
+                };
+                Message::Handshake(player_name.to_string(), token)
+            }
+            tag::REJECT => Message::Reject(fields.str()?.to_string()),
+            tag::QUEUED => {
+                let position = fields.u32()?;
+                if position == 0 {
+                    return Err(invalid_data("Invalid queue position"));
+                }
+                Message::Queued(position)
+            }
+            tag::STORM => {
+                if fields.flag()? {
+                    Message::Storm(Some(StormState {
+                        zone: fields.safe_zone()?,
+                        target: fields.safe_zone()?,
+                        time_left: std::time::Duration::from_millis(fields.u32()?.into()),
+                    }))
+                } else {
+                    Message::Storm(None)
+                }
+            }
+            tag::ACTIVITY => {
+                let player_id = fields.player_id()?;
+                let activity = match fields.u8()? {
+                    ACTIVITY_ACTIVE => PlayerActivity::Active,
+                    ACTIVITY_AFK => PlayerActivity::Afk,
+                    ACTIVITY_SPECTATING => PlayerActivity::Spectating,
+                    _ => return Err(invalid_data("Unknown activity")),
+                };
+                Message::Activity(player_id, activity)
+            }
+            tag::LEADER => {
+                if fields.flag()? {
+                    Message::Leader(Some((fields.player_id()?, fields.u32()?)))
+                } else {
+                    Message::Leader(None)
+                }
+            }
+            tag::HEALTH => {
+                let health = fields.u8()?;
+                if health > storm::MAX_HEALTH {
+                    return Err(invalid_data("Invalid health"));
+                }
+                Message::Health(health)
+            }
+            tag::PAUSED => Message::Paused(fields.flag()?),
+            tag::PROBE => {
+                let size = fields.u16()?;
+                if !fields.rest().iter().all(|byte| *byte == 0) {
+                    return Err(invalid_data("Invalid padding"));
+                }
+                Message::Probe(size)
+            }
+            tag::PROBEACK => Message::ProbeAck(fields.u64()?, fields.u16()?),
+            tag::QUERY => Message::Query,
+            tag::SHUTDOWN => Message::Shutdown,
+            tag::CHAT => Message::Chat(fields.player_id()?, fields.chat_text()?),
+            tag::SAY => Message::Say(fields.u64()?, fields.chat_text()?),
+            tag::UPDATEPROFILE => {
+                let token = fields.u64()?;
+                let color = fields.color()?;
+                let player_name = fields.str()?;
+                validate_player_name(player_name).map_err(invalid_data)?;
+                Message::UpdateProfile(token, color, player_name.to_string())
+            }
+            tag::PROFILE => {
+                let player_id = fields.player_id()?;
+                let color = fields.color()?;
+                let player_name = fields.str()?;
+                validate_player_name(player_name).map_err(invalid_data)?;
+                Message::Profile(player_id, color, player_name.to_string())
+            }
+            tag::NOTICE => Message::Notice(fields.chat_text()?),
+            tag::ACK => {
+                let player_id = fields.player_id()?;
+                let token = fields.u64()?;
+                let color = fields.color()?;
+                let seed = fields.u64()?;
+                let server_name = fields.str()?;
+                validate_server_name(server_name).map_err(invalid_data)?;
+                let world_params = WorldParams {
+                    player_size: fields.f32()?,
+                    player_speed: fields.f32()?,
+                };
+                validate_world_params(&world_params).map_err(invalid_data)?;
+                Message::Ack(
+                    player_id,
+                    token,
+                    color,
+                    seed,
+                    server_name.to_string(),
+                    world_params,
+                )
+            }
+            tag::INFO => {
+                let server_name = fields.str()?;
+                validate_server_name(server_name).map_err(invalid_data)?;
+                Message::Info(
+                    server_name.to_string(),
+                    ServerUsage {
+                        players: fields.count()?,
+                        peak_players: fields.count()?,
+                        joins: fields.u64()?,
+                        messages: fields.u64()?,
+                    },
+                )
+            }
+            tag::BEACON => {
+                let server_name = fields.str()?;
+                validate_server_name(server_name).map_err(invalid_data)?;
+                Message::Beacon(server_name.to_string(), fields.u16()?, fields.u32()?)
+            }
+            tag::LEAVE => Message::Leave(fields.player_id()?),
+            tag::DISCONNECT => Message::Disconnect(fields.u64()?),
+            tag::REPL => Message::Replicate(
+                fields.u64()?,
+                Player {
+                    id: fields.player_id()?,
+                    pos: fields.position()?,
+                    velocity: Vector2::new(0.0, 0.0),
+                    color: fields.color()?,
+                },
+            ),
+            tag::POS => Message::Position(fields.u64()?, fields.position()?),
+            tag::SNAPSHOT => Message::Snapshot(fields.bytes()?.to_vec()),
+            tag::SNAPSHOTACK => Message::SnapshotAck(fields.u64()?, fields.u64()?),
+            tag::INPUT => {
+                let player_id = fields.player_id()?;
+                let first_tick: Tick = fields.u64()?;
+                let count = fields.u8()?;
+                let inputs = fields.take_slice(count.into())?;
+                if !(1..=MAX_INPUTS_PER_MESSAGE).contains(&inputs.len())
+                    || inputs.iter().any(|input| *input > MAX_INPUT)
+                {
+                    return Err(invalid_data("Invalid inputs"));
+                }
+                if first_tick.checked_add(inputs.len() as Tick).is_none() {
+                    return Err(invalid_data("Invalid tick"));
+                }
+                Message::Input(player_id, first_tick, inputs.to_vec())
+            }
+            _ => return Err(invalid_data("Unknown message type")),
+        };
+        fields.finish()?;
+        Ok(msg)
     }
 
-    fn name(&self) -> &'static str {
+    /// Name of the message type in logs and traffic statistics
+    pub fn name(&self) -> &'static str {
         match self {
-            Message::Ping => PING,
-            Message::Handshake => HANDSHAKE,
-            Message::Ack(_, _) => ACK,
+            Message::Ping(_) => PING,
+            Message::Pong(_, _) => PONG,
+            Message::Handshake(_, _) => HANDSHAKE,
+            Message::Ack(_, _, _, _, _, _) => ACK,
+            Message::Reject(_) => REJECT,
+            Message::Queued(_) => QUEUED,
+            Message::Query => QUERY,
+            Message::Info(_, _) => INFO,
+            Message::Beacon(_, _, _) => BEACON,
             Message::Leave(_) => LEAVE,
-            Message::Replicate(_) => REPL,
+            Message::Disconnect(_) => DISCONNECT,
+            Message::Shutdown => SHUTDOWN,
+            Message::Replicate(_, _) => REPL,
+            Message::Snapshot(_) => SNAPSHOT,
+            Message::SnapshotAck(_, _) => SNAPSHOTACK,
             Message::Position(_, _) => POS,
+            Message::Chat(_, _) => CHAT,
+            Message::Say(_, _) => SAY,
+            Message::UpdateProfile(_, _, _) => UPDATEPROFILE,
+            Message::Profile(_, _, _) => PROFILE,
+            Message::Notice(_) => NOTICE,
+            Message::Input(_, _, _) => INPUT,
+            Message::Stats(_) => STATS,
+            Message::Event(_) => EVENT,
+            Message::MatchEnd(_, _) => MATCHEND,
+            Message::TimeOfDay(_) => TIME,
+            Message::Map(_) => MAP,
+            Message::MapRequest(_, _, _) => MAPREQ,
+            Message::MapChunk(_, _, _) => MAPCHUNK,
+            Message::MapVote(_) => MAPVOTE,
+            Message::Probe(_) => PROBE,
+            Message::ProbeAck(_, _) => PROBEACK,
+            Message::Paused(_) => PAUSED,
+            Message::Storm(_) => STORM,
+            Message::Health(_) => HEALTH,
+            Message::Leader(_) => LEADER,
+            Message::Activity(_, _) => ACTIVITY,
         }
     }
+
+    /// Byte identifying the message type on the wire
+    fn tag(&self) -> u8 {
+        match self {
+            Message::Ping(_) => tag::PING,
+            Message::Pong(_, _) => tag::PONG,
+            Message::Handshake(_, _) => tag::HANDSHAKE,
+            Message::Ack(_, _, _, _, _, _) => tag::ACK,
+            Message::Reject(_) => tag::REJECT,
+            Message::Queued(_) => tag::QUEUED,
+            Message::Query => tag::QUERY,
+            Message::Info(_, _) => tag::INFO,
+            Message::Beacon(_, _, _) => tag::BEACON,
+            Message::Leave(_) => tag::LEAVE,
+            Message::Disconnect(_) => tag::DISCONNECT,
+            Message::Shutdown => tag::SHUTDOWN,
+            Message::Replicate(_, _) => tag::REPL,
+            Message::Snapshot(_) => tag::SNAPSHOT,
+            Message::SnapshotAck(_, _) => tag::SNAPSHOTACK,
+            Message::Position(_, _) => tag::POS,
+            Message::Chat(_, _) => tag::CHAT,
+            Message::Say(_, _) => tag::SAY,
+            Message::UpdateProfile(_, _, _) => tag::UPDATEPROFILE,
+            Message::Profile(_, _, _) => tag::PROFILE,
+            Message::Notice(_) => tag::NOTICE,
+            Message::Input(_, _, _) => tag::INPUT,
+            Message::Stats(_) => tag::STATS,
+            Message::Event(_) => tag::EVENT,
+            Message::MatchEnd(_, _) => tag::MATCHEND,
+            Message::TimeOfDay(_) => tag::TIME,
+            Message::Map(_) => tag::MAP,
+            Message::MapRequest(_, _, _) => tag::MAPREQ,
+            Message::MapChunk(_, _, _) => tag::MAPCHUNK,
+            Message::MapVote(_) => tag::MAPVOTE,
+            Message::Probe(_) => tag::PROBE,
+            Message::ProbeAck(_, _) => tag::PROBEACK,
+            Message::Paused(_) => tag::PAUSED,
+            Message::Storm(_) => tag::STORM,
+            Message::Health(_) => tag::HEALTH,
+            Message::Leader(_) => tag::LEADER,
+            Message::Activity(_, _) => tag::ACTIVITY,
+        }
+    }
+
+    /// Channel the message is sent on between clients and the server
+    pub fn channel(&self) -> Channel {
+        match self {
+            Message::Handshake(_, _)
+            | Message::Ack(_, _, _, _, _, _)
+            | Message::Reject(_)
+            | Message::Queued(_)
+            | Message::Query
+            | Message::Info(_, _)
+            | Message::Beacon(_, _, _)
+            | Message::Input(_, _, _)
+            | Message::MapRequest(_, _, _)
+            | Message::MapChunk(_, _, _)
+            | Message::Probe(_)
+            | Message::ProbeAck(_, _) => Channel::Unreliable,
+            Message::Ping(_)
+            | Message::Pong(_, _)
+            | Message::Replicate(_, _)
+            | Message::Snapshot(_)
+            | Message::SnapshotAck(_, _)
+            | Message::Position(_, _)
+            | Message::Stats(_)
+            | Message::TimeOfDay(_)
+            | Message::Paused(_)
+            | Message::Storm(_)
+            | Message::Health(_)
+            | Message::Leader(_) => Channel::UnreliableSequenced,
+            Message::Notice(_) | Message::Event(_) => Channel::ReliableUnordered,
+            Message::Leave(_)
+            | Message::Disconnect(_)
+            | Message::Shutdown
+            | Message::Chat(_, _)
+            | Message::Say(_, _)
+            | Message::UpdateProfile(_, _, _)
+            | Message::Profile(_, _, _)
+            | Message::Activity(_, _)
+            | Message::MatchEnd(_, _)
+            | Message::Map(_)
+            | Message::MapVote(_) => Channel::ReliableOrdered,
+        }
+    }
+}
+
+pub fn validate_player_name(name: &str) -> Result<(), String> {
+    validate_name("Player", name, globals::MAX_PLAYER_NAME_LEN)
+}
+
+pub fn validate_server_name(name: &str) -> Result<(), String> {
+    validate_name("Server", name, globals::MAX_SERVER_NAME_LEN)
+}
+
+/// Player size and speed within the ranges every client can handle
+pub fn validate_world_params(world_params: &WorldParams) -> Result<(), String> {
+    if !(globals::MIN_PLAYER_SIZE..=globals::MAX_PLAYER_SIZE).contains(&world_params.player_size) {
+        return Err(format!(
+            "Player size must be between {} and {}.",
+            globals::MIN_PLAYER_SIZE,
+            globals::MAX_PLAYER_SIZE
+        ));
+    }
+    if !(globals::MIN_PLAYER_SPEED..=globals::MAX_PLAYER_SPEED).contains(&world_params.player_speed)
+    {
+        return Err(format!(
+            "Player speed must be between {} and {}.",
+            globals::MIN_PLAYER_SPEED,
+            globals::MAX_PLAYER_SPEED
+        ));
+    }
+    Ok(())
+}
+
+pub fn validate_chat_message(text: &str) -> Result<(), String> {
+    if text.trim().is_empty() {
+        return Err("Chat message is empty.".to_string());
+    }
+    if text.chars().count() > globals::MAX_CHAT_MESSAGE_LEN {
+        return Err(format!(
+            "Chat message can't be longer than {} characters.",
+            globals::MAX_CHAT_MESSAGE_LEN
+        ));
+    }
+    if text.chars().any(char::is_control) {
+        return Err("Chat message contains invalid characters.".to_string());
+    }
+
+    Ok(())
 }
 
-fn serialize_color(color: &Vector3<f32>) -> String {
-    let r = (color[0] * 255.0).round() as u8;
-    let g = (color[1] * 255.0).round() as u8;
-    let b = (color[2] * 255.0).round() as u8;
+/// Names are shown as they are in the game and in logs, so they can't contain control characters
+/// like line breaks, and have to fit into a single datagram.
+fn validate_name(kind: &str, name: &str, max_len: usize) -> Result<(), String> {
+    if name.chars().count() > max_len {
+        return Err(format!(
+            "{kind} name can't be longer than {max_len} characters."
+        ));
+    }
+    if name.chars().any(char::is_control) {
+        return Err(format!("{kind} name contains invalid characters."));
+    }
 
-    String::from(format!("#{:02X}{:02X}{:02X}", r, g, b))
+    Ok(())
 }
 
-fn deserialize_color(color_hex: &str) -> Result<Vector3<f32>, String> {
-    let color_hex = color_hex.trim_start_matches('#');
+fn invalid_data<E>(reason: E) -> std::io::Error
+where
+    E: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    std::io::Error::new(std::io::ErrorKind::InvalidData, reason)
+}
+
+/// Fixed width field at the start of `bytes`, which is advanced past it
+pub(crate) fn take<const N: usize>(bytes: &mut &[u8]) -> Option<[u8; N]> {
+    let (field, rest) = bytes.split_first_chunk::<N>()?;
+    *bytes = rest;
+    Some(*field)
+}
+
+/// Fields of a received message, taken from the front one at a time without allocating
+struct Fields<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Fields<'a> {
+    fn take<const N: usize>(&mut self) -> Result<[u8; N], std::io::Error> {
+        take(&mut self.bytes).ok_or_else(|| invalid_data("Message is truncated"))
+    }
+
+    fn take_slice(&mut self, len: usize) -> Result<&'a [u8], std::io::Error> {
+        if len > self.bytes.len() {
+            return Err(invalid_data("Message is truncated"));
+        }
+        let (field, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(field)
+    }
+
+    fn u8(&mut self) -> Result<u8, std::io::Error> {
+        Ok(u8::from_be_bytes(self.take()?))
+    }
+
+    fn u16(&mut self) -> Result<u16, std::io::Error> {
+        Ok(u16::from_be_bytes(self.take()?))
+    }
+
+    fn u32(&mut self) -> Result<u32, std::io::Error> {
+        Ok(u32::from_be_bytes(self.take()?))
+    }
 
-    if color_hex.len() != 6 {
-        return Err("Invalid hex color format.".to_string());
+    fn u64(&mut self) -> Result<u64, std::io::Error> {
+        Ok(u64::from_be_bytes(self.take()?))
     }
 
-    let r = u8::from_str_radix(&color_hex[0..2], 16)
-        .map_err(|e| format!("Failed to parse red component: {}", e))?;
-    let g = u8::from_str_radix(&color_hex[2..4], 16)
-        .map_err(|e| format!("Failed to parse green component: {}", e))?;
-    let b = u8::from_str_radix(&color_hex[4..6], 16)
-        .map_err(|e| format!("Failed to parse blue component: {}", e))?;
+    fn f32(&mut self) -> Result<f32, std::io::Error> {
+        Ok(f32::from_be_bytes(self.take()?))
+    }
+
+    /// Presence of an optional group or a yes-no field, sent as 0 or 1
+    fn flag(&mut self) -> Result<bool, std::io::Error> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(invalid_data("Invalid flag")),
+        }
+    }
 
-    let r = r as f32 / 255.0;
-    let g = g as f32 / 255.0;
-    let b = b as f32 / 255.0;
+    /// Counts are sent as u64 regardless of the pointer width of the sender
+    fn count(&mut self) -> Result<usize, std::io::Error> {
+        usize::try_from(self.u64()?).map_err(|_| invalid_data("Invalid count"))
+    }
+
+    /// IDs are assigned from 1, so 0 never refers to a player.
+    fn player_id(&mut self) -> Result<PlayerID, std::io::Error> {
+        match self.u64()? {
+            0 => Err(invalid_data("Invalid PlayerID")),
+            player_id => Ok(player_id),
+        }
+    }
+
+    /// Bytes after their length as u16
+    fn bytes(&mut self) -> Result<&'a [u8], std::io::Error> {
+        let len = self.u16()?;
+        self.take_slice(len.into())
+    }
+
+    fn str(&mut self) -> Result<&'a str, std::io::Error> {
+        std::str::from_utf8(self.bytes()?).map_err(|_| invalid_data("Text is not valid UTF-8"))
+    }
+
+    fn chat_text(&mut self) -> Result<String, std::io::Error> {
+        let text = self.str()?;
+        validate_chat_message(text).map_err(invalid_data)?;
+        Ok(text.to_string())
+    }
+
+    fn color(&mut self) -> Result<Vector3<f32>, std::io::Error> {
+        Ok(dequantize_color(self.take()?))
+    }
+
+    fn position(&mut self) -> Result<Vector2<f32>, std::io::Error> {
+        let x = self.u16()?;
+        let y = self.u16()?;
+        Ok(dequantize_position(x, y))
+    }
+
+    fn safe_zone(&mut self) -> Result<SafeZone, std::io::Error> {
+        Ok(SafeZone {
+            center: self.position()?,
+            radius: self.u16()?.into(),
+        })
+    }
+
+    fn map_offset(&mut self) -> Result<u32, std::io::Error> {
+        let offset = self.u32()?;
+        if offset as usize >= map::MAX_MAP_SIZE {
+            return Err(invalid_data("Invalid map offset"));
+        }
+        Ok(offset)
+    }
+
+    /// Every byte not taken yet, like padding
+    fn rest(&mut self) -> &'a [u8] {
+        std::mem::take(&mut self.bytes)
+    }
+
+    /// Messages have a fixed layout, anything after the last field is garbage
+    fn finish(&self) -> Result<(), std::io::Error> {
+        if !self.bytes.is_empty() {
+            return Err(invalid_data("Trailing bytes after message"));
+        }
+        Ok(())
+    }
+}
+
+/// Text after its length in bytes as u16
+fn write_str(out: &mut Vec<u8>, text: &str) {
+    write_bytes(out, text.as_bytes());
+}
+
+/// Bytes after their length as u16. Anything longer wouldn't fit into a datagram, so it's cut off
+/// and left for the receiver to reject.
+fn write_bytes(out: &mut Vec<u8>, bytes: &[u8]) {
+    let len = u16::try_from(bytes.len()).unwrap_or(u16::MAX);
+    out.extend_from_slice(&len.to_be_bytes());
+    out.extend_from_slice(&bytes[..usize::from(len)]);
+}
+
+/// Number of repeated groups following it. Counts are limited far below 256 by the datagram size,
+/// larger ones are left for the receiver to reject.
+fn write_count(out: &mut Vec<u8>, count: usize) {
+    out.push(u8::try_from(count).unwrap_or(u8::MAX));
+}
+
+/// Positions are sent as 16-bit fixed-point numbers relative to world bounds, which is precise to
+/// a fraction of a pixel. Positions outside of world bounds are clamped, NaN becomes the minimum.
+pub fn quantize_position(pos: Vector2<f32>) -> (u16, u16) {
+    let bounds = &globals::WORLD_BOUNDS;
+    (
+        quantize(pos.x, bounds.min_x, bounds.max_x),
+        quantize(pos.y, bounds.min_y, bounds.max_y),
+    )
+}
+
+pub fn dequantize_position(x: u16, y: u16) -> Vector2<f32> {
+    let bounds = &globals::WORLD_BOUNDS;
+    Vector2::new(
+        dequantize(x, bounds.min_x, bounds.max_x),
+        dequantize(y, bounds.min_y, bounds.max_y),
+    )
+}
+
+/// Velocities are quantized like positions, within the range the fastest player can move at
+pub fn quantize_velocity(velocity: Vector2<f32>) -> (u16, u16) {
+    let max = globals::MAX_PLAYER_SPEED;
+    (
+        quantize(velocity.x, -max, max),
+        quantize(velocity.y, -max, max),
+    )
+}
+
+pub fn dequantize_velocity(x: u16, y: u16) -> Vector2<f32> {
+    let max = globals::MAX_PLAYER_SPEED;
+    Vector2::new(dequantize(x, -max, max), dequantize(y, -max, max))
+}
+
+fn quantize(value: f32, min: f32, max: f32) -> u16 {
+    // Float to integer casts saturate and turn NaN into 0
+    ((value.clamp(min, max) - min) / (max - min) * u16::MAX as f32).round() as u16
+}
+
+fn dequantize(value: u16, min: f32, max: f32) -> f32 {
+    min + value as f32 / u16::MAX as f32 * (max - min)
+}
+
+fn write_position(out: &mut Vec<u8>, pos: Vector2<f32>) {
+    let (x, y) = quantize_position(pos);
+    out.extend_from_slice(&x.to_be_bytes());
+    out.extend_from_slice(&y.to_be_bytes());
+}
+
+/// Center like positions, radius in whole world units
+fn write_safe_zone(out: &mut Vec<u8>, zone: &SafeZone) {
+    write_position(out, zone.center);
+    out.extend_from_slice(&(zone.radius.round() as u16).to_be_bytes());
+}
+
+/// Float to integer casts saturate, so colors out of range end up at the closest valid one
+pub fn quantize_color(color: &Vector3<f32>) -> [u8; 3] {
+    [
+        (color[0] * 255.0).round() as u8,
+        (color[1] * 255.0).round() as u8,
+        (color[2] * 255.0).round() as u8,
+    ]
+}
 
-    Ok(Vector3::new(r, g, b))
+pub fn dequantize_color([r, g, b]: [u8; 3]) -> Vector3<f32> {
+    Vector3::new(r as f32 / 255.0, g as f32 / 255.0, b as f32 / 255.0)
 }
 
 static TRACE_ENABLED: AtomicBool = AtomicBool::new(false);
@@ -183,9 +1408,11 @@ pub fn set_trace(enabled: bool) {
     TRACE_ENABLED.store(enabled, Ordering::Relaxed);
 }
 
-/// In-house application trace logging functionality.
+/// In-house application trace logging functionality. Traces are always kept for the network
+/// log, see `netlog`, and only printed when enabled.
 pub fn trace(s: String) {
     if TRACE_ENABLED.load(Ordering::Relaxed) {
-        println!("[TRACE] {s}");
+        logger::log(logger::Level::Trace, "net", s.clone());
     }
+    netlog::record(s);
 }
@@ -0,0 +1,381 @@
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    net::SocketAddr,
+    time::{Duration, Instant},
+};
+
+use tokio::net::UdpSocket;
+
+use crate::{
+    globals,
+    message::{self, Channel, DatagramHeader, Sequence, FLAG_ACK, FLAG_BATCH, HEADER_LEN},
+};
+
+/// Most datagrams received at once before the receiving task yields
+const RECV_BATCH_SIZE: usize = 32;
+
+/// Reliable messages are resent after this long without an acknowledgement, which is a few round
+/// trips on a typical connection
+pub const RESEND_INTERVAL: Duration = Duration::from_millis(100);
+/// Reliable messages are given up on after waiting this long for an acknowledgement, the peer is
+/// gone by then as far as the connection timeout goes
+pub const GIVE_UP_AFTER: Duration = globals::CONNECTION_TIMEOUT_SEC;
+/// Most reliable-ordered messages held back while waiting for an earlier one. Messages further
+/// ahead are left unacknowledged, so they arrive again later.
+const MAX_HELD_BACK: Sequence = 256;
+/// Sequence numbers of reliable-unordered messages remembered for dropping duplicates
+const RECEIVED_WINDOW: usize = 1024;
+/// Size of the length in front of each message of a batch
+const BATCH_LENGTH_SIZE: usize = 2;
+/// Datagrams of this size get through practically every path on the internet, so batches are
+/// packed up to it until the path MTU to the peer is known
+pub const DEFAULT_PACKET_BUDGET: usize = 1200;
+
+/// Reusable set of receive buffers, filled with every datagram already waiting on a socket on a
+/// single wakeup. Under load this saves a task wakeup for every datagram.
+pub struct RecvBatch {
+    buffers: Vec<[u8; globals::MAX_DATAGRAM_SIZE]>,
+    /// Length and sender of the datagrams in the buffers of the same index
+    received: Vec<(usize, SocketAddr)>,
+}
+
+impl RecvBatch {
+    pub fn new() -> Self {
+        Self {
+            buffers: vec![[0u8; globals::MAX_DATAGRAM_SIZE]; RECV_BATCH_SIZE],
+            received: Vec::with_capacity(RECV_BATCH_SIZE),
+        }
+    }
+
+    /// Wait for the socket to become readable, then receive datagrams until none are left or the
+    /// batch is full. Datagrams of the previous call are discarded. An error is only returned if
+    /// it happened before anything was received, otherwise it shows up again on the next call
+    /// if it persists.
+    pub async fn recv(&mut self, socket: &UdpSocket) -> std::io::Result<()> {
+        self.received.clear();
+        socket.readable().await?;
+        for buf in &mut self.buffers {
+            match socket.try_recv_from(buf) {
+                Ok(received) => self.received.push(received),
+                // Readiness was spurious or everything has been read
+                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => break,
+                Err(e) if self.received.is_empty() => return Err(e),
+                Err(_) => break,
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Datagrams received by the last call to `recv` with their senders, in order of arrival
+    pub fn datagrams(&self) -> impl Iterator<Item = (&[u8], SocketAddr)> {
+        self.buffers
+            .iter()
+            .zip(&self.received)
+            .map(|(buf, (len, sender))| (&buf[..*len], *sender))
+    }
+}
+
+impl Default for RecvBatch {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Messages made available by a received datagram, and the acknowledgement to send back for it
+#[derive(Debug, Default)]
+pub struct Delivery {
+    pub messages: Vec<Vec<u8>>,
+    pub ack: Option<Vec<u8>>,
+}
+
+struct UnackedMessage {
+    channel: Channel,
+    sequence: Sequence,
+    datagram: Vec<u8>,
+    first_sent: Instant,
+    sent_at: Instant,
+}
+
+/// Delivery state of the channels between this peer and a single other one: sequence numbers in
+/// both directions, reliable messages waiting for an acknowledgement, and ordered messages waiting
+/// for an earlier one. Sockets are left to the caller.
+pub struct Channels {
+    next_sequence: HashMap<Channel, Sequence>,
+    /// In order of sending
+    unacked: Vec<UnackedMessage>,
+    /// Latest sequence number received per `message::sequence_key()`
+    latest_sequenced: HashMap<u8, Sequence>,
+    received_unordered: HashSet<Sequence>,
+    /// Same as `received_unordered` in order of arrival, for forgetting the oldest ones
+    received_unordered_order: VecDeque<Sequence>,
+    next_ordered: Sequence,
+    held_back: HashMap<Sequence, Vec<Vec<u8>>>,
+    /// Size of the largest datagram batches are packed into
+    packet_budget: usize,
+    /// When anything was last sent to the peer, including resends
+    last_sent: Option<Instant>,
+}
+
+impl Default for Channels {
+    fn default() -> Self {
+        Self {
+            next_sequence: HashMap::new(),
+            unacked: Vec::new(),
+            latest_sequenced: HashMap::new(),
+            received_unordered: HashSet::new(),
+            received_unordered_order: VecDeque::new(),
+            next_ordered: 0,
+            held_back: HashMap::new(),
+            packet_budget: DEFAULT_PACKET_BUDGET,
+            last_sent: None,
+        }
+    }
+}
+
+impl Channels {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn packet_budget(&self) -> usize {
+        self.packet_budget
+    }
+
+    /// Pack batches up to the given datagram size from now on, e.g. once the path MTU is known
+    pub fn set_packet_budget(&mut self, packet_budget: usize) {
+        self.packet_budget = packet_budget;
+    }
+
+    /// When a datagram was last sent to the peer, `None` if nothing was sent yet. Acknowledgements
+    /// don't count, since they only answer the peer.
+    pub fn last_sent(&self) -> Option<Instant> {
+        self.last_sent
+    }
+
+    /// Datagram carrying the message on the given channel. Reliable messages are kept until they
+    /// are acknowledged, see `resend_due()`.
+    pub fn send(&mut self, channel: Channel, payload: &[u8], now: Instant) -> Vec<u8> {
+        self.send_with_flags(channel, 0, payload, now)
+    }
+
+    /// Datagrams carrying the messages on the given channel, with as many messages in each as
+    /// fit into the packet budget. Messages larger than the budget are sent alone.
+    pub fn send_batch(
+        &mut self,
+        channel: Channel,
+        payloads: &[Vec<u8>],
+        now: Instant,
+    ) -> Vec<Vec<u8>> {
+        let mut datagrams = Vec::new();
+        let mut batch: Vec<&[u8]> = Vec::new();
+        let mut batch_size = HEADER_LEN;
+        for payload in payloads {
+            let size = BATCH_LENGTH_SIZE + payload.len();
+            if !batch.is_empty() && batch_size + size > self.packet_budget {
+                datagrams.push(self.send_batched(channel, &batch, now));
+                batch.clear();
+                batch_size = HEADER_LEN;
+            }
+            batch.push(payload);
+            batch_size += size;
+        }
+        if !batch.is_empty() {
+            datagrams.push(self.send_batched(channel, &batch, now));
+        }
+        datagrams
+    }
+
+    /// A single message is sent as it is, without a length
+    fn send_batched(&mut self, channel: Channel, batch: &[&[u8]], now: Instant) -> Vec<u8> {
+        if let [payload] = batch {
+            return self.send_with_flags(channel, 0, payload, now);
+        }
+        let mut payload = Vec::new();
+        for message in batch {
+            // Messages fit into a datagram, so their length fits
+            payload.extend_from_slice(&(message.len() as u16).to_be_bytes());
+            payload.extend_from_slice(message);
+        }
+        self.send_with_flags(channel, FLAG_BATCH, &payload, now)
+    }
+
+    fn send_with_flags(
+        &mut self,
+        channel: Channel,
+        flags: u8,
+        payload: &[u8],
+        now: Instant,
+    ) -> Vec<u8> {
+        let sequence = match channel {
+            Channel::Unreliable => 0,
+            _ => {
+                let next_sequence = self.next_sequence.entry(channel).or_default();
+                let sequence = *next_sequence;
+                *next_sequence = next_sequence.wrapping_add(1);
+                sequence
+            }
+        };
+        let datagram = DatagramHeader {
+            flags,
+            channel,
+            sequence,
+            ..DatagramHeader::default()
+        }
+        .encode(payload);
+        self.last_sent = Some(now);
+        if channel.is_reliable() {
+            self.unacked.push(UnackedMessage {
+                channel,
+                sequence,
+                datagram: datagram.clone(),
+                first_sent: now,
+                sent_at: now,
+            });
+        }
+        datagram
+    }
+
+    /// Handle a datagram received from the peer. Reliable messages are acknowledged even if they
+    /// arrived before, since the previous acknowledgement may have been lost.
+    pub fn receive(&mut self, header: DatagramHeader, payload: &[u8]) -> Delivery {
+        let mut delivery = Delivery::default();
+        if header.flags & FLAG_ACK != 0 {
+            self.unacked.retain(|unacked| {
+                (unacked.channel, unacked.sequence) != (header.channel, header.sequence)
+            });
+            return delivery;
+        }
+        let sequence = header.sequence;
+        let messages = if header.flags & FLAG_BATCH != 0 {
+            // Datagram is garbage, the peer will resend a reliable message it really sent
+            let Some(messages) = split_batch(payload) else {
+                return delivery;
+            };
+            messages
+        } else {
+            vec![payload.to_vec()]
+        };
+        match header.channel {
+            Channel::Unreliable => delivery.messages = messages,
+            Channel::UnreliableSequenced => {
+                // Messages of a batch share the sequence number, so it is checked once per key
+                let mut fresh: HashMap<u8, bool> = HashMap::new();
+                delivery.messages = messages
+                    .into_iter()
+                    .filter(|msg| {
+                        let Some(key) = message::sequence_key(msg) else {
+                            return true;
+                        };
+                        *fresh.entry(key).or_insert_with(|| {
+                            let is_fresh = self
+                                .latest_sequenced
+                                .get(&key)
+                                .map_or(true, |&latest| is_newer(sequence, latest));
+                            if is_fresh {
+                                self.latest_sequenced.insert(key, sequence);
+                            }
+                            is_fresh
+                        })
+                    })
+                    .collect();
+            }
+            Channel::ReliableUnordered => {
+                if self.received_unordered.insert(sequence) {
+                    self.received_unordered_order.push_back(sequence);
+                    if self.received_unordered_order.len() > RECEIVED_WINDOW {
+                        if let Some(oldest) = self.received_unordered_order.pop_front() {
+                            self.received_unordered.remove(&oldest);
+                        }
+                    }
+                    delivery.messages = messages;
+                }
+            }
+            Channel::ReliableOrdered => {
+                if sequence == self.next_ordered {
+                    delivery.messages = messages;
+                    self.next_ordered = self.next_ordered.wrapping_add(1);
+                    while let Some(held_back) = self.held_back.remove(&self.next_ordered) {
+                        delivery.messages.extend(held_back);
+                        self.next_ordered = self.next_ordered.wrapping_add(1);
+                    }
+                } else if is_newer(sequence, self.next_ordered) {
+                    if sequence.wrapping_sub(self.next_ordered) >= MAX_HELD_BACK {
+                        return delivery;
+                    }
+                    self.held_back.entry(sequence).or_insert(messages);
+                }
+            }
+        }
+        delivery.ack = acknowledgement(header);
+        delivery
+    }
+
+    /// Whether every reliable message sent so far has been acknowledged by the peer
+    pub fn all_acknowledged(&self) -> bool {
+        self.unacked.is_empty()
+    }
+
+    /// Datagrams of reliable messages which have been waiting for an acknowledgement for
+    /// `RESEND_INTERVAL`, to be sent again. None once a message has gone unacknowledged for
+    /// `GIVE_UP_AFTER`, meaning that the peer stopped answering. Every unacknowledged message is
+    /// forgotten then, so that nothing piles up for a peer which is gone.
+    pub fn resend_due(&mut self, now: Instant) -> Option<Vec<Vec<u8>>> {
+        // Oldest one comes first
+        if self
+            .unacked
+            .first()
+            .is_some_and(|unacked| now.duration_since(unacked.first_sent) >= GIVE_UP_AFTER)
+        {
+            self.unacked.clear();
+            return None;
+        }
+        let resent: Vec<Vec<u8>> = self
+            .unacked
+            .iter_mut()
+            .filter(|unacked| now.duration_since(unacked.sent_at) >= RESEND_INTERVAL)
+            .map(|unacked| {
+                unacked.sent_at = now;
+                unacked.datagram.clone()
+            })
+            .collect();
+        if !resent.is_empty() {
+            self.last_sent = Some(now);
+        }
+        Some(resent)
+    }
+}
+
+/// Datagram acknowledging a received one, None for unreliable and acknowledgement datagrams
+pub fn acknowledgement(header: DatagramHeader) -> Option<Vec<u8>> {
+    if header.flags & FLAG_ACK != 0 || !header.channel.is_reliable() {
+        return None;
+    }
+    Some(
+        DatagramHeader {
+            flags: FLAG_ACK,
+            channel: header.channel,
+            sequence: header.sequence,
+            ..DatagramHeader::default()
+        }
+        .encode(&[]),
+    )
+}
+
+/// Messages of a batch, each after its length as u16. None if the lengths don't add up.
+fn split_batch(mut payload: &[u8]) -> Option<Vec<Vec<u8>>> {
+    let mut messages = Vec::new();
+    while !payload.is_empty() {
+        let len = u16::from_be_bytes(message::take(&mut payload)?);
+        let (message, rest) = payload.split_at_checked(len.into())?;
+        messages.push(message.to_vec());
+        payload = rest;
+    }
+    Some(messages)
+}
+
+/// Whether `sequence` was sent after `other`, assuming they are less than half of the sequence
+/// numbers apart
+fn is_newer(sequence: Sequence, other: Sequence) -> bool {
+    sequence != other && sequence.wrapping_sub(other) < Sequence::MAX / 2 + 1
+}
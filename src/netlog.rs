@@ -0,0 +1,126 @@
+use std::{
+    collections::VecDeque,
+    fs::File,
+    io::{BufWriter, Write},
+    ops::Range,
+    path::Path,
+    sync::{Mutex, OnceLock},
+};
+
+use chrono::{DateTime, Local};
+
+/// Traced messages kept for the network log, the last few seconds of traffic of a busy session
+pub const NETLOG_CAPACITY: usize = 4096;
+
+/// Message trace with the time it was traced at
+#[derive(Clone, Debug, PartialEq)]
+pub struct TraceEntry {
+    pub time: DateTime<Local>,
+    pub text: String,
+}
+
+impl std::fmt::Display for TraceEntry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} {}",
+            self.time.format("%Y-%m-%d %H:%M:%S%.3f"),
+            self.text
+        )
+    }
+}
+
+/// Latest traces up to a capacity, forgetting the oldest ones as new ones come in
+#[derive(Debug)]
+pub struct TraceRing {
+    capacity: usize,
+    entries: VecDeque<TraceEntry>,
+}
+
+impl TraceRing {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            entries: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    pub fn push(&mut self, entry: TraceEntry) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.entries.len() == self.capacity {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(entry);
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Entries in the given range counted from the oldest one, cut off at the newest one
+    pub fn entries(&self, range: Range<usize>) -> Vec<TraceEntry> {
+        self.entries
+            .range(range.start.min(self.len())..range.end.min(self.len()))
+            .cloned()
+            .collect()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    /// Every entry from the oldest one, a line each
+    pub fn write_to(&self, out: &mut impl Write) -> std::io::Result<()> {
+        for entry in &self.entries {
+            writeln!(out, "{entry}")?;
+        }
+        Ok(())
+    }
+}
+
+static HISTORY: OnceLock<Mutex<TraceRing>> = OnceLock::new();
+
+fn history() -> &'static Mutex<TraceRing> {
+    HISTORY.get_or_init(|| Mutex::new(TraceRing::new(NETLOG_CAPACITY)))
+}
+
+/// Keep a message trace for the network log, whether traces are printed or not
+pub fn record(text: String) {
+    let entry = TraceEntry {
+        time: Local::now(),
+        text,
+    };
+    history().lock().unwrap().push(entry);
+}
+
+/// Number of traces kept
+pub fn len() -> usize {
+    history().lock().unwrap().len()
+}
+
+/// Traces in the given range counted from the oldest one kept
+pub fn entries(range: Range<usize>) -> Vec<TraceEntry> {
+    history().lock().unwrap().entries(range)
+}
+
+pub fn clear() {
+    history().lock().unwrap().clear();
+}
+
+/// Write the traces kept into a text file, returning how many were written. Tracing goes on
+/// meanwhile, the traces are copied before writing.
+pub fn export(path: &Path) -> std::io::Result<usize> {
+    let entries = entries(0..NETLOG_CAPACITY);
+    let mut out = BufWriter::new(File::create(path)?);
+    for entry in &entries {
+        writeln!(out, "{entry}")?;
+    }
+    out.flush()?;
+    Ok(entries.len())
+}
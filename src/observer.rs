@@ -0,0 +1,128 @@
+use std::net::SocketAddr;
+
+use serde::Serialize;
+use tokio::{
+    io::AsyncWriteExt,
+    net::{TcpListener, TcpStream},
+    sync::broadcast::{self, error::RecvError},
+    task::JoinHandle,
+};
+
+use crate::{logger, message::MatchResult, task, PlayerID, Tick};
+
+/// Most observers connected at once
+const MAX_OBSERVERS: usize = 16;
+/// Events buffered per observer. Observers reading slower than this miss events.
+pub const EVENT_BUFFER: usize = 256;
+
+/// Something that happened on the server, published to observers as a JSON object with the kind
+/// of event in the `event` field
+#[derive(Clone, Debug, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum ObserverEvent {
+    Joined {
+        player_id: PlayerID,
+        name: String,
+    },
+    Left {
+        player_id: PlayerID,
+        name: String,
+    },
+    /// Chat message relayed to every player, after filtering blocked words
+    Chat {
+        player_id: PlayerID,
+        name: String,
+        text: String,
+    },
+    /// Every player with position and score, published a few times per second
+    Snapshot {
+        tick: Tick,
+        players: Vec<ObservedPlayer>,
+    },
+    /// Final standings of a match, best first
+    MatchEnd {
+        standings: Vec<MatchResult>,
+    },
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct ObservedPlayer {
+    pub player_id: PlayerID,
+    pub name: String,
+    pub x: f32,
+    pub y: f32,
+    /// Distance walked in the current match
+    pub score: u32,
+}
+
+/// Only local tools can observe, the stream is not meant to be exposed publicly
+pub async fn bind(port: u16) -> std::io::Result<TcpListener> {
+    TcpListener::bind(("127.0.0.1", port)).await
+}
+
+/// Stream game events to every connected observer as JSON, one event per line, so that
+/// dashboards, stream overlays and bots can follow a match without joining it.
+pub async fn serve(listener: TcpListener, events: broadcast::Sender<ObserverEvent>) {
+    let mut connections = Connections(Vec::new());
+    loop {
+        let (stream, address) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                logger::warn("observer", format!("Failed to accept observer: {e}"));
+                continue;
+            }
+        };
+        connections.0.retain(|connection| !connection.is_finished());
+        if connections.0.len() >= MAX_OBSERVERS {
+            logger::warn(
+                "observer",
+                format!("Refused observer from {address}: too many observers"),
+            );
+            continue;
+        }
+        logger::info("observer", format!("Observer connected from {address}"));
+        connections.0.push(task::spawn_named(
+            "observer_stream",
+            stream_events(stream, address, events.subscribe()),
+        ));
+    }
+}
+
+/// Streams of observers, stopped together with serving
+struct Connections(Vec<JoinHandle<()>>);
+
+impl Drop for Connections {
+    fn drop(&mut self) {
+        for connection in &self.0 {
+            connection.abort();
+        }
+    }
+}
+
+async fn stream_events(
+    mut stream: TcpStream,
+    address: SocketAddr,
+    mut events: broadcast::Receiver<ObserverEvent>,
+) {
+    loop {
+        let event = match events.recv().await {
+            Ok(event) => event,
+            Err(RecvError::Lagged(missed)) => {
+                logger::warn(
+                    "observer",
+                    format!("Observer at {address} is too slow, skipped {missed} events"),
+                );
+                continue;
+            }
+            Err(RecvError::Closed) => break,
+        };
+        let Ok(mut line) = serde_json::to_string(&event) else {
+            continue;
+        };
+        line.push('\n');
+        if stream.write_all(line.as_bytes()).await.is_err() {
+            break;
+        }
+    }
+    logger::info("observer", format!("Observer at {address} disconnected"));
+}
@@ -0,0 +1,78 @@
+use std::{
+    collections::VecDeque,
+    net::SocketAddr,
+    time::{Duration, Instant},
+};
+
+use crate::globals;
+
+/// Queued clients retry their handshake every `QUEUE_POLL_INTERVAL`, they are forgotten when
+/// they stop retrying for this long
+pub const QUEUE_TIMEOUT: Duration = globals::CONNECTION_TIMEOUT_SEC;
+/// Time between two handshakes of a client waiting in the join queue, each answered with its
+/// place in the queue
+pub const QUEUE_POLL_INTERVAL: Duration = Duration::from_secs(1);
+/// Most clients waiting at once, so that floods of join requests can't exhaust memory. Joiners
+/// beyond are refused like without a queue.
+pub const MAX_QUEUED_CLIENTS: usize = 256;
+
+/// Outcome of a join request on a server with a join queue
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Admission {
+    /// Client can join right away
+    Admit,
+    /// Client waits in the queue at the given place, starting from 1
+    Queued(u32),
+    /// Queue is full too
+    Refuse,
+}
+
+/// Clients waiting for a slot on a full server, in the order of their first join request. Free
+/// slots go to the front of the queue, so clients retrying their handshake earlier than the ones
+/// ahead of them can't take their slot.
+#[derive(Default)]
+pub struct JoinQueue {
+    /// Waiting clients with the time of their last handshake, first in line first
+    waiting: VecDeque<(SocketAddr, Instant)>,
+}
+
+impl JoinQueue {
+    /// Decide over a handshake of `client` while `free_slots` players can join. Clients not
+    /// waiting yet get in line.
+    pub fn request(&mut self, client: SocketAddr, free_slots: usize, now: Instant) -> Admission {
+        self.waiting
+            .retain(|(_, last_heard)| now.duration_since(*last_heard) < QUEUE_TIMEOUT);
+        let index = match self
+            .waiting
+            .iter()
+            .position(|(queued, _)| *queued == client)
+        {
+            Some(index) => {
+                self.waiting[index].1 = now;
+                index
+            }
+            None if self.waiting.len() >= free_slots + MAX_QUEUED_CLIENTS => {
+                return Admission::Refuse
+            }
+            None => {
+                self.waiting.push_back((client, now));
+                self.waiting.len() - 1
+            }
+        };
+        if index < free_slots {
+            self.waiting.remove(index);
+            Admission::Admit
+        } else {
+            Admission::Queued((index - free_slots + 1) as u32)
+        }
+    }
+
+    /// Number of clients waiting, including the ones which may have given up already
+    pub fn len(&self) -> usize {
+        self.waiting.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.waiting.is_empty()
+    }
+}
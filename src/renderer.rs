@@ -1,6 +1,11 @@
-use std::{collections::HashMap, sync::Arc};
+use std::{
+    collections::{HashMap, HashSet},
+    error::Error,
+    num::NonZeroU32,
+    sync::Arc,
+};
 
-use cgmath::{Matrix, Matrix4, Vector2, Vector3};
+use cgmath::{Matrix, Matrix4, Vector2, Vector3, VectorSpace};
 use glow::HasContext;
 use glutin::{
     config::{ConfigTemplateBuilder, GlConfig},
@@ -12,19 +17,62 @@ use glutin::{
 use glutin_winit::{DisplayBuilder, GlWindow};
 use raw_window_handle::HasWindowHandle;
 use winit::{
-    dpi::PhysicalSize,
+    dpi::{PhysicalPosition, PhysicalSize},
     event_loop::ActiveEventLoop,
-    window::{Window, WindowAttributes},
+    window::{Fullscreen, Window, WindowAttributes},
 };
 
-use crate::{fsm, globals, gui::Gui, Player, PlayerID};
+use crate::{
+    avatar::{Avatar, AVATAR_CELLS, AVATAR_PATTERN_SCALE, AVATAR_SHADE},
+    globals,
+    gui::{Gui, GuiState},
+    map::Map,
+    storm::SafeZone,
+    Player, PlayerID,
+};
 
-const GRID_COL_COUNT: usize = 40;
+const OBSTACLE_COLOR: Vector3<f32> = Vector3::new(0.3, 0.3, 0.3);
+const SPAWN_POINT_COLOR: Vector3<f32> = Vector3::new(0.2, 0.7, 0.3);
+const STORM_EDGE_COLOR: Vector3<f32> = Vector3::new(0.43, 0.16, 0.63);
+/// Edge of the safe zone is drawn as a dotted circle, with dots this far apart
+const STORM_EDGE_SPACING: f32 = 24.0;
+const STORM_EDGE_DOT_SIZE: f32 = 8.0;
+const CROWN_COLOR: Vector3<f32> = Vector3::new(1.0, 0.8, 0.1);
+/// Crown of the leader floats this far above the quad
+const CROWN_GAP: f32 = 4.0;
+const CROWN_SIZE: Vector2<f32> = Vector2::new(18.0, 10.0);
+/// Players away from keyboard are faded this much towards gray
+const AFK_FADE: f32 = 0.7;
+const AFK_GRAY: Vector3<f32> = Vector3::new(0.6, 0.6, 0.6);
+/// Part of the remaining distance the camera offset eases by in a logic update, so that the camera
+/// doesn't jump when the player changes direction
+const CAMERA_LOOK_AHEAD_SMOOTHING: f32 = 0.05;
+
+/// Background colors of the time of day
+const DAY_SKY_COLOR: Vector3<f32> = Vector3::new(1.0, 1.0, 1.0);
+const NIGHT_SKY_COLOR: Vector3<f32> = Vector3::new(0.15, 0.17, 0.3);
+const TWILIGHT_SKY_COLOR: Vector3<f32> = Vector3::new(1.0, 0.7, 0.5);
+/// Height of the sun below and above the horizon where twilight lasts, relative to its height at
+/// noon
+const TWILIGHT_SUN_HEIGHT: f32 = 0.2;
+
+/// Map editor snaps to the cells of the grid
+pub const GRID_COL_COUNT: usize = 40;
 const GRID_ROW_COUNT: usize = GRID_COL_COUNT;
 
-const GRID_VERTEX_SHADER_SRC: &str = r#"
-    #version 120
+/// Shaders are written in the common subset of desktop GLSL 1.20 and GLSL ES 1.00, only the
+/// header differs between the two.
+#[cfg(not(target_os = "android"))]
+const SHADER_HEADER: &str = "#version 120\n";
+#[cfg(target_os = "android")]
+const SHADER_HEADER: &str = "#version 100\nprecision mediump float;\n";
+
+#[cfg(not(target_os = "android"))]
+const CONTEXT_API: ContextApi = ContextApi::OpenGl(Some(Version { major: 2, minor: 1 }));
+#[cfg(target_os = "android")]
+const CONTEXT_API: ContextApi = ContextApi::Gles(Some(Version { major: 2, minor: 0 }));
 
+const GRID_VERTEX_SHADER_SRC: &str = r#"
     attribute vec2 aPos;
     uniform mat4 uMVP;
 
@@ -34,16 +82,12 @@ const GRID_VERTEX_SHADER_SRC: &str = r#"
 "#;
 
 const GRID_FRAGMENT_SHADER_SRC: &str = r#"
-    #version 120
-
     void main() {
         gl_FragColor = vec4(0.5, 0.5, 0.5, 1.0);
     }
 "#;
 
 const QUAD_VERTEX_SHADER_SRC: &str = r#"
-    #version 120
-
     attribute vec2 aPos;
     uniform mat4 uMVP;
 
@@ -53,8 +97,6 @@ const QUAD_VERTEX_SHADER_SRC: &str = r#"
 "#;
 
 const QUAD_FRAGMENT_SHADER_SRC: &str = r#"
-    #version 120
-
     uniform vec3 uColor;
 
     void main() {
@@ -63,7 +105,7 @@ const QUAD_FRAGMENT_SHADER_SRC: &str = r#"
 "#;
 
 /// Client-side graphics rendering layer for player sprite (quad) and playfield display. Uses
-/// OpenGL 2.1 for backwards compatibility.
+/// OpenGL 2.1 for backwards compatibility, or its OpenGL ES 2.0 counterpart on Android.
 ///
 /// Because "legacy" OpenGL 2.1 does not support Vertex Attribute Arrays, "shader plumbing" is done
 /// directly before draw calls.
@@ -79,24 +121,218 @@ pub struct Renderer {
     gl_surface: Surface<WindowSurface>,
     gl_context: PossiblyCurrentContext,
     gl: Arc<glow::Context>,
+    /// Part of the window the scene is drawn to
+    viewport: Viewport,
+    /// Seed of the server avatars of players are derived from
+    avatar_seed: u64,
+    /// Side length of the quads of players on the server
+    player_size: f32,
+}
+
+/// State of the world replicated by the server, drawn around the players
+pub struct Environment {
+    /// Minutes since midnight, tinting the sky
+    pub time_of_day: Option<u16>,
+    /// Safe zone of a running storm
+    pub safe_zone: Option<SafeZone>,
+    /// Player with the highest score, drawn with a crown
+    pub leader: Option<PlayerID>,
+    /// Players away from keyboard, drawn dimmed
+    pub afk_players: HashSet<PlayerID>,
+}
+
+/// Part of the world shown in the scene. Screen positions are in the coordinates the scene is
+/// laid out in, see `Renderer::window_to_scene()`, and the camera is in the middle of the scene.
+/// The camera never shows anything outside the world.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Camera {
+    /// World position in the middle of the scene
+    position: Vector2<f32>,
+    /// Current distance from the followed player, easing towards the look-ahead
+    look_ahead_offset: Vector2<f32>,
+}
+
+impl Camera {
+    /// Camera centered on the target, or as close to it as the edge of the world allows
+    pub fn centered_on(target: Vector2<f32>) -> Self {
+        Self {
+            position: clamp_to_world(target),
+            look_ahead_offset: Vector2::new(0.0, 0.0),
+        }
+    }
+
+    pub fn position(&self) -> Vector2<f32> {
+        self.position
+    }
+
+    /// Follow a player leading it in the direction of movement by up to `look_ahead` at full
+    /// `player_speed`
+    pub fn follow(&mut self, player: &Player, look_ahead: f32, player_speed: f32) {
+        let target_offset = player.velocity / player_speed * look_ahead;
+        self.look_ahead_offset +=
+            (target_offset - self.look_ahead_offset) * CAMERA_LOOK_AHEAD_SMOOTHING;
+        self.position = clamp_to_world(player.pos + self.look_ahead_offset);
+    }
+
+    /// Move the camera by the given distance, stopping at the edge of the world
+    pub fn pan(&mut self, distance: Vector2<f32>) {
+        self.position = clamp_to_world(self.position + distance);
+    }
+
+    pub fn world_to_screen(&self, world: Vector2<f32>) -> Vector2<f32> {
+        world - self.position + scene_center()
+    }
+
+    pub fn screen_to_world(&self, screen: Vector2<f32>) -> Vector2<f32> {
+        screen - scene_center() + self.position
+    }
+
+    /// Projection-view matrix of the world seen by the camera
+    // Camera moves the world itself around!
+    pub fn projection_view(&self) -> Matrix4<f32> {
+        let projection: Matrix4<f32> = cgmath::ortho(
+            0.0,
+            globals::WINDOW_SIZE.0 as f32,
+            globals::WINDOW_SIZE.1 as f32,
+            0.0,
+            -1.0,
+            1.0,
+        );
+        let offset = scene_center() - self.position;
+        let view = Matrix4::from_translation(Vector3::new(offset.x, offset.y, 0.0));
+        projection * view
+    }
+}
+
+fn scene_center() -> Vector2<f32> {
+    Vector2::new(
+        globals::WINDOW_SIZE.0 as f32 / 2.0,
+        globals::WINDOW_SIZE.1 as f32 / 2.0,
+    )
+}
+
+/// Closest position of the camera to the target that doesn't show anything outside the world
+fn clamp_to_world(target: Vector2<f32>) -> Vector2<f32> {
+    let half_size = scene_center();
+    let bounds = &globals::WORLD_BOUNDS;
+    Vector2::new(
+        target
+            .x
+            .clamp(bounds.min_x + half_size.x, bounds.max_x - half_size.x),
+        target
+            .y
+            .clamp(bounds.min_y + half_size.y, bounds.max_y - half_size.y),
+    )
+}
+
+/// Everything drawn by the passes of a frame
+pub struct Scene<'a> {
+    pub camera: Camera,
+    pub local_player: &'a Player,
+    pub remote_players: &'a HashMap<PlayerID, Player>,
+    pub environment: &'a Environment,
+    pub map: &'a Map,
+}
+
+/// Step of drawing a frame. App assembles the passes of every frame in the order they are drawn,
+/// so that features can add their own passes without changing the renderer.
+pub enum RenderPass<'a> {
+    /// Lines of the playfield
+    Grid,
+    /// Safe zone, obstacles and players
+    World,
+    /// Map being edited in place of the world
+    Editor,
+    /// GUI and HUD painted by egui
+    Gui(&'a mut Gui, &'a Window),
+    /// Pass of a feature drawing with the GL context directly, like a minimap or a screenshot
+    /// capture, with its name
+    Custom(&'static str, CustomPass<'a>),
+}
+
+/// Draws with the GL context and the projection-view matrix of the camera
+pub type CustomPass<'a> = Box<dyn FnOnce(&glow::Context, &Matrix4<f32>) + 'a>;
+
+impl RenderPass<'_> {
+    pub fn name(&self) -> &'static str {
+        match self {
+            RenderPass::Grid => "grid",
+            RenderPass::World => "world",
+            RenderPass::Editor => "editor",
+            RenderPass::Gui(..) => "gui",
+            RenderPass::Custom(name, _) => name,
+        }
+    }
+}
+
+/// Where the window opens. Anything left unset is decided by the system, except for the size
+/// which defaults to the size of the scene.
+#[derive(Default)]
+pub struct WindowPlacement {
+    pub position: Option<PhysicalPosition<i32>>,
+    pub size: Option<PhysicalSize<u32>>,
+    pub fullscreen: Option<Fullscreen>,
+}
+
+/// Area of the window in physical pixels, from the bottom-left corner like OpenGL expects
+#[derive(Clone, Copy)]
+struct Viewport {
+    x: i32,
+    y: i32,
+    width: i32,
+    height: i32,
+}
+
+impl Viewport {
+    /// Largest area in the middle of the window with the aspect ratio of the scene. The rest of
+    /// the window is left as bars of background color.
+    fn letterbox(window_size: PhysicalSize<u32>) -> Self {
+        let scene_aspect = globals::WINDOW_SIZE.0 as f32 / globals::WINDOW_SIZE.1 as f32;
+        let window_width = window_size.width.max(1) as f32;
+        let window_height = window_size.height.max(1) as f32;
+        let (width, height) = if window_width / window_height > scene_aspect {
+            (window_height * scene_aspect, window_height)
+        } else {
+            (window_width, window_width / scene_aspect)
+        };
+        Self {
+            x: ((window_width - width) / 2.0) as i32,
+            y: ((window_height - height) / 2.0) as i32,
+            width: width as i32,
+            height: height as i32,
+        }
+    }
 }
 
 impl Renderer {
-    /// Create native window and initialize OpenGL context.
-    pub fn create_graphics(event_loop: &ActiveEventLoop) -> (Window, Renderer, Gui) {
+    /// Create native window and initialize OpenGL context. GUI is restored from previously kept
+    /// state when graphics are re-created after suspension.
+    pub fn create_graphics(
+        event_loop: &ActiveEventLoop,
+        gui_state: GuiState,
+        placement: WindowPlacement,
+    ) -> Result<(Window, Renderer, Gui), Box<dyn Error>> {
         unsafe {
             // Create window
-            let window_attributes = WindowAttributes::default()
+            let mut window_attributes = WindowAttributes::default()
                 .with_title(globals::WINDOW_TITLE)
-                .with_inner_size(PhysicalSize::new(
-                    globals::WINDOW_SIZE.0,
-                    globals::WINDOW_SIZE.1,
+                .with_inner_size(placement.size.unwrap_or(PhysicalSize::new(
+                    u32::from(globals::WINDOW_SIZE.0),
+                    u32::from(globals::WINDOW_SIZE.1),
+                )))
+                .with_min_inner_size(PhysicalSize::new(
+                    globals::WINDOW_SIZE.0 / 2,
+                    globals::WINDOW_SIZE.1 / 2,
                 ))
-                .with_resizable(false);
+                .with_fullscreen(placement.fullscreen);
+            if let Some(position) = placement.position {
+                window_attributes = window_attributes.with_position(position);
+            }
             let display_builder =
                 DisplayBuilder::new().with_window_attributes(Some(window_attributes));
-            let (window, gl_config) = display_builder
-                .build(event_loop, ConfigTemplateBuilder::new(), |configs| {
+            let (window, gl_config) =
+                display_builder.build(event_loop, ConfigTemplateBuilder::new(), |configs| {
+                    // glutin guarantees at least one matching config
                     configs
                         .reduce(|accum, config| {
                             if config.num_samples() > accum.num_samples() {
@@ -106,8 +342,7 @@ impl Renderer {
                             }
                         })
                         .unwrap()
-                })
-                .unwrap();
+                })?;
 
             let raw_window_handle = window
                 .as_ref()
@@ -116,19 +351,16 @@ impl Renderer {
 
             let gl_display = gl_config.display();
             let context_attributes = ContextAttributesBuilder::new()
-                .with_context_api(ContextApi::OpenGl(Some(Version { major: 2, minor: 1 })))
+                .with_context_api(CONTEXT_API)
                 .build(raw_window_handle);
-            let not_current_gl_context = gl_display
-                .create_context(&gl_config, &context_attributes)
-                .unwrap();
+            let not_current_gl_context =
+                gl_display.create_context(&gl_config, &context_attributes)?;
 
-            let window = window.unwrap();
+            let window = window.ok_or("Failed to create window")?;
 
-            let surface_attributes = window.build_surface_attributes(Default::default()).unwrap();
-            let gl_surface = gl_display
-                .create_window_surface(&gl_config, &surface_attributes)
-                .unwrap();
-            let gl_context = not_current_gl_context.make_current(&gl_surface).unwrap();
+            let surface_attributes = window.build_surface_attributes(Default::default())?;
+            let gl_surface = gl_display.create_window_surface(&gl_config, &surface_attributes)?;
+            let gl_context = not_current_gl_context.make_current(&gl_surface)?;
 
             // Create context
             let gl = glow::Context::from_loader_function_cstr(|s| gl_display.get_proc_address(s));
@@ -137,15 +369,21 @@ impl Renderer {
             gl.clear_color(1.0, 1.0, 1.0, 1.0);
 
             // Load quad shaders
-            let quad_vertex_shader = gl.create_shader(glow::VERTEX_SHADER).unwrap();
-            gl.shader_source(quad_vertex_shader, QUAD_VERTEX_SHADER_SRC);
+            let quad_vertex_shader = gl.create_shader(glow::VERTEX_SHADER)?;
+            gl.shader_source(
+                quad_vertex_shader,
+                &format!("{SHADER_HEADER}{QUAD_VERTEX_SHADER_SRC}"),
+            );
             gl.compile_shader(quad_vertex_shader);
 
-            let quad_fragment_shader = gl.create_shader(glow::FRAGMENT_SHADER).unwrap();
-            gl.shader_source(quad_fragment_shader, QUAD_FRAGMENT_SHADER_SRC);
+            let quad_fragment_shader = gl.create_shader(glow::FRAGMENT_SHADER)?;
+            gl.shader_source(
+                quad_fragment_shader,
+                &format!("{SHADER_HEADER}{QUAD_FRAGMENT_SHADER_SRC}"),
+            );
             gl.compile_shader(quad_fragment_shader);
 
-            let quad_shader_program = gl.create_program().unwrap();
+            let quad_shader_program = gl.create_program()?;
             gl.attach_shader(quad_shader_program, quad_vertex_shader);
             gl.attach_shader(quad_shader_program, quad_fragment_shader);
             gl.link_program(quad_shader_program);
@@ -155,7 +393,7 @@ impl Renderer {
             gl.delete_shader(quad_vertex_shader);
             gl.delete_shader(quad_fragment_shader);
 
-            let quad_vbo = gl.create_buffer().unwrap();
+            let quad_vbo = gl.create_buffer()?;
             gl.bind_buffer(glow::ARRAY_BUFFER, Some(quad_vbo));
 
             // Create quad buffers
@@ -170,23 +408,29 @@ impl Renderer {
 
             let quad_mvp_location = gl
                 .get_uniform_location(quad_shader_program, "uMVP")
-                .unwrap();
+                .ok_or("Missing uMVP uniform in quad shader")?;
             let quad_color_location = gl
                 .get_uniform_location(quad_shader_program, "uColor")
-                .unwrap();
+                .ok_or("Missing uColor uniform in quad shader")?;
 
             gl.use_program(None); // Unbind shader needed to associate uniforms with
 
             // Load grid shaders
-            let grid_vertex_shader = gl.create_shader(glow::VERTEX_SHADER).unwrap();
-            gl.shader_source(grid_vertex_shader, GRID_VERTEX_SHADER_SRC);
+            let grid_vertex_shader = gl.create_shader(glow::VERTEX_SHADER)?;
+            gl.shader_source(
+                grid_vertex_shader,
+                &format!("{SHADER_HEADER}{GRID_VERTEX_SHADER_SRC}"),
+            );
             gl.compile_shader(grid_vertex_shader);
 
-            let grid_fragment_shader = gl.create_shader(glow::FRAGMENT_SHADER).unwrap();
-            gl.shader_source(grid_fragment_shader, GRID_FRAGMENT_SHADER_SRC);
+            let grid_fragment_shader = gl.create_shader(glow::FRAGMENT_SHADER)?;
+            gl.shader_source(
+                grid_fragment_shader,
+                &format!("{SHADER_HEADER}{GRID_FRAGMENT_SHADER_SRC}"),
+            );
             gl.compile_shader(grid_fragment_shader);
 
-            let grid_shader_program = gl.create_program().unwrap();
+            let grid_shader_program = gl.create_program()?;
             gl.attach_shader(grid_shader_program, grid_vertex_shader);
             gl.attach_shader(grid_shader_program, grid_fragment_shader);
             gl.link_program(grid_shader_program);
@@ -202,7 +446,7 @@ impl Renderer {
                 globals::WORLD_BOUNDS.max_x * 2.0,
                 globals::WORLD_BOUNDS.max_y * 2.0,
             );
-            let grid_vbo = gl.create_buffer().unwrap();
+            let grid_vbo = gl.create_buffer()?;
             gl.bind_buffer(glow::ARRAY_BUFFER, Some(grid_vbo));
             gl.buffer_data_u8_slice(
                 glow::ARRAY_BUFFER,
@@ -212,7 +456,7 @@ impl Renderer {
 
             let grid_mvp_location = gl
                 .get_uniform_location(grid_shader_program, "uMVP")
-                .unwrap();
+                .ok_or("Missing uMVP uniform in grid shader")?;
 
             gl.use_program(None);
 
@@ -229,63 +473,81 @@ impl Renderer {
                 quad_vbo,
                 quad_mvp_location,
                 quad_color_location,
+                viewport: Viewport::letterbox(window.inner_size()),
+                avatar_seed: 0,
+                player_size: globals::DEFAULT_PLAYER_SIZE,
             };
 
             // Create GUI
-            let gui = Gui::new(&event_loop, gl.clone());
+            let gui = Gui::new(event_loop, gl.clone(), gui_state);
 
-            (window, renderer, gui)
+            Ok((window, renderer, gui))
         }
     }
 
-    // TODO: Ideally rendering should not know about game logic
+    /// Clear the window to the color of the sky and run the passes of the frame in order. Passes
+    /// drawing the scene get the part of the window the scene fits into, even after the GUI
+    /// painted over the whole window.
     // TODO: Occlusion culling based on camera area
     // TODO: Batch draw calls
-    pub fn draw(
-        &self,
-        camera: &Vector2<f32>,
-        local_player: &Player,
-        remote_players: &HashMap<PlayerID, Player>,
-        state: Option<&fsm::State>,
-    ) {
+    pub fn draw(&self, scene: &Scene, passes: Vec<RenderPass>) {
+        let sky_color = sky_color(scene.environment.time_of_day);
         unsafe {
+            self.gl
+                .clear_color(sky_color.x, sky_color.y, sky_color.z, 1.0);
             self.gl.clear(glow::COLOR_BUFFER_BIT);
+        }
 
-            // Camera calculations
-            // Camera moves the world itself around!
-            let projection: Matrix4<f32> = cgmath::ortho(
-                0.0,
-                globals::WINDOW_SIZE.0 as f32,
-                globals::WINDOW_SIZE.1 as f32,
-                0.0,
-                -1.0,
-                1.0,
-            );
-            let camera_offset = Vector2::new(
-                globals::WINDOW_SIZE.0 as f32 / 2.0,
-                globals::WINDOW_SIZE.1 as f32 / 2.0,
-            );
-            let view = Matrix4::from_translation(Vector3::new(
-                -camera.x + camera_offset.x,
-                -camera.y + camera_offset.y,
-                0.0,
-            ));
-            let pv = projection * view;
-
-            self.draw_grid(&pv);
+        let pv = scene.camera.projection_view();
 
-            // Keep drawing players even when Quit dialog is active
-            if matches!(
-                state,
-                Some(fsm::State::Playing) | Some(fsm::State::QuitDialog)
-            ) {
-                self.draw_quads(local_player, remote_players, &pv);
+        for pass in passes {
+            if !matches!(pass, RenderPass::Gui(..)) {
+                self.use_viewport();
+            }
+            match pass {
+                RenderPass::Grid => self.draw_grid(&pv),
+                RenderPass::World => self.draw_world(scene, &pv),
+                RenderPass::Editor => self.draw_editor(scene.map, &pv),
+                RenderPass::Gui(gui, window) => gui.draw(window),
+                RenderPass::Custom(_, draw) => draw(&self.gl, &pv),
             }
         }
     }
 
-    pub fn swap_buffers(&self) {
-        self.gl_surface.swap_buffers(&self.gl_context).unwrap();
+    pub fn set_avatar_seed(&mut self, seed: u64) {
+        self.avatar_seed = seed;
+    }
+
+    pub fn set_player_size(&mut self, player_size: f32) {
+        self.player_size = player_size;
+    }
+
+    /// Follow the size of the window. The scene keeps its size and aspect ratio, only scaled.
+    pub fn resize(&mut self, size: PhysicalSize<u32>) {
+        // Minimized windows report zero size, which surfaces don't accept
+        let (Some(width), Some(height)) =
+            (NonZeroU32::new(size.width), NonZeroU32::new(size.height))
+        else {
+            return;
+        };
+        self.gl_surface.resize(&self.gl_context, width, height);
+        self.viewport = Viewport::letterbox(size);
+    }
+
+    /// Convert a position in the window to the coordinates the scene is laid out in, where the
+    /// top-left corner is zero and the bottom-right corner is the scene size
+    pub fn window_to_scene(&self, position: PhysicalPosition<f64>) -> Vector2<f32> {
+        let scale = globals::WINDOW_SIZE.0 as f32 / self.viewport.width.max(1) as f32;
+        // Window positions start at the top while the viewport starts at the bottom, but the bars
+        // above and below the scene are equally tall
+        Vector2::new(
+            (position.x as f32 - self.viewport.x as f32) * scale,
+            (position.y as f32 - self.viewport.y as f32) * scale,
+        )
+    }
+
+    pub fn swap_buffers(&self) -> glutin::error::Result<()> {
+        self.gl_surface.swap_buffers(&self.gl_context)
     }
 
     fn draw_grid(&self, pv: &Matrix4<f32>) {
@@ -332,8 +594,131 @@ impl Renderer {
         &self,
         local_player: &Player,
         remote_players: &HashMap<PlayerID, Player>,
+        afk_players: &HashSet<PlayerID>,
+        map: &Map,
         pv: &Matrix4<f32>,
     ) {
+        self.use_quad_program();
+        for obstacle in &map.obstacles {
+            self.draw_rect(&obstacle.min, &obstacle.size, &OBSTACLE_COLOR, pv);
+        }
+        for p in std::iter::once(local_player).chain(remote_players.values()) {
+            let p = if afk_players.contains(&p.id) {
+                &Player {
+                    color: p.color.lerp(AFK_GRAY, AFK_FADE),
+                    ..*p
+                }
+            } else {
+                p
+            };
+            self.draw_quad(&p.pos, &p.color, pv);
+            self.draw_avatar(p, &Avatar::new(self.avatar_seed, p.id), pv);
+        }
+    }
+
+    /// Avatar pattern inside the quad of the player in a darker shade of its color
+    fn draw_avatar(&self, player: &Player, avatar: &Avatar, pv: &Matrix4<f32>) {
+        let pattern_size = AVATAR_PATTERN_SCALE * self.player_size;
+        let cell_size = pattern_size / AVATAR_CELLS as f32;
+        let min = player.pos - Vector2::new(pattern_size, pattern_size) * 0.5;
+        let color = player.color * AVATAR_SHADE;
+        for row in 0..AVATAR_CELLS {
+            for column in 0..AVATAR_CELLS {
+                if avatar.is_filled(row, column) {
+                    self.draw_rect(
+                        &(min + Vector2::new(column as f32, row as f32) * cell_size),
+                        &Vector2::new(cell_size, cell_size),
+                        &color,
+                        pv,
+                    );
+                }
+            }
+        }
+    }
+
+    /// Crown above the quad of the player at `pos`: a band with three points on top
+    fn draw_crown(&self, pos: &Vector2<f32>, pv: &Matrix4<f32>) {
+        let bottom = pos.y - 0.5 * self.player_size - CROWN_GAP;
+        let left = pos.x - 0.5 * CROWN_SIZE.x;
+        let band_height = 0.5 * CROWN_SIZE.y;
+        let point_size = Vector2::new(CROWN_SIZE.x / 5.0, band_height);
+        self.draw_rect(
+            &Vector2::new(left, bottom - band_height),
+            &Vector2::new(CROWN_SIZE.x, band_height),
+            &CROWN_COLOR,
+            pv,
+        );
+        for point in 0..3 {
+            self.draw_rect(
+                &Vector2::new(
+                    left + point as f32 * 2.0 * point_size.x,
+                    bottom - CROWN_SIZE.y,
+                ),
+                &point_size,
+                &CROWN_COLOR,
+                pv,
+            );
+        }
+    }
+
+    /// Edge of the safe zone of a storm, players outside it are losing health
+    fn draw_safe_zone(&self, safe_zone: &SafeZone, pv: &Matrix4<f32>) {
+        self.use_quad_program();
+        let dot_count = (std::f32::consts::TAU * safe_zone.radius / STORM_EDGE_SPACING).ceil();
+        let dot_size = Vector2::new(STORM_EDGE_DOT_SIZE, STORM_EDGE_DOT_SIZE);
+        for index in 0..dot_count as u32 {
+            let angle = index as f32 / dot_count * std::f32::consts::TAU;
+            let pos = safe_zone.center + Vector2::new(angle.cos(), angle.sin()) * safe_zone.radius;
+            self.draw_rect(&(pos - dot_size * 0.5), &dot_size, &STORM_EDGE_COLOR, pv);
+        }
+    }
+
+    /// Safe zone, players and the crown of the leader
+    fn draw_world(&self, scene: &Scene, pv: &Matrix4<f32>) {
+        let environment = scene.environment;
+        if let Some(safe_zone) = &environment.safe_zone {
+            self.draw_safe_zone(safe_zone, pv);
+        }
+        self.draw_quads(
+            scene.local_player,
+            scene.remote_players,
+            &environment.afk_players,
+            scene.map,
+            pv,
+        );
+        if let Some(leader) = std::iter::once(scene.local_player)
+            .chain(scene.remote_players.values())
+            .find(|player| Some(player.id) == environment.leader)
+        {
+            self.draw_crown(&leader.pos, pv);
+        }
+    }
+
+    /// Map being edited, with spawn points drawn as player-sized quads
+    fn draw_editor(&self, map: &Map, pv: &Matrix4<f32>) {
+        self.use_quad_program();
+        for obstacle in &map.obstacles {
+            self.draw_rect(&obstacle.min, &obstacle.size, &OBSTACLE_COLOR, pv);
+        }
+        for spawn_point in &map.spawn_points {
+            self.draw_quad(spawn_point, &SPAWN_POINT_COLOR, pv);
+        }
+    }
+
+    /// GUI painting takes over the whole window, so the viewport has to be restored after it
+    fn use_viewport(&self) {
+        let Viewport {
+            x,
+            y,
+            width,
+            height,
+        } = self.viewport;
+        unsafe {
+            self.gl.viewport(x, y, width, height);
+        }
+    }
+
+    fn use_quad_program(&self) {
         unsafe {
             self.gl.use_program(Some(self.quad_shader_program));
             self.gl.bind_buffer(glow::ARRAY_BUFFER, Some(self.quad_vbo));
@@ -352,26 +737,31 @@ impl Renderer {
                 8,
                 0,
             );
-
-            self.draw_quad(&local_player.pos, &local_player.color, &pv);
-            for (_, p) in remote_players.iter() {
-                self.draw_quad(&p.pos, &p.color, &pv);
-            }
         }
     }
 
     fn draw_quad(&self, pos: &Vector2<f32>, color: &Vector3<f32>, pv: &Matrix4<f32>) {
-        // Move to position
-        let mut model = Matrix4::from_translation(cgmath::vec3(pos.x, pos.y, 0.0));
         // Move local coordinate space origin from bottom-right corner of quad to center
-        model = model
-            * Matrix4::from_translation(cgmath::vec3(
-                -0.5 * globals::PLAYER_QUAD_SIZE,
-                -0.5 * globals::PLAYER_QUAD_SIZE,
-                0.0,
-            ));
+        let half_size = 0.5 * self.player_size;
+        self.draw_rect(
+            &Vector2::new(pos.x - half_size, pos.y - half_size),
+            &Vector2::new(self.player_size, self.player_size),
+            color,
+            pv,
+        );
+    }
+
+    fn draw_rect(
+        &self,
+        min: &Vector2<f32>,
+        size: &Vector2<f32>,
+        color: &Vector3<f32>,
+        pv: &Matrix4<f32>,
+    ) {
+        // Move to position
+        let mut model = Matrix4::from_translation(cgmath::vec3(min.x, min.y, 0.0));
         // Scale
-        model = model * Matrix4::from_scale(globals::PLAYER_QUAD_SIZE);
+        model = model * Matrix4::from_nonuniform_scale(size.x, size.y, 1.0);
         let mvp = pv * model;
 
         unsafe {
@@ -402,6 +792,23 @@ impl Drop for Renderer {
     }
 }
 
+/// Background color at the given minute of the day, which fades from daylight into night with a warm
+/// tint around sunrise and sunset. Always daylight when time is not replicated by a server.
+fn sky_color(time_of_day: Option<u16>) -> Vector3<f32> {
+    let Some(minutes) = time_of_day else {
+        return DAY_SKY_COLOR;
+    };
+    // Sun rises at 6:00, is highest at noon and sets at 18:00
+    let day_fraction = minutes as f32 / globals::MINUTES_PER_DAY as f32;
+    let sun_height = -(day_fraction * std::f32::consts::TAU).cos();
+    let daylight =
+        ((sun_height + TWILIGHT_SUN_HEIGHT) / (2.0 * TWILIGHT_SUN_HEIGHT)).clamp(0.0, 1.0);
+    let twilight = (1.0 - sun_height.abs() / TWILIGHT_SUN_HEIGHT).max(0.0);
+    NIGHT_SKY_COLOR
+        .lerp(DAY_SKY_COLOR, daylight)
+        .lerp(TWILIGHT_SKY_COLOR, twilight * 0.5)
+}
+
 fn create_grid_vertices(
     col_count: usize,
     row_count: usize,
@@ -0,0 +1,406 @@
+use std::{collections::HashMap, fmt, path::Path, str::FromStr};
+
+use cgmath::{Vector2, Vector3};
+
+use crate::{map::Map, Player, PlayerID};
+
+/// Something that happened on the server during a match
+#[derive(Clone, Debug, PartialEq)]
+pub enum ReplayEvent {
+    Joined {
+        player_id: PlayerID,
+        name: String,
+        color: Vector3<f32>,
+    },
+    Left {
+        player_id: PlayerID,
+    },
+    /// Positions of the players who moved since their previous snapshot
+    Snapshot(Vec<(PlayerID, Vector2<f32>)>),
+    /// Match ended, either by its clock running out or by everyone leaving
+    End,
+}
+
+/// Event on a simulation tick of the server
+#[derive(Clone, Debug, PartialEq)]
+pub struct ReplayEntry {
+    pub tick: u64,
+    pub event: ReplayEvent,
+}
+
+/// Authoritative record of a match as the server simulated it, together with the map and seed it
+/// was played with, so that it can be watched without the server.
+///
+/// Stored as text with one entry per line, like input recordings. Map lines are the same as in
+/// map files, positions are rounded to a tenth of a world unit and only players who moved are
+/// listed in a snapshot. Lines starting with `#` are comments:
+///
+/// ```text
+/// seed 42
+/// map-name arena
+/// obstacle -100 -20 200 40
+/// spawn 0 -200
+/// join 120 1 0.5 0.25 1 Alice
+/// tick 122 1 0.0 -195.0
+/// leave 300 1
+/// end 300
+/// ```
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Replay {
+    pub seed: u64,
+    pub map_name: String,
+    pub map: Map,
+    pub entries: Vec<ReplayEntry>,
+}
+
+impl Replay {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read {}: {e}", path.display()))?;
+        contents
+            .parse()
+            .map_err(|e| format!("Invalid replay {}: {e}", path.display()))
+    }
+
+    pub fn save(&self, path: &Path) -> std::io::Result<()> {
+        std::fs::write(path, self.to_string())
+    }
+}
+
+impl fmt::Display for Replay {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "seed {}", self.seed)?;
+        writeln!(f, "map-name {}", self.map_name)?;
+        write!(f, "{}", self.map.serialize())?;
+        for entry in &self.entries {
+            match &entry.event {
+                ReplayEvent::Joined {
+                    player_id,
+                    name,
+                    color,
+                } => writeln!(
+                    f,
+                    "join {} {player_id} {} {} {} {name}",
+                    entry.tick, color.x, color.y, color.z
+                )?,
+                ReplayEvent::Left { player_id } => writeln!(f, "leave {} {player_id}", entry.tick)?,
+                ReplayEvent::Snapshot(positions) => {
+                    write!(f, "tick {}", entry.tick)?;
+                    for (player_id, pos) in positions {
+                        write!(f, " {player_id} {:.1} {:.1}", pos.x, pos.y)?;
+                    }
+                    writeln!(f)?;
+                }
+                ReplayEvent::End => writeln!(f, "end {}", entry.tick)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Replay {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut replay = Replay::default();
+        let mut map_text = String::new();
+        for (index, line) in s.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let invalid = |reason: &str| format!("line {}: {reason}", index + 1);
+            let (keyword, rest) = line.split_once(' ').unwrap_or((line, ""));
+            let fields: Vec<&str> = rest.split_whitespace().collect();
+            let (tick, event) = match keyword {
+                "seed" => {
+                    replay.seed = rest.parse().map_err(|_| invalid("invalid seed"))?;
+                    continue;
+                }
+                "map-name" => {
+                    replay.map_name = rest.trim().to_string();
+                    continue;
+                }
+                "obstacle" | "spawn" => {
+                    map_text.push_str(line);
+                    map_text.push('\n');
+                    continue;
+                }
+                "join" => {
+                    if fields.len() < 6 {
+                        return Err(invalid("expected tick, player ID, color and name"));
+                    }
+                    let number = |field: &str| -> Result<f32, String> {
+                        field.parse().map_err(|_| invalid("invalid color"))
+                    };
+                    // Names can have spaces, so the name is the rest of the line
+                    let mut name = rest;
+                    for _ in 0..5 {
+                        name = name
+                            .trim_start()
+                            .split_once(char::is_whitespace)
+                            .map_or("", |(_, rest)| rest);
+                    }
+                    let name = name.trim_start().to_string();
+                    let event = ReplayEvent::Joined {
+                        player_id: fields[1]
+                            .parse()
+                            .map_err(|_| invalid("invalid player ID"))?,
+                        name,
+                        color: Vector3::new(
+                            number(fields[2])?,
+                            number(fields[3])?,
+                            number(fields[4])?,
+                        ),
+                    };
+                    (fields[0], event)
+                }
+                "leave" => {
+                    let [tick, player_id] = fields[..] else {
+                        return Err(invalid("expected tick and player ID"));
+                    };
+                    let player_id = player_id
+                        .parse()
+                        .map_err(|_| invalid("invalid player ID"))?;
+                    (tick, ReplayEvent::Left { player_id })
+                }
+                "tick" => {
+                    let Some((tick, positions)) = fields.split_first() else {
+                        return Err(invalid("expected tick"));
+                    };
+                    if positions.len() % 3 != 0 {
+                        return Err(invalid("expected player ID and position of each player"));
+                    }
+                    let positions = positions
+                        .chunks(3)
+                        .map(|position| {
+                            match (
+                                position[0].parse(),
+                                position[1].parse(),
+                                position[2].parse(),
+                            ) {
+                                (Ok(player_id), Ok(x), Ok(y)) => {
+                                    Ok((player_id, Vector2::new(x, y)))
+                                }
+                                _ => Err(invalid("invalid position")),
+                            }
+                        })
+                        .collect::<Result<_, _>>()?;
+                    (*tick, ReplayEvent::Snapshot(positions))
+                }
+                "end" => {
+                    let [tick] = fields[..] else {
+                        return Err(invalid("expected tick"));
+                    };
+                    (tick, ReplayEvent::End)
+                }
+                _ => return Err(invalid("unknown entry")),
+            };
+            let tick = tick.parse().map_err(|_| invalid("invalid tick"))?;
+            if replay.entries.last().is_some_and(|last| tick < last.tick) {
+                return Err(invalid("ticks are out of order"));
+            }
+            replay.entries.push(ReplayEntry { tick, event });
+        }
+        replay.map = Map::parse(&map_text).map_err(|e| format!("map: {e}"))?;
+
+        Ok(replay)
+    }
+}
+
+/// Collects what happens during a match on the server into a replay
+pub struct ReplayRecorder {
+    replay: Replay,
+    /// Last recorded position of each player, so that only movement is recorded
+    positions: HashMap<PlayerID, Vector2<f32>>,
+}
+
+impl ReplayRecorder {
+    pub fn new(seed: u64, map_name: String, map: Map) -> Self {
+        Self {
+            replay: Replay {
+                seed,
+                map_name,
+                map,
+                entries: Vec::new(),
+            },
+            positions: HashMap::new(),
+        }
+    }
+
+    pub fn joined(&mut self, tick: u64, player: &Player, name: &str) {
+        self.push(
+            tick,
+            ReplayEvent::Joined {
+                player_id: player.id,
+                name: name.to_string(),
+                color: player.color,
+            },
+        );
+        let pos = stored_position(player.pos);
+        self.push(tick, ReplayEvent::Snapshot(vec![(player.id, pos)]));
+        self.positions.insert(player.id, pos);
+    }
+
+    pub fn left(&mut self, tick: u64, player_id: PlayerID) {
+        self.positions.remove(&player_id);
+        self.push(tick, ReplayEvent::Left { player_id });
+    }
+
+    /// Record the players who moved since their last recorded position. Movement smaller than
+    /// the precision of the file is left out.
+    pub fn snapshot<'a>(&mut self, tick: u64, players: impl IntoIterator<Item = &'a Player>) {
+        let mut moved = Vec::new();
+        for player in players {
+            let pos = stored_position(player.pos);
+            if self.positions.insert(player.id, pos) != Some(pos) {
+                moved.push((player.id, pos));
+            }
+        }
+        if !moved.is_empty() {
+            moved.sort_by_key(|(player_id, _)| *player_id);
+            self.push(tick, ReplayEvent::Snapshot(moved));
+        }
+    }
+
+    pub fn finish(mut self, tick: u64) -> Replay {
+        self.push(tick, ReplayEvent::End);
+        self.replay
+    }
+
+    fn push(&mut self, tick: u64, event: ReplayEvent) {
+        self.replay.entries.push(ReplayEntry { tick, event });
+    }
+}
+
+/// Position as it is written into the file, so that recorded replays are the same as the ones
+/// loaded back
+fn stored_position(pos: Vector2<f32>) -> Vector2<f32> {
+    pos.map(|coordinate| (coordinate * 10.0).round() / 10.0)
+}
+
+/// Plays a replay back tick by tick, with the camera following one of the players
+pub struct ReplayPlayback {
+    replay: Replay,
+    next: usize,
+    /// Tick of the server being shown
+    tick: u64,
+    players: HashMap<PlayerID, Player>,
+    names: HashMap<PlayerID, String>,
+    followed: Option<PlayerID>,
+}
+
+impl ReplayPlayback {
+    pub fn new(replay: Replay) -> Self {
+        let tick = replay.entries.first().map_or(0, |entry| entry.tick);
+        let mut playback = Self {
+            replay,
+            next: 0,
+            tick,
+            players: HashMap::new(),
+            names: HashMap::new(),
+            followed: None,
+        };
+        playback.apply_due_entries();
+        playback
+    }
+
+    /// Move to the next simulation tick of the server
+    pub fn update(&mut self) {
+        if !self.is_finished() {
+            self.tick += 1;
+            self.apply_due_entries();
+        }
+    }
+
+    fn apply_due_entries(&mut self) {
+        while let Some(entry) = self
+            .replay
+            .entries
+            .get(self.next)
+            .filter(|entry| entry.tick <= self.tick)
+        {
+            match &entry.event {
+                ReplayEvent::Joined {
+                    player_id,
+                    name,
+                    color,
+                } => {
+                    self.players
+                        .insert(*player_id, Player::new(*player_id, *color));
+                    self.names.insert(*player_id, name.clone());
+                    self.followed.get_or_insert(*player_id);
+                }
+                ReplayEvent::Left { player_id } => {
+                    self.players.remove(player_id);
+                    self.names.remove(player_id);
+                    if self.followed == Some(*player_id) {
+                        self.followed = self.players.keys().min().copied();
+                    }
+                }
+                ReplayEvent::Snapshot(positions) => {
+                    for (player_id, pos) in positions {
+                        if let Some(player) = self.players.get_mut(player_id) {
+                            player.pos = *pos;
+                        }
+                    }
+                }
+                ReplayEvent::End => (),
+            }
+            self.next += 1;
+        }
+    }
+
+    pub fn map(&self) -> &Map {
+        &self.replay.map
+    }
+
+    pub fn map_name(&self) -> &str {
+        &self.replay.map_name
+    }
+
+    /// Seed of the server, which avatars are derived from
+    pub fn seed(&self) -> u64 {
+        self.replay.seed
+    }
+
+    pub fn players(&self) -> &HashMap<PlayerID, Player> {
+        &self.players
+    }
+
+    pub fn player_name(&self, player_id: PlayerID) -> Option<&str> {
+        self.names.get(&player_id).map(String::as_str)
+    }
+
+    /// Ticks played since the start of the replay
+    pub fn elapsed_ticks(&self) -> u64 {
+        self.tick
+            - self
+                .replay
+                .entries
+                .first()
+                .map_or(self.tick, |entry| entry.tick)
+    }
+
+    /// Player followed by the camera
+    pub fn followed_player(&self) -> Option<&Player> {
+        self.followed
+            .and_then(|player_id| self.players.get(&player_id))
+    }
+
+    /// Follow the player with the next ID, wrapping around to the first one
+    pub fn follow_next(&mut self) {
+        let mut player_ids: Vec<PlayerID> = self.players.keys().copied().collect();
+        player_ids.sort_unstable();
+        self.followed = player_ids
+            .iter()
+            .find(|player_id| Some(**player_id) > self.followed)
+            .or(player_ids.first())
+            .copied();
+    }
+
+    /// Every recorded entry has been played
+    pub fn is_finished(&self) -> bool {
+        self.next >= self.replay.entries.len()
+    }
+}
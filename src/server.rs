@@ -1,43 +1,300 @@
-use cgmath::{Vector2, Vector3};
+use cgmath::{InnerSpace, Vector2, Vector3};
 use rand::Rng;
+use serde::Serialize;
 use std::{
-    collections::HashMap,
+    collections::{BTreeMap, HashMap, HashSet},
     error::Error,
-    net::SocketAddr,
+    future::Future,
+    net::{IpAddr, SocketAddr},
+    path::{Path, PathBuf},
     sync::{
-        atomic::{AtomicU64, Ordering},
-        Arc,
+        atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering},
+        Arc, Weak,
     },
 };
 use tokio::{
+    io::{AsyncBufReadExt, BufReader},
     net::UdpSocket,
-    sync::{mpsc, Mutex},
+    sync::{broadcast, mpsc, Mutex, MutexGuard},
+    task::JoinHandle,
 };
+use tokio_util::{sync::CancellationToken, task::TaskTracker};
 
 use crate::{
-    globals,
-    message::{self, Message},
-    Player, PlayerID,
+    admin::{self, AdminCommand, AdminTarget},
+    afk::{self, PlayerActivity},
+    backoff::AddressBackoff,
+    banlist::{self, Banlist},
+    filter, globals,
+    history::{PositionHistory, PositionSample},
+    lan, logger,
+    map::{self, ServedMap},
+    message::{self, DatagramHeader, GameEvent, MatchResult, Message, ServerUsage, FLAG_ACK},
+    net::{self, Channels, Delivery, RecvBatch},
+    observer::{self, ObservedPlayer, ObserverEvent},
+    queue::{Admission, JoinQueue},
+    replay::ReplayRecorder,
+    settings::ServerSettings,
+    snapshot::{EntityState, SnapshotEncoder, WorldState},
+    spatial::SpatialGrid,
+    stats::{
+        self, ConnectionQuality, MessageTraffic, TickStats, TickSummary, TrafficStats, UsageStats,
+    },
+    storm::{self, SafeZone, Storm},
+    task,
+    whitelist::Whitelist,
+    Player, PlayerID, SessionToken, Tick, WorldParams,
 };
 
-pub type ServerSessionResult = Result<(), Box<dyn Error + Send + Sync>>;
+#[cfg(feature = "chaos")]
+use crate::chaos::{Chaos, ChaosConfig};
+
+pub type ServerSessionResult = Result<ServerHandle, Box<dyn Error + Send + Sync>>;
+
+/// Time given to players to answer the shutdown notification with LEAVE
+const SHUTDOWN_GRACE_PERIOD: std::time::Duration = std::time::Duration::from_secs(2);
+
+/// Parameters of a server session
+pub struct ServerConfig {
+    pub port: u16,
+    /// Advertised in query responses and handshake ACK
+    pub name: String,
+    /// Only players listed in this file are allowed to join if set
+    pub whitelist_path: Option<PathBuf>,
+    /// JSON file where bans and mutes are persisted, kept only in memory if not set
+    pub banlist_path: Option<PathBuf>,
+    /// Read admin commands from standard input. Only makes sense for headless servers.
+    pub admin_console: bool,
+    /// Replicate world state less often while the simulation can't keep up with the tick rate
+    pub shed_load: bool,
+    /// Periodically log current and peak players, joins and processed messages. Only makes sense
+    /// for headless servers, where the log is all the operator sees.
+    pub report_usage: bool,
+    /// Announce the server to clients on the LAN with periodic multicast beacons
+    pub advertise_lan: bool,
+    /// TOML file of settings which can be reloaded while the server is running
+    pub settings_path: Option<PathBuf>,
+    /// Seed for cosmetic randomness of clients, picked randomly if not set
+    pub seed: Option<u64>,
+    /// Player size and speed, sent to players when they join
+    pub world_params: WorldParams,
+    /// Map file played instead of the first one in the settings, like a map being playtested
+    pub map: Option<PathBuf>,
+    /// Serve status and metrics over HTTP on this port
+    #[cfg(feature = "status-http")]
+    pub status_port: Option<u16>,
+    /// File holding the secret of the admin dashboard served next to the status, which is only
+    /// served if set
+    #[cfg(feature = "admin-dashboard")]
+    pub admin_token_file: Option<PathBuf>,
+    /// Stream game events to local tools on this TCP port
+    pub observer_port: Option<u16>,
+    /// Record every match into a replay file in this directory
+    pub replay_dir: Option<PathBuf>,
+    /// Inject faults for testing how clients cope with a misbehaving server
+    #[cfg(feature = "chaos")]
+    pub chaos: Option<ChaosConfig>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            port: globals::DEFAULT_PORT,
+            name: globals::DEFAULT_SERVER_NAME.to_string(),
+            whitelist_path: None,
+            banlist_path: None,
+            admin_console: false,
+            shed_load: false,
+            report_usage: false,
+            advertise_lan: false,
+            settings_path: None,
+            seed: None,
+            world_params: WorldParams::default(),
+            map: None,
+            #[cfg(feature = "status-http")]
+            status_port: None,
+            #[cfg(feature = "admin-dashboard")]
+            admin_token_file: None,
+            observer_port: None,
+            replay_dir: None,
+            #[cfg(feature = "chaos")]
+            chaos: None,
+        }
+    }
+}
 
-pub async fn start_server(port: u16) -> ServerSessionResult {
+pub async fn start_server(config: ServerConfig) -> ServerSessionResult {
     match tokio::time::timeout(globals::CONNECTION_TIMEOUT_SEC, async {
-        let addr = format!("0.0.0.0:{port}"); // Make sure to listen on all interfaces
+        message::validate_world_params(&config.world_params)?;
+        let whitelist =
+            match &config.whitelist_path {
+                Some(path) => Some(Whitelist::load(path).map_err(|e| {
+                    format!("Failed to load whitelist from {}: {e}", path.display())
+                })?),
+                None => None,
+            };
+        let settings = match &config.settings_path {
+            Some(path) => ServerSettings::load(path)
+                .map_err(|e| format!("Failed to load config from {}: {e}", path.display()))?,
+            None => ServerSettings::default(),
+        };
+        #[cfg(feature = "admin-dashboard")]
+        let admin_token =
+            match &config.admin_token_file {
+                Some(path) => Some(crate::dashboard::read_token(path).map_err(|e| {
+                    format!("Failed to load admin token from {}: {e}", path.display())
+                })?),
+                None => None,
+            };
+        let banlist = match settings.banlist.as_ref().or(config.banlist_path.as_ref()) {
+            Some(path) => Banlist::load(path)
+                .map_err(|e| format!("Failed to load banlist from {}: {e}", path.display()))?,
+            None => Banlist::default(),
+        };
+        let map = load_map(
+            config
+                .map
+                .as_ref()
+                .or(settings.map.first())
+                .map(PathBuf::as_path),
+        )?;
+
+        let port = config.port;
+        let admin_console_enabled = config.admin_console;
+        let report_usage = config.report_usage;
+        let advertise_lan = config.advertise_lan;
+        #[cfg(feature = "status-http")]
+        let status_port = config.status_port;
+        let observer_port = config.observer_port;
+        let addr = format!("0.0.0.0:{}", port); // Make sure to listen on all interfaces
         let server_socket = UdpSocket::bind(&addr).await?;
+        // Picked by the system when started on port 0
+        let local_port = server_socket.local_addr()?.port();
+        // Everything which can fail is bound before the first task is spawned, otherwise the
+        // tasks would be left running with the game port taken and no handle to stop them
+        let lan_socket = if advertise_lan {
+            Some(lan::bind_advertiser().await?)
+        } else {
+            None
+        };
+        let observer_listener = match observer_port {
+            Some(observer_port) => Some(observer::bind(observer_port).await?),
+            None => None,
+        };
+        #[cfg(feature = "status-http")]
+        let status_listener = match status_port {
+            Some(status_port) => Some(crate::status::bind(status_port).await?),
+            None => None,
+        };
         let (broadcast_tx, broadcast_rx) = mpsc::unbounded_channel::<BroadcastMessage>();
-        let context = Arc::new(ServerContext::new(server_socket, broadcast_tx.clone()));
+        let context = Arc::new(ServerContext::new(
+            server_socket,
+            broadcast_tx.clone(),
+            config,
+            whitelist,
+            banlist,
+            settings,
+            map,
+        ));
+
+        context.spawn(
+            "broadcast_sender",
+            broadcast_sender(context.clone(), broadcast_rx),
+        );
+        context.spawn("listen_handler", listen_handler(context.clone()));
+        context.spawn("resend_handler", resend_handler(context.clone()));
+        context.spawn("log_flusher", log_flusher());
+        logger::info(
+            "server",
+            format!(
+                "Listening on UDP port {} as \"{}\" with seed {}",
+                local_port, context.server_name, context.seed
+            ),
+        );
+        logger::info(
+            "server",
+            format!("Playing map {}", context.map.lock().await.info.name),
+        );
+        #[cfg(feature = "chaos")]
+        if let Some(chaos) = &context.chaos {
+            logger::warn(
+                "server",
+                format!("Injecting faults with seed {}", chaos.seed()),
+            );
+        }
+        if context.whitelist.is_some() {
+            logger::info(
+                "server",
+                "Whitelist is enabled, only listed players can join".to_string(),
+            );
+        }
+        if report_usage {
+            context.spawn("usage_reporter", usage_reporter(context.clone()));
+        }
+        if let Some(socket) = lan_socket {
+            context.spawn("lan_advertiser", lan_advertiser(context.clone(), socket));
+            logger::info(
+                "server",
+                format!("Advertising on the LAN to {}", lan::MULTICAST_GROUP),
+            );
+        }
+        if admin_console_enabled {
+            context.spawn("admin_console", admin_console(context.clone()));
+            logger::info(
+                "server",
+                "Admin console is enabled, type \"help\" for available commands".to_string(),
+            );
+        }
+
+        if let (Some(observer_port), Some(listener)) = (observer_port, observer_listener) {
+            context.spawn(
+                "observer_endpoint",
+                observer::serve(listener, context.observers.clone()),
+            );
+            context.spawn(
+                "observer_snapshot_sender",
+                observer_snapshot_sender(context.clone()),
+            );
+            logger::info(
+                "server",
+                format!("Streaming game events on tcp://127.0.0.1:{observer_port}"),
+            );
+        }
 
-        tokio::spawn(broadcast_sender(context.clone(), broadcast_rx));
-        tokio::spawn(listen_handler(context.clone()));
-        println!("Listening on UDP port {port}");
+        let server_handle = ServerHandle::new(&context);
+        #[cfg(feature = "status-http")]
+        if let (Some(status_port), Some(listener)) = (status_port, status_listener) {
+            let router = crate::status::router(server_handle.clone());
+            #[cfg(feature = "admin-dashboard")]
+            let router = match admin_token {
+                Some(token) => {
+                    let dashboard = crate::dashboard::Dashboard::new(server_handle.clone(), token);
+                    context.spawn(
+                        "dashboard_chat_collector",
+                        dashboard
+                            .clone()
+                            .collect_chat(context.observers.subscribe()),
+                    );
+                    logger::info(
+                        "server",
+                        format!("Serving admin dashboard on http://0.0.0.0:{status_port}/admin"),
+                    );
+                    router.merge(dashboard.router())
+                }
+                None => router,
+            };
+            context.spawn("status_endpoint", crate::status::serve(listener, router));
+            logger::info(
+                "server",
+                format!("Serving status on http://0.0.0.0:{status_port}/status"),
+            );
+        }
 
-        Ok(()) as ServerSessionResult
+        Ok(server_handle) as ServerSessionResult
     })
     .await
     {
-        Ok(_) => Ok(()),
+        Ok(result) => result,
         Err(e) => Err(format!(
             "Server creation timed out after {} seconds: {e}",
             globals::CONNECTION_TIMEOUT_SEC.as_secs()
@@ -46,11 +303,309 @@ pub async fn start_server(port: u16) -> ServerSessionResult {
     }
 }
 
-type PlayerMap = HashMap<SocketAddr, Player>;
+/// Handle of a running server for the code that started it. Dropping the handle does not stop
+/// the server, call `shutdown()` for that.
+#[derive(Clone)]
+pub struct ServerHandle {
+    /// Owned by the server tasks, so the socket is closed as soon as they have terminated
+    context: Weak<ServerContext>,
+    cancel: CancellationToken,
+    tasks: TaskTracker,
+}
+
+impl ServerHandle {
+    fn new(context: &Arc<ServerContext>) -> Self {
+        Self {
+            context: Arc::downgrade(context),
+            cancel: context.cancel.clone(),
+            tasks: context.tasks.clone(),
+        }
+    }
+
+    /// Notify players that the server is going away, wait briefly for them to leave, flush
+    /// persistent state and then stop every server task. Join requests are refused from the
+    /// start of the shutdown.
+    pub async fn shutdown(&self) {
+        if let Some(context) = self.context.upgrade() {
+            context.shutting_down.store(true, Ordering::SeqCst);
+
+            let deadline = std::time::Instant::now() + SHUTDOWN_GRACE_PERIOD;
+            let mut interval = tokio::time::interval(std::time::Duration::from_millis(250));
+            // Notification is repeated in case it gets lost, until everyone has left
+            while std::time::Instant::now() < deadline && !context.lock_players().await.is_empty() {
+                let _ = context.broadcast_tx.send(BroadcastMessage {
+                    msg: Message::Shutdown,
+                    recipients: Recipients::AllExcept(None),
+                });
+                interval.tick().await;
+            }
+            let remaining_players = context.lock_players().await.len();
+            if remaining_players > 0 {
+                logger::warn(
+                    "server",
+                    format!("{remaining_players} player(s) did not leave before shutdown"),
+                );
+            }
+
+            context.banlist.lock().await.save();
+            save_replay(&context);
+            logger::info("usage", context.usage.report(remaining_players));
+        }
+
+        self.cancel.cancel();
+        self.tasks.close();
+    }
+
+    /// Resolves once the server has started stopping, either after `shutdown()` or on its own
+    /// when its socket fails.
+    pub async fn stopped(&self) {
+        self.cancel.cancelled().await;
+    }
+
+    /// Wait until every server task has stopped after `shutdown()` and the port is released.
+    pub async fn await_terminated(&self) {
+        self.tasks.wait().await;
+    }
+
+    /// Re-read the config file and apply its settings without dropping connected players.
+    pub async fn reload(&self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let context = self.context.upgrade().ok_or("Server is not running")?;
+        reload_settings(&context).await
+    }
+
+    /// None if the server is not running anymore.
+    pub async fn status(&self) -> Option<ServerStatus> {
+        let context = self.context.upgrade()?;
+        let settings = context.settings.lock().await.clone();
+        let mut players: Vec<PlayerStatus> = context
+            .lock_players()
+            .await
+            .values()
+            .map(|session| PlayerStatus {
+                id: session.player.id,
+                name: session.name.clone(),
+                x: session.player.pos.x,
+                y: session.player.pos.y,
+                activity: session.activity,
+            })
+            .collect();
+        players.sort_by_key(|player| player.id);
+        let queued_players = context.join_queue.lock().unwrap().len();
+
+        Some(ServerStatus {
+            name: context.server_name.clone(),
+            uptime_secs: context.started.elapsed().as_secs(),
+            tick_rate: globals::MAX_LOGIC_UPDATE_PER_SEC as u32,
+            snapshot_rate: settings.snapshot_rate,
+            max_players: settings.max_players,
+            motd: settings.motd,
+            queued_players,
+            players,
+        })
+    }
+
+    /// Positions of a player over the last second, None if the player is not on the server or the
+    /// server is not running anymore.
+    pub async fn position_history(&self, player_id: PlayerID) -> Option<PositionHistory> {
+        self.context.upgrade()?.position_history(player_id).await
+    }
+
+    /// Address the server listens on, which tells the port picked by the system when started on
+    /// port 0. None if the server is not running anymore.
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.context.upgrade()?.server_socket.local_addr().ok()
+    }
+
+    /// Execute a command of the admin console and return its response, see `admin::HELP`. None
+    /// if the server is not running anymore.
+    pub async fn execute_admin_command(&self, line: &str) -> Option<String> {
+        let context = self.context.upgrade()?;
+        Some(run_admin_command(&context, line).await)
+    }
+
+    /// None if the server is not running anymore.
+    pub async fn metrics(&self) -> Option<ServerMetrics> {
+        let context = self.context.upgrade()?;
+        let player_count = context.lock_players().await.len();
+        let peer_channels = context.channels.lock().unwrap().len();
+        Some(ServerMetrics {
+            traffic: context.traffic.snapshot(),
+            ticks: context.ticks.summary(),
+            usage: context.usage.summary(player_count),
+            tasks: context.tasks.len(),
+            peer_channels,
+        })
+    }
+}
+
+/// Overview of a running server for hosting panels and uptime monitors
+#[derive(Serialize)]
+pub struct ServerStatus {
+    pub name: String,
+    pub uptime_secs: u64,
+    /// Simulation ticks per second
+    pub tick_rate: u32,
+    /// World state replications per second
+    pub snapshot_rate: u32,
+    pub max_players: Option<usize>,
+    /// Message of the day sent to players after joining
+    pub motd: Option<String>,
+    /// Clients waiting in the join queue of the full server
+    pub queued_players: usize,
+    pub players: Vec<PlayerStatus>,
+}
+
+#[derive(Serialize)]
+pub struct PlayerStatus {
+    pub id: PlayerID,
+    pub name: String,
+    pub x: f32,
+    pub y: f32,
+    pub activity: PlayerActivity,
+}
+
+/// Counters of server performance for finding bottlenecks
+#[derive(Serialize)]
+pub struct ServerMetrics {
+    /// Traffic per message type
+    pub traffic: BTreeMap<&'static str, MessageTraffic>,
+    pub ticks: TickSummary,
+    /// Players and messages since the server started
+    pub usage: ServerUsage,
+    /// Server tasks still running. Growing while the number of players doesn't points to tasks
+    /// left behind by players who left.
+    pub tasks: usize,
+    /// Clients with delivery channel state, which should follow the number of players
+    pub peer_channels: usize,
+}
+
+/// Server-side bookkeeping of a connected client
+struct PlayerSession {
+    player: Player,
+    name: String,
+    /// Secret sent to the client in `Ack`, required in every message of the client
+    token: SessionToken,
+    /// Time of the last message of the client with the right token
+    last_heard: std::time::Instant,
+    /// Accumulated priority of distant players to be replicated to this client, see
+    /// `replicate_by_distance()`
+    distant_priorities: HashMap<PlayerID, f32>,
+    /// Measured connection quality, reported to the client in `Stats`
+    connection: ConnectionQuality,
+    /// Time of the last relayed chat message, for enforcing the chat cooldown
+    last_chat: Option<std::time::Instant>,
+    /// Time of the last accepted name or color change, see `PROFILE_COOLDOWN`
+    last_profile_update: Option<std::time::Instant>,
+    /// Distance walked in the current match
+    score: f32,
+    /// Index of the map voted for in the running map vote
+    map_vote: Option<usize>,
+    /// Recently reported positions
+    history: PositionHistory,
+    /// Lost outside the safe zone of a storm, restored when the storm is over
+    health: f32,
+    /// Time the player last moved, for telling who is away from keyboard
+    last_input: std::time::Instant,
+    /// Spectators are left out of the world and of the player count until they move again
+    activity: PlayerActivity,
+    /// Baseline of the snapshots sent to the client, see `encode_snapshots()`
+    snapshots: SnapshotEncoder,
+}
+
+impl std::fmt::Display for PlayerSession {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.name == default_player_name(self.player.id) {
+            write!(f, "{}", self.name)
+        } else {
+            write!(f, "{} (Player {})", self.name, self.player.id)
+        }
+    }
+}
+
+fn default_player_name(player_id: PlayerID) -> String {
+    format!("Player {player_id}")
+}
+
+/// Players taking up a slot of the server, everyone but spectators
+fn active_player_count(players: &PlayerMap) -> usize {
+    players
+        .values()
+        .filter(|session| session.activity != PlayerActivity::Spectating)
+        .count()
+}
+
+type PlayerMap = HashMap<SocketAddr, PlayerSession>;
+
+/// Session of the client if the token matches the one it received on joining. The address alone
+/// could be spoofed to act on behalf of another player. Clients who haven't joined are backed off
+/// from, but wrong tokens aren't, because anyone could send them in the name of a player.
+fn authenticate<'a>(
+    context: &ServerContext,
+    players: &'a mut PlayerMap,
+    client: &SocketAddr,
+    token: SessionToken,
+) -> Option<&'a mut PlayerSession> {
+    let Some(session) = players.get_mut(client) else {
+        context.report_offence(*client);
+        return None;
+    };
+    if session.token != token {
+        message::trace(format!(
+            "Ignored message with invalid session token from {client}"
+        ));
+        return None;
+    }
+    session.last_heard = std::time::Instant::now();
+    Some(session)
+}
+
+/// Append `#2`, `#3` and so on to a name already taken by another player, ignoring case, so that
+/// players can be told apart. The name is shortened if needed to fit the suffix. The current name
+/// of the player itself doesn't count as taken.
+fn disambiguate_name(name: &str, player_id: PlayerID, players: &PlayerMap) -> String {
+    let is_taken = |candidate: &str| {
+        let candidate = candidate.to_lowercase();
+        players.values().any(|session| {
+            session.player.id != player_id && session.name.to_lowercase() == candidate
+        })
+    };
+    if !is_taken(name) {
+        return name.to_string();
+    }
+
+    let mut number = 2;
+    loop {
+        let suffix = format!("#{number}");
+        let max_base_len = globals::MAX_PLAYER_NAME_LEN.saturating_sub(suffix.len());
+        let candidate = format!(
+            "{}{suffix}",
+            name.chars().take(max_base_len).collect::<String>()
+        );
+        if !is_taken(&candidate) {
+            return candidate;
+        }
+        number += 1;
+    }
+}
 
 struct BroadcastMessage {
-    msg: Vec<u8>,
-    excluded_client: Option<SocketAddr>,
+    msg: Message,
+    recipients: Recipients,
+}
+
+enum Recipients {
+    /// Every player except the one the message is usually about
+    AllExcept(Option<SocketAddr>),
+    Only(Vec<SocketAddr>),
+}
+
+impl Recipients {
+    fn contains(&self, client: &SocketAddr) -> bool {
+        match self {
+            Recipients::AllExcept(excluded_client) => Some(*client) != *excluded_client,
+            Recipients::Only(clients) => clients.contains(client),
+        }
+    }
 }
 
 // Non-blocking channels are used for lock-free message passing from sync main thread to async
@@ -63,210 +618,2509 @@ type ChannelReceiver = mpsc::UnboundedReceiver<BroadcastMessage>;
 struct ServerContext {
     server_socket: UdpSocket,
     broadcast_tx: ChannelSender,
+    /// Advertised in query responses and handshake ACK
+    server_name: String,
+    /// Sent in handshake ACK, so that cosmetic effects look the same to every player
+    seed: u64,
+    /// Sent in handshake ACK, so that players move and get clamped the same way as here
+    world_params: WorldParams,
+    /// Private server mode, checked on each new join
+    whitelist: Option<Mutex<Whitelist>>,
+    banlist: Mutex<Banlist>,
     players: Mutex<PlayerMap>,
+    /// Player positions as of the last simulation tick, for proximity queries. Lock after
+    /// `players` when both are needed.
+    grid: Mutex<SpatialGrid>,
     /// ID acting as player number, increases on every new player
     /// join
     player_id_counter: AtomicU64,
+    /// Current simulation tick, sent with world state so clients can order snapshots. Keeps
+    /// counting across pauses of the simulation on an empty server.
+    tick: AtomicU64,
+    /// Speed of the simulation set with the timescale admin command, as bits of an `f32`
+    time_scale: AtomicU32,
+    /// Set by the pause admin command. Neither the simulation, nor positions of players, nor the
+    /// match clock advance while paused.
+    paused: AtomicBool,
+    /// Set by the storm admin command, the storm starts on the next update of `storm_handler()`
+    storm_requested: AtomicBool,
+    started: std::time::Instant,
+    /// Ping sender, stats sender, match timer, time of day, storms, leader, map announcements
+    /// and simulation, only running while there are players on the server. Players who crashed
+    /// are timed out by the ping sender, so these stop without a DISCONNECT too.
+    game_tasks: Mutex<Vec<JoinHandle<()>>>,
+    /// Set when the server is going away
+    shutting_down: AtomicBool,
+    /// Stops every server task on shutdown
+    cancel: CancellationToken,
+    tasks: TaskTracker,
+    traffic: TrafficStats,
+    ticks: TickStats,
+    usage: UsageStats,
+    /// Game events for observers, see `observer::serve()`
+    observers: broadcast::Sender<ObserverEvent>,
+    /// Clients waiting for a slot while the server is full, see `ServerSettings::join_queue`.
+    /// Only held briefly without awaiting, so it's not an async lock.
+    join_queue: std::sync::Mutex<JoinQueue>,
+    /// Addresses ignored by `listen_handler`, see `report_offence()`. Only held briefly without
+    /// awaiting, so it's not an async lock.
+    backoff: std::sync::Mutex<AddressBackoff>,
+    /// Delivery state of the channels to each player, see `Message::channel()`. Only held briefly
+    /// without awaiting, so it's not an async lock.
+    channels: std::sync::Mutex<HashMap<SocketAddr, Channels>>,
+    /// Sizes of answered MTU probes are passed to the `discover_path_mtu()` task of each client
+    mtu_probes: std::sync::Mutex<HashMap<SocketAddr, mpsc::UnboundedSender<u16>>>,
+    /// Reduce snapshot rate when overloaded
+    shed_load: bool,
+    settings: Mutex<ServerSettings>,
+    settings_path: Option<PathBuf>,
+    /// Map played on the server, changed by rotation and by reloading settings
+    map: Mutex<ServedMap>,
+    /// Maps to choose from while a map vote is running, empty otherwise
+    map_vote: Mutex<Vec<PathBuf>>,
+    /// Banlist file given on the command line, used when the config file doesn't have one
+    banlist_path: Option<PathBuf>,
+    /// Directory of match replays, None if matches are not recorded
+    replay_dir: Option<PathBuf>,
+    /// Match being recorded, see `start_replay()`. Only held briefly without awaiting, so it's
+    /// not an async lock.
+    replay: std::sync::Mutex<Option<ReplayRecorder>>,
+    #[cfg(feature = "chaos")]
+    chaos: Option<Chaos>,
 }
 
 impl ServerContext {
-    fn new(server_socket: UdpSocket, broadcast_tx: ChannelSender) -> Self {
+    fn new(
+        server_socket: UdpSocket,
+        broadcast_tx: ChannelSender,
+        config: ServerConfig,
+        whitelist: Option<Whitelist>,
+        banlist: Banlist,
+        settings: ServerSettings,
+        map: ServedMap,
+    ) -> Self {
         Self {
             server_socket,
             broadcast_tx,
+            server_name: config.name,
+            seed: config.seed.unwrap_or_else(rand::random),
+            world_params: config.world_params,
+            whitelist: whitelist.map(Mutex::new),
+            banlist: Mutex::new(banlist),
             players: Mutex::new(PlayerMap::new()),
+            grid: Mutex::new(SpatialGrid::default()),
             player_id_counter: AtomicU64::new(1),
+            tick: AtomicU64::new(0),
+            time_scale: AtomicU32::new(1.0f32.to_bits()),
+            paused: AtomicBool::new(false),
+            storm_requested: AtomicBool::new(false),
+            started: std::time::Instant::now(),
+            game_tasks: Mutex::new(Vec::new()),
+            shutting_down: AtomicBool::new(false),
+            cancel: CancellationToken::new(),
+            tasks: TaskTracker::new(),
+            traffic: TrafficStats::default(),
+            usage: UsageStats::default(),
+            observers: broadcast::channel(observer::EVENT_BUFFER).0,
+            join_queue: std::sync::Mutex::new(JoinQueue::default()),
+            backoff: std::sync::Mutex::new(AddressBackoff::default()),
+            channels: std::sync::Mutex::new(HashMap::new()),
+            mtu_probes: std::sync::Mutex::new(HashMap::new()),
+            ticks: TickStats::new(std::time::Duration::from_secs_f32(
+                globals::FIXED_UPDATE_TIMESTEP_SEC,
+            )),
+            shed_load: config.shed_load,
+            settings: Mutex::new(settings),
+            settings_path: config.settings_path,
+            map: Mutex::new(map),
+            map_vote: Mutex::new(Vec::new()),
+            banlist_path: config.banlist_path,
+            replay_dir: config.replay_dir,
+            replay: std::sync::Mutex::new(None),
+            #[cfg(feature = "chaos")]
+            chaos: config.chaos.map(Chaos::new),
+        }
+    }
+
+    /// Every access to the players goes through here, so that injected faults can delay it
+    async fn lock_players(&self) -> MutexGuard<'_, PlayerMap> {
+        #[cfg(feature = "chaos")]
+        if let Some(delay) = self.chaos.as_ref().and_then(Chaos::lock_delay) {
+            tokio::time::sleep(delay).await;
+        }
+        self.players.lock().await
+    }
+
+    /// Broadcast datagram isn't sent to one of its recipients as an injected fault
+    fn chaos_drops_broadcast(&self) -> bool {
+        #[cfg(feature = "chaos")]
+        return self.chaos.as_ref().is_some_and(Chaos::drops_broadcast);
+        #[cfg(not(feature = "chaos"))]
+        false
+    }
+
+    /// Received datagram is handled twice as an injected fault
+    fn chaos_duplicates_datagram(&self) -> bool {
+        #[cfg(feature = "chaos")]
+        return self.chaos.as_ref().is_some_and(Chaos::duplicates_datagram);
+        #[cfg(not(feature = "chaos"))]
+        false
+    }
+
+    fn time_scale(&self) -> f32 {
+        f32::from_bits(self.time_scale.load(Ordering::Relaxed))
+    }
+
+    /// Spawn a server task which is stopped on shutdown.
+    fn spawn<F>(&self, name: &str, future: F) -> JoinHandle<()>
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        let cancel = self.cancel.clone();
+        task::spawn_named(
+            name,
+            self.tasks.track_future(async move {
+                tokio::select! {
+                    _ = cancel.cancelled() => (),
+                    _ = future => (),
+                }
+            }),
+        )
+    }
+
+    /// Recent positions of a player, None if the player is not on the server.
+    async fn position_history(&self, player_id: PlayerID) -> Option<PositionHistory> {
+        self.lock_players()
+            .await
+            .values()
+            .find(|session| session.player.id == player_id)
+            .map(|session| session.history.clone())
+    }
+
+    /// Publish a game event to observers, if there are any
+    fn observe(&self, event: ObserverEvent) {
+        let _ = self.observers.send(event);
+    }
+
+    /// Record into the replay of the current match, if one is being recorded
+    fn record_replay(&self, record: impl FnOnce(&mut ReplayRecorder, Tick)) {
+        if let Some(recorder) = self.replay.lock().unwrap().as_mut() {
+            record(recorder, self.tick.load(Ordering::SeqCst));
+        }
+    }
+
+    /// Ignore the client for a while in `listen_handler`. Reported for garbage, refused join
+    /// requests and messages of clients who haven't joined.
+    fn report_offence(&self, client: SocketAddr) {
+        self.backoff
+            .lock()
+            .unwrap()
+            .penalize(client, std::time::Instant::now());
+    }
+
+    /// Datagram carrying the message to the client on its channel. Clients who haven't joined
+    /// have no channels, so they get everything unreliably.
+    fn encode(&self, msg: &Message, serialized_msg: &[u8], client: SocketAddr) -> Vec<u8> {
+        match self.channels.lock().unwrap().get_mut(&client) {
+            Some(channels) => {
+                channels.send(msg.channel(), serialized_msg, std::time::Instant::now())
+            }
+            None => DatagramHeader::default().encode(serialized_msg),
         }
     }
+
+    /// Send message directly to a single client instead of through the broadcast channel.
+    async fn send_to(&self, msg: &Message, client: SocketAddr) -> std::io::Result<()> {
+        let serialized_msg = msg.serialize();
+        let datagram = self.encode(msg, &serialized_msg, client);
+        self.server_socket.send_to(&datagram, client).await?;
+        self.traffic.record_sent(msg.name(), datagram.len());
+        message::trace(format!("Sent: {msg:?}"));
+
+        Ok(())
+    }
 }
 
+/// Number of unexpected receive errors in a row after which the socket is considered broken
+const MAX_CONSECUTIVE_RECV_ERRORS: u32 = 10;
+/// Pause after an unexpected receive error so a broken socket doesn't spin the listener
+const RECV_ERROR_BACKOFF: std::time::Duration = std::time::Duration::from_millis(100);
+
 /// Primary listener loop for incoming client UDP requests, processing each new message in separate task.
+///
+/// Receive errors caused by a single peer don't stop the loop. If the socket keeps failing, the
+/// server is shut down.
 async fn listen_handler(context: Arc<ServerContext>) {
+    let mut consecutive_errors = 0;
+    let mut batch = RecvBatch::new();
+    loop {
+        match batch.recv(&context.server_socket).await {
+            Ok(()) => consecutive_errors = 0,
+            Err(e) if is_transient_recv_error(&e) => {
+                // Can happen on every broadcast to a client that has gone away, so it would
+                // flood the log at a higher level
+                message::trace(format!("Ignored receive error: {e}"));
+                continue;
+            }
+            Err(e) => {
+                consecutive_errors += 1;
+                if consecutive_errors >= MAX_CONSECUTIVE_RECV_ERRORS {
+                    logger::error(
+                        "net",
+                        format!("Server socket is broken, stopping server: {e}"),
+                    );
+                    // Shutdown cancels this task, so it has to run separately
+                    let server_handle = ServerHandle::new(&context);
+                    task::spawn_named("shutdown", async move { server_handle.shutdown().await });
+                    return;
+                }
+                logger::warn("net", format!("Failed to receive datagram: {e}"));
+                tokio::time::sleep(RECV_ERROR_BACKOFF).await;
+                continue;
+            }
+        }
+        let now = std::time::Instant::now();
+        let mut acks = Vec::new();
+        {
+            let backoff = context.backoff.lock().unwrap();
+            let mut channels = context.channels.lock().unwrap();
+            let datagrams = batch.datagrams().flat_map(|received| {
+                let copies = if context.chaos_duplicates_datagram() {
+                    2
+                } else {
+                    1
+                };
+                std::iter::repeat(received).take(copies)
+            });
+            for (datagram, client) in datagrams {
+                if backoff.is_blocked(&client, now) {
+                    context
+                        .traffic
+                        .record_received(stats::BLOCKED_MESSAGE_TYPE, datagram.len());
+                    continue;
+                }
+                // Stray traffic and datagrams of incompatible versions are dropped without
+                // spawning a task for them
+                let (header, payload) = match DatagramHeader::decode(datagram) {
+                    Ok(decoded) => decoded,
+                    Err(e) => {
+                        context
+                            .traffic
+                            .record_received(stats::INVALID_MESSAGE_TYPE, datagram.len());
+                        message::trace(format!("Dropped datagram from {client}: {e}"));
+                        continue;
+                    }
+                };
+                let is_ack = header.flags & FLAG_ACK != 0;
+                if is_ack {
+                    context
+                        .traffic
+                        .record_received(stats::RECEIPT_MESSAGE_TYPE, datagram.len());
+                }
+                // Clients who haven't joined have no channels, their messages are taken as they
+                // arrive. Reliable messages only come from sessions which are over, like the
+                // resent DISCONNECT of a player whose acknowledgement got lost, so they are only
+                // acknowledged to stop the resending.
+                let delivery = match channels.get_mut(&client) {
+                    Some(channels) => channels.receive(header, payload),
+                    None if is_ack => Delivery::default(),
+                    None if header.channel.is_reliable() => Delivery {
+                        messages: Vec::new(),
+                        ack: net::acknowledgement(header),
+                    },
+                    None => Delivery {
+                        messages: vec![payload.to_vec()],
+                        ack: None,
+                    },
+                };
+                if let Some(ack) = delivery.ack {
+                    acks.push((ack, client));
+                }
+                if !delivery.messages.is_empty() {
+                    context.spawn(
+                        "process_client_message",
+                        process_client_messages(context.clone(), client, delivery.messages),
+                    );
+                }
+            }
+        }
+        for (ack, client) in acks {
+            if context.server_socket.send_to(&ack, client).await.is_ok() {
+                context
+                    .traffic
+                    .record_sent(stats::RECEIPT_MESSAGE_TYPE, ack.len());
+            }
+        }
+    }
+}
+
+/// Resend reliable messages the players haven't acknowledged in time. Players who stopped
+/// acknowledging them for `net::GIVE_UP_AFTER` are gone and get dropped.
+async fn resend_handler(context: Arc<ServerContext>) {
+    let mut interval = tokio::time::interval(net::RESEND_INTERVAL / 2);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
     loop {
-        let mut buf = [0u8; 32];
-        // TODO: Consider non-blocking UDP I/O
-        let (len, client) = context.server_socket.recv_from(&mut buf).await.unwrap();
-        if 1 < len {
-            let request_msg = String::from_utf8_lossy(&buf[..len]).to_string();
-            tokio::spawn(process_client_message(context.clone(), client, request_msg));
+        interval.tick().await;
+        let now = std::time::Instant::now();
+        let mut resends: Vec<(SocketAddr, Vec<u8>)> = Vec::new();
+        let mut gone = Vec::new();
+        context.channels.lock().unwrap().retain(|client, channels| {
+            match channels.resend_due(now) {
+                Some(datagrams) => {
+                    resends.extend(datagrams.into_iter().map(|datagram| (*client, datagram)));
+                    true
+                }
+                None => {
+                    gone.push(*client);
+                    false
+                }
+            }
+        });
+        for (client, datagram) in resends {
+            match context.server_socket.send_to(&datagram, client).await {
+                Ok(_) => context
+                    .traffic
+                    .record_sent(stats::RESENT_MESSAGE_TYPE, datagram.len()),
+                Err(e) => logger::error("net", format!("Failed to resend: {:?}", e)),
+            }
+        }
+        for client in gone {
+            logger::info(
+                "net",
+                format!("{client} stopped acknowledging messages, dropping it"),
+            );
+            let _ = drop_player(context.clone(), client).await;
         }
     }
 }
 
-/// Sender loop for broadcasting server UDP responses to all players except the player who owning
-/// the broadcast message.
+/// Errors reported by the socket about a single peer, which don't affect other clients.
+///
+/// Windows reports ICMP port unreachable responses to earlier datagrams as connection reset on the
+/// next receive, and datagrams larger than the buffer as an error instead of truncating them.
+fn is_transient_recv_error(e: &std::io::Error) -> bool {
+    /// WSAEMSGSIZE
+    const WINDOWS_MESSAGE_TOO_LONG: i32 = 10040;
+
+    matches!(
+        e.kind(),
+        std::io::ErrorKind::ConnectionReset
+            | std::io::ErrorKind::ConnectionRefused
+            | std::io::ErrorKind::ConnectionAborted
+            | std::io::ErrorKind::Interrupted
+            | std::io::ErrorKind::WouldBlock
+            | std::io::ErrorKind::TimedOut
+    ) || (cfg!(windows) && e.raw_os_error() == Some(WINDOWS_MESSAGE_TOO_LONG))
+}
+
+/// Sender loop for broadcasting server UDP responses to the recipients of each message, usually
+/// all players except the player owning the broadcast message.
 async fn broadcast_sender(context: Arc<ServerContext>, mut broadcast_rx: ChannelReceiver) {
     while let Some(broadcast) = broadcast_rx.recv().await {
-        message::trace(format!(
-            "Broadcasting: {}",
-            String::from_utf8_lossy(&broadcast.msg)
-        ));
-        let players = context.players.lock().await;
+        let serialized_msg = broadcast.msg.serialize();
+        message::trace(format!("Broadcasting: {:?}", broadcast.msg));
+        let players = context.lock_players().await;
         for (client_addr, _) in players.iter() {
-            if Some(*client_addr) != broadcast.excluded_client {
-                if let Err(e) = context
-                    .server_socket
-                    .send_to(&broadcast.msg, client_addr)
-                    .await
-                {
-                    eprintln!("Failed to broadcast: {:?}", e);
+            if broadcast.recipients.contains(client_addr) {
+                // Sequence numbers differ between clients
+                let datagram = context.encode(&broadcast.msg, &serialized_msg, *client_addr);
+                // Lost on the way as far as delivery channels know, so reliable messages are
+                // still resent
+                if context.chaos_drops_broadcast() {
+                    continue;
+                }
+                match context.server_socket.send_to(&datagram, client_addr).await {
+                    Ok(_) => context
+                        .traffic
+                        .record_sent(broadcast.msg.name(), datagram.len()),
+                    Err(e) => logger::error("net", format!("Failed to broadcast: {:?}", e)),
                 }
             }
         }
     }
 }
 
-/// Periodic ping sender that clients can use as healthcheck of server.
+/// How often players are checked for needing a ping
+const PING_CHECK_INTERVAL: std::time::Duration = std::time::Duration::from_millis(50);
+
+/// Round-trip time and packet loss are sampled this often even while snapshots keep the
+/// connection alive
+const RTT_SAMPLE_INTERVAL: std::time::Duration = std::time::Duration::from_millis(500);
+
+/// Keepalive that clients can use as healthcheck of server. Any datagram shows that the server is
+/// alive, so players are only pinged when nothing was sent to them for
+/// `globals::KEEPALIVE_INTERVAL`, or when a round-trip time sample is due. Replies are used for
+/// measuring connection quality of players.
+///
+/// Clients answer every ping, so players who haven't been heard from for
+/// `globals::CONNECTION_TIMEOUT_SEC` have crashed or lost their DISCONNECT, and are dropped.
 async fn ping_sender(context: Arc<ServerContext>) {
-    let mut interval = tokio::time::interval(globals::PING_INTERVAL_MS);
+    let mut interval = tokio::time::interval(PING_CHECK_INTERVAL);
     loop {
         interval.tick().await;
-        let _ = context.broadcast_tx.send(BroadcastMessage {
-            msg: Message::Ping.serialize().into_bytes(),
-            excluded_client: None,
-        });
+        let now = std::time::Instant::now();
+        let mut recipients = Vec::new();
+        let mut timed_out = Vec::new();
+        {
+            let mut players = context.lock_players().await;
+            let channels = context.channels.lock().unwrap();
+            for (client, session) in players.iter_mut() {
+                if session.last_heard.elapsed() >= globals::CONNECTION_TIMEOUT_SEC {
+                    logger::info("session", format!("{session} timed out"));
+                    timed_out.push(*client);
+                    continue;
+                }
+                let idle = channels
+                    .get(client)
+                    .and_then(Channels::last_sent)
+                    .map_or(true, |last_sent| {
+                        now.duration_since(last_sent) >= globals::KEEPALIVE_INTERVAL
+                    });
+                let sample_due = session
+                    .connection
+                    .last_ping_sent()
+                    .map_or(true, |last_ping| {
+                        now.duration_since(last_ping) >= RTT_SAMPLE_INTERVAL
+                    });
+                if idle || sample_due {
+                    session.connection.record_ping_sent(now);
+                    recipients.push(*client);
+                }
+            }
+        }
+        // Dropping the last player stops this task, so it has to run separately
+        for client in timed_out {
+            context.spawn("drop_player", {
+                let context = context.clone();
+                async move {
+                    let _ = drop_player(context, client).await;
+                }
+            });
+        }
+        if !recipients.is_empty() {
+            let _ = context.broadcast_tx.send(BroadcastMessage {
+                msg: Message::Ping(context.started.elapsed().as_millis() as u64),
+                recipients: Recipients::Only(recipients),
+            });
+        }
     }
 }
 
-/// Authoritative game update logic simulation.
-///
-/// Requires fixed processing, because timing has to be synchronized accross all connected clients.
-/// A server simulation loop does not need to play "catch-up" like a local game loop does, because
-/// there's no point in sending stale packets.
-async fn simulation_handler(context: Arc<ServerContext>) {
-    let desired_frame_duration =
-        std::time::Duration::from_secs_f32(globals::FIXED_UPDATE_TIMESTEP_SEC);
-    let mut interval = tokio::time::interval(desired_frame_duration);
-
-    interval.tick().await; // Skip the first tick (or else there will be bugs)
+/// Interval of sending connection quality to each player
+const STATS_INTERVAL: std::time::Duration = std::time::Duration::from_secs(1);
 
+/// Periodic sender of connection quality measured by the server to each player.
+async fn stats_sender(context: Arc<ServerContext>) {
+    let mut interval = tokio::time::interval(STATS_INTERVAL);
+    interval.tick().await; // Nothing is measured yet
     loop {
-        let current_time = std::time::Instant::now();
-
-        {
-            let mut players = context.players.lock().await;
-            for (client, player) in players.iter_mut() {
-                // Bounds check
-                globals::clamp_player_to_bounds(player);
-
-                // Gameplay state replication
-                let msg = Message::Replicate(*player).serialize();
+        interval.tick().await;
+        for (client, session) in context.lock_players().await.iter_mut() {
+            if let Some(stats) = session.connection.take_stats() {
                 let _ = context.broadcast_tx.send(BroadcastMessage {
-                    msg: msg.into_bytes(),
-                    excluded_client: Some(*client),
+                    msg: Message::Stats(stats),
+                    recipients: Recipients::Only(vec![*client]),
                 });
             }
-        } // Release the lock as soon as possible
-
-        let elapsed_time = current_time.elapsed();
-        if elapsed_time < desired_frame_duration {
-            interval.tick().await;
         }
     }
 }
 
-async fn process_client_message(context: Arc<ServerContext>, client: SocketAddr, msg: String) {
-    message::trace(format!("Received: {msg}"));
-    match Message::deserialize(&msg) {
-        Ok(Message::Handshake) => {
-            accept_client(context, client).await.unwrap();
-        }
-        Ok(Message::Position(player_id, pos)) => {
-            update_position(context, client, player_id, pos)
-                .await
-                .unwrap();
-        }
-        Ok(Message::Leave(player_id)) => {
-            drop_player(context, client, player_id).await.unwrap();
-        }
-        _ => (),
+/// Periodic report of repeated log lines once they stopped, which would otherwise only be
+/// reported on the next line logged.
+async fn log_flusher() {
+    let mut interval = tokio::time::interval(logger::REPEAT_WINDOW);
+    loop {
+        interval.tick().await;
+        logger::flush_repeats();
     }
 }
 
-/// Recieve first time joining client handshake, register as new player and send ACK response
-/// with new player info.
-///
-/// Each new player receives a randomly generated color and the player ID counter is incremented
-/// after each new join.
-async fn accept_client(
-    context: Arc<ServerContext>,
-    client: SocketAddr,
-) -> Result<(), Box<dyn Error + Send + Sync>> {
-    let mut players = context.players.lock().await;
-
-    let ack_msg: String;
-    if let Some(existing_player) = players.get(&client) {
-        // Getting multiple handshakes from and sending out multiple ACK for the same
-        // client is not a problem, that just means that previous ACK was dropped, so the
-        // client retried the HANDSHAKE. Server just resends ACK with same player info that
-        // was already registered as response to new HANDSHAKE. It is made sure here not to
-        // accidentally add the same player multiple times, because that would lead to
-        // "Player 3 joined, Player
-        // 4 joined, Player 5 joined" bug for each accepted HANDSHAKE from the same client.
-        ack_msg = Message::Ack(existing_player.id, existing_player.color).serialize();
-    } else {
-        // Add new player to server
-        let new_player = Player::new(
-            context.player_id_counter.fetch_add(1, Ordering::SeqCst),
-            generate_color(),
-        );
-        players.insert(client, new_player);
-        println!("Player {} joined the server", new_player.id);
+/// Interval of logging usage of headless servers
+const USAGE_REPORT_INTERVAL: std::time::Duration = std::time::Duration::from_secs(10 * 60);
 
-        // First time game startup: start sending out PING messages (to everyone) and start the
-        // game simulation itself when the first player has connected
-        if players.len() == 1 {
-            tokio::spawn(ping_sender(context.clone()));
-            tokio::spawn(simulation_handler(context.clone()));
+/// Periodic log of players and messages since start, see `ServerConfig::report_usage`. Nothing
+/// is logged while the server sits idle, so an empty server doesn't fill the log.
+async fn usage_reporter(context: Arc<ServerContext>) {
+    let mut interval = tokio::time::interval(USAGE_REPORT_INTERVAL);
+    interval.tick().await; // Nothing happened yet
+    let mut previous = ServerUsage::default();
+    loop {
+        interval.tick().await;
+        let player_count = context.lock_players().await.len();
+        let usage = context.usage.summary(player_count);
+        if usage != previous {
+            logger::info("usage", context.usage.report(player_count));
+            previous = usage;
         }
-
-        ack_msg = Message::Ack(new_player.id, new_player.color).serialize();
     }
-
-    // Send ACK
-    context
-        .server_socket
-        .send_to(ack_msg.as_bytes(), client)
-        .await?;
-    message::trace(format!("Sent: {ack_msg}"));
-
-    Ok(())
 }
 
-async fn update_position(
-    context: Arc<ServerContext>,
-    client: SocketAddr,
-    player_id: PlayerID,
-    new_pos: Vector2<f32>,
-) -> Result<(), Box<dyn Error + Send + Sync>> {
-    if let Some(player) = context.players.lock().await.get_mut(&client) {
-        if player_id != player.id {
-            return Ok(());
+/// Periodic beacon announcing the server to the LAN, see `ServerConfig::advertise_lan`
+async fn lan_advertiser(context: Arc<ServerContext>, socket: UdpSocket) {
+    let mut interval = tokio::time::interval(lan::BEACON_INTERVAL);
+    loop {
+        interval.tick().await;
+        let players = context.lock_players().await.len();
+        let port = match context.server_socket.local_addr() {
+            Ok(address) => address.port(),
+            Err(_) => continue,
+        };
+        let beacon = Message::Beacon(
+            context.server_name.clone(),
+            port,
+            u32::try_from(players).unwrap_or(u32::MAX),
+        );
+        match lan::send_beacon(&socket, &beacon).await {
+            Ok(len) => context.traffic.record_sent(beacon.name(), len),
+            // No network to advertise on, like a laptop gone offline
+            Err(e) => message::trace(format!("Failed to send LAN beacon: {e}")),
         }
-
-        player.pos.x = new_pos.x;
-        player.pos.y = new_pos.y;
     }
+}
 
-    Ok(())
+/// Interval of replicating the time of day. The atmosphere changes slowly enough for clients to
+/// simply show the latest value.
+const TIME_OF_DAY_INTERVAL: std::time::Duration = std::time::Duration::from_secs(1);
+
+/// The world wakes up in the morning when the server starts
+const STARTING_TIME_OF_DAY: u16 = 8 * 60;
+
+/// Periodic sender of the time of day in the world, which is derived from the server's uptime so
+/// that it keeps passing while the server is empty.
+async fn time_of_day_sender(context: Arc<ServerContext>) {
+    let mut interval = tokio::time::interval(TIME_OF_DAY_INTERVAL);
+    loop {
+        interval.tick().await;
+        // Day length can change on config reload, making the time jump
+        let day_length = context.settings.lock().await.day_length();
+        let days_passed = context.started.elapsed().as_secs_f64() / day_length.as_secs_f64();
+        let minutes = (STARTING_TIME_OF_DAY as f64 + days_passed * globals::MINUTES_PER_DAY as f64)
+            as u64
+            % globals::MINUTES_PER_DAY as u64;
+        let _ = context.broadcast_tx.send(BroadcastMessage {
+            msg: Message::TimeOfDay(minutes as u16),
+            recipients: Recipients::AllExcept(None),
+        });
+    }
 }
 
-// FIXME: LEAVE packets from can be dropped
-async fn drop_player(
-    context: Arc<ServerContext>,
-    client: SocketAddr,
-    player_id: PlayerID,
-) -> Result<(), Box<dyn Error + Send + Sync>> {
-    let mut players = context.players.lock().await;
-    players.remove(&client);
+/// Interval of updating the storm. Clients show the safe zone closing in smoothly between updates.
+const STORM_UPDATE_INTERVAL: std::time::Duration = std::time::Duration::from_millis(250);
 
-    println!("Player {player_id} left the server");
-    context.broadcast_tx.send(BroadcastMessage {
-        msg: Message::Leave(player_id).serialize().into_bytes(),
-        excluded_client: Some(client),
-    })?;
+/// World event of the server. After `storm_interval_secs` of calm, or right away on the storm
+/// admin command, a storm closes in on a random part of the world, see `Storm`. The state of the
+/// storm is replicated on every update, also while calm, so that clients notice when it's over.
+async fn storm_handler(context: Arc<ServerContext>) {
+    let mut interval = tokio::time::interval(STORM_UPDATE_INTERVAL);
+    let mut calm_since = std::time::Instant::now();
+    let mut running: Option<(Storm, std::time::Instant)> = None;
+    loop {
+        interval.tick().await;
+        // Paused time doesn't count towards storms
+        if context.paused.load(Ordering::SeqCst) {
+            calm_since += interval.period();
+            if let Some((_, started)) = &mut running {
+                *started += interval.period();
+            }
+            continue;
+        }
 
-    Ok(())
+        if running.is_none() {
+            // Interval can change on config reload
+            let storm_interval = context.settings.lock().await.storm_interval();
+            if context.storm_requested.swap(false, Ordering::SeqCst)
+                || storm_interval
+                    .is_some_and(|storm_interval| calm_since.elapsed() >= storm_interval)
+            {
+                running = Some((
+                    Storm::random(&mut rand::thread_rng()),
+                    std::time::Instant::now(),
+                ));
+                logger::info("storm", "Storm started".to_string());
+                let _ = context.broadcast_tx.send(BroadcastMessage {
+                    msg: Message::Notice(
+                        "A storm is coming, stay inside the safe zone!".to_string(),
+                    ),
+                    recipients: Recipients::AllExcept(None),
+                });
+            }
+        }
+        let state = running.and_then(|(storm, started)| storm.state(started.elapsed()));
+        if state.is_none() && running.take().is_some() {
+            calm_since = std::time::Instant::now();
+            for session in context.lock_players().await.values_mut() {
+                session.health = storm::MAX_HEALTH as f32;
+            }
+            logger::info("storm", "Storm cleared up".to_string());
+            let _ = context.broadcast_tx.send(BroadcastMessage {
+                msg: Message::Notice("The storm cleared up.".to_string()),
+                recipients: Recipients::AllExcept(None),
+            });
+        }
+
+        let _ = context.broadcast_tx.send(BroadcastMessage {
+            msg: Message::Storm(state),
+            recipients: Recipients::AllExcept(None),
+        });
+        if let Some(state) = state {
+            damage_outside_zone(&context, &state.zone, interval.period()).await;
+        }
+    }
+}
+
+/// Players outside the safe zone lose health for the given time. Players running out of health
+/// are knocked out: put back into the zone with full health. Every player is told its health.
+async fn damage_outside_zone(
+    context: &ServerContext,
+    zone: &SafeZone,
+    period: std::time::Duration,
+) {
+    let mut knocked_out = Vec::new();
+    for (client, session) in context.lock_players().await.iter_mut() {
+        if session.activity != PlayerActivity::Spectating && !zone.contains(session.player.pos) {
+            session.health -= storm::DAMAGE_PER_SEC * period.as_secs_f32();
+            if session.health <= 0.0 {
+                session.health = storm::MAX_HEALTH as f32;
+                knocked_out.push((session.player.id, session.name.clone(), session.to_string()));
+            }
+        }
+        let _ = context.broadcast_tx.send(BroadcastMessage {
+            msg: Message::Health(session.health.ceil() as u8),
+            recipients: Recipients::Only(vec![*client]),
+        });
+    }
+
+    for (player_id, name, player) in knocked_out {
+        // Anywhere in the inner half of the zone, so knocked out players don't pile up
+        let offset = {
+            let mut rng = rand::thread_rng();
+            Vector2::new(rng.gen_range(-1.0..=1.0), rng.gen_range(-1.0..=1.0))
+                * (zone.radius * 0.5 * std::f32::consts::FRAC_1_SQRT_2)
+        };
+        let mut respawned = Player {
+            pos: zone.center + offset,
+            ..Default::default()
+        };
+        context
+            .map
+            .lock()
+            .await
+            .map
+            .push_out(&mut respawned, context.world_params.player_size);
+        if move_player(context, player_id, respawned.pos)
+            .await
+            .is_none()
+        {
+            continue; // Left meanwhile
+        }
+        logger::info("storm", format!("{player} was knocked out by the storm"));
+        let _ = context.broadcast_tx.send(BroadcastMessage {
+            msg: Message::Event(GameEvent::KnockedOut(player_id, name)),
+            recipients: Recipients::AllExcept(None),
+        });
+    }
+}
+
+/// Interval of announcing the map played on the server
+const MAP_INTERVAL: std::time::Duration = std::time::Duration::from_secs(1);
+
+/// Periodic announcement of the map, so that players notice when it changes and start loading it.
+async fn map_sender(context: Arc<ServerContext>) {
+    let mut interval = tokio::time::interval(MAP_INTERVAL);
+    loop {
+        interval.tick().await;
+        let info = context.map.lock().await.info.clone();
+        let _ = context.broadcast_tx.send(BroadcastMessage {
+            msg: Message::Map(info),
+            recipients: Recipients::AllExcept(None),
+        });
+    }
+}
+
+/// Seconds of showing match results before the next match starts
+const RESULTS_COUNTDOWN_SECS: u32 = 10;
+
+/// Positions don't need to be smooth for dashboards and overlays
+const OBSERVER_SNAPSHOT_INTERVAL: std::time::Duration = std::time::Duration::from_millis(500);
+
+/// Publish positions and scores of every player to observers. Runs for the whole lifetime of the
+/// server when observing is enabled, so that observers see the server becoming empty too.
+async fn observer_snapshot_sender(context: Arc<ServerContext>) {
+    let mut interval = tokio::time::interval(OBSERVER_SNAPSHOT_INTERVAL);
+    loop {
+        interval.tick().await;
+        if context.observers.receiver_count() == 0 {
+            continue;
+        }
+        let mut players: Vec<ObservedPlayer> = context
+            .lock_players()
+            .await
+            .values()
+            .map(|session| ObservedPlayer {
+                player_id: session.player.id,
+                name: session.name.clone(),
+                x: session.player.pos.x,
+                y: session.player.pos.y,
+                score: session.score as u32,
+            })
+            .collect();
+        players.sort_unstable_by_key(|player| player.player_id);
+        context.observe(ObserverEvent::Snapshot {
+            tick: context.tick.load(Ordering::SeqCst),
+            players,
+        });
+    }
+}
+
+/// Match timer. When a match is over, the results are broadcast every second with the time left
+/// until the next match, so that late joiners and players who lost a message see them too. Scores
+/// are reset and the next map in rotation or the map voted for is loaded when the next match
+/// starts.
+///
+/// The match starts over when the server becomes empty, and matches never end if no duration is
+/// configured.
+async fn match_handler(context: Arc<ServerContext>) {
+    // Vote of a match interrupted by the server becoming empty
+    context.map_vote.lock().await.clear();
+    let mut interval = tokio::time::interval(std::time::Duration::from_secs(1));
+    let mut match_started = std::time::Instant::now();
+    start_replay(&context).await;
+    loop {
+        interval.tick().await;
+        // Paused time doesn't count towards the match
+        if context.paused.load(Ordering::SeqCst) {
+            match_started += interval.period();
+            continue;
+        }
+        // Duration can change on config reload
+        let Some(match_duration) = context.settings.lock().await.match_duration() else {
+            match_started = std::time::Instant::now();
+            continue;
+        };
+        if match_started.elapsed() < match_duration {
+            continue;
+        }
+
+        let standings = match_standings(&*context.lock_players().await);
+        save_replay(&context);
+        context.observe(ObserverEvent::MatchEnd {
+            standings: standings.clone(),
+        });
+        logger::info(
+            "match",
+            match standings.first() {
+                Some(winner) => format!("Match is over, {} won", winner.name),
+                None => "Match is over".to_string(),
+            },
+        );
+        let candidates = start_map_vote(&context).await;
+        for countdown in (1..=RESULTS_COUNTDOWN_SECS).rev() {
+            let _ = context.broadcast_tx.send(BroadcastMessage {
+                msg: Message::MatchEnd(countdown, standings.clone()),
+                recipients: Recipients::AllExcept(None),
+            });
+            if !candidates.is_empty() {
+                let votes = count_map_votes(&*context.lock_players().await, candidates.len());
+                let _ = context.broadcast_tx.send(BroadcastMessage {
+                    msg: Message::MapVote(
+                        candidates
+                            .iter()
+                            .map(|path| map::map_name(path))
+                            .zip(votes)
+                            .collect(),
+                    ),
+                    recipients: Recipients::AllExcept(None),
+                });
+            }
+            interval.tick().await;
+        }
+
+        let next_map = pick_next_map(&context, &candidates).await;
+        context.map_vote.lock().await.clear();
+        for session in context.lock_players().await.values_mut() {
+            session.score = 0.0;
+            session.map_vote = None;
+        }
+        if let Some(path) = next_map {
+            match ServedMap::load(&path) {
+                Ok(map) => change_map(&context, map).await,
+                Err(e) => logger::error(
+                    "match",
+                    format!("Failed to load map from {}: {e}", path.display()),
+                ),
+            }
+        }
+        match_started = std::time::Instant::now();
+        logger::info("match", "New match started".to_string());
+        start_replay(&context).await;
+    }
+}
+
+/// Open the vote for the next map if enabled and announce it to players. Returns the maps to
+/// choose from, empty if there's no vote.
+async fn start_map_vote(context: &ServerContext) -> Vec<PathBuf> {
+    let candidates = {
+        let settings = context.settings.lock().await;
+        if !settings.map_vote {
+            return Vec::new();
+        }
+        settings.map.clone()
+    };
+    let choices: Vec<String> = candidates
+        .iter()
+        .enumerate()
+        .map(|(index, path)| format!("{}. {}", index + 1, map::map_name(path)))
+        .collect();
+    let _ = context.broadcast_tx.send(BroadcastMessage {
+        msg: Message::Notice(format!(
+            "Vote for the next map with /vote <number>: {}",
+            choices.join(", ")
+        )),
+        recipients: Recipients::AllExcept(None),
+    });
+    *context.map_vote.lock().await = candidates.clone();
+    candidates
+}
+
+fn count_map_votes(players: &PlayerMap, candidate_count: usize) -> Vec<u32> {
+    let mut votes = vec![0; candidate_count];
+    for session in players.values() {
+        if let Some(count) = session.map_vote.and_then(|index| votes.get_mut(index)) {
+            *count += 1;
+        }
+    }
+    votes
+}
+
+/// Map of the next match: the one with the most votes, or the next one in rotation if nobody
+/// voted. Ties are broken in rotation order.
+async fn pick_next_map(context: &ServerContext, candidates: &[PathBuf]) -> Option<PathBuf> {
+    let current = context.map.lock().await.path.clone();
+    let next_in_rotation = context
+        .settings
+        .lock()
+        .await
+        .next_map(current.as_deref())
+        .map(Path::to_path_buf);
+    let votes = count_map_votes(&*context.lock_players().await, candidates.len());
+    if votes.iter().all(|count| *count == 0) {
+        return next_in_rotation;
+    }
+    let start = next_in_rotation
+        .and_then(|next| candidates.iter().position(|path| *path == next))
+        .unwrap_or(0);
+    (0..candidates.len())
+        .map(|offset| (start + offset) % candidates.len())
+        .reduce(|best, index| {
+            if votes[index] > votes[best] {
+                index
+            } else {
+                best
+            }
+        })
+        .map(|index| candidates[index].clone())
+}
+
+/// Interval of replicating the leader. Scores change with every step, so the score shown with the
+/// leader lags behind by this much at most.
+const LEADER_INTERVAL: std::time::Duration = std::time::Duration::from_millis(500);
+
+/// Periodic sender of the player with the highest score in the current match, which clients mark
+/// with a crown
+async fn leader_sender(context: Arc<ServerContext>) {
+    let mut interval = tokio::time::interval(LEADER_INTERVAL);
+    let mut leader = None;
+    loop {
+        interval.tick().await;
+        leader = match_leader(&*context.lock_players().await, leader);
+        let _ = context.broadcast_tx.send(BroadcastMessage {
+            msg: Message::Leader(leader),
+            recipients: Recipients::AllExcept(None),
+        });
+    }
+}
+
+/// Player with the highest score and the score, None if nobody has scored. The previous leader
+/// keeps the crown on a tie, so that it doesn't flip back and forth between equal players.
+fn match_leader(players: &PlayerMap, previous: Option<(PlayerID, u32)>) -> Option<(PlayerID, u32)> {
+    let best = players
+        .values()
+        .filter(|session| session.activity != PlayerActivity::Spectating)
+        .map(|session| (session.player.id, session.score as u32))
+        .filter(|(_, score)| *score > 0)
+        .max_by_key(|(player_id, score)| (*score, std::cmp::Reverse(*player_id)))?;
+    let previous = previous.and_then(|(previous_id, _)| {
+        players
+            .values()
+            .find(|session| {
+                session.player.id == previous_id && session.activity != PlayerActivity::Spectating
+            })
+            .map(|session| (previous_id, session.score as u32))
+    });
+    match previous {
+        Some(previous) if previous.1 == best.1 => Some(previous),
+        _ => Some(best),
+    }
+}
+
+/// Whether a spectator fits back into the world, ahead of nobody waiting in the join queue
+async fn has_free_slot(context: &ServerContext, players: &PlayerMap) -> bool {
+    let Some(max_players) = context.settings.lock().await.max_players else {
+        return true;
+    };
+    active_player_count(players) + context.join_queue.lock().unwrap().len() < max_players
+}
+
+/// Mark players who stopped moving as away from keyboard, and move them to spectators after a
+/// longer while or when others wait in the join queue. Only moving brings them back, see
+/// `update_position()`. Time spent paused doesn't count, since nobody can move.
+async fn afk_handler(context: Arc<ServerContext>) {
+    let mut interval = tokio::time::interval(afk::AFK_CHECK_INTERVAL);
+    loop {
+        interval.tick().await;
+        let (afk_timeout, spectate_after, join_queue) = {
+            let settings = context.settings.lock().await;
+            (
+                settings.afk_timeout(),
+                settings.afk_spectate_after(),
+                settings.join_queue,
+            )
+        };
+        let queue_waiting = join_queue && !context.join_queue.lock().unwrap().is_empty();
+        let paused = context.paused.load(Ordering::SeqCst);
+        let now = std::time::Instant::now();
+        for session in context.lock_players().await.values_mut() {
+            if paused {
+                session.last_input = now;
+                continue;
+            }
+            let activity = afk::activity_after(
+                now.duration_since(session.last_input),
+                afk_timeout,
+                spectate_after,
+                queue_waiting,
+            );
+            if activity <= session.activity {
+                continue;
+            }
+            session.activity = activity;
+            if activity == PlayerActivity::Spectating {
+                logger::info("session", format!("{session} was moved to spectators"));
+                context.record_replay(|recorder, tick| recorder.left(tick, session.player.id));
+            } else {
+                logger::info("session", format!("{session} is away from keyboard"));
+            }
+            let _ = context.broadcast_tx.send(BroadcastMessage {
+                msg: Message::Activity(session.player.id, activity),
+                recipients: Recipients::AllExcept(None),
+            });
+        }
+    }
+}
+
+/// Best players first, as many as fit into a datagram
+fn match_standings(players: &PlayerMap) -> Vec<MatchResult> {
+    let mut sessions: Vec<&PlayerSession> = players
+        .values()
+        .filter(|session| session.activity != PlayerActivity::Spectating)
+        .collect();
+    sessions.sort_by(|a, b| b.score.total_cmp(&a.score));
+    let mut standings: Vec<MatchResult> = sessions
+        .into_iter()
+        .take(message::MAX_MATCH_RESULTS)
+        .map(|session| MatchResult {
+            player_id: session.player.id,
+            name: session.name.clone(),
+            score: session.score as u32,
+        })
+        .collect();
+    while Message::MatchEnd(RESULTS_COUNTDOWN_SECS, standings.clone())
+        .serialize()
+        .len()
+        > net::DEFAULT_PACKET_BUDGET - message::HEADER_LEN
+    {
+        standings.pop();
+    }
+    standings
+}
+
+/// Ticks between world state replications while shedding load.
+const OVERLOADED_SNAPSHOT_INTERVAL: u64 = 2;
+
+/// Interval of reminding players that the game is paused
+const PAUSE_NOTICE_INTERVAL: std::time::Duration = std::time::Duration::from_secs(1);
+
+/// Authoritative game update logic simulation.
+///
+/// Requires fixed processing, because timing has to be synchronized accross all connected clients.
+/// A server simulation loop does not need to play "catch-up" like a local game loop does, because
+/// there's no point in sending stale packets.
+///
+/// World state is replicated according to the configured snapshot rate. Ticks exceeding the time
+/// budget are reported. If load shedding is enabled, snapshot rate is further divided by
+/// `OVERLOADED_SNAPSHOT_INTERVAL` while the server is overloaded. While the game is paused, ticks
+/// don't advance and players are only reminded of the pause.
+async fn simulation_handler(context: Arc<ServerContext>) {
+    let desired_frame_duration =
+        std::time::Duration::from_secs_f32(globals::FIXED_UPDATE_TIMESTEP_SEC);
+    // Slow motion spreads the same ticks over more time
+    let mut time_scale = context.time_scale();
+    let mut interval = tokio::time::interval(desired_frame_duration.div_f32(time_scale));
+
+    interval.tick().await; // Skip the first tick (or else there will be bugs)
+
+    let mut ticks_since_snapshot: u64 = 0;
+    let mut was_overloaded = false;
+    let mut pause_notice_sent: Option<std::time::Instant> = None;
+    loop {
+        if context.paused.load(Ordering::SeqCst) {
+            // Remind clients who missed the announcement, like those joining meanwhile
+            if !pause_notice_sent.is_some_and(|sent| sent.elapsed() < PAUSE_NOTICE_INTERVAL) {
+                let _ = context.broadcast_tx.send(BroadcastMessage {
+                    msg: Message::Paused(true),
+                    recipients: Recipients::AllExcept(None),
+                });
+                pause_notice_sent = Some(std::time::Instant::now());
+            }
+            interval.tick().await;
+            continue;
+        }
+        pause_notice_sent = None;
+
+        let current_time = std::time::Instant::now();
+        let tick = context.tick.fetch_add(1, Ordering::SeqCst) + 1;
+        // Snapshot rate can change on config reload
+        let (mut snapshot_interval, distant_snapshot_fraction) = {
+            let settings = context.settings.lock().await;
+            (
+                settings.snapshot_interval(),
+                settings.distant_snapshot_fraction(),
+            )
+        };
+        if context.shed_load && was_overloaded {
+            snapshot_interval *= OVERLOADED_SNAPSHOT_INTERVAL;
+        }
+        ticks_since_snapshot += 1;
+        let replicate = ticks_since_snapshot >= snapshot_interval;
+        if replicate {
+            ticks_since_snapshot = 0;
+        }
+
+        let mut snapshots = Vec::new();
+        {
+            let mut players = context.lock_players().await;
+            // Bounds check
+            for session in players.values_mut() {
+                context
+                    .world_params
+                    .clamp_player_to_bounds(&mut session.player);
+            }
+            // Spectators watch the world without being part of it
+            let in_world =
+                |session: &&PlayerSession| session.activity != PlayerActivity::Spectating;
+            let mut grid = context.grid.lock().await;
+            grid.rebuild(
+                players
+                    .values()
+                    .filter(in_world)
+                    .map(|session| &session.player),
+            );
+
+            // Gameplay state replication
+            if replicate {
+                let replicated = match distant_snapshot_fraction {
+                    Some(distant_snapshot_fraction) => {
+                        replicate_by_distance(&mut players, &grid, distant_snapshot_fraction)
+                    }
+                    None => players
+                        .keys()
+                        .map(|client| Replicated {
+                            client: *client,
+                            players: players
+                                .iter()
+                                .filter(|(other_client, session)| {
+                                    *other_client != client && in_world(session)
+                                })
+                                .map(|(_, session)| session.player)
+                                .collect(),
+                            held: Vec::new(),
+                        })
+                        .collect(),
+                };
+                snapshots = encode_snapshots(&context, tick, &mut players, replicated);
+                context.record_replay(|recorder, tick| {
+                    recorder.snapshot(
+                        tick,
+                        players
+                            .values()
+                            .filter(in_world)
+                            .map(|session| &session.player),
+                    )
+                });
+            }
+        } // Release the lock as soon as possible
+        send_snapshots(&context, snapshots).await;
+
+        let elapsed_time = current_time.elapsed();
+        let overloaded = context.ticks.record_tick(elapsed_time);
+        if overloaded != was_overloaded {
+            if overloaded {
+                logger::warn(
+                    "sim",
+                    format!(
+                        "Server is overloaded, simulation ticks take longer than {} ms",
+                        desired_frame_duration.as_millis()
+                    ),
+                );
+            } else {
+                logger::info("sim", "Server recovered from overload".to_string());
+            }
+            was_overloaded = overloaded;
+        }
+        if context.time_scale() != time_scale {
+            time_scale = context.time_scale();
+            interval = tokio::time::interval(desired_frame_duration.div_f32(time_scale));
+            interval.tick().await;
+        }
+        if elapsed_time < interval.period() {
+            interval.tick().await;
+        }
+    }
+}
+
+/// Players within this distance of a client are on or close to its screen, which is 500 units
+/// from center to corner.
+const NEARBY_DISTANCE: f32 = 600.0;
+
+/// Players replicated to a client in a single snapshot
+struct Replicated {
+    client: SocketAddr,
+    /// Players whose current state is sent
+    players: Vec<Player>,
+    /// Distant players left out of this snapshot, who stay where the client last saw them
+    held: Vec<PlayerID>,
+}
+
+/// Replicate nearby players to a client in every snapshot, but distant players only in the given
+/// fraction of snapshots to save bandwidth on crowded servers.
+///
+/// Each client accumulates priority for every distant player per snapshot, and the player is sent
+/// once the priority reaches 1. Players coming into view are sent right away.
+fn replicate_by_distance(
+    players: &mut PlayerMap,
+    grid: &SpatialGrid,
+    distant_snapshot_fraction: f32,
+) -> Vec<Replicated> {
+    let replicated: HashMap<PlayerID, Player> = players
+        .values()
+        .filter(|session| session.activity != PlayerActivity::Spectating)
+        .map(|session| (session.player.id, session.player))
+        .collect();
+    let mut snapshots = Vec::new();
+    for (client, session) in players.iter_mut() {
+        let mut snapshot = Replicated {
+            client: *client,
+            players: Vec::new(),
+            held: Vec::new(),
+        };
+        let nearby: HashSet<PlayerID> = grid
+            .query_radius(session.player.pos, NEARBY_DISTANCE)
+            .map(|(player_id, _)| player_id)
+            .collect();
+        for (&player_id, player) in &replicated {
+            if player_id == session.player.id {
+                continue;
+            }
+            let send = if nearby.contains(&player_id) {
+                session.distant_priorities.remove(&player_id);
+                true
+            } else {
+                let priority = session.distant_priorities.entry(player_id).or_insert(0.0);
+                *priority += distant_snapshot_fraction;
+                if *priority >= 1.0 {
+                    *priority -= 1.0;
+                    true
+                } else {
+                    false
+                }
+            };
+            if send {
+                snapshot.players.push(*player);
+            } else {
+                snapshot.held.push(player_id);
+            }
+        }
+        snapshots.push(snapshot);
+    }
+    snapshots
+}
+
+/// Snapshot of the players replicated to each client, delta compressed against the latest
+/// snapshot the client acknowledged. Held players keep the state they were last sent with, so
+/// that only their changes go out once they are sent again. Snapshots which might not fit into the
+/// packet budget of the client are sent as a `Replicate` per sent player instead, packed into as
+/// many datagrams as needed. Clients without anything to replicate get no snapshot.
+fn encode_snapshots(
+    context: &ServerContext,
+    tick: Tick,
+    players: &mut PlayerMap,
+    replicated: Vec<Replicated>,
+) -> Vec<(SocketAddr, Vec<Message>)> {
+    let snapshot_overhead = message::HEADER_LEN + Message::Snapshot(Vec::new()).serialize().len();
+    let channels = context.channels.lock().unwrap();
+    let mut snapshots = Vec::new();
+    for Replicated {
+        client,
+        players: sent,
+        held,
+    } in replicated
+    {
+        let (Some(session), Some(channels)) = (players.get_mut(&client), channels.get(&client))
+        else {
+            continue;
+        };
+        if sent.is_empty() {
+            continue;
+        }
+        let world: WorldState = sent
+            .iter()
+            .map(|player| (player.id, EntityState::new(player)))
+            .chain(held.into_iter().filter_map(|player_id| {
+                Some((player_id, session.snapshots.latest_state(player_id)?))
+            }))
+            .collect();
+        let snapshot = if session.snapshots.max_encoded_len(&world) + snapshot_overhead
+            <= channels.packet_budget()
+        {
+            let mut encoded = Vec::new();
+            session.snapshots.encode(tick, &world, &mut encoded);
+            vec![Message::Snapshot(encoded)]
+        } else {
+            sent.into_iter()
+                .map(|player| Message::Replicate(tick, player))
+                .collect()
+        };
+        snapshots.push((client, snapshot));
+    }
+    snapshots
+}
+
+/// Send the snapshot of each client in as few datagrams as its packet budget allows
+async fn send_snapshots(context: &ServerContext, snapshots: Vec<(SocketAddr, Vec<Message>)>) {
+    let now = std::time::Instant::now();
+    for (client, snapshot) in snapshots {
+        let Some(first_msg) = snapshot.first() else {
+            continue;
+        };
+        let serialized_msgs: Vec<Vec<u8>> = snapshot.iter().map(Message::serialize).collect();
+        let Some(datagrams) = context
+            .channels
+            .lock()
+            .unwrap()
+            .get_mut(&client)
+            .map(|channels| channels.send_batch(first_msg.channel(), &serialized_msgs, now))
+        else {
+            continue;
+        };
+        message::trace(format!(
+            "Sent snapshot to {client} in {} datagrams: {snapshot:?}",
+            datagrams.len()
+        ));
+        for datagram in datagrams {
+            match context.server_socket.send_to(&datagram, client).await {
+                Ok(_) => context
+                    .traffic
+                    .record_sent(first_msg.name(), datagram.len()),
+                Err(e) => logger::error("net", format!("Failed to send snapshot: {:?}", e)),
+            }
+        }
+    }
+}
+
+/// Messages made available by a single datagram, which have to be processed in order
+async fn process_client_messages(
+    context: Arc<ServerContext>,
+    client: SocketAddr,
+    messages: Vec<Vec<u8>>,
+) {
+    for msg in messages {
+        process_client_message(context.clone(), client, msg).await;
+    }
+}
+
+async fn process_client_message(context: Arc<ServerContext>, client: SocketAddr, msg: Vec<u8>) {
+    let deserialized_msg = Message::deserialize(&msg);
+    message::trace(format!("Received: {deserialized_msg:?}"));
+    let message_type = match &deserialized_msg {
+        Ok(deserialized_msg) => deserialized_msg.name(),
+        Err(_) => stats::INVALID_MESSAGE_TYPE,
+    };
+    context
+        .traffic
+        .record_received(message_type, message::HEADER_LEN + msg.len());
+    if deserialized_msg.is_ok() {
+        context.usage.record_message();
+    }
+
+    let result = match deserialized_msg {
+        Ok(Message::Handshake(player_name, previous_token)) => {
+            accept_client(context, client, player_name, previous_token).await
+        }
+        Ok(Message::Position(token, pos)) => update_position(context, client, token, pos).await,
+        Ok(Message::Disconnect(token)) => disconnect_client(context, client, token).await,
+        Ok(Message::Query) => send_info(context, client).await,
+        Ok(Message::Say(token, text)) => relay_chat(context, client, token, text).await,
+        Ok(Message::UpdateProfile(token, color, name)) => {
+            update_profile(context, client, token, color, name).await
+        }
+        Ok(Message::Pong(token, time)) => record_pong(context, client, token, time).await,
+        Ok(Message::SnapshotAck(token, tick)) => {
+            acknowledge_snapshot(context, client, token, tick).await
+        }
+        Ok(Message::ProbeAck(token, size)) => record_probe_ack(context, client, token, size).await,
+        Ok(Message::MapRequest(token, checksum, offset)) => {
+            send_map_chunk(context, client, token, checksum, offset).await
+        }
+        // Garbage could be sent in the name of a player too, so only strangers are backed off
+        Err(_) => {
+            if !context.lock_players().await.contains_key(&client) {
+                context.report_offence(client);
+            }
+            Ok(())
+        }
+        _ => Ok(()),
+    };
+    if let Err(e) = result {
+        logger::error(
+            "net",
+            format!("Failed to process {message_type} from {client}: {e}"),
+        );
+    }
+}
+
+/// Respond to map download requests of players. Requests for another map are left unanswered,
+/// the player will learn about the current map from the next announcement.
+async fn send_map_chunk(
+    context: Arc<ServerContext>,
+    client: SocketAddr,
+    token: SessionToken,
+    checksum: u32,
+    offset: u32,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    if authenticate(&context, &mut *context.lock_players().await, &client, token).is_none() {
+        return Ok(());
+    }
+    let chunk_msg = {
+        let map = context.map.lock().await;
+        match map.chunk(offset) {
+            Some(data) if map.info.checksum == checksum => {
+                Message::MapChunk(checksum, offset, data.to_vec())
+            }
+            _ => return Ok(()),
+        }
+    };
+    context.send_to(&chunk_msg, client).await?;
+
+    Ok(())
+}
+
+/// Respond to server info query. Querying does not require joining the game.
+async fn send_info(
+    context: Arc<ServerContext>,
+    client: SocketAddr,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let player_count = context.lock_players().await.len();
+    let info_msg = Message::Info(
+        context.server_name.clone(),
+        context.usage.summary(player_count),
+    );
+    context.send_to(&info_msg, client).await?;
+
+    Ok(())
+}
+
+/// Recieve first time joining client handshake, register as new player and send ACK response
+/// with new player info.
+///
+/// Each new player receives a randomly generated color and the player ID counter is incremented
+/// after each new join. Players without a requested name are called by their player number.
+///
+/// Clients reconnecting from another address take their player over, see
+/// `find_previous_session()`.
+async fn accept_client(
+    context: Arc<ServerContext>,
+    client: SocketAddr,
+    player_name: String,
+    previous_token: Option<SessionToken>,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let mut players = context.lock_players().await;
+
+    let ack_msg: Message;
+    let mut rename_notice = None;
+    let repeated_handshake = players.contains_key(&client);
+    if let Some(PlayerSession {
+        player: existing_player,
+        token,
+        ..
+    }) = players.get(&client)
+    {
+        // Getting multiple handshakes from and sending out multiple ACK for the same
+        // client is not a problem, that just means that previous ACK was dropped, so the
+        // client retried the HANDSHAKE. Server just resends ACK with same player info that
+        // was already registered as response to new HANDSHAKE. It is made sure here not to
+        // accidentally add the same player multiple times, because that would lead to
+        // "Player 3 joined, Player
+        // 4 joined, Player 5 joined" bug for each accepted HANDSHAKE from the same client.
+        ack_msg = Message::Ack(
+            existing_player.id,
+            *token,
+            existing_player.color,
+            context.seed,
+            context.server_name.clone(),
+            context.world_params,
+        );
+    } else {
+        if context.shutting_down.load(Ordering::SeqCst) {
+            return reject_client(&context, client, "server is shutting down").await;
+        }
+        if let Some(ban) = context.banlist.lock().await.find_ban(client.ip()) {
+            logger::info(
+                "session",
+                format!("Rejected join request from {client}: banned"),
+            );
+            let reason = ban_reason(&ban.reason);
+            return reject_client(&context, client, &reason).await;
+        }
+        if let Some(whitelist) = &context.whitelist {
+            let mut whitelist = whitelist.lock().await;
+            whitelist.reload_if_changed();
+            if !whitelist.is_allowed(&player_name, client.ip()) {
+                logger::info(
+                    "session",
+                    format!("Rejected join request from {client}: not whitelisted"),
+                );
+                return reject_client(&context, client, "not whitelisted").await;
+            }
+        }
+        let previous_session =
+            find_previous_session(&players, client, &player_name, previous_token).and_then(
+                |previous_client| {
+                    players
+                        .remove(&previous_client)
+                        .map(|session| (previous_client, session))
+                },
+            );
+        if let Some((previous_client, mut session)) = previous_session {
+            // Take the player over instead of leaving a ghost behind on the old address
+            logger::info(
+                "session",
+                format!("{session} reconnected from {client}, previously {previous_client}"),
+            );
+            session.last_heard = std::time::Instant::now();
+            // Client on the new address starts without any baseline
+            session.snapshots = SnapshotEncoder::default();
+            ack_msg = Message::Ack(
+                session.player.id,
+                session.token,
+                session.player.color,
+                context.seed,
+                context.server_name.clone(),
+                context.world_params,
+            );
+            context.channels.lock().unwrap().remove(&previous_client);
+            players.insert(client, session);
+        } else {
+            let player_name = {
+                let settings = context.settings.lock().await;
+                if let Some(max_players) = settings.max_players {
+                    let free_slots = max_players.saturating_sub(active_player_count(&players));
+                    let admission = if settings.join_queue {
+                        context.join_queue.lock().unwrap().request(
+                            client,
+                            free_slots,
+                            std::time::Instant::now(),
+                        )
+                    } else if free_slots > 0 {
+                        Admission::Admit
+                    } else {
+                        Admission::Refuse
+                    };
+                    match admission {
+                        Admission::Admit => (),
+                        Admission::Queued(position) => {
+                            message::trace(format!(
+                                "Join request from {client} is queued at {position}"
+                            ));
+                            context.send_to(&Message::Queued(position), client).await?;
+                            return Ok(());
+                        }
+                        Admission::Refuse => {
+                            logger::info(
+                                "session",
+                                format!("Rejected join request from {client}: server is full"),
+                            );
+                            return reject_client(&context, client, "server is full").await;
+                        }
+                    }
+                }
+                filter::apply(&player_name, &settings.blocked_words, settings.word_filter)
+            };
+            let Some(player_name) = player_name else {
+                logger::info(
+                    "session",
+                    format!("Rejected join request from {client}: name contains blocked words"),
+                );
+                return reject_client(&context, client, "name is not allowed").await;
+            };
+
+            // Add new player to server
+            let new_player = Player::new(
+                context.player_id_counter.fetch_add(1, Ordering::SeqCst),
+                generate_color(),
+            );
+            let requested_name = if player_name.is_empty() {
+                default_player_name(new_player.id)
+            } else {
+                player_name
+            };
+            let name = disambiguate_name(&requested_name, new_player.id, &players);
+            if name != requested_name {
+                rename_notice = Some(format!(
+                    "Name {requested_name} is already taken, you are known as {name}."
+                ));
+            }
+            let session = PlayerSession {
+                player: new_player,
+                name,
+                token: rand::random(),
+                last_heard: std::time::Instant::now(),
+                distant_priorities: HashMap::new(),
+                connection: ConnectionQuality::default(),
+                last_chat: None,
+                last_profile_update: None,
+                score: 0.0,
+                map_vote: None,
+                history: PositionHistory::default(),
+                health: storm::MAX_HEALTH as f32,
+                last_input: std::time::Instant::now(),
+                activity: PlayerActivity::Active,
+                snapshots: SnapshotEncoder::default(),
+            };
+            logger::info(
+                "session",
+                format!("{session} joined the server from {client}"),
+            );
+            context.observe(ObserverEvent::Joined {
+                player_id: new_player.id,
+                name: session.name.clone(),
+            });
+            context.record_replay(|recorder, tick| {
+                recorder.joined(tick, &session.player, &session.name)
+            });
+            let _ = context.broadcast_tx.send(BroadcastMessage {
+                msg: Message::Event(GameEvent::Joined(new_player.id, session.name.clone())),
+                recipients: Recipients::AllExcept(Some(client)),
+            });
+            let _ = context.broadcast_tx.send(BroadcastMessage {
+                msg: Message::Profile(new_player.id, new_player.color, session.name.clone()),
+                recipients: Recipients::AllExcept(Some(client)),
+            });
+            ack_msg = Message::Ack(
+                new_player.id,
+                session.token,
+                new_player.color,
+                context.seed,
+                context.server_name.clone(),
+                context.world_params,
+            );
+            players.insert(client, session);
+            context.usage.record_join(players.len());
+
+            // Game startup: start sending out PING messages (to everyone) and start the game
+            // simulation itself when the first player has connected to an empty server
+            if players.len() == 1 {
+                start_game_tasks(&context).await;
+            }
+        }
+    }
+
+    // Sequence numbers start over with every new session, since the client only sets up its
+    // channels after the ACK arrives. A repeated or duplicated handshake keeps them, the client
+    // may already be counting.
+    {
+        let mut channels = context.channels.lock().unwrap();
+        if repeated_handshake {
+            channels.entry(client).or_default();
+        } else {
+            channels.insert(client, Channels::new());
+        }
+    }
+
+    // Send ACK
+    context.send_to(&ack_msg, client).await?;
+
+    if let Some(rename_notice) = rename_notice {
+        context
+            .send_to(&Message::Notice(rename_notice), client)
+            .await?;
+    }
+
+    // Names of everyone present including the player itself, since the ACK only carries the color
+    let profiles: Vec<Message> = players
+        .values()
+        .map(|session| {
+            Message::Profile(
+                session.player.id,
+                session.player.color,
+                session.name.clone(),
+            )
+        })
+        .collect();
+    for profile in &profiles {
+        context.send_to(profile, client).await?;
+    }
+    // Everyone else is active, like joining players
+    let activities: Vec<Message> = players
+        .values()
+        .filter(|session| session.activity != PlayerActivity::Active)
+        .map(|session| Message::Activity(session.player.id, session.activity))
+        .collect();
+    for activity in &activities {
+        context.send_to(activity, client).await?;
+    }
+
+    // Also resent with each ACK, it was probably lost together with the previous one
+    if let Some(motd) = context.settings.lock().await.motd.clone() {
+        context.send_to(&Message::Notice(motd), client).await?;
+    }
+    // Start loading the map right away instead of waiting for the next announcement
+    let map_info = context.map.lock().await.info.clone();
+    context.send_to(&Message::Map(map_info), client).await?;
+
+    // Channels start over with the default packet budget
+    context.spawn(
+        "discover_path_mtu",
+        discover_path_mtu(context.clone(), client),
+    );
+
+    Ok(())
+}
+
+/// How long to wait for the answer to an MTU probe
+const PROBE_TIMEOUT: std::time::Duration = std::time::Duration::from_millis(250);
+/// Probes of each size sent before concluding that datagrams of that size don't get through,
+/// since a lost probe looks the same
+const PROBE_ATTEMPTS: u32 = 2;
+
+/// Find the largest datagram reaching the client by binary search with padded probes between the
+/// default packet budget and the largest datagram accepted, then pack snapshots of the client up
+/// to that size. Routers drop datagrams larger than the path MTU without a word, so sizes in
+/// between are never used. Only one search runs per client at a time.
+async fn discover_path_mtu(context: Arc<ServerContext>, client: SocketAddr) {
+    let (probe_tx, mut probe_rx) = mpsc::unbounded_channel();
+    {
+        let mut mtu_probes = context.mtu_probes.lock().unwrap();
+        if mtu_probes.contains_key(&client) {
+            return;
+        }
+        mtu_probes.insert(client, probe_tx);
+    }
+    let mut largest_arrived = net::DEFAULT_PACKET_BUDGET;
+    let mut smallest_lost = globals::MAX_DATAGRAM_SIZE + 1;
+    while smallest_lost - largest_arrived > 1 {
+        let size = (largest_arrived + smallest_lost) / 2;
+        if probe(&context, client, size as u16, &mut probe_rx).await {
+            largest_arrived = size;
+        } else {
+            smallest_lost = size;
+        }
+    }
+    context.mtu_probes.lock().unwrap().remove(&client);
+
+    if let Some(channels) = context.channels.lock().unwrap().get_mut(&client) {
+        channels.set_packet_budget(largest_arrived);
+        logger::info(
+            "net",
+            format!("Packet budget of {client} is {largest_arrived} bytes"),
+        );
+    }
+}
+
+/// Whether a datagram of the given size reaches the client and gets answered
+async fn probe(
+    context: &ServerContext,
+    client: SocketAddr,
+    size: u16,
+    probe_rx: &mut mpsc::UnboundedReceiver<u16>,
+) -> bool {
+    for _ in 0..PROBE_ATTEMPTS {
+        if context
+            .send_to(&Message::Probe(size), client)
+            .await
+            .is_err()
+        {
+            return false;
+        }
+        let deadline = tokio::time::Instant::now() + PROBE_TIMEOUT;
+        // Late answers to earlier probes are skipped
+        while let Ok(Some(answered_size)) = tokio::time::timeout_at(deadline, probe_rx.recv()).await
+        {
+            if answered_size == size {
+                return true;
+            }
+        }
+    }
+    false
+}
+
+/// Players who went silent for this long can be taken over by name from the same IP address
+const TAKEOVER_SILENCE: std::time::Duration = std::time::Duration::from_secs(2);
+
+/// Address of the session a joining client continues: the one with the token of the client's
+/// previous session, or the one with the same name joined from the same IP address which
+/// stopped responding, like a crashed client restarted on a new port.
+fn find_previous_session(
+    players: &PlayerMap,
+    client: SocketAddr,
+    player_name: &str,
+    previous_token: Option<SessionToken>,
+) -> Option<SocketAddr> {
+    players
+        .iter()
+        .find(|(_, session)| Some(session.token) == previous_token)
+        .or_else(|| {
+            players.iter().find(|(previous_client, session)| {
+                !player_name.is_empty()
+                    && previous_client.ip() == client.ip()
+                    && session.name == player_name
+                    && session.last_heard.elapsed() >= TAKEOVER_SILENCE
+            })
+        })
+        .map(|(previous_client, _)| *previous_client)
+}
+
+/// Players map must be locked by the caller, so that starting and stopping can't interleave.
+async fn start_game_tasks(context: &Arc<ServerContext>) {
+    let mut game_tasks = context.game_tasks.lock().await;
+    // Leftovers are not expected, but never let duplicates run side by side
+    for game_task in game_tasks.drain(..) {
+        game_task.abort();
+    }
+    game_tasks.push(context.spawn("ping_sender", ping_sender(context.clone())));
+    game_tasks.push(context.spawn("stats_sender", stats_sender(context.clone())));
+    game_tasks.push(context.spawn("match_handler", match_handler(context.clone())));
+    game_tasks.push(context.spawn("time_of_day_sender", time_of_day_sender(context.clone())));
+    game_tasks.push(context.spawn("storm_handler", storm_handler(context.clone())));
+    game_tasks.push(context.spawn("leader_sender", leader_sender(context.clone())));
+    game_tasks.push(context.spawn("afk_handler", afk_handler(context.clone())));
+    game_tasks.push(context.spawn("map_sender", map_sender(context.clone())));
+    game_tasks.push(context.spawn("simulation_handler", simulation_handler(context.clone())));
+}
+
+/// Nobody needs pings and world updates on an empty server. Players map must be locked by the
+/// caller. The match is over too, so its replay is saved.
+async fn stop_game_tasks(context: &ServerContext) {
+    for game_task in context.game_tasks.lock().await.drain(..) {
+        game_task.abort();
+    }
+    save_replay(context);
+}
+
+/// Start recording a match with the players already on the server, if replays are enabled. A
+/// match still being recorded is saved first.
+async fn start_replay(context: &ServerContext) {
+    if context.replay_dir.is_none() {
+        return;
+    }
+    save_replay(context);
+    // Players joining meanwhile are recorded by the join handler once the recorder is in place
+    let players = context.lock_players().await;
+    let map = context.map.lock().await;
+    let tick = context.tick.load(Ordering::SeqCst);
+    let mut recorder = ReplayRecorder::new(context.seed, map.info.name.clone(), map.map.clone());
+    let mut sessions: Vec<&PlayerSession> = players.values().collect();
+    sessions.sort_by_key(|session| session.player.id);
+    for session in sessions {
+        recorder.joined(tick, &session.player, &session.name);
+    }
+    *context.replay.lock().unwrap() = Some(recorder);
+}
+
+/// Finish the replay of the match being recorded and write it into the replay directory as
+/// `match-<unix time>-<first tick>.replay`.
+fn save_replay(context: &ServerContext) {
+    let Some(recorder) = context.replay.lock().unwrap().take() else {
+        return;
+    };
+    let Some(replay_dir) = &context.replay_dir else {
+        return;
+    };
+    let replay = recorder.finish(context.tick.load(Ordering::SeqCst));
+    let first_tick = replay.entries.first().map_or(0, |entry| entry.tick);
+    let path = replay_dir.join(format!(
+        "match-{}-{first_tick}.replay",
+        banlist::unix_time_now()
+    ));
+    match std::fs::create_dir_all(replay_dir).and_then(|()| replay.save(&path)) {
+        Ok(()) => logger::info(
+            "replay",
+            format!("Match replay saved to {}", path.display()),
+        ),
+        Err(e) => logger::error(
+            "replay",
+            format!("Failed to save match replay to {}: {e}", path.display()),
+        ),
+    }
+}
+
+/// Refuse a join request. Reason is displayed to the player. Retries are ignored for a while.
+async fn reject_client(
+    context: &ServerContext,
+    client: SocketAddr,
+    reason: &str,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    context.report_offence(client);
+    let reject_msg = Message::Reject(reason.to_string());
+    context.send_to(&reject_msg, client).await?;
+
+    Ok(())
+}
+
+/// Moves longer than this many logic updates of movement between two position updates don't
+/// count into the score, so teleporting doesn't win matches. Leaves room for a few lost updates.
+const MAX_SCORED_STEP_UPDATES: f32 = 4.0;
+
+/// Take over the position reported by the player. Moving brings players who are away from
+/// keyboard or spectating back, see `afk_handler()`.
+async fn update_position(
+    context: Arc<ServerContext>,
+    client: SocketAddr,
+    token: SessionToken,
+    new_pos: Vector2<f32>,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let mut players = context.lock_players().await;
+    let Some(session) = authenticate(&context, &mut players, &client, token) else {
+        return Ok(());
+    };
+    // Players are frozen in place while paused
+    if context.paused.load(Ordering::SeqCst) {
+        return Ok(());
+    }
+    let moved = (new_pos - session.player.pos).magnitude() >= afk::MIN_INPUT_DISTANCE;
+    if moved {
+        session.last_input = std::time::Instant::now();
+    }
+    let activity = session.activity;
+    if moved && activity != PlayerActivity::Active {
+        // Spectators can't take the slot of someone waiting in the join queue
+        if activity == PlayerActivity::Spectating && !has_free_slot(&context, &players).await {
+            return Ok(());
+        }
+        let Some(session) = players.get_mut(&client) else {
+            return Ok(());
+        };
+        session.activity = PlayerActivity::Active;
+        logger::info("session", format!("{session} is back"));
+        if activity == PlayerActivity::Spectating {
+            context.record_replay(|recorder, tick| {
+                recorder.joined(tick, &session.player, &session.name)
+            });
+        }
+        context.broadcast_tx.send(BroadcastMessage {
+            msg: Message::Activity(session.player.id, PlayerActivity::Active),
+            recipients: Recipients::AllExcept(None),
+        })?;
+    }
+
+    // Positions of spectators are ignored until they get back into the world
+    if let Some(PlayerSession {
+        player,
+        connection,
+        score,
+        history,
+        activity: PlayerActivity::Active | PlayerActivity::Afk,
+        ..
+    }) = players.get_mut(&client)
+    {
+        let previous_pos = player.pos;
+        player.pos.x = new_pos.x;
+        player.pos.y = new_pos.y;
+        // Don't replicate out of bounds positions until the next simulation tick
+        context.world_params.clamp_player_to_bounds(player);
+        let step = (player.pos - previous_pos).magnitude();
+        if step <= context.world_params.player_speed * MAX_SCORED_STEP_UPDATES {
+            *score += step;
+        }
+        // Sub-pixel differences are just quantization error
+        if (player.pos - new_pos).magnitude() >= 1.0 {
+            connection.record_correction();
+        }
+        history.record(PositionSample {
+            at: std::time::Instant::now(),
+            tick: context.tick.load(Ordering::SeqCst),
+            pos: player.pos,
+        });
+    }
+
+    Ok(())
+}
+
+/// Round-trip time is measured from the time sent in the ping. Replies from unknown clients and
+/// times not sent by the server yet are ignored.
+async fn record_pong(
+    context: Arc<ServerContext>,
+    client: SocketAddr,
+    token: SessionToken,
+    time: u64,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let sent = std::time::Duration::from_millis(time);
+    let Some(rtt) = context.started.elapsed().checked_sub(sent) else {
+        return Ok(());
+    };
+    if let Some(session) =
+        authenticate(&context, &mut *context.lock_players().await, &client, token)
+    {
+        session.connection.record_pong(rtt);
+    }
+
+    Ok(())
+}
+
+/// Pass the answer to an MTU probe to the task probing the client
+async fn record_probe_ack(
+    context: Arc<ServerContext>,
+    client: SocketAddr,
+    token: SessionToken,
+    size: u16,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    if authenticate(&context, &mut *context.lock_players().await, &client, token).is_some() {
+        if let Some(probe_tx) = context.mtu_probes.lock().unwrap().get(&client) {
+            let _ = probe_tx.send(size);
+        }
+    }
+
+    Ok(())
+}
+
+async fn acknowledge_snapshot(
+    context: Arc<ServerContext>,
+    client: SocketAddr,
+    token: SessionToken,
+    tick: Tick,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    if let Some(session) =
+        authenticate(&context, &mut *context.lock_players().await, &client, token)
+    {
+        session.snapshots.acknowledge(tick);
+    }
+
+    Ok(())
+}
+
+async fn disconnect_client(
+    context: Arc<ServerContext>,
+    client: SocketAddr,
+    token: SessionToken,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    if authenticate(&context, &mut *context.lock_players().await, &client, token).is_none() {
+        return Ok(());
+    }
+    drop_player(context, client).await
+}
+
+async fn drop_player(
+    context: Arc<ServerContext>,
+    client: SocketAddr,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let mut players = context.lock_players().await;
+    let Some(session) = players.remove(&client) else {
+        return Ok(());
+    };
+    context.channels.lock().unwrap().remove(&client);
+    logger::info("session", format!("{session} left the server"));
+    for other_session in players.values_mut() {
+        other_session.distant_priorities.remove(&session.player.id);
+    }
+    context.record_replay(|recorder, tick| recorder.left(tick, session.player.id));
+    if players.is_empty() {
+        stop_game_tasks(&context).await;
+    }
+
+    context.observe(ObserverEvent::Left {
+        player_id: session.player.id,
+        name: session.name.clone(),
+    });
+    context.broadcast_tx.send(BroadcastMessage {
+        msg: Message::Event(GameEvent::Left(session.player.id, session.name)),
+        recipients: Recipients::AllExcept(Some(client)),
+    })?;
+    context.broadcast_tx.send(BroadcastMessage {
+        msg: Message::Leave(session.player.id),
+        recipients: Recipients::AllExcept(Some(client)),
+    })?;
+
+    Ok(())
+}
+
+/// Forward chat message to everyone, including the sender as a delivery confirmation. Messages
+/// of muted players are dropped. Messages sent faster than the chat cooldown or containing blocked
+/// words in reject mode are dropped too, with a notice to the sender.
+async fn relay_chat(
+    context: Arc<ServerContext>,
+    client: SocketAddr,
+    token: SessionToken,
+    text: String,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let (player_id, name, filtered_text) = {
+        let mut players = context.lock_players().await;
+        let Some(session) = authenticate(&context, &mut players, &client, token) else {
+            return Ok(());
+        };
+        let player_id = session.player.id;
+        let name = session.name.clone();
+        if let Some(choice) = text.strip_prefix(VOTE_COMMAND) {
+            let reply = vote_for_map(session, &context.map_vote.lock().await, choice);
+            drop(players);
+            context.send_to(&Message::Notice(reply), client).await?;
+            return Ok(());
+        }
+        if context.banlist.lock().await.is_muted(client.ip()) {
+            message::trace(format!("Dropped chat message of muted {client}"));
+            return Ok(());
+        }
+
+        let settings = context.settings.lock().await;
+        let now = std::time::Instant::now();
+        let flooding = settings.chat_cooldown().is_some_and(|cooldown| {
+            session
+                .last_chat
+                .is_some_and(|last_chat| now.duration_since(last_chat) < cooldown)
+        });
+        let filtered_text = if flooding {
+            Err("You are sending messages too fast.")
+        } else {
+            session.last_chat = Some(now);
+            filter::apply(&text, &settings.blocked_words, settings.word_filter)
+                .ok_or("Your message contains blocked words and was not sent.")
+        };
+        (player_id, name, filtered_text)
+    };
+
+    match filtered_text {
+        Ok(text) => {
+            context.observe(ObserverEvent::Chat {
+                player_id,
+                name,
+                text: text.clone(),
+            });
+            context.broadcast_tx.send(BroadcastMessage {
+                msg: Message::Chat(player_id, text),
+                recipients: Recipients::AllExcept(None),
+            })?;
+        }
+        Err(reason) => {
+            message::trace(format!("Dropped chat message of {client}: {reason}"));
+            context
+                .send_to(&Message::Notice(reason.to_string()), client)
+                .await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Shortest time between name or color changes of a player, so that others can keep track of who
+/// is who
+const PROFILE_COOLDOWN: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// Change the name and color of a player and announce them to everyone, including the player as
+/// a confirmation. Names are filtered and disambiguated like when joining. Changes sent faster
+/// than the profile cooldown or with a name refused by the word filter are dropped, with a notice
+/// to the sender.
+async fn update_profile(
+    context: Arc<ServerContext>,
+    client: SocketAddr,
+    token: SessionToken,
+    color: Vector3<f32>,
+    requested_name: String,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let (player_id, name, rename_notice) = {
+        let mut players = context.lock_players().await;
+        let Some(session) = authenticate(&context, &mut players, &client, token) else {
+            return Ok(());
+        };
+        let player_id = session.player.id;
+        let now = std::time::Instant::now();
+        let filtered_name = if session
+            .last_profile_update
+            .is_some_and(|last_update| now.duration_since(last_update) < PROFILE_COOLDOWN)
+        {
+            Err("You are changing your profile too fast.")
+        } else {
+            let settings = context.settings.lock().await;
+            filter::apply(
+                &requested_name,
+                &settings.blocked_words,
+                settings.word_filter,
+            )
+            .ok_or("Your name contains blocked words and was not changed.")
+        };
+        let filtered_name = match filtered_name {
+            Ok(filtered_name) => filtered_name,
+            Err(reason) => {
+                drop(players);
+                message::trace(format!("Dropped profile update of {client}: {reason}"));
+                context
+                    .send_to(&Message::Notice(reason.to_string()), client)
+                    .await?;
+                return Ok(());
+            }
+        };
+
+        let requested_name = if filtered_name.is_empty() {
+            default_player_name(player_id)
+        } else {
+            filtered_name
+        };
+        let name = disambiguate_name(&requested_name, player_id, &players);
+        let rename_notice = (name != requested_name)
+            .then(|| format!("Name {requested_name} is already taken, you are known as {name}."));
+        let Some(session) = players.get_mut(&client) else {
+            return Ok(());
+        };
+        if session.name != name {
+            logger::info("session", format!("{session} is now known as {name}"));
+        }
+        session.last_profile_update = Some(now);
+        session.name = name.clone();
+        session.player.color = color;
+        (player_id, name, rename_notice)
+    };
+
+    context.broadcast_tx.send(BroadcastMessage {
+        msg: Message::Profile(player_id, color, name),
+        recipients: Recipients::AllExcept(None),
+    })?;
+    if let Some(rename_notice) = rename_notice {
+        context
+            .send_to(&Message::Notice(rename_notice), client)
+            .await?;
+    }
+
+    Ok(())
+}
+
+/// Chat command for voting in map votes, followed by the number of the map
+const VOTE_COMMAND: &str = "/vote";
+
+/// Record the vote of a player. Returns the reply to the player.
+fn vote_for_map(session: &mut PlayerSession, candidates: &[PathBuf], choice: &str) -> String {
+    if candidates.is_empty() {
+        return "There's no map vote running.".to_string();
+    }
+    match choice.trim().parse::<usize>() {
+        Ok(number) if (1..=candidates.len()).contains(&number) => {
+            session.map_vote = Some(number - 1);
+            format!("You voted for {}.", map::map_name(&candidates[number - 1]))
+        }
+        _ => format!("Vote with a number between 1 and {}.", candidates.len()),
+    }
+}
+
+/// Line-based command interpreter on standard input for operators of headless servers.
+async fn admin_console(context: Arc<ServerContext>) {
+    let mut lines = BufReader::new(tokio::io::stdin()).lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        if line.trim().is_empty() {
+            continue;
+        }
+        println!("{}", run_admin_command(&context, &line).await);
+    }
+}
+
+/// Parse and execute a line of the admin console, returning the response for the operator. Also
+/// used by the admin dashboard, so that both are audited the same way.
+async fn run_admin_command(context: &Arc<ServerContext>, line: &str) -> String {
+    // Responses are meant for the operator, only the command itself is logged for auditing
+    match line.parse::<AdminCommand>() {
+        Ok(command) => {
+            logger::info("admin", format!("Executing \"{}\"", line.trim()));
+            execute_admin_command(context, command).await
+        }
+        Err(e) => e,
+    }
+}
+
+async fn execute_admin_command(context: &Arc<ServerContext>, command: AdminCommand) -> String {
+    match command {
+        AdminCommand::Ban {
+            target,
+            duration,
+            reason,
+        } => {
+            let Some(address) = resolve_admin_target(context, &target).await else {
+                return "No such player".to_string();
+            };
+            context
+                .banlist
+                .lock()
+                .await
+                .ban(address, reason.clone(), duration);
+
+            // Kick every player connected from the banned address
+            let banned_clients: Vec<SocketAddr> = context
+                .lock_players()
+                .await
+                .keys()
+                .filter(|client| client.ip() == address)
+                .copied()
+                .collect();
+            for client in banned_clients {
+                let _ = reject_client(context, client, &ban_reason(&reason)).await;
+                let _ = drop_player(context.clone(), client).await;
+            }
+
+            format!("Banned {address}")
+        }
+        AdminCommand::Unban(address) => {
+            if context.banlist.lock().await.unban(address) {
+                format!("Unbanned {address}")
+            } else {
+                format!("{address} is not banned")
+            }
+        }
+        AdminCommand::Mute {
+            target,
+            duration,
+            reason,
+        } => {
+            let Some(address) = resolve_admin_target(context, &target).await else {
+                return "No such player".to_string();
+            };
+            context.banlist.lock().await.mute(address, reason, duration);
+            format!("Muted {address}")
+        }
+        AdminCommand::Unmute(address) => {
+            if context.banlist.lock().await.unmute(address) {
+                format!("Unmuted {address}")
+            } else {
+                format!("{address} is not muted")
+            }
+        }
+        AdminCommand::Kick { player_id, reason } => {
+            let client = context
+                .lock_players()
+                .await
+                .iter()
+                .find(|(_, session)| session.player.id == player_id)
+                .map(|(client, _)| *client);
+            let Some(client) = client else {
+                return "No such player".to_string();
+            };
+            let _ = reject_client(context, client, &kick_reason(&reason)).await;
+            let _ = drop_player(context.clone(), client).await;
+            format!("Kicked Player {player_id}")
+        }
+        AdminCommand::Motd(motd) => {
+            if let Some(motd) = &motd {
+                if let Err(e) = message::validate_chat_message(motd) {
+                    return format!("Invalid motd: {e}");
+                }
+            }
+            let cleared = motd.is_none();
+            context.settings.lock().await.motd = motd;
+            if cleared {
+                "Message of the day cleared".to_string()
+            } else {
+                "Message of the day changed".to_string()
+            }
+        }
+        AdminCommand::Reload => match reload_settings(context).await {
+            Ok(_) => "Configuration reloaded".to_string(),
+            Err(e) => e.to_string(),
+        },
+        AdminCommand::Teleport(player_id, pos) => {
+            match move_player(context, player_id, pos).await {
+                Some(pos) => format!("Teleported Player {player_id} to {}, {}", pos.x, pos.y),
+                None => "No such player".to_string(),
+            }
+        }
+        AdminCommand::Respawn(player_id) => {
+            // Map without spawn points lets players start anywhere, which is the origin on join
+            let spawn_point = context
+                .map
+                .lock()
+                .await
+                .map
+                .spawn_point(player_id)
+                .unwrap_or(Vector2::new(0.0, 0.0));
+            match move_player(context, player_id, spawn_point).await {
+                Some(_) => format!("Respawned Player {player_id}"),
+                None => "No such player".to_string(),
+            }
+        }
+        AdminCommand::Pause | AdminCommand::Resume => {
+            let pause = matches!(command, AdminCommand::Pause);
+            if context.paused.swap(pause, Ordering::SeqCst) == pause {
+                return format!(
+                    "Game is already {}",
+                    if pause { "paused" } else { "running" }
+                );
+            }
+            let _ = context.broadcast_tx.send(BroadcastMessage {
+                msg: Message::Paused(pause),
+                recipients: Recipients::AllExcept(None),
+            });
+            if pause {
+                "Game paused".to_string()
+            } else {
+                "Game resumed".to_string()
+            }
+        }
+        AdminCommand::Storm => {
+            context.storm_requested.store(true, Ordering::SeqCst);
+            "Storm is coming".to_string()
+        }
+        AdminCommand::TimeScale(scale) => {
+            context.time_scale.store(scale.to_bits(), Ordering::Relaxed);
+            format!("Simulation runs at {scale}x speed")
+        }
+        AdminCommand::Stats => {
+            let player_count = context.lock_players().await.len();
+            format!(
+                "{}\n{}\n{}",
+                context.traffic.report(),
+                context.ticks.report(),
+                context.usage.report(player_count)
+            )
+        }
+        AdminCommand::Help => admin::HELP.to_string(),
+    }
+}
+
+/// Move a player on the authoritative player map and correct every client right away, including
+/// the moved player itself. Returns where the player ended up, None if there's no such player.
+async fn move_player(
+    context: &ServerContext,
+    player_id: PlayerID,
+    pos: Vector2<f32>,
+) -> Option<Vector2<f32>> {
+    let mut players = context.lock_players().await;
+    let session = players
+        .values_mut()
+        .find(|session| session.player.id == player_id)?;
+    session.player.pos = pos;
+    context
+        .world_params
+        .clamp_player_to_bounds(&mut session.player);
+    // Correction gets a tick of its own, so that clients don't drop it as a duplicate of the
+    // latest snapshot
+    let tick = context.tick.fetch_add(1, Ordering::SeqCst) + 1;
+    session.history.record(PositionSample {
+        at: std::time::Instant::now(),
+        tick,
+        pos: session.player.pos,
+    });
+    let _ = context.broadcast_tx.send(BroadcastMessage {
+        msg: Message::Replicate(tick, session.player),
+        recipients: Recipients::AllExcept(None),
+    });
+    Some(session.player.pos)
+}
+
+async fn resolve_admin_target(context: &ServerContext, target: &AdminTarget) -> Option<IpAddr> {
+    match target {
+        AdminTarget::Address(address) => Some(*address),
+        AdminTarget::Player(player_id) => context
+            .lock_players()
+            .await
+            .iter()
+            .find(|(_, session)| session.player.id == *player_id)
+            .map(|(client, _)| client.ip()),
+    }
+}
+
+/// Re-read config file and replace current settings. Banlist is reloaded as well, so bans edited
+/// by hand in the file take effect. Settings stay unchanged if the file is invalid.
+async fn reload_settings(context: &ServerContext) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let Some(settings_path) = &context.settings_path else {
+        return Err("No config file was given with --config".into());
+    };
+    let settings = ServerSettings::load(settings_path).map_err(|e| {
+        format!(
+            "Failed to load config from {}: {e}",
+            settings_path.display()
+        )
+    })?;
+    // Keep playing the current map if it's still in rotation, picking up changes of its file
+    let current_map = context
+        .map
+        .lock()
+        .await
+        .path
+        .clone()
+        .filter(|path| settings.map.contains(path));
+    let map = load_map(
+        current_map
+            .as_deref()
+            .or(settings.map.first().map(PathBuf::as_path)),
+    )?;
+    if let Some(path) = settings.banlist.as_ref().or(context.banlist_path.as_ref()) {
+        let banlist = Banlist::load(path)
+            .map_err(|e| format!("Failed to load banlist from {}: {e}", path.display()))?;
+        *context.banlist.lock().await = banlist;
+    }
+    change_map(context, map).await;
+    *context.settings.lock().await = settings;
+
+    Ok(())
+}
+
+fn load_map(path: Option<&Path>) -> Result<ServedMap, Box<dyn Error + Send + Sync>> {
+    match path {
+        Some(path) => ServedMap::load(path)
+            .map_err(|e| format!("Failed to load map from {}: {e}", path.display()).into()),
+        None => Ok(ServedMap::open_field()),
+    }
+}
+
+/// Switch to another map and announce it right away, so that players start loading it without
+/// waiting for the next periodic announcement.
+async fn change_map(context: &ServerContext, map: ServedMap) {
+    let mut served_map = context.map.lock().await;
+    if map.info == served_map.info {
+        return;
+    }
+    logger::info("server", format!("Changing map to {}", map.info.name));
+    let _ = context.broadcast_tx.send(BroadcastMessage {
+        msg: Message::Map(map.info.clone()),
+        recipients: Recipients::AllExcept(None),
+    });
+    *served_map = map;
+    drop(served_map);
+    // Replays are of a single map, so the rest of a match interrupted by the change is recorded
+    // as a new one
+    let recording = context.replay.lock().unwrap().is_some();
+    if recording {
+        start_replay(context).await;
+    }
+}
+
+fn ban_reason(reason: &str) -> String {
+    if reason.is_empty() {
+        "banned".to_string()
+    } else {
+        format!("banned ({reason})")
+    }
+}
+
+fn kick_reason(reason: &str) -> String {
+    if reason.is_empty() {
+        "kicked".to_string()
+    } else {
+        format!("kicked ({reason})")
+    }
 }
 
 fn generate_color() -> Vector3<f32> {
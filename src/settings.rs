@@ -0,0 +1,229 @@
+use std::{
+    error::Error,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Deserializer};
+
+use crate::{filter::WordFilterAction, globals, map, message};
+
+/// Server settings that can be changed while the server is running, read from the TOML file given
+/// with `--config`. Re-read on the `reload` admin command or SIGHUP without dropping players.
+///
+/// ```toml
+/// motd = "Welcome! Be nice."
+/// max_players = 16
+/// join_queue = true
+/// afk_timeout_secs = 60
+/// afk_spectate_secs = 300
+/// snapshot_rate = 30
+/// distant_snapshot_rate = 10
+/// banlist = "bans.json"
+/// blocked_words = ["darn", "heck"]
+/// word_filter = "reject"
+/// chat_cooldown_ms = 1000
+/// match_duration_secs = 300
+/// day_length_secs = 600
+/// storm_interval_secs = 300
+/// map = ["maps/arena.map", "maps/maze.map"]
+/// map_vote = true
+/// ```
+#[derive(Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerSettings {
+    /// Message of the day, sent to players after joining
+    pub motd: Option<String>,
+    /// Join requests are refused while this many players are connected
+    pub max_players: Option<usize>,
+    /// Players joining a full server wait in a queue instead of being refused, and join in order
+    /// as slots free up
+    pub join_queue: bool,
+    /// Players who haven't moved for this long are marked away from keyboard. Nobody is if
+    /// omitted.
+    pub afk_timeout_secs: Option<u64>,
+    /// Players who haven't moved for this long are moved to spectators, freeing their slot. Away
+    /// players are moved right away while others wait in the join queue. Needs
+    /// `afk_timeout_secs`.
+    pub afk_spectate_secs: Option<u64>,
+    /// World state replications per second, at most the logic update rate
+    pub snapshot_rate: u32,
+    /// Replications per second of players far from the receiving player, at most
+    /// `snapshot_rate`. Nearby players are always replicated at `snapshot_rate`.
+    pub distant_snapshot_rate: Option<u32>,
+    /// JSON file where bans and mutes are persisted. Overrides `--banlist`.
+    pub banlist: Option<PathBuf>,
+    /// Words not allowed in player names and chat messages, matched case-insensitively
+    pub blocked_words: Vec<String>,
+    /// Whether blocked words are masked or the name or message is refused
+    pub word_filter: WordFilterAction,
+    /// Minimum time between two chat messages of a player, faster messages are dropped
+    pub chat_cooldown_ms: Option<u64>,
+    /// Length of a match, after which results are shown and a new match starts. Matches never end
+    /// if omitted.
+    pub match_duration_secs: Option<u64>,
+    /// Real time it takes for a full day and night to pass in the world
+    pub day_length_secs: u64,
+    /// Calm time between two storms closing in on a random part of the world. No storms if
+    /// omitted.
+    pub storm_interval_secs: Option<u64>,
+    /// Map files played on the server, downloaded by players who don't have them. Either a single
+    /// map or a list of maps rotated at the end of each match. Entries like
+    /// `procedural:seed=42,density=0.1` generate a map instead. The world is an open field if
+    /// omitted.
+    #[serde(deserialize_with = "one_or_many")]
+    pub map: Vec<PathBuf>,
+    /// Let players vote for the next map while match results are shown, instead of plain rotation
+    pub map_vote: bool,
+}
+
+impl Default for ServerSettings {
+    fn default() -> Self {
+        Self {
+            motd: None,
+            max_players: None,
+            join_queue: false,
+            afk_timeout_secs: None,
+            afk_spectate_secs: None,
+            snapshot_rate: globals::MAX_LOGIC_UPDATE_PER_SEC as u32,
+            distant_snapshot_rate: None,
+            banlist: None,
+            blocked_words: Vec::new(),
+            word_filter: WordFilterAction::default(),
+            chat_cooldown_ms: None,
+            match_duration_secs: None,
+            day_length_secs: 600,
+            storm_interval_secs: None,
+            map: Vec::new(),
+            map_vote: false,
+        }
+    }
+}
+
+impl ServerSettings {
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let contents = std::fs::read_to_string(path)?;
+        let settings: Self = toml::from_str(&contents)?;
+
+        if let Some(motd) = &settings.motd {
+            message::validate_chat_message(motd).map_err(|e| format!("Invalid motd: {e}"))?;
+        }
+        let max_snapshot_rate = globals::MAX_LOGIC_UPDATE_PER_SEC as u32;
+        if !(1..=max_snapshot_rate).contains(&settings.snapshot_rate) {
+            return Err(format!("snapshot_rate must be between 1 and {max_snapshot_rate}").into());
+        }
+        if let Some(distant_snapshot_rate) = settings.distant_snapshot_rate {
+            if !(1..=settings.snapshot_rate).contains(&distant_snapshot_rate) {
+                return Err(format!(
+                    "distant_snapshot_rate must be between 1 and snapshot_rate ({})",
+                    settings.snapshot_rate
+                )
+                .into());
+            }
+        }
+
+        if settings.match_duration_secs == Some(0) {
+            return Err("match_duration_secs must be positive".into());
+        }
+        for path in &settings.map {
+            match map::ProceduralParams::from_entry(path) {
+                Some(params) => params.map(|_| ()),
+                None => map::validate_map_name(&map::map_name(path)),
+            }
+            .map_err(|e| format!("Invalid map {}: {e}", path.display()))?;
+        }
+        let max_candidates = message::MAX_MAP_VOTE_CANDIDATES;
+        if settings.map_vote && !(2..=max_candidates).contains(&settings.map.len()) {
+            return Err(format!("map_vote needs between 2 and {max_candidates} maps").into());
+        }
+        if settings.day_length_secs == 0 {
+            return Err("day_length_secs must be positive".into());
+        }
+        if settings.storm_interval_secs == Some(0) {
+            return Err("storm_interval_secs must be positive".into());
+        }
+        if settings.afk_timeout_secs == Some(0) {
+            return Err("afk_timeout_secs must be positive".into());
+        }
+        if let Some(afk_spectate_secs) = settings.afk_spectate_secs {
+            match settings.afk_timeout_secs {
+                None => return Err("afk_spectate_secs needs afk_timeout_secs".into()),
+                Some(afk_timeout_secs) if afk_spectate_secs < afk_timeout_secs => {
+                    return Err("afk_spectate_secs can't be shorter than afk_timeout_secs".into())
+                }
+                Some(_) => (),
+            }
+        }
+        // Empty word would match everything
+        if settings
+            .blocked_words
+            .iter()
+            .any(|word| word.trim().is_empty())
+        {
+            return Err("blocked_words can't contain empty words".into());
+        }
+
+        Ok(settings)
+    }
+
+    /// Map played after the given one in rotation, the first map if it's not in the rotation
+    pub fn next_map(&self, current: Option<&Path>) -> Option<&Path> {
+        let next_index = current
+            .and_then(|current| self.map.iter().position(|path| path == current))
+            .map_or(0, |index| (index + 1) % self.map.len());
+        self.map.get(next_index).map(PathBuf::as_path)
+    }
+
+    pub fn match_duration(&self) -> Option<std::time::Duration> {
+        self.match_duration_secs.map(std::time::Duration::from_secs)
+    }
+
+    pub fn day_length(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.day_length_secs)
+    }
+
+    pub fn storm_interval(&self) -> Option<std::time::Duration> {
+        self.storm_interval_secs.map(std::time::Duration::from_secs)
+    }
+
+    pub fn afk_timeout(&self) -> Option<std::time::Duration> {
+        self.afk_timeout_secs.map(std::time::Duration::from_secs)
+    }
+
+    pub fn afk_spectate_after(&self) -> Option<std::time::Duration> {
+        self.afk_spectate_secs.map(std::time::Duration::from_secs)
+    }
+
+    pub fn chat_cooldown(&self) -> Option<std::time::Duration> {
+        self.chat_cooldown_ms.map(std::time::Duration::from_millis)
+    }
+
+    /// Number of simulation ticks between two world state replications.
+    pub fn snapshot_interval(&self) -> u64 {
+        (globals::MAX_LOGIC_UPDATE_PER_SEC / self.snapshot_rate as f32).round() as u64
+    }
+
+    /// Fraction of snapshots in which distant players are replicated, None if all players are
+    /// replicated in every snapshot.
+    pub fn distant_snapshot_fraction(&self) -> Option<f32> {
+        self.distant_snapshot_rate
+            .map(|rate| rate as f32 / self.snapshot_rate as f32)
+    }
+}
+
+/// Accept both a single value and a list in the config file.
+fn one_or_many<'de, D>(deserializer: D) -> Result<Vec<PathBuf>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(PathBuf),
+        Many(Vec<PathBuf>),
+    }
+
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(path) => vec![path],
+        OneOrMany::Many(paths) => paths,
+    })
+}
@@ -0,0 +1,263 @@
+use std::{
+    collections::{BTreeMap, VecDeque},
+    io,
+};
+
+use crate::{message, Player, PlayerID, Tick};
+
+/// Bits of the change mask preceding the fields of every player in a binary snapshot. Fields are
+/// only included when their bit is set, otherwise the receiver keeps the value of the baseline.
+pub const CHANGED_POSITION: u8 = 1 << 0;
+pub const CHANGED_VELOCITY: u8 = 1 << 1;
+pub const CHANGED_COLOR: u8 = 1 << 2;
+/// Player of the baseline who is no longer replicated, no fields follow
+pub const REMOVED: u8 = 1 << 7;
+
+/// Most snapshots remembered while waiting for an acknowledgement on the server, and after
+/// receiving them on the client. The server stops referring to a baseline once this many newer
+/// snapshots were sent, so the client still has every baseline it can be sent.
+pub const MAX_PENDING_SNAPSHOTS: usize = 32;
+
+/// Tick of the baseline in snapshots encoded without one, which contain every field
+const NO_BASELINE: Tick = Tick::MAX;
+
+/// Size of the tick, the baseline tick and the number of players
+const HEADER_LEN: usize = 8 + 8 + 2;
+/// Size of a player with every field changed: ID, change mask, position, velocity and color
+const FULL_PLAYER_LEN: usize = 8 + 1 + 4 + 4 + 3;
+/// Size of a player no longer replicated: ID and change mask
+const REMOVED_PLAYER_LEN: usize = 8 + 1;
+
+/// Replicated state of a player, quantized the same way as in messages so that comparing it with
+/// a baseline tells what the receiver would see change
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct EntityState {
+    pub pos: (u16, u16),
+    pub velocity: (u16, u16),
+    pub color: [u8; 3],
+}
+
+impl EntityState {
+    pub fn new(player: &Player) -> Self {
+        Self {
+            pos: message::quantize_position(player.pos),
+            velocity: message::quantize_velocity(player.velocity),
+            color: message::quantize_color(&player.color),
+        }
+    }
+
+    pub fn to_player(&self, id: PlayerID) -> Player {
+        Player {
+            id,
+            pos: message::dequantize_position(self.pos.0, self.pos.1),
+            velocity: message::dequantize_velocity(self.velocity.0, self.velocity.1),
+            color: message::dequantize_color(self.color),
+        }
+    }
+
+    fn changes(&self, baseline: Option<&EntityState>) -> u8 {
+        let Some(baseline) = baseline else {
+            return CHANGED_POSITION | CHANGED_VELOCITY | CHANGED_COLOR;
+        };
+        let mut mask = 0;
+        if self.pos != baseline.pos {
+            mask |= CHANGED_POSITION;
+        }
+        if self.velocity != baseline.velocity {
+            mask |= CHANGED_VELOCITY;
+        }
+        if self.color != baseline.color {
+            mask |= CHANGED_COLOR;
+        }
+        mask
+    }
+}
+
+/// Every replicated player of a snapshot, ordered by ID so that encoding is deterministic
+pub type WorldState = BTreeMap<PlayerID, EntityState>;
+
+/// Server side of delta compressed snapshots for a single client. Snapshots only contain the
+/// fields that changed since the latest snapshot the client acknowledged, which is the baseline.
+/// Snapshots are unreliable, so a field is only left out once the client is known to have it.
+///
+/// Layout of an encoded snapshot, numbers in big endian:
+/// tick (u64), baseline tick (u64, `u64::MAX` without a baseline), number of players (u16), then
+/// for every player the ID (u64), the change mask (u8) and the changed fields in the order of the
+/// mask bits: position (2 x u16), velocity (2 x u16), color (3 x u8).
+/// Players unchanged since the baseline are left out entirely.
+#[derive(Default)]
+pub struct SnapshotEncoder {
+    /// Snapshots sent after the baseline, oldest first
+    pending: VecDeque<(Tick, WorldState)>,
+    baseline: Option<(Tick, WorldState)>,
+}
+
+impl SnapshotEncoder {
+    /// Append the snapshot of `world` at `tick` to `out`. Ticks have to increase between calls.
+    pub fn encode(&mut self, tick: Tick, world: &WorldState, out: &mut Vec<u8>) {
+        if self.pending.len() == MAX_PENDING_SNAPSHOTS {
+            // Client may have forgotten the baseline by now, start over from a full snapshot
+            self.pending.pop_front();
+            self.baseline = None;
+        }
+
+        let baseline_world = self.baseline.as_ref().map(|(_, world)| world);
+        let baseline_tick = self
+            .baseline
+            .as_ref()
+            .map_or(NO_BASELINE, |(tick, _)| *tick);
+        out.extend_from_slice(&tick.to_be_bytes());
+        out.extend_from_slice(&baseline_tick.to_be_bytes());
+        let count_at = out.len();
+        out.extend_from_slice(&[0, 0]);
+
+        let mut count: u16 = 0;
+        for (id, state) in world {
+            let mask = state.changes(baseline_world.and_then(|baseline| baseline.get(id)));
+            if mask == 0 {
+                continue;
+            }
+            out.extend_from_slice(&id.to_be_bytes());
+            out.push(mask);
+            if mask & CHANGED_POSITION != 0 {
+                out.extend_from_slice(&state.pos.0.to_be_bytes());
+                out.extend_from_slice(&state.pos.1.to_be_bytes());
+            }
+            if mask & CHANGED_VELOCITY != 0 {
+                out.extend_from_slice(&state.velocity.0.to_be_bytes());
+                out.extend_from_slice(&state.velocity.1.to_be_bytes());
+            }
+            if mask & CHANGED_COLOR != 0 {
+                out.extend_from_slice(&state.color);
+            }
+            count += 1;
+        }
+        for id in baseline_world
+            .into_iter()
+            .flat_map(|baseline| baseline.keys())
+            .filter(|id| !world.contains_key(id))
+        {
+            out.extend_from_slice(&id.to_be_bytes());
+            out.push(REMOVED);
+            count += 1;
+        }
+        out[count_at..count_at + 2].copy_from_slice(&count.to_be_bytes());
+
+        self.pending.push_back((tick, world.clone()));
+    }
+
+    /// Client received the snapshot of `tick`, so it can be the baseline of later snapshots.
+    /// Acknowledgements of unknown or older snapshots than the baseline are ignored.
+    pub fn acknowledge(&mut self, tick: Tick) {
+        let Some(index) = self
+            .pending
+            .iter()
+            .position(|(pending, _)| *pending == tick)
+        else {
+            return;
+        };
+        self.baseline = self.pending.drain(..=index).next_back();
+    }
+
+    /// Longest the snapshot of `world` can be encoded into, for telling whether it fits into a
+    /// datagram before encoding it
+    pub fn max_encoded_len(&self, world: &WorldState) -> usize {
+        let baseline_len = self.baseline.as_ref().map_or(0, |(_, world)| world.len());
+        HEADER_LEN + world.len() * FULL_PLAYER_LEN + baseline_len * REMOVED_PLAYER_LEN
+    }
+
+    /// State of the player in the latest snapshot it was encoded into, for keeping a player in the
+    /// snapshot without sending anything new about it
+    pub fn latest_state(&self, id: PlayerID) -> Option<EntityState> {
+        self.pending
+            .iter()
+            .rev()
+            .chain(&self.baseline)
+            .find_map(|(_, world)| world.get(&id))
+            .copied()
+    }
+
+    /// Tick of the snapshot unchanged fields are left out relative to
+    pub fn baseline_tick(&self) -> Option<Tick> {
+        self.baseline.as_ref().map(|(tick, _)| *tick)
+    }
+}
+
+/// Client side of delta compressed snapshots, see `SnapshotEncoder`. Every snapshot decoded
+/// successfully has to be acknowledged to the server, otherwise snapshots stay full.
+#[derive(Default)]
+pub struct SnapshotDecoder {
+    /// Latest snapshots received, oldest first
+    received: VecDeque<(Tick, WorldState)>,
+}
+
+impl SnapshotDecoder {
+    /// Every replicated player at the tick of the snapshot
+    pub fn decode(&mut self, mut bytes: &[u8]) -> Result<(Tick, WorldState), io::Error> {
+        let invalid = |reason: &str| io::Error::new(io::ErrorKind::InvalidData, reason.to_string());
+        let bytes = &mut bytes;
+        let tick = Tick::from_be_bytes(take(bytes)?);
+        let baseline_tick = Tick::from_be_bytes(take(bytes)?);
+        let count = u16::from_be_bytes(take(bytes)?);
+
+        let mut world = if baseline_tick == NO_BASELINE {
+            WorldState::new()
+        } else {
+            self.received
+                .iter()
+                .find(|(received, _)| *received == baseline_tick)
+                .map(|(_, world)| world.clone())
+                .ok_or_else(|| invalid("Unknown snapshot baseline"))?
+        };
+        for _ in 0..count {
+            let id = PlayerID::from_be_bytes(take(bytes)?);
+            let mask = u8::from_be_bytes(take(bytes)?);
+            if mask == REMOVED {
+                world.remove(&id);
+                continue;
+            }
+            if mask & !(CHANGED_POSITION | CHANGED_VELOCITY | CHANGED_COLOR) != 0 {
+                return Err(invalid("Invalid change mask"));
+            }
+            let state = match world.get(&id) {
+                Some(state) => *state,
+                // Players new to the client have to come with all of their fields
+                None if mask == CHANGED_POSITION | CHANGED_VELOCITY | CHANGED_COLOR => {
+                    EntityState::default()
+                }
+                None => return Err(invalid("Change of unknown player")),
+            };
+            let state = world.entry(id).or_insert(state);
+            if mask & CHANGED_POSITION != 0 {
+                state.pos = take_pair(bytes)?;
+            }
+            if mask & CHANGED_VELOCITY != 0 {
+                state.velocity = take_pair(bytes)?;
+            }
+            if mask & CHANGED_COLOR != 0 {
+                state.color = take(bytes)?;
+            }
+        }
+        if !bytes.is_empty() {
+            return Err(invalid("Trailing bytes after snapshot"));
+        }
+
+        if self.received.len() == MAX_PENDING_SNAPSHOTS {
+            self.received.pop_front();
+        }
+        self.received.push_back((tick, world.clone()));
+        Ok((tick, world))
+    }
+}
+
+fn take<const N: usize>(bytes: &mut &[u8]) -> Result<[u8; N], io::Error> {
+    message::take(bytes)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Truncated snapshot"))
+}
+
+fn take_pair(bytes: &mut &[u8]) -> Result<(u16, u16), io::Error> {
+    Ok((
+        u16::from_be_bytes(take(bytes)?),
+        u16::from_be_bytes(take(bytes)?),
+    ))
+}
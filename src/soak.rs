@@ -0,0 +1,240 @@
+use std::{
+    error::Error,
+    fs::File,
+    io::{BufWriter, Write},
+    net::SocketAddr,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use rand::{Rng, SeedableRng};
+
+use crate::{
+    bot::{self, Bot},
+    client::{self, ClientEvent, ConnectionState},
+    globals, logger,
+    map::Map,
+    message::Message,
+    server::{self, ServerHandle},
+    task, ClientSession,
+};
+
+pub const DEFAULT_BOTS: usize = 16;
+
+/// Interval of writing a row of measurements
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(10);
+/// Bots stay for a random time in this range before leaving and joining again, so that joins and
+/// leaves keep happening for the whole test
+const BOT_STAY: std::ops::Range<Duration> = Duration::from_secs(30)..Duration::from_secs(300);
+/// Time a bot waits before joining again after leaving or losing the connection
+const REJOIN_DELAY: Duration = Duration::from_secs(1);
+
+const CSV_HEADER: &str = "elapsed_secs,players,bot_joins,resident_kb,alive_tasks,server_tasks,\
+                          peer_channels,ticks,tick_average_ms,tick_max_ms,tick_overruns";
+
+pub struct SoakOptions {
+    pub port: u16,
+    pub bots: usize,
+    /// Runs until CTRL+C if not given
+    pub duration: Option<Duration>,
+    pub csv_path: PathBuf,
+}
+
+/// Run a server with bots joining, walking around and leaving it for a long time, writing memory
+/// usage, task counts and tick durations into a CSV file. Measurements growing over hours while
+/// the number of players stays the same point to leaked tasks or state of players who left.
+pub async fn run_soak_test(options: SoakOptions) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let mut csv = BufWriter::new(
+        File::create(&options.csv_path)
+            .map_err(|e| format!("Failed to create {}: {e}", options.csv_path.display()))?,
+    );
+    writeln!(csv, "{CSV_HEADER}")?;
+
+    let server_handle = server::start_server(server::ServerConfig {
+        port: options.port,
+        name: "Soak test".to_string(),
+        ..Default::default()
+    })
+    .await?;
+    logger::info(
+        "soak",
+        format!(
+            "Soak test started with {} bots, writing measurements into {}",
+            options.bots,
+            options.csv_path.display()
+        ),
+    );
+
+    let server_address = SocketAddr::from(([127, 0, 0, 1], options.port));
+    let bot_joins = Arc::new(AtomicUsize::new(0));
+    let bot_tasks: Vec<_> = (0..options.bots)
+        .map(|index| {
+            task::spawn_named(
+                "soak_bot",
+                run_bot(server_address, index, bot_joins.clone()),
+            )
+        })
+        .collect();
+
+    let started = Instant::now();
+    let deadline = options.duration.map(|duration| started + duration);
+    let mut interval = tokio::time::interval(SAMPLE_INTERVAL);
+    interval.tick().await; // Nothing to measure yet
+    let mut previous_ticks = TickTotals::default();
+    let result = loop {
+        tokio::select! {
+            _ = interval.tick() => {}
+            _ = tokio::signal::ctrl_c() => {
+                logger::info("soak", "CTRL+C interrupt received. Stopping soak test...".to_string());
+                break Ok(());
+            }
+            _ = server_handle.stopped() => break Err("Server stopped during soak test".into()),
+        }
+        if let Err(e) = write_sample(
+            &mut csv,
+            &server_handle,
+            started,
+            bot_joins.load(Ordering::Relaxed),
+            &mut previous_ticks,
+        )
+        .await
+        {
+            break Err(e);
+        }
+        if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            logger::info("soak", "Soak test finished".to_string());
+            break Ok(());
+        }
+    };
+
+    // Bots leave when told that the server is shutting down
+    server_handle.shutdown().await;
+    for bot_task in bot_tasks {
+        bot_task.abort();
+    }
+    server_handle.await_terminated().await;
+    result
+}
+
+/// Tick counters of the server at the previous sample, for averaging over a single interval
+#[derive(Default)]
+struct TickTotals {
+    ticks: u64,
+    total_ms: f64,
+}
+
+async fn write_sample(
+    csv: &mut impl Write,
+    server_handle: &ServerHandle,
+    started: Instant,
+    bot_joins: usize,
+    previous_ticks: &mut TickTotals,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let (Some(status), Some(metrics)) =
+        (server_handle.status().await, server_handle.metrics().await)
+    else {
+        return Err("Server stopped during soak test".into());
+    };
+    let ticks = metrics.ticks;
+    let total_ms = ticks.average_ms * ticks.ticks as f64;
+    let interval_ticks = ticks.ticks - previous_ticks.ticks;
+    let tick_average_ms = if interval_ticks > 0 {
+        (total_ms - previous_ticks.total_ms) / interval_ticks as f64
+    } else {
+        0.0
+    };
+    *previous_ticks = TickTotals {
+        ticks: ticks.ticks,
+        total_ms,
+    };
+    let resident_kb = resident_memory_kb().map_or(String::new(), |kb| kb.to_string());
+    let alive_tasks = tokio::runtime::Handle::current()
+        .metrics()
+        .num_alive_tasks();
+    writeln!(
+        csv,
+        "{},{},{bot_joins},{resident_kb},{alive_tasks},{},{},{},{tick_average_ms:.3},{:.3},{}",
+        started.elapsed().as_secs(),
+        status.players.len(),
+        metrics.tasks,
+        metrics.peer_channels,
+        ticks.ticks,
+        ticks.max_ms,
+        ticks.overruns,
+    )?;
+    // Every row is kept even if the process gets killed
+    csv.flush()?;
+    Ok(())
+}
+
+/// Resident memory of the process, only known on Linux
+fn resident_memory_kb() -> Option<u64> {
+    #[cfg(target_os = "linux")]
+    {
+        let status = std::fs::read_to_string("/proc/self/status").ok()?;
+        status
+            .lines()
+            .find_map(|line| line.strip_prefix("VmRSS:"))
+            .and_then(|value| value.trim().trim_end_matches("kB").trim().parse().ok())
+    }
+    #[cfg(not(target_os = "linux"))]
+    None
+}
+
+/// Bot joining the server, walking in circles for a while, then leaving and joining again.
+/// Keeps trying if joining fails or the connection is lost.
+async fn run_bot(server_address: SocketAddr, index: usize, bot_joins: Arc<AtomicUsize>) {
+    let name = format!("Bot {}", index + 1);
+    let mut rng = rand::rngs::StdRng::from_entropy();
+    loop {
+        match ClientSession::new(
+            server_address.to_string(),
+            name.clone(),
+            client::DEFAULT_SEND_RATE,
+            None,
+            None,
+        )
+        .await
+        {
+            Ok(client_session) => {
+                bot_joins.fetch_add(1, Ordering::Relaxed);
+                let stay = rng.gen_range(BOT_STAY);
+                let bot = Bot::new(
+                    rng.gen_range(0.0..std::f32::consts::TAU),
+                    bot::DEFAULT_TURN_RATE,
+                );
+                play_bot(client_session, bot, stay).await;
+            }
+            Err(e) => logger::warn("soak", format!("{name} failed to join: {e}")),
+        }
+        tokio::time::sleep(REJOIN_DELAY).await;
+    }
+}
+
+/// Walk around until the stay is over, the server shuts down or the connection is lost
+async fn play_bot(mut client_session: ClientSession, mut bot: Bot, stay: Duration) {
+    let mut local_player = client_session.get_session_player_data();
+    // Map is not downloaded, so obstacles are walked through
+    let map = Map::default();
+    let leave_at = Instant::now() + stay;
+    let mut interval =
+        tokio::time::interval(Duration::from_secs_f32(globals::FIXED_UPDATE_TIMESTEP_SEC));
+    'playing: while Instant::now() < leave_at {
+        interval.tick().await;
+        for event in client_session.try_events() {
+            match event {
+                ClientEvent::ConnectionStateChanged(ConnectionState::Lost) => return,
+                ClientEvent::Message(Message::Shutdown) => break 'playing,
+                ClientEvent::Message(Message::Leave(id)) => client_session.forget_player(id),
+                _ => (),
+            }
+        }
+        bot.step(&mut local_player, &map, &client_session.get_world_params());
+        client_session.send_pos(&local_player);
+    }
+    client_session.leave_and_wait().await;
+}
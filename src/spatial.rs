@@ -0,0 +1,82 @@
+use cgmath::{InnerSpace, Vector2};
+
+use crate::{globals, Player, PlayerID};
+
+/// Side length of grid cells in world units. Queries are cheapest when their radius is close to
+/// the cell size, so only a 3x3 block of cells has to be visited.
+pub const DEFAULT_CELL_SIZE: f32 = 200.0;
+
+/// Uniform grid over `WORLD_BOUNDS` bucketing players by position, so that proximity queries
+/// (collision, interest management, hit tests) only look at nearby players instead of everyone.
+///
+/// The grid is rebuilt from scratch on every server tick, which is cheaper than tracking cell
+/// changes of moving players. Cell buffers are kept between rebuilds to avoid allocations.
+pub struct SpatialGrid {
+    cell_size: f32,
+    columns: usize,
+    rows: usize,
+    /// Row-major cells with the ID and position of players inside
+    cells: Vec<Vec<(PlayerID, Vector2<f32>)>>,
+}
+
+impl Default for SpatialGrid {
+    fn default() -> Self {
+        Self::new(DEFAULT_CELL_SIZE)
+    }
+}
+
+impl SpatialGrid {
+    pub fn new(cell_size: f32) -> Self {
+        let bounds = &globals::WORLD_BOUNDS;
+        let columns = ((bounds.max_x - bounds.min_x) / cell_size).ceil().max(1.0) as usize;
+        let rows = ((bounds.max_y - bounds.min_y) / cell_size).ceil().max(1.0) as usize;
+        Self {
+            cell_size,
+            columns,
+            rows,
+            cells: vec![Vec::new(); columns * rows],
+        }
+    }
+
+    /// Replace grid contents with the current positions of players.
+    pub fn rebuild<'a>(&mut self, players: impl IntoIterator<Item = &'a Player>) {
+        for cell in &mut self.cells {
+            cell.clear();
+        }
+        for player in players {
+            self.insert(player.id, player.pos);
+        }
+    }
+
+    /// Positions outside of world bounds are put into the closest edge cell.
+    pub fn insert(&mut self, player_id: PlayerID, pos: Vector2<f32>) {
+        let (column, row) = self.cell_of(pos);
+        self.cells[row * self.columns + column].push((player_id, pos));
+    }
+
+    /// Players within `radius` of `pos`, including the one standing at `pos` itself.
+    pub fn query_radius(
+        &self,
+        pos: Vector2<f32>,
+        radius: f32,
+    ) -> impl Iterator<Item = (PlayerID, Vector2<f32>)> + '_ {
+        let (min_column, min_row) = self.cell_of(pos - Vector2::new(radius, radius));
+        let (max_column, max_row) = self.cell_of(pos + Vector2::new(radius, radius));
+        (min_row..=max_row)
+            .flat_map(move |row| {
+                (min_column..=max_column)
+                    .map(move |column| &self.cells[row * self.columns + column])
+            })
+            .flatten()
+            .copied()
+            .filter(move |(_, other_pos)| (other_pos - pos).magnitude2() <= radius * radius)
+    }
+
+    fn cell_of(&self, pos: Vector2<f32>) -> (usize, usize) {
+        let bounds = &globals::WORLD_BOUNDS;
+        // Float to integer casts saturate, so far away positions end up at the edges too
+        let column = ((pos.x - bounds.min_x) / self.cell_size) as usize;
+        let row = ((pos.y - bounds.min_y) / self.cell_size) as usize;
+        (column.min(self.columns - 1), row.min(self.rows - 1))
+    }
+}
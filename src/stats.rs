@@ -0,0 +1,328 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use serde::Serialize;
+
+use crate::message::{ConnectionStats, ServerUsage};
+
+/// Message type of datagrams that could not be deserialized.
+pub const INVALID_MESSAGE_TYPE: &str = "INVALID";
+/// Message type of datagrams dropped unread, because their sender is being backed off from.
+pub const BLOCKED_MESSAGE_TYPE: &str = "BLOCKED";
+/// Message type of acknowledgements of reliable messages, which carry no message.
+pub const RECEIPT_MESSAGE_TYPE: &str = "RECEIPT";
+/// Message type of reliable messages sent again for lack of an acknowledgement.
+pub const RESENT_MESSAGE_TYPE: &str = "RESENT";
+
+/// Number of consecutive ticks over or under budget needed to enter or leave overloaded state.
+/// A single slow tick is usually just a hiccup of the scheduler.
+const OVERLOAD_TICK_STREAK: u32 = 30;
+
+/// Weight of a new round-trip time sample, same as in TCP (RFC 6298)
+const RTT_SMOOTHING: f64 = 0.125;
+/// Weight of the packet loss of the latest stats period
+const PACKET_LOSS_SMOOTHING: f64 = 0.25;
+
+/// Amount of traffic of a single message type in a single direction.
+#[derive(Clone, Copy, Default, Serialize)]
+pub struct TrafficCounter {
+    pub packets: u64,
+    pub bytes: u64,
+}
+
+impl TrafficCounter {
+    fn add(&mut self, bytes: usize) {
+        self.packets += 1;
+        self.bytes += bytes as u64;
+    }
+}
+
+#[derive(Clone, Copy, Default, Serialize)]
+pub struct MessageTraffic {
+    pub received: TrafficCounter,
+    pub sent: TrafficCounter,
+}
+
+/// Packet and byte counters of the server per message type, for finding out which messages are
+/// worth optimizing in the protocol. Message types are the tags used on the wire.
+pub struct TrafficStats {
+    started: Instant,
+    messages: Mutex<BTreeMap<&'static str, MessageTraffic>>,
+}
+
+impl Default for TrafficStats {
+    fn default() -> Self {
+        Self {
+            started: Instant::now(),
+            messages: Mutex::new(BTreeMap::new()),
+        }
+    }
+}
+
+impl TrafficStats {
+    pub fn record_received(&self, message_type: &'static str, bytes: usize) {
+        let mut messages = self.messages.lock().unwrap();
+        messages
+            .entry(message_type)
+            .or_default()
+            .received
+            .add(bytes);
+    }
+
+    pub fn record_sent(&self, message_type: &'static str, bytes: usize) {
+        let mut messages = self.messages.lock().unwrap();
+        messages.entry(message_type).or_default().sent.add(bytes);
+    }
+
+    pub fn snapshot(&self) -> BTreeMap<&'static str, MessageTraffic> {
+        self.messages.lock().unwrap().clone()
+    }
+
+    /// Human readable table of the counters for the admin console.
+    pub fn report(&self) -> String {
+        let messages = self.snapshot();
+        let elapsed = self.started.elapsed().as_secs();
+        let mut report = format!(
+            "Traffic in the last {}h {}m {}s\n",
+            elapsed / 3600,
+            elapsed / 60 % 60,
+            elapsed % 60
+        );
+        let _ = writeln!(
+            report,
+            "{:<10}{:>12}{:>12}{:>12}{:>12}",
+            "TYPE", "RECV PKTS", "RECV BYTES", "SENT PKTS", "SENT BYTES"
+        );
+
+        let mut total = MessageTraffic::default();
+        for (message_type, traffic) in &messages {
+            write_row(&mut report, message_type, traffic);
+            total.received.packets += traffic.received.packets;
+            total.received.bytes += traffic.received.bytes;
+            total.sent.packets += traffic.sent.packets;
+            total.sent.bytes += traffic.sent.bytes;
+        }
+        write_row(&mut report, "TOTAL", &total);
+
+        report.pop(); // Trailing newline
+        report
+    }
+}
+
+fn write_row(report: &mut String, message_type: &str, traffic: &MessageTraffic) {
+    let _ = writeln!(
+        report,
+        "{:<10}{:>12}{:>12}{:>12}{:>12}",
+        message_type,
+        traffic.received.packets,
+        traffic.received.bytes,
+        traffic.sent.packets,
+        traffic.sent.bytes
+    );
+}
+
+/// Duration measurements of server simulation ticks against the tick budget.
+pub struct TickStats {
+    budget: Duration,
+    counters: Mutex<TickCounters>,
+}
+
+#[derive(Default)]
+struct TickCounters {
+    ticks: u64,
+    total: Duration,
+    max: Duration,
+    last: Duration,
+    /// Number of ticks that took longer than the budget
+    overruns: u64,
+    /// Consecutive ticks on the same side of the budget as the last one
+    streak: u32,
+    overloaded: bool,
+}
+
+impl TickStats {
+    pub fn new(budget: Duration) -> Self {
+        Self {
+            budget,
+            counters: Mutex::new(TickCounters::default()),
+        }
+    }
+
+    /// Returns whether the server is overloaded after this tick, which is the case when the
+    /// budget is exceeded by many ticks in a row.
+    pub fn record_tick(&self, duration: Duration) -> bool {
+        let mut counters = self.counters.lock().unwrap();
+        counters.ticks += 1;
+        counters.total += duration;
+        counters.max = counters.max.max(duration);
+        counters.last = duration;
+
+        let over_budget = duration > self.budget;
+        if over_budget {
+            counters.overruns += 1;
+        }
+        if over_budget == counters.overloaded {
+            counters.streak = 0;
+        } else {
+            counters.streak += 1;
+            if counters.streak >= OVERLOAD_TICK_STREAK {
+                counters.overloaded = over_budget;
+                counters.streak = 0;
+            }
+        }
+
+        counters.overloaded
+    }
+
+    pub fn summary(&self) -> TickSummary {
+        let counters = self.counters.lock().unwrap();
+        let average_ms = if counters.ticks > 0 {
+            counters.total.as_secs_f64() * 1000.0 / counters.ticks as f64
+        } else {
+            0.0
+        };
+        TickSummary {
+            ticks: counters.ticks,
+            overruns: counters.overruns,
+            average_ms,
+            max_ms: counters.max.as_secs_f64() * 1000.0,
+            last_ms: counters.last.as_secs_f64() * 1000.0,
+            budget_ms: self.budget.as_secs_f64() * 1000.0,
+            overloaded: counters.overloaded,
+        }
+    }
+
+    /// Human readable summary for the admin console.
+    pub fn report(&self) -> String {
+        let summary = self.summary();
+        format!(
+            "Tick duration: average {:.2} ms, max {:.2} ms, budget {:.2} ms, {} of {} ticks over budget{}",
+            summary.average_ms,
+            summary.max_ms,
+            summary.budget_ms,
+            summary.overruns,
+            summary.ticks,
+            if summary.overloaded { ", OVERLOADED" } else { "" }
+        )
+    }
+}
+
+#[derive(Serialize)]
+pub struct TickSummary {
+    pub ticks: u64,
+    /// Number of ticks that took longer than the budget
+    pub overruns: u64,
+    pub average_ms: f64,
+    pub max_ms: f64,
+    /// Duration of the latest tick
+    pub last_ms: f64,
+    pub budget_ms: f64,
+    pub overloaded: bool,
+}
+
+/// Counters of how much the server has been played since it started, for operators of
+/// long-running servers reporting usage.
+#[derive(Default)]
+pub struct UsageStats {
+    peak_players: AtomicUsize,
+    joins: AtomicU64,
+    messages: AtomicU64,
+}
+
+impl UsageStats {
+    /// Count a new session, with the number of players connected after it joined.
+    pub fn record_join(&self, players: usize) {
+        self.joins.fetch_add(1, Ordering::Relaxed);
+        self.peak_players.fetch_max(players, Ordering::Relaxed);
+    }
+
+    /// Count a message received from a client which could be deserialized.
+    pub fn record_message(&self) {
+        self.messages.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn summary(&self, players: usize) -> ServerUsage {
+        ServerUsage {
+            players,
+            // Players are counted when they join, but the current count is taken later
+            peak_players: self.peak_players.load(Ordering::Relaxed).max(players),
+            joins: self.joins.load(Ordering::Relaxed),
+            messages: self.messages.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Human readable summary for the admin console and the server log.
+    pub fn report(&self, players: usize) -> String {
+        let usage = self.summary(players);
+        format!(
+            "Usage: {} players (peak {}), {} joins, {} messages since start",
+            usage.players, usage.peak_players, usage.joins, usage.messages
+        )
+    }
+}
+
+/// Connection quality of a single player, measured by the server from replies to pings.
+#[derive(Default)]
+pub struct ConnectionQuality {
+    /// Smoothed round-trip time, unknown until the first reply
+    rtt: Option<Duration>,
+    /// Pings sent and answered since the stats were last taken
+    pings_sent: u32,
+    pongs_received: u32,
+    /// Smoothed share of lost pings. Only a few pings are sent per period, so the loss of a
+    /// single period would jump between extremes.
+    packet_loss: f64,
+    last_ping_sent: Option<Instant>,
+    corrections: u64,
+}
+
+impl ConnectionQuality {
+    pub fn record_ping_sent(&mut self, now: Instant) {
+        self.pings_sent += 1;
+        self.last_ping_sent = Some(now);
+    }
+
+    pub fn last_ping_sent(&self) -> Option<Instant> {
+        self.last_ping_sent
+    }
+
+    pub fn record_pong(&mut self, rtt: Duration) {
+        self.pongs_received += 1;
+        self.rtt = Some(match self.rtt {
+            Some(smoothed) => smoothed.mul_f64(1.0 - RTT_SMOOTHING) + rtt.mul_f64(RTT_SMOOTHING),
+            None => rtt,
+        });
+    }
+
+    /// Reported position had to be corrected, e.g. because it was out of bounds
+    pub fn record_correction(&mut self) {
+        self.corrections += 1;
+    }
+
+    /// Stats since the previous call, or `None` until the round-trip time is known. Replies still
+    /// in flight are counted as lost, and are made up for by counting them in the next period.
+    pub fn take_stats(&mut self) -> Option<ConnectionStats> {
+        let rtt = self.rtt?;
+        if self.pings_sent > 0 {
+            let lost = self.pings_sent.saturating_sub(self.pongs_received);
+            let loss = f64::from(lost) / f64::from(self.pings_sent);
+            self.packet_loss =
+                self.packet_loss * (1.0 - PACKET_LOSS_SMOOTHING) + loss * PACKET_LOSS_SMOOTHING;
+        }
+        let packet_loss_percent = (self.packet_loss * 100.0).round() as u8;
+        self.pings_sent = 0;
+        self.pongs_received = 0;
+        Some(ConnectionStats {
+            rtt_ms: rtt.as_millis().try_into().unwrap_or(u32::MAX),
+            packet_loss_percent,
+            corrections: self.corrections,
+        })
+    }
+}
@@ -0,0 +1,46 @@
+use axum::{extract::State, http::StatusCode, routing::get, Json, Router};
+use tokio::net::TcpListener;
+
+use crate::server::{ServerHandle, ServerMetrics, ServerStatus};
+
+pub async fn bind(port: u16) -> std::io::Result<TcpListener> {
+    TcpListener::bind(("0.0.0.0", port)).await
+}
+
+/// Read-only HTTP API for tools that don't speak the game protocol, like hosting panels and
+/// uptime monitors.
+///
+/// - `GET /status`: server name, uptime, tick rate and connected players
+/// - `GET /metrics`: traffic per message type, simulation tick durations and usage since start
+pub fn router(server_handle: ServerHandle) -> Router {
+    Router::new()
+        .route("/status", get(status))
+        .route("/metrics", get(metrics))
+        .with_state(server_handle)
+}
+
+pub async fn serve(listener: TcpListener, router: Router) {
+    if let Err(e) = axum::serve(listener, router).await {
+        crate::logger::error("status", format!("Status endpoint stopped: {e}"));
+    }
+}
+
+async fn status(
+    State(server_handle): State<ServerHandle>,
+) -> Result<Json<ServerStatus>, StatusCode> {
+    server_handle
+        .status()
+        .await
+        .map(Json)
+        .ok_or(StatusCode::SERVICE_UNAVAILABLE)
+}
+
+async fn metrics(
+    State(server_handle): State<ServerHandle>,
+) -> Result<Json<ServerMetrics>, StatusCode> {
+    server_handle
+        .metrics()
+        .await
+        .map(Json)
+        .ok_or(StatusCode::SERVICE_UNAVAILABLE)
+}
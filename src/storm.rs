@@ -0,0 +1,122 @@
+use std::time::Duration;
+
+use cgmath::{InnerSpace, Vector2, VectorSpace};
+use rand::Rng;
+
+use crate::globals;
+
+/// Time it takes for the safe zone to close in from the whole world to its final size
+pub const SHRINK_DURATION: Duration = Duration::from_secs(60);
+/// Time the safe zone stays at its final size before the storm clears up
+pub const HOLD_DURATION: Duration = Duration::from_secs(30);
+/// Radius of the safe zone at the end, enough room for a crowd to fight over
+pub const FINAL_RADIUS: f32 = 250.0;
+
+/// Health of players when joining and after every storm
+pub const MAX_HEALTH: u8 = 100;
+/// Health lost every second outside the safe zone, so players have a few seconds to get back in
+pub const DAMAGE_PER_SEC: f32 = 10.0;
+
+/// Circle of the world where players are safe from the storm
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SafeZone {
+    pub center: Vector2<f32>,
+    pub radius: f32,
+}
+
+impl SafeZone {
+    /// Circle around the whole world, which has nobody outside
+    pub fn whole_world() -> Self {
+        let bounds = &globals::WORLD_BOUNDS;
+        let min = Vector2::new(bounds.min_x, bounds.min_y);
+        let max = Vector2::new(bounds.max_x, bounds.max_y);
+        Self {
+            center: (min + max) * 0.5,
+            radius: (max - min).magnitude() * 0.5,
+        }
+    }
+
+    pub fn contains(&self, pos: Vector2<f32>) -> bool {
+        (pos - self.center).magnitude2() <= self.radius * self.radius
+    }
+
+    /// Zone the given fraction of the way towards `target`
+    pub fn lerp(&self, target: &SafeZone, fraction: f32) -> Self {
+        let fraction = fraction.clamp(0.0, 1.0);
+        Self {
+            center: self.center.lerp(target.center, fraction),
+            radius: self.radius + (target.radius - self.radius) * fraction,
+        }
+    }
+}
+
+/// Safe zone of a running storm as replicated to clients: the current zone closes in on
+/// `target` linearly, reaching it when `time_left` is over.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct StormState {
+    pub zone: SafeZone,
+    pub target: SafeZone,
+    pub time_left: Duration,
+}
+
+impl StormState {
+    /// Zone after `elapsed` time since the state was taken, so that clients can show the zone
+    /// closing in smoothly between updates
+    pub fn zone_after(&self, elapsed: Duration) -> SafeZone {
+        if elapsed >= self.time_left {
+            return self.target;
+        }
+        self.zone.lerp(
+            &self.target,
+            elapsed.as_secs_f32() / self.time_left.as_secs_f32(),
+        )
+    }
+}
+
+/// World event of the server: the safe zone closes in from the whole world on a random circle,
+/// holds there for a while, then the storm clears up. Players outside the zone lose health.
+#[derive(Clone, Copy, Debug)]
+pub struct Storm {
+    final_zone: SafeZone,
+}
+
+impl Storm {
+    pub fn new(final_zone: SafeZone) -> Self {
+        Self { final_zone }
+    }
+
+    /// Storm ending at a random place, with the final zone inside world bounds
+    pub fn random(rng: &mut impl Rng) -> Self {
+        let bounds = &globals::WORLD_BOUNDS;
+        Self::new(SafeZone {
+            center: Vector2::new(
+                rng.gen_range(bounds.min_x + FINAL_RADIUS..=bounds.max_x - FINAL_RADIUS),
+                rng.gen_range(bounds.min_y + FINAL_RADIUS..=bounds.max_y - FINAL_RADIUS),
+            ),
+            radius: FINAL_RADIUS,
+        })
+    }
+
+    /// State of the storm after it has been running for `elapsed` time, None once it's over
+    pub fn state(&self, elapsed: Duration) -> Option<StormState> {
+        if elapsed < SHRINK_DURATION {
+            let zone = SafeZone::whole_world().lerp(
+                &self.final_zone,
+                elapsed.as_secs_f32() / SHRINK_DURATION.as_secs_f32(),
+            );
+            Some(StormState {
+                zone,
+                target: self.final_zone,
+                time_left: SHRINK_DURATION - elapsed,
+            })
+        } else if elapsed < SHRINK_DURATION + HOLD_DURATION {
+            Some(StormState {
+                zone: self.final_zone,
+                target: self.final_zone,
+                time_left: Duration::ZERO,
+            })
+        } else {
+            None
+        }
+    }
+}
@@ -0,0 +1,56 @@
+use std::time::Instant;
+
+/// Statistics of a single stay on a server, shown to the player when leaving it.
+pub struct SessionSummary {
+    connected_at: Instant,
+    /// Distance walked by the local player in world units
+    distance: f32,
+    /// Most players on the server at once, including the local player
+    peak_players: usize,
+    /// Sum of round-trip times reported by the server, for averaging
+    rtt_sum_ms: u64,
+    rtt_samples: u64,
+}
+
+impl SessionSummary {
+    pub fn new() -> Self {
+        Self {
+            connected_at: Instant::now(),
+            distance: 0.0,
+            peak_players: 1,
+            rtt_sum_ms: 0,
+            rtt_samples: 0,
+        }
+    }
+
+    pub fn record_movement(&mut self, distance: f32) {
+        self.distance += distance;
+    }
+
+    pub fn record_player_count(&mut self, player_count: usize) {
+        self.peak_players = self.peak_players.max(player_count);
+    }
+
+    pub fn record_rtt(&mut self, rtt_ms: u32) {
+        self.rtt_sum_ms += rtt_ms as u64;
+        self.rtt_samples += 1;
+    }
+}
+
+impl std::fmt::Display for SessionSummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let connected_secs = self.connected_at.elapsed().as_secs();
+        write!(
+            f,
+            "Connected for {}m {}s, walked {:.0} units, peak of {} players, ",
+            connected_secs / 60,
+            connected_secs % 60,
+            self.distance,
+            self.peak_players
+        )?;
+        match self.rtt_sum_ms.checked_div(self.rtt_samples) {
+            Some(average_rtt_ms) => write!(f, "average ping {average_rtt_ms} ms"),
+            None => write!(f, "ping unknown"),
+        }
+    }
+}
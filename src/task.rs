@@ -0,0 +1,35 @@
+use std::future::Future;
+
+use tokio::task::JoinHandle;
+
+/// Spawn a task with a name that shows up in tokio-console. Names are only recorded when built
+/// with the `tokio-console` feature and `RUSTFLAGS="--cfg tokio_unstable"`, otherwise this is the
+/// same as `tokio::spawn`.
+pub fn spawn_named<F>(name: &str, future: F) -> JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    #[cfg(all(feature = "tokio-console", tokio_unstable))]
+    return tokio::task::Builder::new()
+        .name(name)
+        .spawn(future)
+        .expect("Failed to spawn task");
+
+    #[cfg(not(all(feature = "tokio-console", tokio_unstable)))]
+    {
+        let _ = name;
+        tokio::spawn(future)
+    }
+}
+
+/// Start reporting task instrumentation to tokio-console. Does nothing without the
+/// `tokio-console` feature.
+pub fn init_instrumentation() {
+    #[cfg(feature = "tokio-console")]
+    {
+        #[cfg(not(tokio_unstable))]
+        eprintln!("tokio-console needs the application to be built with RUSTFLAGS=\"--cfg tokio_unstable\"");
+        console_subscriber::init();
+    }
+}
@@ -0,0 +1,114 @@
+use cgmath::{InnerSpace, Vector2};
+use winit::event::TouchPhase;
+
+use crate::globals;
+
+/// Radius of the joystick base in logical window coordinates.
+pub const JOYSTICK_RADIUS: f32 = 60.0;
+
+/// Joystick is anchored to the bottom-left corner of the surface, within reach of the left thumb.
+fn joystick_center(surface_height: f32) -> Vector2<f32> {
+    Vector2::new(
+        JOYSTICK_RADIUS * 1.5,
+        surface_height - JOYSTICK_RADIUS * 1.5,
+    )
+}
+
+/// Knob displacement below this fraction of the radius is ignored to avoid drifting from an
+/// imprecise thumb.
+const DEAD_ZONE: f32 = 0.2;
+
+/// On-screen virtual joystick for touch input. The first finger landing on the joystick base is
+/// captured and drives the knob until it is lifted, so other fingers can press GUI buttons at the
+/// same time.
+pub struct VirtualJoystick {
+    /// Touch ID of the captured finger
+    finger: Option<u64>,
+    /// Center of the joystick base in logical window coordinates, follows the surface size
+    center: Vector2<f32>,
+    /// Knob position relative to `center`, clamped to `JOYSTICK_RADIUS`
+    knob_offset: Vector2<f32>,
+    /// Joystick is only shown after the first touch event, keyboard players don't need it
+    visible: bool,
+}
+
+impl Default for VirtualJoystick {
+    fn default() -> Self {
+        Self {
+            finger: None,
+            center: joystick_center(globals::WINDOW_SIZE.1 as f32),
+            knob_offset: Vector2::new(0.0, 0.0),
+            visible: false,
+        }
+    }
+}
+
+impl VirtualJoystick {
+    /// Feed a touch event with position given in logical window coordinates.
+    pub fn handle_touch(&mut self, phase: TouchPhase, finger: u64, pos: Vector2<f32>) {
+        self.visible = true;
+
+        match phase {
+            TouchPhase::Started => {
+                if self.finger.is_none() && (pos - self.center).magnitude() <= JOYSTICK_RADIUS {
+                    self.finger = Some(finger);
+                    self.move_knob(pos);
+                }
+            }
+            TouchPhase::Moved => {
+                if self.finger == Some(finger) {
+                    self.move_knob(pos);
+                }
+            }
+            TouchPhase::Ended | TouchPhase::Cancelled => {
+                if self.finger == Some(finger) {
+                    self.release();
+                }
+            }
+        }
+    }
+
+    /// Anchor the joystick to a surface of the given size in logical window coordinates. The
+    /// surface is the whole display on Android, and changes when the device is rotated.
+    pub fn resize(&mut self, surface_size: Vector2<f32>) {
+        self.center = joystick_center(surface_size.y);
+        self.release();
+    }
+
+    /// Let go of the knob, e.g. when window loses focus and the lift event would never arrive.
+    pub fn release(&mut self) {
+        self.finger = None;
+        self.knob_offset = Vector2::new(0.0, 0.0);
+    }
+
+    /// Movement direction with magnitude between 0.0 and 1.0.
+    pub fn direction(&self) -> Vector2<f32> {
+        let direction = self.knob_offset / JOYSTICK_RADIUS;
+        if direction.magnitude() < DEAD_ZONE {
+            return Vector2::new(0.0, 0.0);
+        }
+        direction
+    }
+
+    pub fn center(&self) -> Vector2<f32> {
+        self.center
+    }
+
+    pub fn knob_offset(&self) -> Vector2<f32> {
+        self.knob_offset
+    }
+
+    pub fn is_visible(&self) -> bool {
+        self.visible
+    }
+
+    fn move_knob(&mut self, pos: Vector2<f32>) {
+        let offset = pos - self.center;
+        let distance = offset.magnitude();
+        self.knob_offset = if distance > JOYSTICK_RADIUS {
+            offset * (JOYSTICK_RADIUS / distance)
+        } else {
+            offset
+        };
+    }
+}
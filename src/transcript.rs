@@ -0,0 +1,413 @@
+use std::time::Duration;
+
+use cgmath::{Vector2, Vector3};
+
+use crate::{
+    afk::PlayerActivity,
+    map::MapInfo,
+    message::{
+        self, ConnectionStats, DatagramHeader, GameEvent, MatchResult, Message, ServerUsage,
+    },
+    net::{self, Channels},
+    storm::{SafeZone, StormState},
+    Player, WorldParams,
+};
+
+const TOKEN: u64 = 0x0123_4567_89AB_CDEF;
+const PLAYER_ID: u64 = 1;
+const OTHER_PLAYER_ID: u64 = 2;
+const MAP_CHECKSUM: u32 = 0xC0FF_EE00;
+
+/// Which way a datagram went
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Direction {
+    ClientToServer,
+    ServerToClient,
+}
+
+impl Direction {
+    /// Start of the line of a datagram in a transcript
+    fn marker(self) -> char {
+        match self {
+            Direction::ClientToServer => '>',
+            Direction::ServerToClient => '<',
+        }
+    }
+
+    fn reversed(self) -> Self {
+        match self {
+            Direction::ClientToServer => Direction::ServerToClient,
+            Direction::ServerToClient => Direction::ClientToServer,
+        }
+    }
+}
+
+/// Datagram of a transcript with the bytes as sent. The description only helps readers of the
+/// transcript and is not compared.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Datagram {
+    pub direction: Direction,
+    pub bytes: Vec<u8>,
+    pub description: String,
+}
+
+/// Client and server of the scripted session, passing datagrams through their channels the same
+/// way the real ones do and recording every datagram on the way
+struct ScriptedSession {
+    client: Channels,
+    server: Channels,
+    now: std::time::Instant,
+    datagrams: Vec<Datagram>,
+}
+
+impl ScriptedSession {
+    fn new() -> Self {
+        Self {
+            client: Channels::new(),
+            server: Channels::new(),
+            now: std::time::Instant::now(),
+            datagrams: Vec::new(),
+        }
+    }
+
+    /// Channels of the sending and of the receiving side
+    fn channels(&mut self, direction: Direction) -> (&mut Channels, &mut Channels) {
+        match direction {
+            Direction::ClientToServer => (&mut self.client, &mut self.server),
+            Direction::ServerToClient => (&mut self.server, &mut self.client),
+        }
+    }
+
+    /// Message exchanged without joining, which skips the channels
+    fn send_unjoined(&mut self, direction: Direction, msg: Message) {
+        let datagram = DatagramHeader::default().encode(&msg.serialize());
+        self.record(direction, datagram, format!("{msg:?}"));
+    }
+
+    fn send(&mut self, direction: Direction, msg: Message) {
+        let now = self.now;
+        let (sender, _) = self.channels(direction);
+        let datagram = sender.send(msg.channel(), &msg.serialize(), now);
+        self.deliver(direction, datagram, format!("{msg:?}"));
+    }
+
+    /// Messages packed into as few datagrams as fit, like snapshots
+    fn send_batch(&mut self, direction: Direction, msgs: &[Message]) {
+        let now = self.now;
+        let payloads: Vec<Vec<u8>> = msgs.iter().map(Message::serialize).collect();
+        let (sender, _) = self.channels(direction);
+        for datagram in sender.send_batch(msgs[0].channel(), &payloads, now) {
+            self.deliver(direction, datagram, format!("{msgs:?}"));
+        }
+    }
+
+    /// Message lost on the way, which is recorded as sent but never arrives
+    fn lose(&mut self, direction: Direction, msg: Message) {
+        let now = self.now;
+        let (sender, _) = self.channels(direction);
+        let datagram = sender.send(msg.channel(), &msg.serialize(), now);
+        self.record(direction, datagram, format!("Lost: {msg:?}"));
+    }
+
+    /// Let the resend interval pass, so that unacknowledged messages are sent again
+    fn resend(&mut self, direction: Direction) {
+        self.now += net::RESEND_INTERVAL;
+        let now = self.now;
+        let (sender, _) = self.channels(direction);
+        for datagram in sender.resend_due(now).unwrap_or_default() {
+            self.deliver(direction, datagram, "Resent".to_string());
+        }
+    }
+
+    /// Record the datagram and hand it to the receiver, sending its acknowledgement back
+    fn deliver(&mut self, direction: Direction, datagram: Vec<u8>, description: String) {
+        let (header, payload) =
+            DatagramHeader::decode(&datagram).expect("Scripted datagram is valid");
+        let (_, receiver) = self.channels(direction);
+        let ack = receiver.receive(header, payload).ack;
+        self.record(direction, datagram, description);
+        if let Some(ack) = ack {
+            let (header, payload) = DatagramHeader::decode(&ack).expect("Ack is valid");
+            let (sender, _) = self.channels(direction);
+            sender.receive(header, payload);
+            self.record(direction.reversed(), ack, "Ack".to_string());
+        }
+    }
+
+    fn record(&mut self, direction: Direction, bytes: Vec<u8>, description: String) {
+        self.datagrams.push(Datagram {
+            direction,
+            bytes,
+            description,
+        });
+    }
+}
+
+/// Datagrams of a scripted session of a player querying a server, joining, playing a match and
+/// leaving, with every message type on its channel and a lost reliable message being resent.
+/// Values are fixed, so the bytes only change with the wire format.
+pub fn record() -> Vec<Datagram> {
+    use Direction::{ClientToServer as Up, ServerToClient as Down};
+
+    let color = Vector3::new(1.0, 0.5, 0.0);
+    let pos = Vector2::new(100.0, -50.0);
+    let zone = SafeZone {
+        center: Vector2::new(0.0, 0.0),
+        radius: 400.0,
+    };
+    let target = SafeZone {
+        center: Vector2::new(50.0, 25.0),
+        radius: 200.0,
+    };
+    let mut session = ScriptedSession::new();
+
+    session.send_unjoined(Up, Message::Query);
+    session.send_unjoined(
+        Down,
+        Message::Info(
+            "Golden server".to_string(),
+            ServerUsage {
+                players: 1,
+                peak_players: 3,
+                joins: 12,
+                messages: 3456,
+            },
+        ),
+    );
+    session.send_unjoined(Up, Message::Handshake("Alice".to_string(), None));
+    session.send_unjoined(Down, Message::Queued(2));
+    session.send_unjoined(Up, Message::Handshake("Alice".to_string(), Some(TOKEN)));
+    session.send_unjoined(
+        Down,
+        Message::Ack(
+            PLAYER_ID,
+            TOKEN,
+            color,
+            42,
+            "Golden server".to_string(),
+            WorldParams::default(),
+        ),
+    );
+    session.send(
+        Down,
+        Message::Map(MapInfo {
+            name: "arena".to_string(),
+            checksum: MAP_CHECKSUM,
+            size: 4,
+        }),
+    );
+    session.send(Up, Message::MapRequest(TOKEN, MAP_CHECKSUM, 0));
+    session.send(Down, Message::MapChunk(MAP_CHECKSUM, 0, b"wall".to_vec()));
+    session.send(Down, Message::Probe(64));
+    session.send(Up, Message::ProbeAck(TOKEN, 64));
+    session.send(
+        Down,
+        Message::Profile(PLAYER_ID, color, "Alice".to_string()),
+    );
+    session.send(
+        Down,
+        Message::Event(GameEvent::Joined(OTHER_PLAYER_ID, "Bob".to_string())),
+    );
+    session.send(Down, Message::Notice("Welcome! Be nice.".to_string()));
+    session.send(Down, Message::TimeOfDay(8 * 60));
+    session.send_batch(
+        Down,
+        &[
+            Message::Replicate(
+                100,
+                Player {
+                    id: PLAYER_ID,
+                    pos,
+                    velocity: Vector2::new(0.0, 0.0),
+                    color,
+                },
+            ),
+            Message::Replicate(
+                100,
+                Player {
+                    id: OTHER_PLAYER_ID,
+                    pos: -pos,
+                    velocity: Vector2::new(0.0, 0.0),
+                    color: Vector3::new(0.0, 0.0, 1.0),
+                },
+            ),
+        ],
+    );
+    session.send(Up, Message::Position(TOKEN, pos));
+    session.send(Down, Message::Ping(1_000));
+    session.send(Up, Message::Pong(TOKEN, 1_000));
+    session.send(
+        Down,
+        Message::Stats(ConnectionStats {
+            rtt_ms: 24,
+            packet_loss_percent: 1,
+            corrections: 2,
+        }),
+    );
+    session.send(Up, Message::Say(TOKEN, "Hello!".to_string()));
+    session.send(Down, Message::Chat(PLAYER_ID, "Hello!".to_string()));
+    session.lose(Down, Message::Chat(OTHER_PLAYER_ID, "Hi Alice".to_string()));
+    session.resend(Down);
+    session.send(
+        Up,
+        Message::UpdateProfile(TOKEN, Vector3::new(0.0, 1.0, 0.0), "Alicia".to_string()),
+    );
+    session.send(
+        Down,
+        Message::Profile(PLAYER_ID, Vector3::new(0.0, 1.0, 0.0), "Alicia".to_string()),
+    );
+    session.send(
+        Down,
+        Message::Activity(OTHER_PLAYER_ID, PlayerActivity::Afk),
+    );
+    session.send(
+        Down,
+        Message::Storm(Some(StormState {
+            zone,
+            target,
+            time_left: Duration::from_secs(30),
+        })),
+    );
+    session.send(Down, Message::Health(80));
+    session.send(
+        Down,
+        Message::Event(GameEvent::KnockedOut(OTHER_PLAYER_ID, "Bob".to_string())),
+    );
+    session.send(Down, Message::Leader(Some((PLAYER_ID, 250))));
+    session.send(Down, Message::Paused(true));
+    session.send(Down, Message::Paused(false));
+    session.send(
+        Down,
+        Message::MatchEnd(
+            10,
+            vec![
+                MatchResult {
+                    player_id: PLAYER_ID,
+                    name: "Alicia".to_string(),
+                    score: 250,
+                },
+                MatchResult {
+                    player_id: OTHER_PLAYER_ID,
+                    name: "Bob".to_string(),
+                    score: 120,
+                },
+            ],
+        ),
+    );
+    session.send(
+        Down,
+        Message::MapVote(vec![("arena".to_string(), 1), ("maze".to_string(), 0)]),
+    );
+    session.send(Down, Message::Storm(None));
+    session.send(Down, Message::Leader(None));
+    session.send(
+        Down,
+        Message::Event(GameEvent::Left(OTHER_PLAYER_ID, "Bob".to_string())),
+    );
+    session.send(Down, Message::Leave(OTHER_PLAYER_ID));
+    session.send(Up, Message::Disconnect(TOKEN));
+    session.send(Down, Message::Reject("Server is restarting".to_string()));
+    session.send(Down, Message::Shutdown);
+    // Lockstep peers and LAN beacons don't need a session, but their formats are covered too
+    session.send_unjoined(Up, Message::Input(PLAYER_ID, 100, vec![0b0101, 0b0110]));
+    session.send_unjoined(Down, Message::Beacon("Golden server".to_string(), 8080, 1));
+    session.datagrams
+}
+
+/// Transcript as text, one datagram per line after a comment describing it, in hexadecimal after
+/// `>` if sent by the client and after `<` if sent by the server
+pub fn format(datagrams: &[Datagram]) -> String {
+    let mut text = format!(
+        "# Datagrams of the scripted session in `transcript.rs`, protocol version {}.\n\
+         # Re-record with --record-transcript only after an intended wire format change.\n",
+        message::PROTOCOL_VERSION
+    );
+    for datagram in datagrams {
+        text += &format!(
+            "\n# {}\n{} ",
+            datagram.description,
+            datagram.direction.marker()
+        );
+        for byte in &datagram.bytes {
+            text += &format!("{byte:02x}");
+        }
+        text += "\n";
+    }
+    text
+}
+
+/// Datagrams of a transcript written by `format()`, described by the comment in front of them
+pub fn parse(text: &str) -> Result<Vec<Datagram>, String> {
+    let mut datagrams = Vec::new();
+    let mut description = String::new();
+    for (index, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if let Some(comment) = line.strip_prefix('#') {
+            description = comment.trim().to_string();
+            continue;
+        }
+        if line.is_empty() {
+            continue;
+        }
+        let (direction, hex) = match line.split_once(' ') {
+            Some((">", hex)) => (Direction::ClientToServer, hex),
+            Some(("<", hex)) => (Direction::ServerToClient, hex),
+            _ => return Err(format!("Line {} is not a datagram", index + 1)),
+        };
+        let bytes = parse_hex(hex).ok_or_else(|| format!("Line {} is not hex", index + 1))?;
+        datagrams.push(Datagram {
+            direction,
+            bytes,
+            description: std::mem::take(&mut description),
+        });
+    }
+    Ok(datagrams)
+}
+
+fn parse_hex(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() % 2 != 0 || !hex.is_ascii() {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|index| u8::from_str_radix(&hex[index..index + 2], 16).ok())
+        .collect()
+}
+
+/// Differences of the recorded datagrams from the golden ones, empty if they are the same
+pub fn compare(golden: &[Datagram], recorded: &[Datagram]) -> Vec<String> {
+    let mut differences = Vec::new();
+    for (index, (expected, actual)) in golden.iter().zip(recorded).enumerate() {
+        let name = format!("Datagram {} ({})", index + 1, actual.description);
+        if expected.direction != actual.direction {
+            differences.push(format!(
+                "{name} was sent {:?} instead of {:?}",
+                actual.direction, expected.direction
+            ));
+        } else if let Some(offset) = expected
+            .bytes
+            .iter()
+            .zip(&actual.bytes)
+            .position(|(expected, actual)| expected != actual)
+        {
+            differences.push(format!(
+                "{name} differs at byte {offset}: expected {:02x}, got {:02x}",
+                expected.bytes[offset], actual.bytes[offset]
+            ));
+        } else if expected.bytes.len() != actual.bytes.len() {
+            differences.push(format!(
+                "{name} is {} bytes long instead of {}",
+                actual.bytes.len(),
+                expected.bytes.len()
+            ));
+        }
+    }
+    if golden.len() != recorded.len() {
+        differences.push(format!(
+            "{} datagrams were recorded instead of {}",
+            recorded.len(),
+            golden.len()
+        ));
+    }
+    differences
+}
@@ -0,0 +1,86 @@
+use std::{
+    collections::HashSet,
+    net::IpAddr,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use crate::logger;
+
+/// Allowed players of a private server, loaded from a plain text file with one entry per line.
+/// An entry is either an IP address or a player name. Empty lines and lines starting with `#` are
+/// ignored.
+///
+/// File is re-read whenever its modification time changes, so operators can edit the list
+/// without restarting the server.
+pub struct Whitelist {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+    names: HashSet<String>,
+    addresses: HashSet<IpAddr>,
+}
+
+impl Whitelist {
+    pub fn load(path: &Path) -> std::io::Result<Self> {
+        let mut whitelist = Self {
+            path: path.to_path_buf(),
+            modified: None,
+            names: HashSet::new(),
+            addresses: HashSet::new(),
+        };
+        whitelist.read()?;
+
+        Ok(whitelist)
+    }
+
+    /// Keeps the previous entries if the file became unreadable, and only tries again once the
+    /// file changes, so that the failure is logged once.
+    pub fn reload_if_changed(&mut self) {
+        let modified = std::fs::metadata(&self.path)
+            .and_then(|metadata| metadata.modified())
+            .ok();
+        if modified.is_none() || modified == self.modified {
+            return;
+        }
+
+        match self.read() {
+            Ok(_) => logger::info(
+                "whitelist",
+                format!("Whitelist reloaded from {}", self.path.display()),
+            ),
+            Err(e) => {
+                self.modified = modified;
+                logger::error("whitelist", format!("Failed to reload whitelist: {e}"))
+            }
+        }
+    }
+
+    pub fn is_allowed(&self, player_name: &str, address: IpAddr) -> bool {
+        self.addresses.contains(&address) || self.names.contains(player_name)
+    }
+
+    fn read(&mut self) -> std::io::Result<()> {
+        let modified = std::fs::metadata(&self.path)?.modified().ok();
+        let contents = std::fs::read_to_string(&self.path)?;
+
+        self.names.clear();
+        self.addresses.clear();
+        for entry in contents
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        {
+            match entry.parse::<IpAddr>() {
+                Ok(address) => {
+                    self.addresses.insert(address);
+                }
+                Err(_) => {
+                    self.names.insert(entry.to_string());
+                }
+            }
+        }
+        self.modified = modified;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,55 @@
+use std::time::Duration;
+
+use multiplayer_game_demo_rust::afk::{self, PlayerActivity};
+use proptest::prelude::*;
+
+fn secs() -> impl Strategy<Value = Duration> {
+    (0..3600u64).prop_map(Duration::from_secs)
+}
+
+/// AFK timeout and the longer time until spectating, if any
+fn timeouts() -> impl Strategy<Value = (Duration, Option<Duration>)> {
+    (secs(), proptest::option::of(secs()))
+        .prop_map(|(afk_timeout, extra)| (afk_timeout, extra.map(|extra| afk_timeout + extra)))
+}
+
+proptest! {
+    #[test]
+    fn nobody_is_away_without_a_timeout(
+        idle in secs(),
+        spectate_after in proptest::option::of(secs()),
+        queue_waiting in any::<bool>(),
+    ) {
+        prop_assert_eq!(
+            afk::activity_after(idle, None, spectate_after, queue_waiting),
+            PlayerActivity::Active
+        );
+    }
+
+    #[test]
+    fn idling_longer_never_brings_players_back(
+        idle in secs(),
+        longer in secs(),
+        (afk_timeout, spectate_after) in timeouts(),
+        queue_waiting in any::<bool>(),
+    ) {
+        let activity = afk::activity_after(idle, Some(afk_timeout), spectate_after, queue_waiting);
+        let later =
+            afk::activity_after(idle + longer, Some(afk_timeout), spectate_after, queue_waiting);
+        prop_assert!(later >= activity);
+    }
+
+    #[test]
+    fn waiting_queue_makes_away_players_spectators(
+        idle in secs(),
+        (afk_timeout, spectate_after) in timeouts(),
+    ) {
+        let activity = afk::activity_after(idle, Some(afk_timeout), spectate_after, true);
+        let expected = if idle >= afk_timeout {
+            PlayerActivity::Spectating
+        } else {
+            PlayerActivity::Active
+        };
+        prop_assert_eq!(activity, expected);
+    }
+}
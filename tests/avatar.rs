@@ -0,0 +1,29 @@
+use multiplayer_game_demo_rust::avatar::{Avatar, AVATAR_CELLS};
+use proptest::prelude::*;
+
+proptest! {
+    #[test]
+    fn avatar_is_mirrored(seed in any::<u64>(), player_id in any::<u64>()) {
+        let avatar = Avatar::new(seed, player_id);
+        for row in 0..AVATAR_CELLS {
+            for column in 0..AVATAR_CELLS {
+                prop_assert_eq!(
+                    avatar.is_filled(row, column),
+                    avatar.is_filled(row, AVATAR_CELLS - 1 - column)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn cells_outside_the_pattern_are_empty(
+        seed in any::<u64>(),
+        player_id in any::<u64>(),
+        row in AVATAR_CELLS..64,
+        column in 0..64usize,
+    ) {
+        let avatar = Avatar::new(seed, player_id);
+        prop_assert!(!avatar.is_filled(row, column));
+        prop_assert!(!avatar.is_filled(column, row));
+    }
+}
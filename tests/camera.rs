@@ -0,0 +1,54 @@
+use cgmath::{Vector2, Vector4};
+use multiplayer_game_demo_rust::{globals, Camera};
+use proptest::prelude::*;
+
+/// Includes positions outside of world bounds, which the camera doesn't follow
+fn position() -> impl Strategy<Value = Vector2<f32>> {
+    (-1500.0f32..1500.0, -1500.0f32..1500.0).prop_map(|(x, y)| Vector2::new(x, y))
+}
+
+fn screen_position() -> impl Strategy<Value = Vector2<f32>> {
+    (
+        0.0..globals::WINDOW_SIZE.0 as f32,
+        0.0..globals::WINDOW_SIZE.1 as f32,
+    )
+        .prop_map(|(x, y)| Vector2::new(x, y))
+}
+
+fn assert_close(a: Vector2<f32>, b: Vector2<f32>) -> Result<(), TestCaseError> {
+    prop_assert!(
+        (a.x - b.x).abs() < 0.01 && (a.y - b.y).abs() < 0.01,
+        "{:?} != {:?}",
+        a,
+        b
+    );
+    Ok(())
+}
+
+proptest! {
+    #[test]
+    fn screen_positions_survive_conversion(target in position(), screen in screen_position()) {
+        let camera = Camera::centered_on(target);
+        assert_close(camera.world_to_screen(camera.screen_to_world(screen)), screen)?;
+    }
+
+    #[test]
+    fn nothing_outside_the_world_is_shown(target in position(), screen in screen_position()) {
+        let world = Camera::centered_on(target).screen_to_world(screen);
+        let bounds = &globals::WORLD_BOUNDS;
+        prop_assert!(world.x >= bounds.min_x && world.x <= bounds.max_x);
+        prop_assert!(world.y >= bounds.min_y && world.y <= bounds.max_y);
+    }
+
+    #[test]
+    fn drawing_matches_conversion(target in position(), world in position()) {
+        let camera = Camera::centered_on(target);
+        let clip = camera.projection_view() * Vector4::new(world.x, world.y, 0.0, 1.0);
+        // Clip space goes from -1 to 1 with y upwards, the scene starts at the top-left corner
+        let drawn = Vector2::new(
+            (clip.x + 1.0) / 2.0 * globals::WINDOW_SIZE.0 as f32,
+            (1.0 - clip.y) / 2.0 * globals::WINDOW_SIZE.1 as f32,
+        );
+        assert_close(drawn, camera.world_to_screen(world))?;
+    }
+}
@@ -0,0 +1,94 @@
+#![cfg(feature = "chaos")]
+
+use std::time::Duration;
+
+use common::{join, start_with};
+use multiplayer_game_demo_rust::{
+    chaos::ChaosConfig,
+    client::{ClientEvent, ConnectionState},
+    message::Message,
+    server::ServerConfig,
+    ClientSession,
+};
+use proptest::prelude::*;
+
+mod common;
+
+/// Logic updates both players stay for, at 60 updates per second
+const UPDATES: usize = 90;
+
+/// What a client made of the misbehaving server
+#[derive(Debug, Default)]
+struct Observed {
+    chats: Vec<String>,
+    snapshots: usize,
+    lost: bool,
+}
+
+fn observe(client_session: &mut ClientSession, observed: &mut Observed) {
+    for event in client_session.try_events() {
+        match event {
+            ClientEvent::Message(Message::Chat(_, text)) => observed.chats.push(text),
+            ClientEvent::Message(Message::Replicate(tick, player))
+                if client_session.accept_snapshot(player.id, tick) =>
+            {
+                observed.snapshots += 1
+            }
+            ClientEvent::ConnectionStateChanged(ConnectionState::Lost) => observed.lost = true,
+            _ => (),
+        }
+    }
+}
+
+/// Two players on a server dropping broadcasts, handling datagrams twice and stalling on its
+/// locks. Returns what the second player observed.
+async fn play(seed: u64) -> Observed {
+    let (server_handle, server_address) = start_with(ServerConfig {
+        chaos: Some(ChaosConfig {
+            seed,
+            lock_delay_chance: 0.2,
+            broadcast_drop_chance: 0.3,
+            duplicate_chance: 0.3,
+            ..ChaosConfig::default()
+        }),
+        ..ServerConfig::default()
+    })
+    .await;
+
+    let mut alice = join(server_address, "Alice").await;
+    let mut bob = join(server_address, "Bob").await;
+    alice.send_chat("hello".to_string());
+
+    let mut alice_player = alice.get_session_player_data();
+    let mut ignored = Observed::default();
+    let mut observed = Observed::default();
+    let mut interval = tokio::time::interval(Duration::from_secs_f32(1.0 / 60.0));
+    for _ in 0..UPDATES {
+        interval.tick().await;
+        alice_player.pos.x += 1.0;
+        alice.send_pos(&alice_player);
+        observe(&mut alice, &mut ignored);
+        observe(&mut bob, &mut observed);
+    }
+
+    alice.leave_server();
+    bob.leave_server();
+    server_handle.shutdown().await;
+    server_handle.await_terminated().await;
+    observed
+}
+
+proptest! {
+    // Every case runs a server for a while
+    #![proptest_config(ProptestConfig::with_cases(4))]
+
+    #[test]
+    fn client_copes_with_misbehaving_server(seed in any::<u64>()) {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let observed = runtime.block_on(play(seed));
+        prop_assert!(!observed.lost);
+        // Reliable messages get through dropped broadcasts, and duplicates are filtered out
+        prop_assert_eq!(observed.chats, vec!["hello".to_string()]);
+        prop_assert!(observed.snapshots > 0);
+    }
+}
@@ -0,0 +1,108 @@
+use multiplayer_game_demo_rust::{
+    client::ConnectionState,
+    client_metrics::{escape_label_value, ClientMetrics},
+    message::ConnectionStats,
+};
+use proptest::prelude::*;
+
+/// Label value of an escaped sample line up to its closing quote, with the rest of the line
+fn unescape_label_value(escaped: &str) -> Option<(String, &str)> {
+    let mut value = String::new();
+    let mut chars = escaped.char_indices();
+    while let Some((index, c)) = chars.next() {
+        match c {
+            '"' => return Some((value, &escaped[index + 1..])),
+            '\\' => match chars.next()?.1 {
+                '\\' => value.push('\\'),
+                '"' => value.push('"'),
+                'n' => value.push('\n'),
+                _ => return None,
+            },
+            c => value.push(c),
+        }
+    }
+    None
+}
+
+/// Names and values of the labels of a sample, in order
+type Labels = Vec<(String, String)>;
+
+/// Labels and value of a sample line like `name{label="value",...} 1.5`
+fn parse_sample(line: &str) -> Option<(String, Labels, f64)> {
+    let (name, mut rest) = line.split_once('{')?;
+    let mut labels = Vec::new();
+    loop {
+        let (label, after_name) = rest.split_once("=\"")?;
+        let (value, after_value) = unescape_label_value(after_name)?;
+        labels.push((label.to_string(), value));
+        match after_value.strip_prefix(',') {
+            Some(next) => rest = next,
+            None => {
+                let value = after_value.strip_prefix("} ")?.parse().ok()?;
+                return Some((name.to_string(), labels, value));
+            }
+        }
+    }
+}
+
+proptest! {
+    #[test]
+    fn label_values_survive_escaping(value in any::<String>()) {
+        let escaped = format!("{}\"", escape_label_value(&value));
+        prop_assert!(!escaped.contains('\n'));
+        prop_assert_eq!(unescape_label_value(&escaped), Some((value, "")));
+    }
+
+    #[test]
+    fn every_sample_is_described_and_labeled(
+        player_id in 1..u64::MAX,
+        player_name in any::<String>(),
+        server_address in any::<String>(),
+        stats in proptest::option::of((any::<u32>(), 0..=100u8, any::<u64>())),
+        degraded in any::<bool>(),
+        messages in 0..100usize,
+    ) {
+        let mut metrics = ClientMetrics::new(player_id, player_name.clone(), server_address.clone());
+        if let Some((rtt_ms, packet_loss_percent, corrections)) = stats {
+            metrics.record_stats(ConnectionStats { rtt_ms, packet_loss_percent, corrections });
+        }
+        if degraded {
+            metrics.record_connection_state(ConnectionState::Degraded);
+        }
+        for _ in 0..messages {
+            metrics.record_message();
+        }
+
+        let rendered = metrics.render();
+        let mut described = Vec::new();
+        let mut samples = Vec::new();
+        for line in rendered.lines() {
+            if let Some(help) = line.strip_prefix("# HELP ") {
+                described.push(help.split(' ').next().unwrap().to_string());
+            } else if !line.starts_with('#') {
+                let sample = parse_sample(line);
+                prop_assert!(sample.is_some(), "Invalid sample line {}", line);
+                samples.push(sample.unwrap());
+            }
+        }
+        prop_assert_eq!(samples.len(), described.len());
+        prop_assert_eq!(samples.len(), if stats.is_some() { 7 } else { 4 });
+        for (name, labels, value) in samples {
+            prop_assert!(described.contains(&name));
+            prop_assert_eq!(
+                labels,
+                vec![
+                    ("player_id".to_string(), player_id.to_string()),
+                    ("player_name".to_string(), player_name.clone()),
+                    ("server".to_string(), server_address.clone()),
+                ]
+            );
+            match name.as_str() {
+                "game_client_connected" => prop_assert_eq!(value, if degraded { 0.0 } else { 1.0 }),
+                "game_client_messages_received_total" => prop_assert_eq!(value, messages as f64),
+                "game_client_rtt_milliseconds" => prop_assert_eq!(value, stats.unwrap().0 as f64),
+                _ => (),
+            }
+        }
+    }
+}
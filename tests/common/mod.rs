@@ -0,0 +1,30 @@
+//! Helpers for tests running a server with players on it
+
+use std::net::SocketAddr;
+
+use multiplayer_game_demo_rust::{
+    client::DEFAULT_SEND_RATE,
+    server::{self, ServerConfig, ServerHandle},
+    ClientSession,
+};
+
+/// Server on a port picked by the system
+pub async fn start_with(config: ServerConfig) -> (ServerHandle, SocketAddr) {
+    let server_handle = server::start_server(ServerConfig { port: 0, ..config })
+        .await
+        .expect("Failed to start server");
+    let port = server_handle.local_addr().unwrap().port();
+    (server_handle, SocketAddr::from(([127, 0, 0, 1], port)))
+}
+
+pub async fn join(server_address: SocketAddr, name: &str) -> ClientSession {
+    ClientSession::new(
+        server_address.to_string(),
+        name.to_string(),
+        DEFAULT_SEND_RATE,
+        None,
+        None,
+    )
+    .await
+    .expect("Failed to join")
+}
@@ -0,0 +1,75 @@
+use std::time::{Duration, Instant};
+
+use cgmath::{InnerSpace, Vector2};
+use multiplayer_game_demo_rust::history::{
+    PositionHistory, PositionSample, HISTORY_DURATION, MAX_HISTORY_SAMPLES,
+};
+use proptest::prelude::*;
+
+/// Positions with milliseconds passed since the previous one
+fn samples() -> impl Strategy<Value = Vec<(u64, Vector2<f32>)>> {
+    proptest::collection::vec(
+        (
+            0u64..100,
+            (-1200.0f32..1200.0, -1200.0f32..1200.0).prop_map(|(x, y)| Vector2::new(x, y)),
+        ),
+        1..300,
+    )
+}
+
+fn record_all(start: Instant, samples: &[(u64, Vector2<f32>)]) -> PositionHistory {
+    let mut history = PositionHistory::default();
+    let mut at = start;
+    for (tick, (elapsed_ms, pos)) in samples.iter().enumerate() {
+        at += Duration::from_millis(*elapsed_ms);
+        history.record(PositionSample {
+            at,
+            tick: tick as u64,
+            pos: *pos,
+        });
+    }
+    history
+}
+
+proptest! {
+    #[test]
+    fn history_keeps_only_recent_samples_in_order(samples in samples()) {
+        let history = record_all(Instant::now(), &samples);
+        let latest = *history.latest().unwrap();
+        prop_assert_eq!(latest.pos, samples.last().unwrap().1);
+        prop_assert!(history.samples().count() <= MAX_HISTORY_SAMPLES);
+        let kept: Vec<&PositionSample> = history.samples().collect();
+        for pair in kept.windows(2) {
+            prop_assert!(pair[0].at <= pair[1].at);
+        }
+        for sample in kept {
+            prop_assert!(latest.at.duration_since(sample.at) <= HISTORY_DURATION);
+        }
+    }
+
+    #[test]
+    fn position_at_lies_between_surrounding_samples(
+        samples in samples(),
+        offset_ms in 0u64..30_000,
+    ) {
+        let start = Instant::now();
+        let history = record_all(start, &samples);
+        let at = start + Duration::from_millis(offset_ms);
+        let pos = history.position_at(at).unwrap();
+
+        let kept: Vec<&PositionSample> = history.samples().collect();
+        let oldest = kept.first().unwrap();
+        let latest = kept.last().unwrap();
+        if at < oldest.at {
+            prop_assert_eq!(pos, oldest.pos);
+        } else if at >= latest.at {
+            prop_assert_eq!(pos, latest.pos);
+        } else {
+            let after_index = kept.iter().position(|sample| sample.at > at).unwrap();
+            let (before, after) = (kept[after_index - 1], kept[after_index]);
+            // On the segment between the two samples
+            let along = (pos - before.pos).magnitude() + (after.pos - pos).magnitude();
+            prop_assert!((along - (after.pos - before.pos).magnitude()).abs() < 0.1);
+        }
+    }
+}
@@ -0,0 +1,80 @@
+use std::{collections::HashSet, time::Instant};
+
+use multiplayer_game_demo_rust::{
+    fsm::{SessionMode, State},
+    hud::{self, GameMode, HudWidget},
+};
+
+/// Widgets showing what only a server knows about or can do
+const SERVER_WIDGETS: [HudWidget; 6] = [
+    HudWidget::EventFeed,
+    HudWidget::NetworkIndicator,
+    HudWidget::Profile,
+    HudWidget::PauseBanner,
+    HudWidget::HealthBar,
+    HudWidget::ServerPanel,
+];
+
+const GAME_MODES: [GameMode; 2] = [GameMode::Online, GameMode::Lockstep];
+
+/// Every state, and no state at all before the first one is pushed
+fn states() -> Vec<Option<State>> {
+    vec![
+        None,
+        Some(State::Menu),
+        Some(State::Attract),
+        Some(State::Replay),
+        Some(State::Connecting {
+            server_address: String::new(),
+            session_mode: SessionMode::ConnectAsClientOnly,
+        }),
+        Some(State::Loading { progress: 0.5 }),
+        Some(State::Playing),
+        Some(State::Reconnecting {
+            reason: String::new(),
+            give_up_at: Instant::now(),
+        }),
+        Some(State::Results {
+            standings: Vec::new(),
+            next_match_at: Instant::now(),
+        }),
+        Some(State::Disconnected),
+        Some(State::Editor),
+        Some(State::QuitDialog),
+        Some(State::Quit),
+    ]
+}
+
+#[test]
+fn widgets_are_drawn_once() {
+    for state in states() {
+        for mode in GAME_MODES {
+            let widgets = hud::widgets(state.as_ref(), mode);
+            let unique: HashSet<_> = widgets.iter().collect();
+            assert_eq!(unique.len(), widgets.len(), "{widgets:?}");
+        }
+    }
+}
+
+#[test]
+fn only_gameplay_has_a_hud() {
+    for state in states() {
+        let gameplay = matches!(state, Some(State::Playing) | Some(State::Replay));
+        for mode in GAME_MODES {
+            assert_eq!(!hud::widgets(state.as_ref(), mode).is_empty(), gameplay);
+        }
+    }
+}
+
+#[test]
+fn lockstep_has_no_server_widgets() {
+    for state in states() {
+        let widgets = hud::widgets(state.as_ref(), GameMode::Lockstep);
+        for widget in SERVER_WIDGETS {
+            assert!(
+                !widgets.contains(&widget),
+                "{widget:?} shown in lockstep mode"
+            );
+        }
+    }
+}
@@ -0,0 +1,115 @@
+use std::collections::HashMap;
+
+use multiplayer_game_demo_rust::input::{
+    InputBuffer, InputEvent, InputPlayback, InputRecorder, InputRecording, InputState, KeyTarget,
+    KeyboardFocus,
+};
+use proptest::prelude::*;
+use winit::keyboard::KeyCode;
+
+/// Keys routed in focus tests, whether the game uses them
+const KEYS: [(KeyCode, bool); 4] = [
+    (KeyCode::KeyW, true),
+    (KeyCode::ArrowLeft, true),
+    (KeyCode::Enter, false),
+    (KeyCode::KeyT, false),
+];
+
+/// Keys held on consecutive ticks
+fn held_keys() -> impl Strategy<Value = Vec<InputState>> {
+    proptest::collection::vec(any::<[bool; 4]>(), 0..200)
+}
+
+/// Key presses, holds and releases in order
+fn key_changes() -> impl Strategy<Value = Vec<(usize, u8)>> {
+    proptest::collection::vec((0..InputEvent::ALL.len(), 0..3u8), 0..50)
+}
+
+proptest! {
+    #[test]
+    fn playback_reproduces_recorded_keys(ticks in held_keys()) {
+        let mut recorder = InputRecorder::default();
+        for (tick, state) in ticks.iter().enumerate() {
+            recorder.record(tick as u64, state);
+        }
+        let recording = recorder.finish(ticks.len() as u64);
+
+        let mut playback = InputPlayback::new(recording);
+        for (tick, state) in ticks.iter().enumerate() {
+            prop_assert_eq!(playback.advance(tick as u64), *state);
+        }
+        prop_assert_eq!(playback.advance(ticks.len() as u64), InputState::default());
+        prop_assert!(playback.is_finished());
+    }
+
+    #[test]
+    fn recording_text_round_trips(ticks in held_keys()) {
+        let mut recorder = InputRecorder::default();
+        for (tick, state) in ticks.iter().enumerate() {
+            recorder.record(tick as u64, state);
+        }
+        let recording = recorder.finish(ticks.len() as u64);
+
+        let parsed: InputRecording = recording.to_string().parse().unwrap();
+        prop_assert_eq!(parsed, recording);
+    }
+
+    #[test]
+    fn buffered_presses_are_taken_once(changes in key_changes()) {
+        let mut buffer = InputBuffer::default();
+        let mut held = InputState::default();
+        let mut pressed = InputState::default();
+        for (index, change) in changes {
+            let event = InputEvent::ALL[index];
+            match change {
+                0 => {
+                    buffer.press(event);
+                    held[event] = true;
+                    pressed[event] = true;
+                }
+                1 => {
+                    buffer.hold(event);
+                    held[event] = true;
+                }
+                _ => {
+                    buffer.release(event);
+                    held[event] = false;
+                }
+            }
+        }
+        prop_assert_eq!(buffer.held(), held);
+        let taken: InputState = std::array::from_fn(|index| held[index] || pressed[index]);
+        prop_assert_eq!(buffer.take(), taken);
+        prop_assert_eq!(buffer.take(), held);
+        buffer.release_all();
+        prop_assert_eq!(buffer.take(), InputState::default());
+    }
+
+    #[test]
+    fn releases_go_where_presses_went(
+        events in proptest::collection::vec((0..KEYS.len(), any::<bool>(), any::<bool>()), 0..100),
+    ) {
+        let mut focus = KeyboardFocus::default();
+        // Receiver of the press of every key held down
+        let mut presses = HashMap::new();
+        for (index, pressed, gui_wants_keyboard) in events {
+            let (key, game_key) = KEYS[index];
+            let target = focus.route(key, pressed, game_key, gui_wants_keyboard);
+            if !game_key {
+                prop_assert_eq!(target, KeyTarget::Gui);
+            }
+            if pressed {
+                // Repeats of a held key go where the first press went
+                let first = *presses.entry(key).or_insert(target);
+                prop_assert_eq!(target, first);
+            } else if let Some(press) = presses.remove(&key) {
+                prop_assert_eq!(target, press);
+            }
+        }
+    }
+
+    #[test]
+    fn recording_rejects_garbage_without_panicking(text in "\\PC*") {
+        let _ = text.parse::<InputRecording>();
+    }
+}
@@ -0,0 +1,40 @@
+use std::net::{IpAddr, SocketAddr};
+
+use multiplayer_game_demo_rust::invite;
+use proptest::prelude::*;
+
+/// Scope and flow of IPv6 addresses are only meaningful on the host itself, so codes leave them out
+fn server_address() -> impl Strategy<Value = SocketAddr> {
+    (any::<IpAddr>(), any::<u16>()).prop_map(|(ip, port)| SocketAddr::new(ip, port))
+}
+
+proptest! {
+    #[test]
+    fn code_round_trips(address in server_address()) {
+        prop_assert_eq!(invite::decode(&invite::encode(address)), Ok(address));
+    }
+
+    #[test]
+    fn code_ignores_case_and_dashes(address in server_address()) {
+        let code = invite::encode(address).to_lowercase().replace('-', "");
+        prop_assert_eq!(invite::decode(&code), Ok(address));
+    }
+
+    #[test]
+    fn mistyped_character_is_rejected(
+        address in server_address(),
+        index in any::<prop::sample::Index>(),
+        replacement in prop::sample::select(b"0123456789ABCDEFGHJKMNPQRSTVWXYZ".to_vec()),
+    ) {
+        let mut code: Vec<u8> = invite::encode(address).replace('-', "").into_bytes();
+        let index = index.index(code.len());
+        prop_assume!(code[index] != replacement);
+        code[index] = replacement;
+        prop_assert!(invite::decode(std::str::from_utf8(&code).unwrap()).is_err());
+    }
+
+    #[test]
+    fn garbage_is_rejected_without_panicking(text in "\\PC*") {
+        let _ = invite::decode(&text);
+    }
+}
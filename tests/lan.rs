@@ -0,0 +1,58 @@
+use std::{
+    collections::HashMap,
+    net::{Ipv4Addr, SocketAddr},
+    time::{Duration, Instant},
+};
+
+use multiplayer_game_demo_rust::lan::{self, LanServer, LanServers};
+use proptest::prelude::*;
+
+/// Beacons at increasing times, as milliseconds since the previous beacon, from one of a few
+/// servers with one of a few player counts
+fn beacons() -> impl Strategy<Value = Vec<(u64, u8, u32)>> {
+    prop::collection::vec((0..4_000u64, 0..4u8, 0..3u32), 0..100)
+}
+
+fn server(host: u8, players: u32) -> LanServer {
+    LanServer {
+        address: SocketAddr::new(Ipv4Addr::new(192, 168, 0, host).into(), 8080),
+        name: format!("Server {}", 3 - host),
+        players,
+    }
+}
+
+proptest! {
+    #[test]
+    fn servers_are_listed_until_their_beacons_stop(
+        beacons in beacons(),
+        silence_ms in 0..10_000u64,
+    ) {
+        let mut servers = LanServers::default();
+        let mut now = Instant::now();
+        let mut latest = HashMap::new();
+        for (delay, host, players) in beacons {
+            now += Duration::from_millis(delay);
+            servers.expire(now);
+            servers.record(server(host, players), now);
+            latest.insert(host, (server(host, players), now));
+        }
+        now += Duration::from_millis(silence_ms);
+        servers.expire(now);
+        let mut expected: Vec<LanServer> = latest
+            .into_values()
+            .filter(|(_, heard_at)| now.duration_since(*heard_at) < lan::SERVER_TIMEOUT)
+            .map(|(server, _)| server)
+            .collect();
+        expected.sort_by(|a, b| a.name.cmp(&b.name));
+        prop_assert_eq!(servers.list(), expected);
+    }
+
+    #[test]
+    fn repeated_beacon_does_not_change_the_list(host in 0..4u8, players in any::<u32>()) {
+        let mut servers = LanServers::default();
+        let now = Instant::now();
+        prop_assert!(servers.record(server(host, players), now));
+        prop_assert!(!servers.record(server(host, players), now + lan::BEACON_INTERVAL));
+        prop_assert!(servers.record(server(host, players.wrapping_add(1)), now));
+    }
+}
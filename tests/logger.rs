@@ -0,0 +1,55 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use multiplayer_game_demo_rust::logger::LogDedup;
+use proptest::prelude::*;
+
+const WINDOW: Duration = Duration::from_secs(10);
+
+/// Lines logged at increasing times, as milliseconds since the previous line and one of a few
+/// messages so that repeats are common
+fn lines() -> impl Strategy<Value = Vec<(u64, u8)>> {
+    prop::collection::vec((0..5_000u64, 0..4u8), 0..200)
+}
+
+proptest! {
+    #[test]
+    fn every_line_is_written_or_counted(lines in lines()) {
+        let mut dedup = LogDedup::new(WINDOW);
+        let mut now = Instant::now();
+        let mut logged = HashMap::<u8, u32>::new();
+        let mut reported = HashMap::<u8, u32>::new();
+        for (delay, line) in lines {
+            now += Duration::from_millis(delay);
+            *logged.entry(line).or_default() += 1;
+            for (line, count) in dedup.expired(now) {
+                *reported.entry(line).or_default() += count;
+            }
+            if dedup.admit(line, now) {
+                *reported.entry(line).or_default() += 1;
+            }
+        }
+        for (line, count) in dedup.expired(now + WINDOW) {
+            *reported.entry(line).or_default() += count;
+        }
+        prop_assert_eq!(reported, logged);
+    }
+
+    #[test]
+    fn line_is_written_at_most_once_per_window(lines in lines()) {
+        let mut dedup = LogDedup::new(WINDOW);
+        let mut now = Instant::now();
+        let mut written_at = HashMap::<u8, Instant>::new();
+        for (delay, line) in lines {
+            now += Duration::from_millis(delay);
+            dedup.expired(now);
+            if dedup.admit(line, now) {
+                if let Some(previous) = written_at.insert(line, now) {
+                    prop_assert!(now.duration_since(previous) >= WINDOW);
+                }
+            }
+        }
+    }
+}
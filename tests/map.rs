@@ -0,0 +1,99 @@
+use cgmath::{Vector2, Vector3};
+use multiplayer_game_demo_rust::{
+    globals,
+    map::{self, Map, MapDownload, MapInfo, Obstacle, ProceduralParams},
+    Player,
+};
+use proptest::prelude::*;
+
+/// Whole-unit obstacles, so that printing and parsing them is exact
+fn obstacle() -> impl Strategy<Value = Obstacle> {
+    (-1200i32..1100, -1200i32..1100, 1i32..=100, 1i32..=100).prop_map(|(x, y, width, height)| {
+        Obstacle {
+            min: Vector2::new(x as f32, y as f32),
+            size: Vector2::new(width as f32, height as f32),
+        }
+    })
+}
+
+fn spawn_point() -> impl Strategy<Value = Vector2<f32>> {
+    (-1200i32..=1200, -1200i32..=1200).prop_map(|(x, y)| Vector2::new(x as f32, y as f32))
+}
+
+proptest! {
+    #[test]
+    fn serialized_map_is_parsed_unchanged(
+        obstacles in proptest::collection::vec(obstacle(), 0..=map::MAX_OBSTACLES),
+        spawn_points in proptest::collection::vec(spawn_point(), 0..=map::MAX_SPAWN_POINTS),
+    ) {
+        let map = Map { obstacles, spawn_points };
+        prop_assert_eq!(Map::parse(&map.serialize()), Ok(map));
+    }
+
+    #[test]
+    fn pushed_out_player_does_not_overlap_obstacle(
+        obstacle in obstacle(),
+        x in -1200.0f32..1200.0,
+        y in -1200.0f32..1200.0,
+        player_size in globals::MIN_PLAYER_SIZE..=globals::MAX_PLAYER_SIZE,
+    ) {
+        let map = Map {
+            obstacles: vec![obstacle],
+            ..Map::default()
+        };
+        let mut player = Player {
+            pos: Vector2::new(x, y),
+            ..Player::new(1, Vector3::new(1.0, 1.0, 1.0))
+        };
+        map.push_out(&mut player, player_size);
+        let half_size = player_size / 2.0;
+        let overlaps = player.pos.x + half_size > obstacle.min.x + 0.01
+            && player.pos.x - half_size < obstacle.min.x + obstacle.size.x - 0.01
+            && player.pos.y + half_size > obstacle.min.y + 0.01
+            && player.pos.y - half_size < obstacle.min.y + obstacle.size.y - 0.01;
+        prop_assert!(!overlaps, "{:?} overlaps {:?}", player.pos, obstacle);
+    }
+
+    #[test]
+    fn download_reassembles_chunks_arriving_in_any_order(
+        data in proptest::collection::vec(any::<u8>(), 1..=map::MAX_MAP_SIZE),
+        seed in any::<u64>(),
+    ) {
+        let info = MapInfo {
+            name: "test".to_string(),
+            checksum: map::checksum(&data),
+            size: data.len() as u32,
+        };
+        let mut download = MapDownload::new(info);
+        let mut offsets: Vec<usize> = (0..data.len()).step_by(map::MAP_CHUNK_SIZE).collect();
+        // Deterministic shuffle
+        offsets.sort_by_key(|offset| (*offset as u64 ^ seed).wrapping_mul(0x9E37_79B9_7F4A_7C15));
+        for (i, offset) in offsets.iter().enumerate() {
+            prop_assert!(download.data().is_none());
+            let end = data.len().min(offset + map::MAP_CHUNK_SIZE);
+            download.add_chunk(*offset as u32, data[*offset..end].to_vec());
+            prop_assert!(download.progress() >= (i + 1) as f32 / offsets.len() as f32 - 0.001);
+        }
+        prop_assert_eq!(download.data(), Some(data));
+    }
+
+    #[test]
+    fn generated_map_keeps_obstacles_apart_and_spawn_points_clear(
+        seed in any::<u64>(),
+        density in 0.0f32..=0.5,
+    ) {
+        let map = map::generate(&ProceduralParams { seed, density });
+        prop_assert!(!map.spawn_points.is_empty());
+        for (index, obstacle) in map.obstacles.iter().enumerate() {
+            for spawn_point in &map.spawn_points {
+                prop_assert!(map::distance_to(obstacle, *spawn_point) > globals::MAX_PLAYER_SIZE);
+            }
+            for other in &map.obstacles[index + 1..] {
+                prop_assert!(map::gap_between(obstacle, other) > 0.0);
+            }
+        }
+        // Served as a map file, so it has to survive the map transfer
+        prop_assert_eq!(Map::parse(&map.serialize()), Ok(map.clone()));
+        prop_assert_eq!(map::generate(&ProceduralParams { seed, density }), map);
+    }
+}
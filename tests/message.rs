@@ -0,0 +1,361 @@
+use cgmath::{Vector2, Vector3};
+use multiplayer_game_demo_rust::{
+    afk::PlayerActivity,
+    globals,
+    map::{self, MapInfo},
+    message::{
+        self, Channel, ConnectionStats, DatagramHeader, GameEvent, MatchResult, Message,
+        ServerUsage,
+    },
+    storm::{self, SafeZone, StormState},
+    Player, WorldParams,
+};
+use proptest::prelude::*;
+
+fn player_id() -> impl Strategy<Value = u64> {
+    1..=u64::MAX
+}
+
+/// Colors are sent with 8 bits per channel
+fn color() -> impl Strategy<Value = Vector3<f32>> {
+    any::<[u8; 3]>()
+        .prop_map(|[r, g, b]| Vector3::new(r as f32 / 255.0, g as f32 / 255.0, b as f32 / 255.0))
+}
+
+fn world_params() -> impl Strategy<Value = WorldParams> {
+    (
+        globals::MIN_PLAYER_SIZE..=globals::MAX_PLAYER_SIZE,
+        globals::MIN_PLAYER_SPEED..=globals::MAX_PLAYER_SPEED,
+    )
+        .prop_map(|(player_size, player_speed)| WorldParams {
+            player_size,
+            player_speed,
+        })
+}
+
+/// Positions are sent as 16-bit fixed-point numbers
+fn position() -> impl Strategy<Value = Vector2<f32>> {
+    any::<(u16, u16)>().prop_map(|(x, y)| message::dequantize_position(x, y))
+}
+
+/// Radiuses are sent in whole world units
+fn safe_zone() -> impl Strategy<Value = SafeZone> {
+    (position(), any::<u16>()).prop_map(|(center, radius)| SafeZone {
+        center,
+        radius: radius.into(),
+    })
+}
+
+fn name(max_len: usize) -> impl Strategy<Value = String> {
+    proptest::string::string_regex(&format!("[^\\p{{Cc}}]{{0,{max_len}}}")).unwrap()
+}
+
+fn text() -> impl Strategy<Value = String> {
+    proptest::string::string_regex(&format!(
+        "[^\\p{{Cc}}\\s][^\\p{{Cc}}]{{0,{}}}",
+        globals::MAX_CHAT_MESSAGE_LEN - 1
+    ))
+    .unwrap()
+}
+
+fn message() -> impl Strategy<Value = Message> {
+    prop_oneof![
+        any::<u64>().prop_map(Message::Ping),
+        any::<(u64, u64)>().prop_map(|(token, time)| Message::Pong(token, time)),
+        (name(globals::MAX_PLAYER_NAME_LEN), any::<Option<u64>>())
+            .prop_map(|(name, token)| Message::Handshake(name, token)),
+        (
+            player_id(),
+            any::<u64>(),
+            color(),
+            any::<u64>(),
+            name(globals::MAX_SERVER_NAME_LEN),
+            world_params(),
+        )
+            .prop_map(
+                |(id, token, color, seed, server_name, world_params)| Message::Ack(
+                    id,
+                    token,
+                    color,
+                    seed,
+                    server_name,
+                    world_params
+                )
+            ),
+        text().prop_map(Message::Reject),
+        (1..=u32::MAX).prop_map(Message::Queued),
+        Just(Message::Query),
+        (
+            name(globals::MAX_SERVER_NAME_LEN),
+            any::<(usize, usize, u64, u64)>()
+        )
+            .prop_map(|(server_name, (players, peak_players, joins, messages))| {
+                Message::Info(
+                    server_name,
+                    ServerUsage {
+                        players,
+                        peak_players,
+                        joins,
+                        messages,
+                    },
+                )
+            }),
+        (
+            name(globals::MAX_SERVER_NAME_LEN),
+            any::<u16>(),
+            any::<u32>()
+        )
+            .prop_map(|(server_name, port, players)| Message::Beacon(
+                server_name,
+                port,
+                players
+            )),
+        player_id().prop_map(Message::Leave),
+        any::<u64>().prop_map(Message::Disconnect),
+        Just(Message::Shutdown),
+        (any::<u64>(), player_id(), position(), color()).prop_map(|(tick, id, pos, color)| {
+            Message::Replicate(
+                tick,
+                Player {
+                    id,
+                    pos,
+                    velocity: Vector2::new(0.0, 0.0),
+                    color,
+                },
+            )
+        }),
+        proptest::collection::vec(any::<u8>(), 0..256).prop_map(Message::Snapshot),
+        any::<(u64, u64)>().prop_map(|(token, tick)| Message::SnapshotAck(token, tick)),
+        text().prop_map(Message::Notice),
+        (0..=globals::MAX_DATAGRAM_SIZE as u16).prop_map(Message::Probe),
+        (any::<u64>(), any::<u16>()).prop_map(|(token, size)| Message::ProbeAck(token, size)),
+        (player_id(), text()).prop_map(|(id, text)| Message::Chat(id, text)),
+        (any::<u64>(), text()).prop_map(|(token, text)| Message::Say(token, text)),
+        (any::<u64>(), color(), name(globals::MAX_PLAYER_NAME_LEN))
+            .prop_map(|(token, color, name)| Message::UpdateProfile(token, color, name)),
+        (player_id(), color(), name(globals::MAX_PLAYER_NAME_LEN))
+            .prop_map(|(id, color, name)| Message::Profile(id, color, name)),
+        (
+            player_id(),
+            prop_oneof![
+                Just(PlayerActivity::Active),
+                Just(PlayerActivity::Afk),
+                Just(PlayerActivity::Spectating)
+            ]
+        )
+            .prop_map(|(id, activity)| Message::Activity(id, activity)),
+        (any::<u64>(), position()).prop_map(|(token, pos)| Message::Position(token, pos)),
+        (
+            player_id(),
+            0..u64::MAX / 2,
+            proptest::collection::vec(0u8..16, 1..=message::MAX_INPUTS_PER_MESSAGE)
+        )
+            .prop_map(|(id, first_tick, inputs)| Message::Input(id, first_tick, inputs)),
+        (player_id(), name(globals::MAX_PLAYER_NAME_LEN))
+            .prop_map(|(id, name)| Message::Event(GameEvent::Joined(id, name))),
+        (player_id(), name(globals::MAX_PLAYER_NAME_LEN))
+            .prop_map(|(id, name)| Message::Event(GameEvent::Left(id, name))),
+        (player_id(), name(globals::MAX_PLAYER_NAME_LEN))
+            .prop_map(|(id, name)| Message::Event(GameEvent::KnockedOut(id, name))),
+        // Few enough results to fit into a datagram even with long names
+        (
+            any::<u32>(),
+            proptest::collection::vec(
+                (
+                    player_id(),
+                    name(globals::MAX_PLAYER_NAME_LEN),
+                    any::<u32>()
+                ),
+                0..=4
+            )
+        )
+            .prop_map(|(countdown, standings)| Message::MatchEnd(
+                countdown,
+                standings
+                    .into_iter()
+                    .map(|(player_id, name, score)| MatchResult {
+                        player_id,
+                        name,
+                        score
+                    })
+                    .collect()
+            )),
+        (any::<u32>(), 0u8..=100, any::<u64>()).prop_map(
+            |(rtt_ms, packet_loss_percent, corrections)| Message::Stats(ConnectionStats {
+                rtt_ms,
+                packet_loss_percent,
+                corrections,
+            })
+        ),
+        (0..globals::MINUTES_PER_DAY).prop_map(Message::TimeOfDay),
+        any::<bool>().prop_map(Message::Paused),
+        proptest::option::of((safe_zone(), safe_zone(), any::<u32>()).prop_map(
+            |(zone, target, time_left_ms)| StormState {
+                zone,
+                target,
+                time_left: std::time::Duration::from_millis(time_left_ms.into()),
+            }
+        ))
+        .prop_map(Message::Storm),
+        (0..=storm::MAX_HEALTH).prop_map(Message::Health),
+        proptest::option::of((player_id(), any::<u32>())).prop_map(Message::Leader),
+        (
+            "[a-zA-Z0-9_-]{1,32}",
+            any::<u32>(),
+            0..=map::MAX_MAP_SIZE as u32
+        )
+            .prop_map(|(name, checksum, size)| Message::Map(MapInfo {
+                name,
+                checksum,
+                size
+            })),
+        (any::<u64>(), any::<u32>(), 0..map::MAX_MAP_SIZE as u32)
+            .prop_map(|(token, checksum, offset)| Message::MapRequest(token, checksum, offset)),
+        (
+            any::<u32>(),
+            0..map::MAX_MAP_SIZE as u32,
+            proptest::collection::vec(any::<u8>(), 1..=map::MAP_CHUNK_SIZE)
+        )
+            .prop_map(|(checksum, offset, data)| Message::MapChunk(checksum, offset, data)),
+        proptest::collection::vec(
+            ("[a-zA-Z0-9_-]{1,32}", any::<u32>()),
+            1..=message::MAX_MAP_VOTE_CANDIDATES
+        )
+        .prop_map(Message::MapVote),
+    ]
+}
+
+proptest! {
+    #[test]
+    fn quantized_position_is_within_step_of_original(
+        x in -1200.0f32..=1200.0,
+        y in -1200.0f32..=1200.0,
+    ) {
+        let (quantized_x, quantized_y) = message::quantize_position(Vector2::new(x, y));
+        let pos = message::dequantize_position(quantized_x, quantized_y);
+        let bounds = &globals::WORLD_BOUNDS;
+        prop_assert!((pos.x - x).abs() <= (bounds.max_x - bounds.min_x) / u16::MAX as f32);
+        prop_assert!((pos.y - y).abs() <= (bounds.max_y - bounds.min_y) / u16::MAX as f32);
+    }
+
+    #[test]
+    fn serialized_message_is_deserialized_unchanged(msg in message()) {
+        let deserialized = Message::deserialize(&msg.serialize());
+        prop_assert!(deserialized.is_ok(), "{:?} was rejected", msg.serialize());
+        prop_assert_eq!(deserialized.unwrap(), msg);
+    }
+
+    #[test]
+    fn serialize_into_appends_to_buffer(
+        prefix in proptest::collection::vec(any::<u8>(), 0..64),
+        msg in message(),
+    ) {
+        let mut buffer = prefix.clone();
+        msg.serialize_into(&mut buffer);
+        prop_assert_eq!(buffer, [prefix, msg.serialize()].concat());
+    }
+
+    #[test]
+    fn probe_fills_datagram_of_its_size(size in message::HEADER_LEN as u16 + 16..=globals::MAX_DATAGRAM_SIZE as u16) {
+        let datagram = DatagramHeader::default().encode(&Message::Probe(size).serialize());
+        prop_assert_eq!(datagram.len(), usize::from(size));
+    }
+
+    #[test]
+    fn datagram_is_decoded_unchanged(
+        msg in message(),
+        channel in proptest::sample::select(Channel::ALL.to_vec()),
+        sequence in any::<u16>(),
+    ) {
+        let header = DatagramHeader {
+            channel,
+            sequence,
+            ..DatagramHeader::default()
+        };
+        let serialized = msg.serialize();
+        let datagram = header.encode(&serialized);
+        prop_assert_eq!(datagram.len(), message::HEADER_LEN + serialized.len());
+        let (decoded_header, payload) = DatagramHeader::decode(&datagram).unwrap();
+        prop_assert_eq!(decoded_header, header);
+        prop_assert_eq!(Message::deserialize(payload).unwrap(), msg);
+    }
+
+    #[test]
+    fn foreign_datagrams_are_rejected(
+        msg in message(),
+        magic in any::<[u8; 2]>(),
+        version in any::<u8>(),
+        flags in any::<u8>(),
+    ) {
+        let mut datagram = DatagramHeader::default().encode(&msg.serialize());
+        datagram[..2].copy_from_slice(&magic);
+        datagram[2] = version;
+        datagram[3] = flags;
+        let is_compatible = &magic == message::PROTOCOL_MAGIC
+            && version == message::PROTOCOL_VERSION
+            && flags & !message::FLAG_ACK == 0;
+        prop_assert_eq!(DatagramHeader::decode(&datagram).is_ok(), is_compatible);
+    }
+
+    #[test]
+    fn decode_does_not_panic_on_any_input(bytes in proptest::collection::vec(any::<u8>(), 0..1024)) {
+        let _ = DatagramHeader::decode(&bytes);
+    }
+
+    #[test]
+    fn protocol_schema_documents_every_message(msg in message()) {
+        let schema: serde_json::Value = serde_json::from_str(message::PROTOCOL_SCHEMA).unwrap();
+        let tag = msg.serialize()[0];
+        let entry = schema["messages"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|entry| entry["tag"] == u64::from(tag));
+        prop_assert!(entry.is_some(), "{:#04X} is not in the schema", tag);
+        let entry = entry.unwrap();
+        // Debug output of a variant starts with its name
+        let name = entry["name"].as_str().unwrap();
+        prop_assert!(format!("{msg:?}").starts_with(name), "{} is not {:?}", name, msg);
+        prop_assert_eq!(entry["type"].as_str().unwrap(), msg.name());
+        prop_assert!(entry["format"].as_str().unwrap().starts_with(msg.name()));
+        prop_assert_eq!(entry["channel"].as_str().unwrap(), format!("{:?}", msg.channel()));
+    }
+
+    #[test]
+    fn deserialize_does_not_panic_on_any_input(bytes in proptest::collection::vec(any::<u8>(), 0..1024)) {
+        let _ = Message::deserialize(&bytes);
+    }
+
+    #[test]
+    fn deserialize_does_not_panic_on_malformed_fields(
+        msg in message(),
+        fields in proptest::collection::vec(any::<u8>(), 0..64),
+    ) {
+        // Known tag followed by fields of any length and content
+        let bytes = [&msg.serialize()[..1], &fields].concat();
+        let _ = Message::deserialize(&bytes);
+    }
+
+    #[test]
+    fn truncated_message_is_rejected(msg in message(), cut in any::<prop::sample::Index>()) {
+        // Padding of probes can be lost without changing their meaning
+        prop_assume!(!matches!(msg, Message::Probe(_)));
+        let serialized = msg.serialize();
+        let len = cut.index(serialized.len());
+        prop_assert!(Message::deserialize(&serialized[..len]).is_err());
+    }
+
+    #[test]
+    fn ack_with_unsupported_world_params_is_rejected(
+        player_size in any::<f32>(),
+        player_speed in any::<f32>(),
+    ) {
+        let world_params = WorldParams { player_size, player_speed };
+        let serialized = Message::Ack(1, 1, Vector3::new(0.0, 0.0, 0.0), 0, "Server".to_string(), world_params)
+            .serialize();
+        prop_assert_eq!(
+            Message::deserialize(&serialized).is_ok(),
+            message::validate_world_params(&world_params).is_ok()
+        );
+    }
+}
@@ -0,0 +1,276 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use cgmath::Vector3;
+use multiplayer_game_demo_rust::{
+    message::{self, Channel, DatagramHeader, Message},
+    net::{self, Channels},
+    Player,
+};
+use proptest::prelude::*;
+
+/// Message payload telling its index
+fn indexed(index: usize) -> Vec<u8> {
+    (index as u32).to_be_bytes().to_vec()
+}
+
+fn index_of(msg: &[u8]) -> usize {
+    u32::from_be_bytes(msg.try_into().unwrap()) as usize
+}
+
+/// What happens to each datagram on the way: dropped, duplicated, and a delay deciding the order
+/// of arrival
+fn fates(count: usize) -> impl Strategy<Value = Vec<(bool, bool, u8)>> {
+    proptest::collection::vec(
+        (proptest::bool::weighted(0.3), any::<bool>(), any::<u8>()),
+        count,
+    )
+}
+
+/// Datagrams in order of arrival, with the dropped ones left out
+fn deliver(datagrams: Vec<Vec<u8>>, fates: &[(bool, bool, u8)]) -> Vec<Vec<u8>> {
+    let mut arriving: Vec<(u8, Vec<u8>)> = Vec::new();
+    for (datagram, &(dropped, duplicated, delay)) in datagrams.into_iter().zip(fates) {
+        if dropped {
+            continue;
+        }
+        if duplicated {
+            arriving.push((delay / 2, datagram.clone()));
+        }
+        arriving.push((delay, datagram));
+    }
+    arriving.sort_by_key(|(delay, _)| *delay);
+    arriving.into_iter().map(|(_, datagram)| datagram).collect()
+}
+
+/// Messages received by the peer of `sender`, passing acknowledgements back until every message
+/// has been acknowledged. Every round loses datagrams according to the fates.
+fn transfer(
+    sender: &mut Channels,
+    sent: Vec<Vec<u8>>,
+    fates: &[Vec<(bool, bool, u8)>],
+) -> Vec<Vec<u8>> {
+    let mut receiver = Channels::new();
+    let mut received = Vec::new();
+    let mut now = Instant::now();
+    let mut datagrams = sent;
+    for round_fates in fates
+        .iter()
+        .map(Vec::as_slice)
+        .chain(std::iter::repeat(&[][..]))
+    {
+        // Last rounds are lossless, so that everything arrives eventually
+        let round_fates: Vec<(bool, bool, u8)> = (0..datagrams.len())
+            .map(|index| round_fates.get(index).copied().unwrap_or((false, false, 0)))
+            .collect();
+        let mut acks = Vec::new();
+        for datagram in deliver(datagrams, &round_fates) {
+            let (header, payload) = DatagramHeader::decode(&datagram).unwrap();
+            let delivery = receiver.receive(header, payload);
+            received.extend(delivery.messages);
+            acks.extend(delivery.ack);
+        }
+        for ack in deliver(acks, &round_fates) {
+            let (header, payload) = DatagramHeader::decode(&ack).unwrap();
+            assert!(sender.receive(header, payload).messages.is_empty());
+        }
+        now += net::RESEND_INTERVAL + Duration::from_millis(1);
+        datagrams = sender.resend_due(now).unwrap();
+        if datagrams.is_empty() {
+            break;
+        }
+    }
+    received
+}
+
+proptest! {
+    #[test]
+    fn reliable_ordered_messages_arrive_once_in_order(
+        count in 1..50usize,
+        fates in proptest::collection::vec(fates(50), 0..5),
+    ) {
+        let mut sender = Channels::new();
+        let messages: Vec<Vec<u8>> = (0..count).map(indexed).collect();
+        let sent = messages
+            .iter()
+            .map(|msg| sender.send(Channel::ReliableOrdered, msg, Instant::now()))
+            .collect();
+        prop_assert_eq!(transfer(&mut sender, sent, &fates), messages);
+    }
+
+    #[test]
+    fn reliable_unordered_messages_arrive_once(
+        count in 1..50usize,
+        fates in proptest::collection::vec(fates(50), 0..5),
+    ) {
+        let mut sender = Channels::new();
+        let messages: Vec<Vec<u8>> = (0..count).map(indexed).collect();
+        let sent = messages
+            .iter()
+            .map(|msg| sender.send(Channel::ReliableUnordered, msg, Instant::now()))
+            .collect();
+        let mut received = transfer(&mut sender, sent, &fates);
+        received.sort_by_key(|msg| index_of(msg));
+        prop_assert_eq!(received, messages);
+    }
+
+    #[test]
+    fn sequenced_messages_never_go_back(count in 1..50usize, fates in fates(50)) {
+        let mut sender = Channels::new();
+        let sent = (0..count)
+            .map(|index| sender.send(Channel::UnreliableSequenced, &indexed(index), Instant::now()))
+            .collect();
+        let mut receiver = Channels::new();
+        let mut received = Vec::new();
+        for datagram in deliver(sent, &fates) {
+            let (header, payload) = DatagramHeader::decode(&datagram).unwrap();
+            let delivery = receiver.receive(header, payload);
+            prop_assert!(delivery.ack.is_none());
+            received.extend(delivery.messages.iter().map(|msg| index_of(msg)));
+        }
+        prop_assert!(received.windows(2).all(|pair| pair[0] < pair[1]), "{:?}", received);
+        prop_assert!(sender.resend_due(Instant::now() + net::RESEND_INTERVAL)
+            .is_some_and(|resent| resent.is_empty()));
+    }
+
+    #[test]
+    fn batches_fit_into_packet_budget(
+        lengths in proptest::collection::vec(1..300usize, 1..40),
+        packet_budget in 200..=1472usize,
+    ) {
+        let mut sender = Channels::new();
+        sender.set_packet_budget(packet_budget);
+        let messages: Vec<Vec<u8>> = lengths
+            .iter()
+            .enumerate()
+            .map(|(index, &len)| [indexed(index), vec![b'x'; len]].concat())
+            .collect();
+        let datagrams = sender.send_batch(Channel::UnreliableSequenced, &messages, Instant::now());
+        let mut receiver = Channels::new();
+        let mut received = Vec::new();
+        for datagram in &datagrams {
+            let (header, payload) = DatagramHeader::decode(datagram).unwrap();
+            // Only messages too large for the budget on their own may exceed it
+            prop_assert!(
+                datagram.len() <= packet_budget || header.flags & message::FLAG_BATCH == 0
+            );
+            received.extend(receiver.receive(header, payload).messages);
+        }
+        prop_assert_eq!(received, messages);
+    }
+
+    #[test]
+    fn last_sent_follows_sends_and_resends(
+        channels in proptest::collection::vec(0..4u8, 1..20),
+        resend_after_ms in 0..300u64,
+    ) {
+        let start = Instant::now();
+        let mut sender = Channels::new();
+        prop_assert_eq!(sender.last_sent(), None);
+        for (index, &channel) in channels.iter().enumerate() {
+            let now = start + Duration::from_millis(index as u64);
+            sender.send(Channel::from_id(channel).unwrap(), b"x", now);
+            prop_assert_eq!(sender.last_sent(), Some(now));
+        }
+        let last_send = sender.last_sent();
+        let later = start + Duration::from_millis(channels.len() as u64 + resend_after_ms);
+        let resent = sender.resend_due(later).unwrap();
+        let expected = if resent.is_empty() { last_send } else { Some(later) };
+        prop_assert_eq!(sender.last_sent(), expected);
+    }
+
+    #[test]
+    fn acknowledgements_without_channels_stop_resending(
+        channels in proptest::collection::vec(0..4u8, 1..20),
+    ) {
+        let mut sender = Channels::new();
+        let channels: Vec<Channel> = channels
+            .into_iter()
+            .map(|channel| Channel::from_id(channel).unwrap())
+            .collect();
+        let datagrams: Vec<Vec<u8>> = channels
+            .iter()
+            .map(|&channel| sender.send(channel, b"x", Instant::now()))
+            .collect();
+        prop_assert_eq!(
+            sender.all_acknowledged(),
+            !channels.iter().any(|channel| channel.is_reliable())
+        );
+        // Peer whose session is over, like a server the player already left
+        for datagram in &datagrams {
+            let (header, _) = DatagramHeader::decode(datagram).unwrap();
+            let ack = net::acknowledgement(header);
+            prop_assert_eq!(ack.is_some(), header.channel.is_reliable());
+            if let Some(ack) = ack {
+                let (header, payload) = DatagramHeader::decode(&ack).unwrap();
+                prop_assert!(net::acknowledgement(header).is_none());
+                sender.receive(header, payload);
+            }
+        }
+        prop_assert!(sender.all_acknowledged());
+        prop_assert!(sender.resend_due(Instant::now() + net::RESEND_INTERVAL)
+            .is_some_and(|resent| resent.is_empty()));
+    }
+
+    #[test]
+    fn unacknowledged_messages_are_given_up_on(
+        channels in proptest::collection::vec(0..4u8, 1..20),
+        waited_ms in 0..10_000u64,
+    ) {
+        let start = Instant::now();
+        let mut sender = Channels::new();
+        let any_reliable = channels
+            .iter()
+            .any(|&channel| Channel::from_id(channel).unwrap().is_reliable());
+        for &channel in &channels {
+            sender.send(Channel::from_id(channel).unwrap(), b"x", start);
+        }
+        let waited = Duration::from_millis(waited_ms);
+        let resent = sender.resend_due(start + waited);
+        prop_assert_eq!(resent.is_none(), any_reliable && waited >= net::GIVE_UP_AFTER);
+        if resent.is_none() {
+            // Nothing is left to resend to the peer which is gone
+            prop_assert!(sender.all_acknowledged());
+            prop_assert_eq!(sender.resend_due(start + waited), Some(Vec::new()));
+        }
+    }
+
+    #[test]
+    fn sequenced_messages_only_supersede_their_own_kind(
+        kinds in proptest::collection::vec(0..4usize, 1..50),
+        fates in fates(50),
+    ) {
+        // Snapshots are not superseded on the channel, whatever their order
+        let snapshot_tag = Message::Replicate(0, Player::new(1, Vector3::new(1.0, 1.0, 1.0)))
+            .serialize()[0];
+        let tags = [
+            Message::Ping(0).serialize()[0],
+            Message::TimeOfDay(0).serialize()[0],
+            Message::Health(0).serialize()[0],
+            snapshot_tag,
+        ];
+        let mut sender = Channels::new();
+        let sent = kinds
+            .iter()
+            .enumerate()
+            .map(|(index, &kind)| {
+                let msg = [vec![tags[kind]], indexed(index)].concat();
+                sender.send(Channel::UnreliableSequenced, &msg, Instant::now())
+            })
+            .collect();
+        let mut receiver = Channels::new();
+        let mut newest_arrived: HashMap<u8, usize> = HashMap::new();
+        for datagram in deliver(sent, &fates) {
+            let (header, payload) = DatagramHeader::decode(&datagram).unwrap();
+            let (tag, index) = (payload[0], index_of(&payload[1..]));
+            let superseded = newest_arrived.get(&tag).is_some_and(|&newest| newest >= index);
+            if !superseded {
+                newest_arrived.insert(tag, index);
+            }
+            let delivered = !receiver.receive(header, payload).messages.is_empty();
+            prop_assert_eq!(delivered, tag == snapshot_tag || !superseded);
+        }
+    }
+}
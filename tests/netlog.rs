@@ -0,0 +1,63 @@
+use chrono::Local;
+use multiplayer_game_demo_rust::netlog::{TraceEntry, TraceRing};
+use proptest::prelude::*;
+
+fn entry(index: usize) -> TraceEntry {
+    TraceEntry {
+        time: Local::now(),
+        text: format!("Sent: {index}"),
+    }
+}
+
+proptest! {
+    #[test]
+    fn ring_keeps_newest_entries_in_order(capacity in 0..64usize, count in 0..200usize) {
+        let mut ring = TraceRing::new(capacity);
+        for index in 0..count {
+            ring.push(entry(index));
+        }
+        let kept: Vec<String> = ring
+            .entries(0..usize::MAX)
+            .into_iter()
+            .map(|entry| entry.text)
+            .collect();
+        let expected: Vec<String> = (count.saturating_sub(capacity)..count)
+            .map(|index| format!("Sent: {index}"))
+            .collect();
+        prop_assert_eq!(ring.len(), expected.len());
+        prop_assert_eq!(kept, expected);
+    }
+
+    #[test]
+    fn ranges_past_the_newest_entry_are_cut_off(
+        count in 0..100usize,
+        start in 0..150usize,
+        len in 0..150usize,
+    ) {
+        let mut ring = TraceRing::new(100);
+        for index in 0..count {
+            ring.push(entry(index));
+        }
+        let entries = ring.entries(start..start + len);
+        prop_assert_eq!(entries.len(), (start + len).min(count).saturating_sub(start));
+        for (offset, entry) in entries.iter().enumerate() {
+            prop_assert_eq!(&entry.text, &format!("Sent: {}", start + offset));
+        }
+    }
+
+    #[test]
+    fn written_log_has_a_line_per_entry(texts in proptest::collection::vec("[^\\p{Cc}]{0,40}", 0..50)) {
+        let mut ring = TraceRing::new(100);
+        for text in &texts {
+            ring.push(TraceEntry { time: Local::now(), text: text.clone() });
+        }
+        let mut out = Vec::new();
+        ring.write_to(&mut out).unwrap();
+        let written = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = written.lines().collect();
+        prop_assert_eq!(lines.len(), texts.len());
+        for (line, text) in lines.iter().zip(&texts) {
+            prop_assert!(line.ends_with(text.as_str()), "{:?} doesn't end with {:?}", line, text);
+        }
+    }
+}
@@ -0,0 +1,66 @@
+use std::{net::SocketAddr, time::Instant};
+
+use multiplayer_game_demo_rust::queue::{self, Admission, JoinQueue};
+use proptest::prelude::*;
+
+fn client(index: usize) -> SocketAddr {
+    SocketAddr::from(([127, 0, 0, 1], 10000 + index as u16))
+}
+
+/// Queue with the given number of clients who found the server full, in the order of their index
+fn full_queue(clients: usize, now: Instant) -> JoinQueue {
+    let mut join_queue = JoinQueue::default();
+    for index in 0..clients {
+        join_queue.request(client(index), 0, now);
+    }
+    join_queue
+}
+
+proptest! {
+    #[test]
+    fn queued_clients_get_consecutive_places(clients in 1..50usize) {
+        let now = Instant::now();
+        let mut join_queue = full_queue(clients, now);
+        for index in 0..clients {
+            prop_assert_eq!(
+                join_queue.request(client(index), 0, now),
+                Admission::Queued(index as u32 + 1)
+            );
+        }
+        prop_assert_eq!(join_queue.len(), clients);
+    }
+
+    #[test]
+    fn free_slots_go_to_the_front_of_the_queue(
+        (clients, free_slots, retries) in (1..30usize).prop_flat_map(|clients| {
+            (
+                Just(clients),
+                0..=clients,
+                Just((0..clients).collect::<Vec<_>>()).prop_shuffle(),
+            )
+        }),
+    ) {
+        let now = Instant::now();
+        let mut join_queue = full_queue(clients, now);
+        let mut free_slots_left = free_slots;
+        for index in retries {
+            let admission = join_queue.request(client(index), free_slots_left, now);
+            // Clients retrying early can't take the slot of those ahead of them
+            prop_assert_eq!(admission == Admission::Admit, index < free_slots);
+            if admission == Admission::Admit {
+                free_slots_left -= 1;
+            }
+        }
+        prop_assert_eq!(free_slots_left, 0);
+        prop_assert_eq!(join_queue.len(), clients - free_slots);
+    }
+
+    #[test]
+    fn clients_giving_up_leave_the_queue(clients in 1..50usize) {
+        let now = Instant::now();
+        let mut join_queue = full_queue(clients, now);
+        let later = now + queue::QUEUE_TIMEOUT;
+        prop_assert_eq!(join_queue.request(client(clients), 0, later), Admission::Queued(1));
+        prop_assert_eq!(join_queue.len(), 1);
+    }
+}
@@ -0,0 +1,72 @@
+use cgmath::{Vector2, Vector3};
+use multiplayer_game_demo_rust::{
+    map::Map,
+    replay::{Replay, ReplayPlayback, ReplayRecorder},
+    Player,
+};
+use proptest::prelude::*;
+
+/// Position already rounded to a tenth, like positions stored in replays
+fn position() -> impl Strategy<Value = Vector2<f32>> {
+    (-20_000..20_000i32, -20_000..20_000i32)
+        .prop_map(|(x, y)| Vector2::new(x as f32 / 10.0, y as f32 / 10.0))
+}
+
+/// Positions of a few players on consecutive snapshots
+fn snapshots() -> impl Strategy<Value = Vec<Vec<Vector2<f32>>>> {
+    (1..5usize).prop_flat_map(|player_count| {
+        proptest::collection::vec(proptest::collection::vec(position(), player_count), 0..50)
+    })
+}
+
+fn players(count: usize) -> Vec<Player> {
+    (1..=count as u64)
+        .map(|id| Player::new(id, Vector3::new(0.5, 0.25, 1.0)))
+        .collect()
+}
+
+fn record(name: &str, snapshots: &[Vec<Vector2<f32>>]) -> Replay {
+    let mut recorder = ReplayRecorder::new(42, "arena".to_string(), Map::default());
+    let mut players = players(snapshots.first().map_or(1, Vec::len));
+    for player in &players {
+        recorder.joined(0, player, name);
+    }
+    for (tick, positions) in snapshots.iter().enumerate() {
+        for (player, pos) in players.iter_mut().zip(positions) {
+            player.pos = *pos;
+        }
+        recorder.snapshot(tick as u64 + 1, &players);
+    }
+    recorder.finish(snapshots.len() as u64 + 1)
+}
+
+proptest! {
+    #[test]
+    fn replay_text_round_trips(
+        name in "[A-Za-z0-9]([A-Za-z0-9 ]{0,10}[A-Za-z0-9])?",
+        snapshots in snapshots(),
+    ) {
+        let replay = record(&name, &snapshots);
+        let parsed: Replay = replay.to_string().parse().unwrap();
+        prop_assert_eq!(parsed, replay);
+    }
+
+    #[test]
+    fn playback_shows_recorded_positions(snapshots in snapshots()) {
+        let mut playback = ReplayPlayback::new(record("Player", &snapshots));
+        for positions in &snapshots {
+            playback.update();
+            for (index, pos) in positions.iter().enumerate() {
+                let player = &playback.players()[&(index as u64 + 1)];
+                prop_assert_eq!(player.pos, *pos);
+            }
+        }
+        playback.update();
+        prop_assert!(playback.is_finished());
+    }
+
+    #[test]
+    fn replay_rejects_garbage_without_panicking(text in "\\PC*") {
+        let _ = text.parse::<Replay>();
+    }
+}
@@ -0,0 +1,359 @@
+use std::{
+    future::Future,
+    net::SocketAddr,
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+use common::{join, start_with};
+use multiplayer_game_demo_rust::{
+    client::ClientEvent,
+    globals,
+    message::{self, DatagramHeader, Message},
+    server::{self, ServerConfig, ServerHandle},
+    Player,
+};
+use proptest::prelude::*;
+use tokio::net::{TcpListener, UdpSocket};
+
+mod common;
+
+/// Time on top of the connection timeout for the server to notice the silence
+const TIMEOUT_MARGIN: Duration = Duration::from_secs(2);
+
+async fn start() -> (ServerHandle, SocketAddr) {
+    start_with(ServerConfig::default()).await
+}
+
+/// Client sending a handshake, then nothing at all, like one which crashed right after joining.
+/// The socket has to be kept, otherwise the server may learn from ICMP that it's gone.
+async fn join_silently(server_address: SocketAddr, name: &str) -> UdpSocket {
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let handshake = Message::Handshake(name.to_string(), None);
+    let datagram = DatagramHeader::default().encode(&handshake.serialize());
+    socket.send_to(&datagram, server_address).await.unwrap();
+    socket
+}
+
+async fn player_count(server_handle: &ServerHandle) -> usize {
+    server_handle.status().await.unwrap().players.len()
+}
+
+/// Poll until the condition holds, false if it still doesn't after the timeout
+async fn wait_until<F: Future<Output = bool>>(
+    timeout: Duration,
+    condition: impl Fn() -> F,
+) -> bool {
+    let deadline = Instant::now() + timeout;
+    while !condition().await {
+        if Instant::now() >= deadline {
+            return false;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    true
+}
+
+/// Whether every silent client joined, and whether all of them were dropped in time
+async fn silent_clients_time_out(clients: usize) -> (bool, bool) {
+    let (server_handle, server_address) = start().await;
+    let mut sockets = Vec::new();
+    for index in 0..clients {
+        sockets.push(join_silently(server_address, &format!("Silent {index}")).await);
+    }
+    let joined = wait_until(Duration::from_secs(1), || async {
+        player_count(&server_handle).await == clients
+    })
+    .await;
+    let dropped = wait_until(globals::CONNECTION_TIMEOUT_SEC + TIMEOUT_MARGIN, || async {
+        player_count(&server_handle).await == 0
+    })
+    .await;
+
+    server_handle.shutdown().await;
+    server_handle.await_terminated().await;
+    (joined, dropped)
+}
+
+async fn task_count(server_handle: &ServerHandle) -> usize {
+    server_handle.metrics().await.unwrap().tasks
+}
+
+/// Whether the server went back to the tasks it had before anyone joined, after each round of
+/// silent clients timed out
+async fn game_tasks_stop(rounds: usize) -> Vec<bool> {
+    let (server_handle, server_address) = start().await;
+    let idle_tasks = task_count(&server_handle).await;
+    let mut stopped = Vec::new();
+    for round in 0..rounds {
+        let _socket = join_silently(server_address, &format!("Silent {round}")).await;
+        wait_until(Duration::from_secs(1), || async {
+            player_count(&server_handle).await == 1
+        })
+        .await;
+        stopped.push(
+            wait_until(globals::CONNECTION_TIMEOUT_SEC + TIMEOUT_MARGIN, || async {
+                task_count(&server_handle).await == idle_tasks
+            })
+            .await,
+        );
+    }
+
+    server_handle.shutdown().await;
+    server_handle.await_terminated().await;
+    stopped
+}
+
+/// What a player saw of another one walking, and what went over the wire
+struct Replicated {
+    walked_to: Player,
+    /// Latest position of the walking player applied by the watching one
+    seen: Option<Player>,
+    snapshots_sent: u64,
+    snapshot_acks_received: u64,
+    replicates_sent: u64,
+}
+
+/// One player walking right for the given number of updates while another one watches
+async fn walk(updates: usize) -> Replicated {
+    let (server_handle, server_address) = start().await;
+    let mut walker = join(server_address, "Walker").await;
+    let mut watcher = join(server_address, "Watcher").await;
+
+    let mut walker_player = walker.get_session_player_data();
+    let mut seen = None;
+    let mut interval = tokio::time::interval(Duration::from_secs_f32(1.0 / 60.0));
+    // Time for the last position to get through after stopping
+    for update in 0..updates + 60 {
+        interval.tick().await;
+        if update < updates {
+            walker_player.pos.x += 2.0;
+            walker.send_pos(&walker_player);
+        }
+        walker.try_events();
+        for event in watcher.try_events() {
+            if let ClientEvent::Message(Message::Replicate(tick, player)) = event {
+                if player.id == walker_player.id && watcher.accept_snapshot(player.id, tick) {
+                    seen = Some(player);
+                }
+            }
+        }
+    }
+    let traffic = server_handle.metrics().await.unwrap().traffic;
+
+    walker.leave_and_wait().await;
+    watcher.leave_and_wait().await;
+    server_handle.shutdown().await;
+    server_handle.await_terminated().await;
+    Replicated {
+        walked_to: walker_player,
+        seen,
+        snapshots_sent: traffic.get("SNAPSHOT").map_or(0, |t| t.sent.packets),
+        snapshot_acks_received: traffic.get("SNAPSHOTACK").map_or(0, |t| t.received.packets),
+        replicates_sent: traffic.get("REPL").map_or(0, |t| t.sent.packets),
+    }
+}
+
+/// What a player saw of a distant player who walked a bit, and the average size of the snapshot
+/// datagrams sent once everyone stood still
+struct DistantReplicated {
+    walked_to: Player,
+    seen: Option<Player>,
+    bytes_per_snapshot: f64,
+}
+
+/// Settings replicating distant players in every sixth snapshot only
+fn distant_settings() -> PathBuf {
+    let path = std::env::temp_dir().join(format!("distant-{}.toml", std::process::id()));
+    std::fs::write(&path, "snapshot_rate = 60\ndistant_snapshot_rate = 10\n").unwrap();
+    path
+}
+
+/// A player with another one nearby and a third one across the world, who walks the given number
+/// of updates and stops
+async fn walk_distant(updates: usize) -> DistantReplicated {
+    let settings_path = distant_settings();
+    let (server_handle, server_address) = start_with(ServerConfig {
+        settings_path: Some(settings_path.clone()),
+        ..ServerConfig::default()
+    })
+    .await;
+    let mut sessions = Vec::new();
+    for (name, x) in [
+        ("Watcher", -1000.0),
+        ("Neighbour", -950.0),
+        ("Distant", 1000.0),
+    ] {
+        let session = join(server_address, name).await;
+        let mut player = session.get_session_player_data();
+        player.pos.x = x;
+        player.pos.y = 0.0;
+        session.send_pos(&player);
+        sessions.push((session, player));
+    }
+
+    let mut seen = None;
+    let mut traffic_before = None;
+    let mut interval = tokio::time::interval(Duration::from_secs_f32(1.0 / 60.0));
+    // Time for the last position to get through, then for measuring snapshots of nobody moving
+    for update in 0..updates + 120 {
+        interval.tick().await;
+        if update < updates {
+            let (distant, distant_player) = &mut sessions[2];
+            distant_player.pos.y += 2.0;
+            distant.send_pos(distant_player);
+        }
+        if update == updates + 60 {
+            traffic_before = server_handle
+                .metrics()
+                .await
+                .unwrap()
+                .traffic
+                .remove("SNAPSHOT");
+        }
+        let distant_id = sessions[2].1.id;
+        for (index, (session, _)) in sessions.iter_mut().enumerate() {
+            for event in session.try_events() {
+                if let ClientEvent::Message(Message::Replicate(tick, player)) = event {
+                    if index == 0
+                        && player.id == distant_id
+                        && session.accept_snapshot(player.id, tick)
+                    {
+                        seen = Some(player);
+                    }
+                }
+            }
+        }
+    }
+    let traffic_after = server_handle
+        .metrics()
+        .await
+        .unwrap()
+        .traffic
+        .remove("SNAPSHOT");
+    let walked_to = sessions[2].1;
+
+    for (session, _) in sessions.drain(..) {
+        session.leave_and_wait().await;
+    }
+    server_handle.shutdown().await;
+    server_handle.await_terminated().await;
+    let _ = std::fs::remove_file(settings_path);
+    let (before, after) = (traffic_before.unwrap().sent, traffic_after.unwrap().sent);
+    DistantReplicated {
+        walked_to,
+        seen,
+        bytes_per_snapshot: (after.bytes - before.bytes) as f64
+            / (after.packets - before.packets) as f64,
+    }
+}
+
+/// Position the server has of a player who took the given steps to the right and stopped
+async fn stop_after(steps: usize, step: f32) -> (Player, Option<(f32, f32)>) {
+    let (server_handle, server_address) = start().await;
+    let walker = join(server_address, "Walker").await;
+    let mut walker_player = walker.get_session_player_data();
+    let mut interval = tokio::time::interval(Duration::from_secs_f32(1.0 / 60.0));
+    for _ in 0..steps {
+        interval.tick().await;
+        walker_player.pos.x += step;
+        walker.send_pos(&walker_player);
+    }
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    let on_server = server_handle
+        .status()
+        .await
+        .unwrap()
+        .players
+        .iter()
+        .find(|player| player.id == walker_player.id)
+        .map(|player| (player.x, player.y));
+
+    walker.leave_and_wait().await;
+    server_handle.shutdown().await;
+    server_handle.await_terminated().await;
+    (walker_player, on_server)
+}
+
+/// Whether the game port is free again after the server failed to start because its observer
+/// port was taken
+async fn game_port_freed_after_failed_start() -> bool {
+    let taken = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let observer_port = taken.local_addr().unwrap().port();
+    let port = UdpSocket::bind("0.0.0.0:0")
+        .await
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let started = server::start_server(ServerConfig {
+        port,
+        observer_port: Some(observer_port),
+        ..ServerConfig::default()
+    })
+    .await;
+    assert!(started.is_err(), "Server started on a taken observer port");
+    // Give orphaned tasks, if any, the chance to keep running
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    UdpSocket::bind(("0.0.0.0", port)).await.is_ok()
+}
+
+#[test]
+fn failed_start_leaves_nothing_running() {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    assert!(runtime.block_on(game_port_freed_after_failed_start()));
+}
+
+proptest! {
+    // Every case runs a server for a while, some until the connection timeout
+    #![proptest_config(ProptestConfig::with_cases(2))]
+
+    #[test]
+    fn silent_players_are_dropped(clients in 1..=3usize) {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let (joined, dropped) = runtime.block_on(silent_clients_time_out(clients));
+        prop_assert!(joined);
+        prop_assert!(dropped);
+    }
+
+    #[test]
+    fn walking_player_is_replicated_through_snapshots(updates in 10..120usize) {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let replicated = runtime.block_on(walk(updates));
+        let seen = replicated.seen.expect("Walker was never replicated");
+        prop_assert!((seen.pos - replicated.walked_to.pos).x.abs() < 1.0, "{:?}", seen.pos);
+        prop_assert!(replicated.snapshots_sent > 0);
+        prop_assert!(replicated.snapshot_acks_received > 0);
+        // Two players always fit into a snapshot
+        prop_assert_eq!(replicated.replicates_sent, 0);
+    }
+
+    #[test]
+    fn distant_players_are_replicated_through_snapshot_changes(updates in 10..60usize) {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let replicated = runtime.block_on(walk_distant(updates));
+        let seen = replicated.seen.expect("Distant player was never replicated");
+        prop_assert!((seen.pos - replicated.walked_to.pos).y.abs() < 1.0, "{:?}", seen.pos);
+        // Nobody changed, so snapshots are only their header, whether distant players are in them
+        // or not
+        let empty_snapshot = message::HEADER_LEN + Message::Snapshot(vec![0; 8 + 8 + 2]).serialize().len();
+        prop_assert_eq!(replicated.bytes_per_snapshot, empty_snapshot as f64);
+    }
+
+    #[test]
+    fn stopped_player_ends_up_where_it_stopped(steps in 1..60usize, step in 0.05f32..2.0) {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let (walker, on_server) = runtime.block_on(stop_after(steps, step));
+        let (x, y) = on_server.expect("Walker is not on the server");
+        // Positions are quantized on the wire
+        prop_assert!((x - walker.pos.x).abs() < 0.1, "{} on the server, {} locally", x, walker.pos.x);
+        prop_assert!((y - walker.pos.y).abs() < 0.1);
+    }
+
+    #[test]
+    fn game_tasks_stop_after_silent_players_time_out(rounds in 1..=2usize) {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let stopped = runtime.block_on(game_tasks_stop(rounds));
+        prop_assert_eq!(stopped, vec![true; rounds]);
+    }
+}
@@ -0,0 +1,73 @@
+use multiplayer_game_demo_rust::snapshot::{
+    EntityState, SnapshotDecoder, SnapshotEncoder, WorldState,
+};
+use proptest::prelude::*;
+
+/// Few distinct values, so that fields often stay the same between snapshots
+fn entity_state() -> impl Strategy<Value = EntityState> {
+    (0..3u16, 0..3u16, 0..2u8).prop_map(|(pos, velocity, color)| EntityState {
+        pos: (pos, pos * 7),
+        velocity: (velocity, u16::MAX - velocity),
+        color: [color, 0, 255],
+    })
+}
+
+/// Players joining and leaving between snapshots
+fn world_state() -> impl Strategy<Value = WorldState> {
+    proptest::collection::btree_map(1..8u64, entity_state(), 0..6)
+}
+
+proptest! {
+    #[test]
+    fn decoded_snapshot_matches_world_despite_losses(
+        // World, whether its snapshot is lost, and whether the acknowledgement is lost
+        steps in proptest::collection::vec(
+            (world_state(), proptest::bool::weighted(0.3), proptest::bool::weighted(0.5)),
+            0..100,
+        ),
+    ) {
+        let mut encoder = SnapshotEncoder::default();
+        let mut decoder = SnapshotDecoder::default();
+        for (tick, (world, snapshot_lost, ack_lost)) in (1..).zip(steps) {
+            let mut snapshot = Vec::new();
+            encoder.encode(tick, &world, &mut snapshot);
+            if snapshot_lost {
+                continue;
+            }
+            let (decoded_tick, decoded_world) = decoder.decode(&snapshot).unwrap();
+            prop_assert_eq!(decoded_tick, tick);
+            prop_assert_eq!(decoded_world, world);
+            if !ack_lost {
+                encoder.acknowledge(tick);
+                prop_assert_eq!(encoder.baseline_tick(), Some(tick));
+            }
+        }
+    }
+
+    #[test]
+    fn unchanged_world_leaves_out_every_player(world in world_state()) {
+        let mut encoder = SnapshotEncoder::default();
+        let mut decoder = SnapshotDecoder::default();
+        let mut full = Vec::new();
+        encoder.encode(1, &world, &mut full);
+        decoder.decode(&full).unwrap();
+        encoder.acknowledge(1);
+
+        let mut delta = Vec::new();
+        encoder.encode(2, &world, &mut delta);
+        // Only the tick, the baseline tick and the number of players
+        prop_assert_eq!(delta.len(), 8 + 8 + 2);
+        prop_assert_eq!(decoder.decode(&delta).unwrap(), (2, world));
+    }
+
+    #[test]
+    fn entity_state_survives_conversion_to_player(id in 1..u64::MAX, state in any::<((u16, u16), (u16, u16), [u8; 3])>()) {
+        let state = EntityState { pos: state.0, velocity: state.1, color: state.2 };
+        prop_assert_eq!(EntityState::new(&state.to_player(id)), state);
+    }
+
+    #[test]
+    fn decode_does_not_panic_on_any_input(bytes in proptest::collection::vec(any::<u8>(), 0..64)) {
+        let _ = SnapshotDecoder::default().decode(&bytes);
+    }
+}
@@ -0,0 +1,38 @@
+use cgmath::{InnerSpace, Vector2, Vector3};
+use multiplayer_game_demo_rust::{spatial::SpatialGrid, Player};
+use proptest::prelude::*;
+
+/// Includes positions outside of world bounds, which are only clamped by the next server tick
+fn position() -> impl Strategy<Value = Vector2<f32>> {
+    (-1500.0f32..1500.0, -1500.0f32..1500.0).prop_map(|(x, y)| Vector2::new(x, y))
+}
+
+proptest! {
+    #[test]
+    fn query_radius_finds_same_players_as_brute_force(
+        positions in proptest::collection::vec(position(), 0..64),
+        center in position(),
+        radius in 0.0f32..1000.0,
+        cell_size in 50.0f32..800.0,
+    ) {
+        let players: Vec<Player> = positions
+            .iter()
+            .enumerate()
+            .map(|(i, pos)| Player {
+                pos: *pos,
+                ..Player::new(i as u64 + 1, Vector3::new(1.0, 1.0, 1.0))
+            })
+            .collect();
+        let mut grid = SpatialGrid::new(cell_size);
+        grid.rebuild(&players);
+
+        let mut found: Vec<u64> = grid.query_radius(center, radius).map(|(id, _)| id).collect();
+        found.sort_unstable();
+        let expected: Vec<u64> = players
+            .iter()
+            .filter(|player| (player.pos - center).magnitude2() <= radius * radius)
+            .map(|player| player.id)
+            .collect();
+        prop_assert_eq!(found, expected);
+    }
+}
@@ -0,0 +1,65 @@
+use std::time::Duration;
+
+use cgmath::{InnerSpace, Vector2};
+use multiplayer_game_demo_rust::{
+    globals,
+    storm::{self, SafeZone, Storm},
+};
+use proptest::prelude::*;
+
+fn final_zone() -> impl Strategy<Value = SafeZone> {
+    let bounds = &globals::WORLD_BOUNDS;
+    (
+        bounds.min_x + storm::FINAL_RADIUS..=bounds.max_x - storm::FINAL_RADIUS,
+        bounds.min_y + storm::FINAL_RADIUS..=bounds.max_y - storm::FINAL_RADIUS,
+    )
+        .prop_map(|(x, y)| SafeZone {
+            center: Vector2::new(x, y),
+            radius: storm::FINAL_RADIUS,
+        })
+}
+
+/// Any time from the start of a storm until a while after it's over
+fn elapsed() -> impl Strategy<Value = Duration> {
+    let total = storm::SHRINK_DURATION + storm::HOLD_DURATION;
+    (0..total.as_millis() as u64 * 2).prop_map(Duration::from_millis)
+}
+
+proptest! {
+    #[test]
+    fn safe_zone_only_closes_in(final_zone in final_zone(), earlier in elapsed(), later in elapsed()) {
+        let (earlier, later) = (earlier.min(later), earlier.max(later));
+        let storm = Storm::new(final_zone);
+        let (Some(earlier_state), Some(later_state)) = (storm.state(earlier), storm.state(later))
+        else {
+            return Ok(());
+        };
+        let (earlier_zone, later_zone) = (earlier_state.zone, later_state.zone);
+        // Later zone lies within the earlier one, so staying in the final zone is always safe
+        let distance = (later_zone.center - earlier_zone.center).magnitude();
+        prop_assert!(distance + later_zone.radius <= earlier_zone.radius + 0.01);
+        prop_assert!(earlier_zone.contains(final_zone.center));
+    }
+
+    #[test]
+    fn storm_is_over_after_holding_the_final_zone(final_zone in final_zone(), elapsed in elapsed()) {
+        let state = Storm::new(final_zone).state(elapsed);
+        let over = elapsed >= storm::SHRINK_DURATION + storm::HOLD_DURATION;
+        prop_assert_eq!(state.is_none(), over);
+        if elapsed >= storm::SHRINK_DURATION && !over {
+            prop_assert_eq!(state.unwrap().zone, final_zone);
+        }
+    }
+
+    #[test]
+    fn replicated_zone_follows_storm(final_zone in final_zone(), taken in elapsed(), since in elapsed()) {
+        let storm = Storm::new(final_zone);
+        let (Some(state), Some(expected)) = (storm.state(taken), storm.state(taken + since)) else {
+            return Ok(());
+        };
+        let zone = state.zone_after(since);
+        prop_assert!((zone.radius - expected.zone.radius).abs() < 0.1);
+        prop_assert!((zone.center.x - expected.zone.center.x).abs() < 0.1);
+        prop_assert!((zone.center.y - expected.zone.center.y).abs() < 0.1);
+    }
+}
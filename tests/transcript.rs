@@ -0,0 +1,49 @@
+use multiplayer_game_demo_rust::transcript;
+use proptest::prelude::*;
+
+const GOLDEN_TRANSCRIPT: &str = include_str!("golden/session.transcript");
+
+proptest! {
+    #[test]
+    fn changed_byte_is_flagged_against_golden_transcript(
+        datagram in any::<prop::sample::Index>(),
+        byte in any::<prop::sample::Index>(),
+        flip in 1..=u8::MAX,
+    ) {
+        let golden = transcript::parse(GOLDEN_TRANSCRIPT).unwrap();
+        let mut recorded = transcript::record();
+        prop_assert_eq!(transcript::compare(&golden, &recorded), Vec::<String>::new());
+
+        let datagram = datagram.index(recorded.len());
+        let byte = byte.index(recorded[datagram].bytes.len());
+        recorded[datagram].bytes[byte] ^= flip;
+        let differences = transcript::compare(&golden, &recorded);
+        prop_assert_eq!(differences.len(), 1);
+        let expected = format!("Datagram {} ", datagram + 1);
+        prop_assert!(differences[0].starts_with(&expected), "{}", differences[0]);
+        prop_assert!(differences[0].contains(&format!("at byte {byte}:")), "{}", differences[0]);
+    }
+
+    #[test]
+    fn transcript_is_parsed_unchanged(
+        datagrams in prop::collection::vec(
+            (any::<bool>(), prop::collection::vec(any::<u8>(), 1..64), "[^\\p{Cc}#]{0,40}"),
+            0..20,
+        ),
+    ) {
+        let datagrams: Vec<transcript::Datagram> = datagrams
+            .into_iter()
+            .map(|(from_client, bytes, description)| transcript::Datagram {
+                direction: if from_client {
+                    transcript::Direction::ClientToServer
+                } else {
+                    transcript::Direction::ServerToClient
+                },
+                bytes,
+                description: description.trim().to_string(),
+            })
+            .collect();
+        let parsed = transcript::parse(&transcript::format(&datagrams)).unwrap();
+        prop_assert_eq!(parsed, datagrams);
+    }
+}